@@ -15,15 +15,16 @@ fn main() {
     // This is the path to the `c` headers file.
     let headers_path = libdir_path.join("include");
     // Is the solution to not owning this necessarily something without a lambda?
-    let header_file_paths = headers_path
+    let header_file_paths: Vec<_> = headers_path
         .read_dir()
         .expect("headers was not a directory")
         .map(|e| {
             e.expect("Something wrong with a header file's directory entry.")
                 .path()
-        });
+        })
+        .collect();
     // It's definitely not useful to focus on this now, but it is irritating that it can't borrow the path.
-    let header_file_path_strings = header_file_paths.map(|path| path.to_str().unwrap().to_owned());
+    let header_file_path_strings = header_file_paths.iter().map(|path| path.to_str().unwrap().to_owned());
     let src_path = libdir_path.join("src");
     let src_file_paths = src_path
         .read_dir()
@@ -47,24 +48,61 @@ fn main() {
 
     // MY ADDITION: Tell Cargo to re-run the script if any of c files change:
     println!("cargo::rerun-if-changed={}", src_path.to_str().unwrap());
+    // And if any header changes, or one is added or removed. bindgen's
+    // callbacks only mention the headers it read last time, so a new header
+    // wouldn't be noticed without this. Cargo looks inside directories for
+    // changes, but naming each header too makes it clear what's tracked.
+    println!("cargo::rerun-if-changed={}", headers_path.to_str().unwrap());
+    for header_file_path in &header_file_paths {
+        println!("cargo::rerun-if-changed={}", header_file_path.to_str().unwrap());
+    }
 
     let mut build = cc::Build::new();
     build.files(src_file_paths)
         .include(headers_path)
         .out_dir(build_path)
-        .flag("-O0")
         .flag("-Wall")
-        .flag("-ggdb")
         .flag("-Wextra")
         .flag("-Werror")
         .flag("-std=c18")
         .flag("-Wpedantic")
         .flag("-Wno-unused-parameter");
 
-    // Libasan just...doesn't work on aarch64 macOS, as of now. I really thought we were through the transition.
-    if cfg!(not(all(target_os = "macos", target_arch = "aarch64"))) {
-        build.flag("-fsanitize=address");
-        println!("cargo::rustc-link-lib=asan");
+    // The C build profile is picked with features, not with Cargo's profile,
+    // so a release build of the Rust code can still debug the C code. See the
+    // [features] section of Cargo.toml.
+    let feature = |name: &str| env::var_os(format!("CARGO_FEATURE_{name}")).is_some();
+    match (feature("C_DEBUG"), feature("C_OPT")) {
+        (true, true) => panic!(
+            "The c-debug and c-opt features can't both be enabled. To use c-opt, turn off the \
+             default features."
+        ),
+        (true, false) => {
+            build.opt_level(0).debug(false).flag("-ggdb");
+        }
+        (false, true) => {
+            build.opt_level(2).debug(false);
+        }
+        // Let cc follow Cargo's profile.
+        (false, false) => {}
+    }
+
+    if feature("C_ASAN") {
+        // Libasan just...doesn't work on aarch64 macOS, as of now. I really thought we were through the transition.
+        // These are the target's OS and architecture, which is what matters
+        // here; `cfg!` in a build script describes the host.
+        let target_os = env::var("CARGO_CFG_TARGET_OS").unwrap();
+        let target_arch = env::var("CARGO_CFG_TARGET_ARCH").unwrap();
+        if target_os == "macos" && target_arch == "aarch64" {
+            println!(
+                "cargo::warning=AddressSanitizer doesn't work on aarch64 macOS, so the C code is \
+                 built without it. Turn off the default features and pick c-debug or c-opt to \
+                 silence this."
+            );
+        } else {
+            build.flag("-fsanitize=address");
+            println!("cargo::rustc-link-lib=asan");
+        }
     }
 
     build.compile("aves");
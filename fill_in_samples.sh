@@ -12,14 +12,18 @@
 # already exist, it indicates so on stderr and doesn't regenerate that file.
 
 # Then, the script interprets all the bytecode files and produces files ending
-# in ".expected" with their output. If, for any file, a corresponding
-# ".expected" file already exists, the file is not interpreted and this is
-# indicated on stderr.
+# in ".expected" with their output, and ones ending in ".expected_exit_code"
+# with the code they exit with. If, for any file, a corresponding ".expected"
+# file already exists, the file is not interpreted and this is indicated on
+# stderr. The samples copied from the course's tests have no
+# ".expected_exit_code" files, since they all exit with 0.
 
-PRINT='cargo run --bin aves_interpreter -- --print --bytecode'
-ASSEMBLE='cargo run --bin aves_interpreter -- --print --text' # TODO: Bad name.
+PRINT='cargo run --bin aves_interpreter -- print --bytecode'
+ASSEMBLE='cargo run --bin aves_interpreter -- assemble'
+RECORD_EXPECTED='cargo run --bin aves_interpreter -- run --record-expected --bytecode'
 BYTECODE_EXTENSION=".aves_bytecode"
 TEXT_EXTENSION=".aves_text"
+EXPECTED_EXTENSION=".expected"
 IR_DIR=ir_samples
 HANDWRITTEN_DIR=ir_samples/handwritten
 
@@ -45,7 +49,16 @@ do
         echo "Skipping assembly of ${HANDWRITTEN_FILE} because ${OUTPUT_BYTECODE_FILE} already exists." >&2
         continue
     fi
-    "$ASSEMBLE" "$HANDWRITTEN_FILE" --output-bytecode "$OUTPUT_BYTECODE_FILE" 
+    "$ASSEMBLE" "$HANDWRITTEN_FILE" --output "$OUTPUT_BYTECODE_FILE" 
 done
 
-# TODO: Interpret.
\ No newline at end of file
+for BYTECODE_FILE in $(find "$IR_DIR" -type f | grep "${BYTECODE_EXTENSION}\$" | sort)
+do
+    EXPECTED_FILE=$(sed "s/${BYTECODE_EXTENSION}/${EXPECTED_EXTENSION}/g" <<<"$BYTECODE_FILE")
+    if [ -e "$EXPECTED_FILE" ]
+    then
+        echo "Skipping interpretation of ${BYTECODE_FILE} because ${EXPECTED_FILE} already exists." >&2
+        continue
+    fi
+    $RECORD_EXPECTED "$BYTECODE_FILE"
+done
\ No newline at end of file
@@ -0,0 +1,197 @@
+// Facts about programs that more than one tool needs.
+
+pub mod cfg;
+pub mod slice;
+
+use std::ops::Range;
+
+use crate::ir_definition::{Instruction, Intrinsic, Label};
+
+#[derive(Debug, PartialEq)]
+pub struct FunctionSpan<'a> {
+    pub label: &'a Label,
+    /// Indices into the program, starting at the `Function` instruction.
+    pub range: Range<usize>,
+}
+
+/// Finds where each function's body is. There's nothing in the IR marking
+/// where a function ends, so a function is taken to run from its `Function`
+/// instruction up to and including the last `Ret` before the next `Function`
+/// (or the end of the program). That way, the code that front-ends put after
+/// all the functions isn't counted as part of the last one.
+pub fn function_spans(program: &[Instruction]) -> Vec<FunctionSpan<'_>> {
+    let starts: Vec<_> = program
+        .iter()
+        .enumerate()
+        .filter_map(|(index, instruction)| match instruction {
+            Instruction::Function { label, .. } => Some((index, label)),
+            _ => None,
+        })
+        .collect();
+
+    starts
+        .iter()
+        .enumerate()
+        .map(|(i, &(start, label))| {
+            let next_start = starts.get(i + 1).map_or(program.len(), |&(next, _)| next);
+            let end = program[start..next_start]
+                .iter()
+                .rposition(|instruction| *instruction == Instruction::Ret)
+                .map_or(next_start, |last_ret| start + last_ret + 1);
+            FunctionSpan {
+                label,
+                range: start..end,
+            }
+        })
+        .collect()
+}
+
+/// How many arguments a `Function` instruction declares it takes, with an
+/// `@args(n)` attribute, or `None` if it doesn't (or isn't a `Function`). The
+/// error describes an `@args` that doesn't give a number.
+///
+/// FUNCTION itself only says how many locals there are, and the bytecode has
+/// nowhere to put attributes, so this is only known for text programs.
+pub fn declared_num_args(instruction: &Instruction) -> Option<Result<u64, String>> {
+    let Instruction::Function { attributes, .. } = instruction else {
+        return None;
+    };
+    let args = attributes
+        .iter()
+        .find(|attribute| attribute.name == "args")?;
+    Some(match &args.value {
+        Some(value) => value
+            .parse()
+            .map_err(|_| format!("@args({value}) isn't a number of arguments")),
+        None => Err("@args needs a number of arguments, like @args(2)".into()),
+    })
+}
+
+/// How many values an instruction pops, then how many it pushes. A call pops
+/// its arguments and leaves the placeholder under them for the result, which
+/// is the same as popping the placeholder too and pushing the result.
+pub fn stack_effect(instruction: &Instruction) -> (usize, usize) {
+    match instruction {
+        Instruction::Nop
+        | Instruction::ReserveString { .. }
+        | Instruction::ReserveInt { .. }
+        | Instruction::Label(_)
+        | Instruction::Jump(_)
+        | Instruction::Function { .. } => (0, 0),
+        Instruction::Iconst(_)
+        | Instruction::Sconst(_)
+        | Instruction::Read(_)
+        | Instruction::ArgLocalRead(_)
+        | Instruction::Push { .. } => (0, 1),
+        Instruction::Add
+        | Instruction::Sub
+        | Instruction::Mul
+        | Instruction::Div
+        | Instruction::Mod
+        | Instruction::Bor
+        | Instruction::Band
+        | Instruction::Xor
+        | Instruction::Or
+        | Instruction::And
+        | Instruction::Eq
+        | Instruction::Lt
+        | Instruction::Gt => (2, 1),
+        Instruction::Not => (1, 1),
+        Instruction::Write(_)
+        | Instruction::ArgLocalWrite(_)
+        | Instruction::BranchZero(_)
+        | Instruction::Pop { .. }
+        | Instruction::Intrinsic(Intrinsic::PrintInt | Intrinsic::PrintString | Intrinsic::Exit) => {
+            (1, 0)
+        }
+        // However many arguments it says it takes, which can be more than
+        // any stack holds.
+        Instruction::Call { num_args, .. } => (
+            usize::try_from(*num_args)
+                .unwrap_or(usize::MAX)
+                .saturating_add(1),
+            1,
+        ),
+        // Everything the function pushed goes.
+        Instruction::Ret => (1, 0),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assemble;
+
+    #[test]
+    fn spans_stop_at_the_last_ret() {
+        let program = assemble::program(
+            "JUMP main
+             FUNCTION f 0
+             ICONST 1
+             BRANCHZERO L1
+             ICONST 2
+             RET
+             L1:
+             ICONST 3
+             RET
+             NOP
+             FUNCTION g 1
+             ICONST 4
+             RET
+             NOP
+             main:
+             ICONST 42
+             CALL g 0",
+        )
+        .unwrap();
+        let spans = function_spans(&program);
+        assert_eq!(
+            spans,
+            vec![
+                FunctionSpan {
+                    label: &Label::named("f"),
+                    range: 1..9
+                },
+                FunctionSpan {
+                    label: &Label::named("g"),
+                    range: 10..13
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn declared_num_args_test() {
+        let program = assemble::program(
+            "@args(2) FUNCTION two 0
+             FUNCTION undeclared 0
+             @args(x) FUNCTION bad 0
+             @args FUNCTION empty 0
+             ICONST 1",
+        )
+        .unwrap();
+        let declared: Vec<_> = program.iter().map(declared_num_args).collect();
+        assert_eq!(
+            declared,
+            vec![
+                Some(Ok(2)),
+                None,
+                Some(Err("@args(x) isn't a number of arguments".into())),
+                Some(Err(
+                    "@args needs a number of arguments, like @args(2)".into()
+                )),
+                None,
+            ]
+        );
+    }
+
+    #[test]
+    fn functions_without_ret_run_to_the_next_function() {
+        let program = assemble::program("FUNCTION f 0\nNOP\nFUNCTION g 0\nNOP").unwrap();
+        let ranges: Vec<_> = function_spans(&program)
+            .into_iter()
+            .map(|span| span.range)
+            .collect();
+        assert_eq!(ranges, vec![0..2, 2..4]);
+    }
+}
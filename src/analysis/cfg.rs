@@ -0,0 +1,255 @@
+// Control-flow graphs: the program cut into basic blocks, with edges for
+// every way control can get from one to another.
+
+use std::{collections::HashMap, fmt::Write as _, ops::Range};
+
+use crate::ir_definition::{Instruction, Intrinsic, Label};
+
+/// An index into `Cfg::blocks`.
+pub type BlockId = usize;
+
+#[derive(Debug, PartialEq)]
+pub struct BasicBlock {
+    /// Indices into the program. Only the first instruction can be jumped to,
+    /// and only the last can jump.
+    pub range: Range<usize>,
+    /// In the order of the blocks, without repeats.
+    pub successors: Vec<BlockId>,
+    /// In the order of the blocks, without repeats.
+    pub predecessors: Vec<BlockId>,
+}
+
+/// The control-flow graph of a whole program.
+///
+/// Calls are treated like any other instruction: control goes on to the
+/// instruction after them, and there's no edge into the function called.
+/// `RET` and `INTRINSIC EXIT` have no successors, and neither do jumps to
+/// labels that aren't defined. Falling into a `FUNCTION` is an edge like any
+/// other, since that's what the interpreters do.
+#[derive(Debug, PartialEq)]
+pub struct Cfg {
+    /// In the order they're in the program, so the first block is where the
+    /// program starts, if it isn't empty.
+    pub blocks: Vec<BasicBlock>,
+    /// The block each function starts with, by name, in the order they're
+    /// defined. If a name is defined twice, only the first counts.
+    pub function_entries: Vec<(String, BlockId)>,
+}
+
+impl Cfg {
+    pub fn new(program: &[Instruction]) -> Self {
+        let mut labels = HashMap::new();
+        for (index, instruction) in program.iter().enumerate() {
+            if let Instruction::Label(label) | Instruction::Function { label, .. } = instruction {
+                labels.entry(label.name()).or_insert(index);
+            }
+        }
+
+        let mut starts = vec![false; program.len() + 1];
+        starts[0] = true;
+        for (index, instruction) in program.iter().enumerate() {
+            match instruction {
+                Instruction::Label(_) | Instruction::Function { .. } => starts[index] = true,
+                Instruction::Jump(_)
+                | Instruction::BranchZero(_)
+                | Instruction::Ret
+                | Instruction::Intrinsic(Intrinsic::Exit) => starts[index + 1] = true,
+                _ => {}
+            }
+        }
+        let start_indices: Vec<_> = (0..program.len()).filter(|&index| starts[index]).collect();
+        let mut block_of = vec![0; program.len()];
+        let mut blocks: Vec<_> = start_indices
+            .iter()
+            .enumerate()
+            .map(|(id, &start)| {
+                let end = start_indices.get(id + 1).copied().unwrap_or(program.len());
+                block_of[start..end].fill(id);
+                BasicBlock {
+                    range: start..end,
+                    successors: Vec::new(),
+                    predecessors: Vec::new(),
+                }
+            })
+            .collect();
+
+        for id in 0..blocks.len() {
+            let last = blocks[id].range.end - 1;
+            let target = |label: &Label| labels.get(label.name()).map(|&index| block_of[index]);
+            let fallthrough = (id + 1 < blocks.len()).then_some(id + 1);
+            let mut successors: Vec<_> = match &program[last] {
+                Instruction::Jump(label) => target(label).into_iter().collect(),
+                Instruction::BranchZero(label) => {
+                    target(label).into_iter().chain(fallthrough).collect()
+                }
+                Instruction::Ret | Instruction::Intrinsic(Intrinsic::Exit) => Vec::new(),
+                _ => fallthrough.into_iter().collect(),
+            };
+            successors.sort();
+            successors.dedup();
+            for &successor in &successors {
+                blocks[successor].predecessors.push(id);
+            }
+            blocks[id].successors = successors;
+        }
+
+        let mut function_entries: Vec<(String, BlockId)> = Vec::new();
+        for (index, instruction) in program.iter().enumerate() {
+            if let Instruction::Function { label, .. } = instruction {
+                if labels[label.name()] == index {
+                    function_entries.push((label.name().to_string(), block_of[index]));
+                }
+            }
+        }
+
+        Cfg {
+            blocks,
+            function_entries,
+        }
+    }
+
+    /// The block the instruction at `index` is in.
+    pub fn block_of(&self, index: usize) -> Option<BlockId> {
+        let id = self
+            .blocks
+            .partition_point(|block| block.range.end <= index);
+        (id < self.blocks.len()).then_some(id)
+    }
+
+    /// The blocks that can be reached from `entry`, including it, in order.
+    pub fn reachable_from(&self, entry: BlockId) -> Vec<BlockId> {
+        let mut reached = vec![false; self.blocks.len()];
+        let mut to_visit = vec![entry];
+        while let Some(id) = to_visit.pop() {
+            if !std::mem::replace(&mut reached[id], true) {
+                to_visit.extend(&self.blocks[id].successors);
+            }
+        }
+        (0..self.blocks.len()).filter(|&id| reached[id]).collect()
+    }
+
+    /// The graph in Graphviz's format, with each block labeled with its
+    /// instructions.
+    pub fn to_dot(&self, program: &[Instruction]) -> String {
+        let mut dot = String::from("digraph cfg {\n    node [shape=box, fontname=monospace];\n");
+        for (id, block) in self.blocks.iter().enumerate() {
+            let mut label = String::new();
+            for instruction in &program[block.range.clone()] {
+                // Graphviz's escapes: \l ends a left-justified line.
+                let text = instruction
+                    .to_string()
+                    .replace('\\', "\\\\")
+                    .replace('"', "\\\"");
+                write!(label, "{}\\l", text.replace('\n', "\\l")).unwrap();
+            }
+            writeln!(dot, "    b{id} [label=\"{label}\"];").unwrap();
+            for successor in &block.successors {
+                writeln!(dot, "    b{id} -> b{successor};").unwrap();
+            }
+        }
+        dot.push_str("}\n");
+        dot
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        assemble,
+        generate::{generate, GeneratorOptions},
+    };
+
+    fn edges(cfg: &Cfg) -> Vec<(Range<usize>, Vec<BlockId>)> {
+        cfg.blocks
+            .iter()
+            .map(|block| (block.range.clone(), block.successors.clone()))
+            .collect()
+    }
+
+    #[test]
+    fn blocks_and_edges() {
+        let program = assemble::program(
+            "JUMP main
+             FUNCTION f 0
+             ARGLOCAL_READ 0
+             BRANCHZERO zero
+             ICONST 1
+             RET
+             zero:
+             ICONST 0
+             RET
+             main:
+             loop:
+             ICONST 42
+             ICONST 1
+             CALL f 1
+             BRANCHZERO loop
+             JUMP nowhere
+             ICONST 0
+             INTRINSIC EXIT
+             NOP",
+        )
+        .unwrap();
+        let cfg = Cfg::new(&program);
+        assert_eq!(
+            edges(&cfg),
+            [
+                (0..1, vec![4]),
+                (1..4, vec![2, 3]),
+                (4..6, vec![]),
+                (6..9, vec![]),
+                (9..10, vec![5]),
+                (10..15, vec![5, 6]),
+                (15..16, vec![]),
+                (16..18, vec![]),
+                (18..19, vec![]),
+            ]
+        );
+        assert_eq!(cfg.blocks[4].predecessors, [0]);
+        assert_eq!(cfg.blocks[5].predecessors, [4, 5]);
+        assert_eq!(cfg.function_entries, [("f".to_string(), 1)]);
+        assert_eq!(cfg.block_of(12), Some(5));
+        assert_eq!(cfg.block_of(15), Some(6));
+        assert_eq!(cfg.block_of(19), None);
+        assert_eq!(cfg.reachable_from(0), [0, 4, 5, 6]);
+        assert_eq!(cfg.reachable_from(1), [1, 2, 3]);
+    }
+
+    #[test]
+    fn generated_programs_are_consistent() {
+        for seed in 0..20 {
+            let program = generate(&GeneratorOptions {
+                seed,
+                ..Default::default()
+            });
+            let cfg = Cfg::new(&program);
+            let mut next = 0;
+            for (id, block) in cfg.blocks.iter().enumerate() {
+                assert_eq!(block.range.start, next, "seed {seed}");
+                assert!(!block.range.is_empty(), "seed {seed}");
+                next = block.range.end;
+                for &successor in &block.successors {
+                    assert!(
+                        cfg.blocks[successor].predecessors.contains(&id),
+                        "seed {seed}"
+                    );
+                }
+                for &predecessor in &block.predecessors {
+                    assert!(
+                        cfg.blocks[predecessor].successors.contains(&id),
+                        "seed {seed}"
+                    );
+                }
+            }
+            assert_eq!(next, program.len(), "seed {seed}");
+        }
+    }
+
+    #[test]
+    fn empty_program() {
+        let cfg = Cfg::new(&[]);
+        assert!(cfg.blocks.is_empty());
+        assert_eq!(cfg.block_of(0), None);
+    }
+}
@@ -0,0 +1,432 @@
+// Backward slicing: which instructions can affect what one instruction sees,
+// to cut a miscompilation down to the instructions that matter.
+
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    fmt::Write as _,
+};
+
+use crate::{
+    analysis::{
+        cfg::{BlockId, Cfg},
+        stack_effect,
+    },
+    ir_definition::{Instruction, Intrinsic},
+};
+
+/// The instructions a value depends on, by index.
+type Deps = BTreeSet<usize>;
+
+/// What's known at one point in the program: what each value on the stack
+/// and in the function's slots depends on, and what decided that the program
+/// got there at all.
+#[derive(Debug, Default, Clone, PartialEq)]
+struct State {
+    stack: Vec<Deps>,
+    /// By index. Only the ones written are here, since an index can be
+    /// anything up to `u64::MAX`.
+    slots: BTreeMap<u64, Deps>,
+    control: Deps,
+}
+
+impl State {
+    /// Adds what's known in `other`. Where the stacks are different heights
+    /// (which `verify::stack` reports), the first height is kept, so that a
+    /// loop that pushes more each time around can't grow the stack forever.
+    fn join(&mut self, other: &State) -> bool {
+        let before = self.clone();
+        for (deps, other) in self.stack.iter_mut().zip(&other.stack) {
+            deps.extend(other);
+        }
+        for (slot, other) in &other.slots {
+            self.slots.entry(*slot).or_default().extend(other);
+        }
+        self.control.extend(&other.control);
+        *self != before
+    }
+
+    fn pop(&mut self) -> Deps {
+        self.stack.pop().unwrap_or_default()
+    }
+}
+
+/// What's known about the whole program, however it got there. Globals and
+/// registers aren't tracked along paths: whatever is written to one anywhere
+/// can be read from it everywhere.
+#[derive(Debug, Default, Clone, PartialEq)]
+struct Summaries {
+    globals: HashMap<String, Deps>,
+    registers: HashMap<i64, Deps>,
+    /// The state each function starts in, from all of its calls.
+    entries: HashMap<String, State>,
+    /// What each function's result depends on.
+    results: HashMap<String, Deps>,
+}
+
+struct Slicer<'a> {
+    program: &'a [Instruction],
+    cfg: Cfg,
+    summaries: Summaries,
+}
+
+impl Slicer<'_> {
+    /// Where analysis starts: the start of the program, and of each function.
+    fn roots(&self) -> Vec<(BlockId, Option<String>)> {
+        let mut roots = Vec::new();
+        if !self.cfg.blocks.is_empty() {
+            roots.push((0, None));
+        }
+        for (name, block) in &self.cfg.function_entries {
+            roots.push((*block, Some(name.clone())));
+        }
+        roots
+    }
+
+    fn step(&mut self, index: usize, state: &mut State, function: Option<&str>) {
+        let summaries = &mut self.summaries;
+        // Whatever decided that this runs is part of what it makes.
+        let control = state.control.clone();
+        let made = |deps: Deps| {
+            let mut deps = deps;
+            deps.extend(&control);
+            deps.insert(index);
+            deps
+        };
+        match &self.program[index] {
+            Instruction::Iconst(_) | Instruction::Sconst(_) => {
+                let deps = made(Deps::new());
+                state.stack.push(deps);
+            }
+            Instruction::Read(name) => {
+                let deps = made(summaries.globals.get(name).cloned().unwrap_or_default());
+                state.stack.push(deps);
+            }
+            Instruction::Write(name) => {
+                let value = state.pop();
+                let deps = made(value);
+                summaries
+                    .globals
+                    .entry(name.clone())
+                    .or_default()
+                    .extend(deps);
+            }
+            Instruction::ReserveInt { name } | Instruction::ReserveString { name, .. } => {
+                let deps = made(Deps::new());
+                summaries
+                    .globals
+                    .entry(name.clone())
+                    .or_default()
+                    .extend(deps);
+            }
+            Instruction::ArgLocalRead(slot) => {
+                let slot = state.slots.get(slot).cloned().unwrap_or_default();
+                let deps = made(slot);
+                state.stack.push(deps);
+            }
+            Instruction::ArgLocalWrite(slot) => {
+                let value = state.pop();
+                let deps = made(value);
+                state.slots.insert(*slot, deps);
+            }
+            Instruction::Push { reg } => {
+                let deps = made(summaries.registers.get(reg).cloned().unwrap_or_default());
+                state.stack.push(deps);
+            }
+            Instruction::Pop { reg } => {
+                let value = state.pop();
+                let deps = made(value);
+                // Register -1 is where unused values go to be thrown away.
+                if *reg != -1 {
+                    summaries.registers.entry(*reg).or_default().extend(deps);
+                }
+            }
+            Instruction::Not => {
+                let value = state.pop();
+                let deps = made(value);
+                state.stack.push(deps);
+            }
+            Instruction::BranchZero(_) => {
+                let value = state.pop();
+                let deps = made(value);
+                state.control.extend(deps);
+            }
+            Instruction::Call { label, num_args } => {
+                // Only what's on the stack can be an argument. The arguments
+                // that aren't there are the first ones, and are left out.
+                let available = state.stack.len() as u64;
+                let first = num_args.saturating_sub(available);
+                let args = state
+                    .stack
+                    .split_off(state.stack.len() - (num_args - first) as usize);
+                // The placeholder doesn't affect the result, but it's where
+                // the result goes, so a reduced program needs it.
+                let mut result = state.pop();
+                match summaries.results.get(label.name()) {
+                    Some(returned) => result.extend(returned),
+                    // A function that hasn't returned yet, or doesn't exist.
+                    None => result.extend(args.iter().flatten()),
+                }
+                let result = made(result);
+                let entry = summaries
+                    .entries
+                    .entry(label.name().to_string())
+                    .or_default();
+                entry.join(&State {
+                    stack: Vec::new(),
+                    slots: (first..).zip(args).collect(),
+                    control: made(Deps::new()),
+                });
+                state.stack.push(result);
+            }
+            Instruction::Ret => {
+                let value = state.pop();
+                let deps = made(value);
+                if let Some(function) = function {
+                    summaries
+                        .results
+                        .entry(function.to_string())
+                        .or_default()
+                        .extend(deps);
+                }
+            }
+            Instruction::Intrinsic(
+                Intrinsic::PrintInt | Intrinsic::PrintString | Intrinsic::Exit,
+            ) => {
+                state.pop();
+            }
+            Instruction::Nop
+            | Instruction::Label(_)
+            | Instruction::Jump(_)
+            | Instruction::Function { .. } => {}
+            _ => {
+                // The binary operations.
+                let b = state.pop();
+                let mut a = state.pop();
+                a.extend(b);
+                let deps = made(a);
+                state.stack.push(deps);
+            }
+        }
+    }
+
+    /// Runs the analysis from every root once, giving the state each block
+    /// starts in, for each root.
+    fn pass(&mut self) -> Vec<(Option<String>, HashMap<BlockId, State>)> {
+        let mut passes = Vec::new();
+        for (root, function) in self.roots() {
+            let start = match &function {
+                Some(function) => self
+                    .summaries
+                    .entries
+                    .get(function)
+                    .cloned()
+                    .unwrap_or_default(),
+                None => State::default(),
+            };
+            let mut states = HashMap::from([(root, start)]);
+            let mut to_visit = vec![root];
+            while let Some(block) = to_visit.pop() {
+                let mut state = states[&block].clone();
+                for index in self.cfg.blocks[block].range.clone() {
+                    self.step(index, &mut state, function.as_deref());
+                }
+                for &successor in &self.cfg.blocks[block].successors {
+                    let changed = match states.get_mut(&successor) {
+                        Some(successor_state) => successor_state.join(&state),
+                        None => {
+                            states.insert(successor, state.clone());
+                            true
+                        }
+                    };
+                    if changed {
+                        to_visit.push(successor);
+                    }
+                }
+            }
+            passes.push((function, states));
+        }
+        passes
+    }
+}
+
+/// The instructions that can affect the values `target` uses, or whether it
+/// runs at all, including `target` itself.
+///
+/// This is conservative: everything that can affect `target` is in the
+/// slice, but so may be some things that can't. Whatever decides a branch is
+/// taken to affect everything after it, and a call's result is taken to
+/// depend on every call's arguments.
+pub fn backward_slice(program: &[Instruction], target: usize) -> BTreeSet<usize> {
+    let mut slicer = Slicer {
+        program,
+        cfg: Cfg::new(program),
+        summaries: Summaries::default(),
+    };
+    // The summaries only ever grow, so this ends.
+    let passes = loop {
+        let before = slicer.summaries.clone();
+        let passes = slicer.pass();
+        if slicer.summaries == before {
+            break passes;
+        }
+    };
+
+    let mut slice = BTreeSet::from([target]);
+    let Some(block) = slicer.cfg.block_of(target) else {
+        return slice;
+    };
+    let (pops, _) = stack_effect(&program[target]);
+    for (function, states) in passes {
+        let Some(state) = states.get(&block) else {
+            continue;
+        };
+        let mut state = state.clone();
+        for index in slicer.cfg.blocks[block].range.start..target {
+            slicer.step(index, &mut state, function.as_deref());
+        }
+        for deps in state.stack.iter().rev().take(pops) {
+            slice.extend(deps);
+        }
+        slice.extend(&state.control);
+    }
+    slice
+}
+
+/// The instructions in `slice`, with every label, function and jump kept so
+/// that the slice runs the same way it did in the whole program.
+pub fn reduced_program(program: &[Instruction], slice: &BTreeSet<usize>) -> Vec<Instruction> {
+    program
+        .iter()
+        .enumerate()
+        .filter(|(index, instruction)| {
+            slice.contains(index)
+                || matches!(
+                    instruction,
+                    Instruction::Label(_) | Instruction::Function { .. } | Instruction::Jump(_)
+                )
+        })
+        .map(|(_, instruction)| instruction.clone())
+        .collect()
+}
+
+/// The program as text, with the instructions in `slice` marked with a `*`
+/// and `target` with a `>`.
+pub fn annotated_listing(
+    program: &[Instruction],
+    slice: &BTreeSet<usize>,
+    target: usize,
+) -> String {
+    let width = program.len().saturating_sub(1).to_string().len();
+    let mut listing = String::new();
+    for (index, instruction) in program.iter().enumerate() {
+        let marker = if index == target {
+            '>'
+        } else if slice.contains(&index) {
+            '*'
+        } else {
+            ' '
+        };
+        let indent = match instruction {
+            Instruction::Label(_) | Instruction::Function { .. } => "",
+            _ => "  ",
+        };
+        writeln!(listing, "{marker} {index:>width$}  {indent}{instruction}").unwrap();
+    }
+    listing
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assemble;
+
+    #[test]
+    fn globals() {
+        let program = assemble::program(
+            "RESERVE x 4 (null)
+             RESERVE y 4 (null)
+             ICONST 1
+             WRITE x
+             ICONST 2
+             WRITE y
+             READ y
+             INTRINSIC PRINT_INT
+             READ x
+             ICONST 3
+             ADD
+             INTRINSIC PRINT_INT",
+        )
+        .unwrap();
+        assert_eq!(
+            backward_slice(&program, 11),
+            BTreeSet::from([0, 2, 3, 8, 9, 10, 11])
+        );
+        assert_eq!(backward_slice(&program, 7), BTreeSet::from([1, 4, 5, 6, 7]));
+    }
+
+    #[test]
+    fn calls_and_branches() {
+        let program = assemble::program(
+            "JUMP main
+             FUNCTION double 0
+             ARGLOCAL_READ 0
+             ICONST 2
+             MUL
+             RET
+             main:
+             ICONST 42
+             ICONST 5
+             CALL double 1
+             ICONST 7
+             POP -1
+             ICONST 0
+             BRANCHZERO skip
+             ICONST 9
+             POP -1
+             skip:
+             INTRINSIC PRINT_INT",
+        )
+        .unwrap();
+        let slice = backward_slice(&program, 17);
+        assert_eq!(slice, BTreeSet::from([2, 3, 4, 5, 7, 8, 9, 12, 13, 17]));
+        let listing = annotated_listing(&program, &slice, 17);
+        assert!(listing
+            .starts_with("   0    JUMP main\n   1  FUNCTION double 0\n*  2    ARGLOCAL_READ 0\n"));
+        assert!(listing.ends_with("  16  skip:\n> 17    INTRINSIC PRINT_INT\n"));
+        assert_eq!(
+            reduced_program(&program, &slice),
+            assemble::program(
+                "JUMP main
+                 FUNCTION double 0
+                 ARGLOCAL_READ 0
+                 ICONST 2
+                 MUL
+                 RET
+                 main:
+                 ICONST 42
+                 ICONST 5
+                 CALL double 1
+                 ICONST 0
+                 BRANCHZERO skip
+                 skip:
+                 INTRINSIC PRINT_INT"
+            )
+            .unwrap()
+        );
+    }
+
+    #[test]
+    fn huge_operands() {
+        let program = assemble::program(
+            "ICONST 1
+             ARGLOCAL_WRITE 18446744073709551615
+             ICONST 2
+             ARGLOCAL_READ 18446744073709551615
+             ICONST 3
+             CALL f 18446744073709551615
+             INTRINSIC PRINT_INT",
+        )
+        .unwrap();
+        assert_eq!(backward_slice(&program, 6), BTreeSet::from_iter(0..7));
+    }
+}
@@ -0,0 +1,109 @@
+// What the instructions that take two integers compute, in one place, so the
+// Rust interpreter, constant folding and the verifier can't disagree.
+//
+// DIV rounds towards zero and MOD has the sign of the dividend, like in C99.
+// The one place C leaves it up to the platform is the smallest integer divided
+// by -1, which doesn't fit: here it wraps around like the other arithmetic,
+// but the C interpreter is usually killed by SIGFPE.
+
+use std::fmt;
+
+use crate::ir_definition::Instruction;
+
+/// What DIV and MOD do with the smallest integer and -1.
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+pub enum DivisionOverflow {
+    /// The smallest integer DIV -1 is itself, and MOD -1 is 0.
+    #[default]
+    Wrap,
+    /// It's an error, to match the C interpreter.
+    Fail,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum ArithmeticError {
+    DivisionByZero,
+    /// The smallest integer divided by -1, with `DivisionOverflow::Fail`.
+    DivisionOverflow,
+}
+
+impl fmt::Display for ArithmeticError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ArithmeticError::DivisionByZero => write!(f, "division by zero"),
+            ArithmeticError::DivisionOverflow => {
+                write!(f, "the smallest integer divided by -1 doesn't fit")
+            }
+        }
+    }
+}
+
+/// Whether dividing `a` by `b` is where C leaves the result up to the
+/// platform.
+pub fn division_overflows(a: i32, b: i32) -> bool {
+    a == i32::MIN && b == -1
+}
+
+/// What `instruction` gives for `a`, the value below the top of the stack,
+/// and `b`, the top, or `None` if it isn't an instruction that takes two
+/// integers.
+pub fn binary(
+    instruction: &Instruction,
+    a: i32,
+    b: i32,
+    overflow: DivisionOverflow,
+) -> Option<Result<i32, ArithmeticError>> {
+    let divide = |divide: fn(i32, i32) -> i32| {
+        if b == 0 {
+            Err(ArithmeticError::DivisionByZero)
+        } else if division_overflows(a, b) && overflow == DivisionOverflow::Fail {
+            Err(ArithmeticError::DivisionOverflow)
+        } else {
+            Ok(divide(a, b))
+        }
+    };
+    Some(match instruction {
+        Instruction::Add => Ok(a.wrapping_add(b)),
+        Instruction::Sub => Ok(a.wrapping_sub(b)),
+        Instruction::Mul => Ok(a.wrapping_mul(b)),
+        Instruction::Div => divide(i32::wrapping_div),
+        Instruction::Mod => divide(i32::wrapping_rem),
+        Instruction::Bor => Ok(a | b),
+        Instruction::Band => Ok(a & b),
+        Instruction::Xor => Ok(a ^ b),
+        Instruction::Or => Ok((a != 0 || b != 0).into()),
+        Instruction::And => Ok((a != 0 && b != 0).into()),
+        Instruction::Eq => Ok((a == b).into()),
+        Instruction::Lt => Ok((a < b).into()),
+        Instruction::Gt => Ok((a > b).into()),
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn division() {
+        let div = |a, b, overflow| binary(&Instruction::Div, a, b, overflow).unwrap();
+        let rem = |a, b, overflow| binary(&Instruction::Mod, a, b, overflow).unwrap();
+        use DivisionOverflow::*;
+        assert_eq!(div(-7, 2, Wrap), Ok(-3));
+        assert_eq!(rem(-7, 2, Wrap), Ok(-1));
+        assert_eq!(rem(7, -2, Wrap), Ok(1));
+        assert_eq!(div(i32::MIN, -1, Wrap), Ok(i32::MIN));
+        assert_eq!(rem(i32::MIN, -1, Wrap), Ok(0));
+        assert_eq!(
+            div(i32::MIN, -1, Fail),
+            Err(ArithmeticError::DivisionOverflow)
+        );
+        assert_eq!(
+            rem(i32::MIN, -1, Fail),
+            Err(ArithmeticError::DivisionOverflow)
+        );
+        assert_eq!(div(i32::MIN, 1, Fail), Ok(i32::MIN));
+        assert_eq!(div(1, 0, Wrap), Err(ArithmeticError::DivisionByZero));
+        assert_eq!(binary(&Instruction::Not, 1, 0, Wrap), None);
+    }
+}
@@ -0,0 +1,215 @@
+// Checks that calls pass as many arguments as their callees take. A mismatch
+// isn't caught at runtime: the callee just reads and pops the wrong slots.
+
+use std::{collections::BTreeMap, fmt};
+
+use crate::{analysis::declared_num_args, ir_definition::Instruction};
+
+#[derive(Debug, PartialEq)]
+pub enum ArityProblem {
+    /// A call passes a different number of arguments than its callee's
+    /// `@args(n)` declares.
+    Mismatch {
+        call: usize,
+        callee: String,
+        declared: u64,
+        given: u64,
+    },
+    /// A function's `@args` attribute doesn't give a number.
+    BadDeclaration { function: usize, message: String },
+    /// Without a declaration to go on, calls to a function pass different
+    /// numbers of arguments. Each call is given with its number of arguments.
+    InconsistentCalls {
+        callee: String,
+        calls: Vec<(usize, u64)>,
+    },
+    /// A call to a function that isn't in the program.
+    UnknownFunction { call: usize, callee: String },
+}
+
+impl ArityProblem {
+    /// Calls to unknown functions might be to functions that are defined
+    /// elsewhere, so they're a different kind of problem from the rest.
+    pub fn is_unknown_function(&self) -> bool {
+        matches!(self, ArityProblem::UnknownFunction { .. })
+    }
+}
+
+impl fmt::Display for ArityProblem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ArityProblem::Mismatch {
+                call,
+                callee,
+                declared,
+                given,
+            } => write!(
+                f,
+                "the call at instruction {call} passes {given} arguments to {callee}, \
+                 which takes {declared}"
+            ),
+            ArityProblem::BadDeclaration { function, message } => {
+                write!(f, "the function at instruction {function}: {message}")
+            }
+            ArityProblem::InconsistentCalls { callee, calls } => {
+                let calls: Vec<_> = calls
+                    .iter()
+                    .map(|(call, given)| format!("{given} at instruction {call}"))
+                    .collect();
+                write!(
+                    f,
+                    "calls to {callee} pass different numbers of arguments: {}",
+                    calls.join(", ")
+                )
+            }
+            ArityProblem::UnknownFunction { call, callee } => write!(
+                f,
+                "the call at instruction {call} is to {callee}, which isn't defined"
+            ),
+        }
+    }
+}
+
+/// Finds every call that can be shown to pass the wrong number of arguments.
+/// Calls are checked against their callee's `@args(n)` attribute if it has
+/// one, and otherwise against each other. Problems come in the order they
+/// appear in the program, except that calls to unknown functions come last.
+pub fn check_arities(program: &[Instruction]) -> Vec<ArityProblem> {
+    let mut problems = Vec::new();
+    // `None` for functions without a (valid) declaration.
+    let mut declared = BTreeMap::new();
+    for (index, instruction) in program.iter().enumerate() {
+        if let Instruction::Function { label, .. } = instruction {
+            let num_args = match declared_num_args(instruction) {
+                Some(Ok(num_args)) => Some(num_args),
+                Some(Err(message)) => {
+                    problems.push(ArityProblem::BadDeclaration {
+                        function: index,
+                        message,
+                    });
+                    None
+                }
+                None => None,
+            };
+            declared.insert(label.name(), num_args);
+        }
+    }
+
+    let mut undeclared_calls: BTreeMap<&str, Vec<(usize, u64)>> = BTreeMap::new();
+    let mut unknown = Vec::new();
+    for (index, instruction) in program.iter().enumerate() {
+        let Instruction::Call { label, num_args } = instruction else {
+            continue;
+        };
+        match declared.get(label.name()) {
+            Some(Some(declared)) if declared != num_args => problems.push(ArityProblem::Mismatch {
+                call: index,
+                callee: label.name().to_string(),
+                declared: *declared,
+                given: *num_args,
+            }),
+            Some(Some(_)) => {}
+            Some(None) => undeclared_calls
+                .entry(label.name())
+                .or_default()
+                .push((index, *num_args)),
+            None => unknown.push(ArityProblem::UnknownFunction {
+                call: index,
+                callee: label.name().to_string(),
+            }),
+        }
+    }
+
+    for (callee, calls) in undeclared_calls {
+        if calls.iter().any(|&(_, given)| given != calls[0].1) {
+            problems.push(ArityProblem::InconsistentCalls {
+                callee: callee.to_string(),
+                calls,
+            });
+        }
+    }
+    problems.sort_by_key(|problem| match problem {
+        ArityProblem::Mismatch { call, .. } => *call,
+        ArityProblem::BadDeclaration { function, .. } => *function,
+        ArityProblem::InconsistentCalls { calls, .. } => calls[0].0,
+        ArityProblem::UnknownFunction { call, .. } => *call,
+    });
+    problems.extend(unknown);
+    problems
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assemble;
+
+    #[test]
+    fn problems() {
+        let program = assemble::program(
+            "JUMP main
+             @args(2) FUNCTION declared 0
+             ICONST 0
+             RET
+             FUNCTION undeclared 0
+             ICONST 0
+             RET
+             @args(two) FUNCTION bad 0
+             ICONST 0
+             RET
+             main:
+             CALL nowhere 0
+             CALL declared 2
+             CALL declared 1
+             CALL undeclared 1
+             CALL undeclared 3
+             CALL undeclared 1",
+        )
+        .unwrap();
+        let problems = check_arities(&program);
+        assert_eq!(
+            problems,
+            vec![
+                ArityProblem::BadDeclaration {
+                    function: 7,
+                    message: "@args(two) isn't a number of arguments".into()
+                },
+                ArityProblem::Mismatch {
+                    call: 13,
+                    callee: "declared".into(),
+                    declared: 2,
+                    given: 1
+                },
+                ArityProblem::InconsistentCalls {
+                    callee: "undeclared".into(),
+                    calls: vec![(14, 1), (15, 3), (16, 1)]
+                },
+                ArityProblem::UnknownFunction {
+                    call: 11,
+                    callee: "nowhere".into()
+                },
+            ]
+        );
+        assert_eq!(
+            problems[1].to_string(),
+            "the call at instruction 13 passes 1 arguments to declared, which takes 2"
+        );
+        assert_eq!(
+            problems[2].to_string(),
+            "calls to undeclared pass different numbers of arguments: \
+             1 at instruction 14, 3 at instruction 15, 1 at instruction 16"
+        );
+        assert!(problems[3].is_unknown_function());
+    }
+
+    #[test]
+    fn consistent_programs_have_no_problems() {
+        use crate::generate::{generate, GeneratorOptions};
+        for seed in 0..20 {
+            let program = generate(&GeneratorOptions {
+                seed,
+                ..Default::default()
+            });
+            assert_eq!(check_arities(&program), vec![], "seed {seed}");
+        }
+    }
+}
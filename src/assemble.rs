@@ -1,21 +1,30 @@
 use nom::{
     branch::alt,
-    bytes::complete::{escaped_transform, tag_no_case, take_till, take_while1},
-    character::complete::{char as nom_char, i64 as nom_i64, none_of, u64 as nom_u64},
-    combinator::{all_consuming, map, opt, value},
-    multi::{many0_count, many1_count, separated_list0},
-    sequence::{delimited, preceded, terminated, tuple},
+    bytes::complete::{escaped_transform, tag_no_case, take_till, take_while, take_while1},
+    character::complete::{char as nom_char, none_of, one_of, satisfy},
+    combinator::{all_consuming, cut, map, map_opt, map_res, opt, recognize, value},
+    error::{context, convert_error, VerboseError},
+    multi::{many0, many0_count, many1_count, separated_list0},
+    sequence::{delimited, pair, preceded, terminated, tuple},
     IResult,
 };
 
-use crate::ir_definition::{Intrinsic, Instruction, Label};
-type NodeResult<'a> = IResult<&'a str, Instruction>;
+use std::ops::Range;
 
-fn identifier(input: &str) -> IResult<&str, &str> {
+use crate::{
+    ir_definition::{Attribute, Intrinsic, Instruction, Label},
+    source_map::SourceMap,
+};
+// Verbose errors are what let us give targeted diagnostics, via `context`.
+type ParseResult<'a, O> = IResult<&'a str, O, VerboseError<&'a str>>;
+type NodeResult<'a> = ParseResult<'a, Instruction>;
+type SpannedNode = (Instruction, Range<usize>);
+
+fn identifier(input: &str) -> ParseResult<'_, &str> {
     take_while1(|c| char::is_alphanumeric(c) || c == '$' || c == '_')(input)
 }
 
-fn inside_string(input: &str) -> IResult<&str, String> {
+fn inside_string(input: &str) -> ParseResult<'_, String> {
     use nom::bytes::complete::tag;
     // The `opt` is necessary because escaped_transform must consume at least
     // one character. If it sees a '"' (the end of a string), it fails. If we
@@ -36,30 +45,86 @@ fn inside_string(input: &str) -> IResult<&str, String> {
     )(input)
 }
 
-fn string_literal(input: &str) -> IResult<&str, String> {
+pub(crate) fn string_literal(input: &str) -> ParseResult<'_, String> {
     delimited(nom_char('"'), inside_string, nom_char('"'))(input)
 }
 
-fn multi_line_comment(input: &str) -> IResult<&str, &str> {
+// Digits in the given radix, optionally separated by underscores, as in Rust.
+// The first character must be a digit.
+fn digits(radix: u32) -> impl Fn(&str) -> ParseResult<'_, u128> {
+    move |input| {
+        map_res(
+            recognize(pair(
+                satisfy(|c| c.is_digit(radix)),
+                take_while(|c: char| c.is_digit(radix) || c == '_'),
+            )),
+            |text: &str| u128::from_str_radix(&text.replace('_', ""), radix),
+        )(input)
+    }
+}
+
+// Only the escapes that make sense inside single quotes are supported, mirroring
+// how `inside_string` only supports escaped backslashes and double quotes.
+fn char_literal(input: &str) -> ParseResult<'_, u128> {
+    map(
+        delimited(
+            nom_char('\''),
+            alt((preceded(nom_char('\\'), one_of(r"\'")), none_of(r"\'"))),
+            nom_char('\''),
+        ),
+        u128::from,
+    )(input)
+}
+
+// The magnitude of an integer literal, before any sign is applied. The `cut`s
+// stop something like "0x" or an out-of-range hex literal from being accepted
+// as a decimal 0 followed by garbage.
+fn magnitude(input: &str) -> ParseResult<'_, u128> {
+    alt((
+        preceded(tag_no_case("0x"), cut(digits(16))),
+        preceded(tag_no_case("0o"), cut(digits(8))),
+        preceded(tag_no_case("0b"), cut(digits(2))),
+        digits(10),
+        char_literal,
+    ))(input)
+}
+
+fn signed_integer(input: &str) -> ParseResult<'_, i64> {
+    map_res(pair(opt(one_of("+-")), magnitude), |(sign, magnitude)| {
+        let magnitude = i128::try_from(magnitude)?;
+        let value = if sign == Some('-') {
+            -magnitude
+        } else {
+            magnitude
+        };
+        i64::try_from(value)
+    })(input)
+}
+
+fn unsigned_integer(input: &str) -> ParseResult<'_, u64> {
+    map_res(magnitude, u64::try_from)(input)
+}
+
+fn multi_line_comment(input: &str) -> ParseResult<'_, &str> {
     use nom::bytes::complete::{tag, take_until};
     delimited(tag("/*"), take_until("*/"), tag("*/"))(input)
 }
 
 // Does not consume the thing that ended the single_line_comment (either a newline or the end of the file).
-fn single_line_comment(input: &str) -> IResult<&str, &str> {
+fn single_line_comment(input: &str) -> ParseResult<'_, &str> {
     use nom::bytes::complete::tag;
 
     // TODO: Try making this use `terminated`, `line_ending`, and `eof`.
     preceded(tag("#"), take_till(|c| c == '\n' || c == '\r'))(input)
 }
 
-fn within_node(input: &str) -> IResult<&str, &str> {
-    use nom::{character::complete::space1, combinator::recognize};
+fn within_node(input: &str) -> ParseResult<'_, &str> {
+    use nom::character::complete::space1;
     recognize(many0_count(alt((space1, multi_line_comment))))(input)
 }
 
-fn between_nodes(input: &str) -> IResult<&str, &str> {
-    use nom::{character::complete::multispace1, combinator::recognize};
+fn between_nodes(input: &str) -> ParseResult<'_, &str> {
+    use nom::character::complete::multispace1;
     recognize(many1_count(alt((
         multispace1,
         multi_line_comment,
@@ -69,7 +134,7 @@ fn between_nodes(input: &str) -> IResult<&str, &str> {
 
 macro_rules! noarg_node {
     ($func_name:ident, $tag_text:literal, $result:expr) => {
-        fn $func_name(input: &str) -> NodeResult {
+        fn $func_name(input: &str) -> NodeResult<'_> {
             let (rest, _) = tag_no_case($tag_text)(input)?;
             Ok((rest, $result))
         }
@@ -80,12 +145,12 @@ macro_rules! noarg_node {
 // left to the thing that processes multiple instructions, that can take
 // newlines and spaces.
 
-fn iconst(input: &str) -> NodeResult {
-    let (rest, i) = preceded(tuple((tag_no_case("ICONST"), within_node)), nom_i64)(input)?;
+fn iconst(input: &str) -> NodeResult<'_> {
+    let (rest, i) = preceded(tuple((tag_no_case("ICONST"), within_node)), signed_integer)(input)?;
     Ok((rest, Instruction::Iconst(i)))
 }
 
-fn sconst(input: &str) -> NodeResult {
+fn sconst(input: &str) -> NodeResult<'_> {
     let (rest, transformed_text) =
         preceded(tuple((tag_no_case("SCONST"), within_node)), string_literal)(input)?;
     Ok((rest, Instruction::Sconst(transformed_text.into())))
@@ -107,88 +172,141 @@ noarg_node!(lt, "LT", Instruction::Lt);
 noarg_node!(gt, "GT", Instruction::Gt);
 noarg_node!(not, "NOT", Instruction::Not);
 
-fn reserve(input: &str) -> NodeResult {
-    let (start_of_string_or_null, (name, size)) = preceded(
+// Integers are reserved with a null initial value. Comments and spaces are
+// allowed inside the parentheses, as they are anywhere else within a node.
+fn null(input: &str) -> ParseResult<'_, &str> {
+    recognize(tuple((
+        nom_char('('),
+        within_node,
+        tag_no_case("null"),
+        within_node,
+        nom_char(')'),
+    )))(input)
+}
+
+fn reserve(input: &str) -> NodeResult<'_> {
+    let (rest, (name, size, initial_value)) = preceded(
         tag_no_case("RESERVE"),
         tuple((
             preceded(within_node, identifier),
             // Is there every a good reason to reserve a negative amount of space?
-            delimited(within_node, nom_u64, within_node),
+            preceded(within_node, unsigned_integer),
+            preceded(
+                within_node,
+                context(
+                    "a RESERVE needs a string literal or (null) after its size",
+                    cut(alt((map(string_literal, Some), value(None, null)))),
+                ),
+            ),
         )),
     )(input)?;
 
-    if start_of_string_or_null.as_bytes()[0] == b'\"' {
-        let (rest, initial_value) = string_literal(start_of_string_or_null)?;
-        return Ok((
-            rest,
-            Instruction::ReserveString {
-                size,
-                name: name.into(),
-                initial_value,
-            },
-        ));
-    } else {
-        let (rest, _) = tag_no_case("(null)")(start_of_string_or_null)?;
-        return Ok((rest, Instruction::ReserveInt { name: name.into() }));
-    }
+    let name = name.into();
+    let reservation = match initial_value {
+        Some(initial_value) => Instruction::ReserveString {
+            size,
+            name,
+            initial_value,
+        },
+        None => Instruction::ReserveInt { name },
+    };
+    Ok((rest, reservation))
 }
 
-fn read(input: &str) -> NodeResult {
+fn read(input: &str) -> NodeResult<'_> {
     let (rest, name) = preceded(tuple((tag_no_case("READ"), within_node)), identifier)(input)?;
     Ok((rest, Instruction::Read(name.into())))
 }
 
-fn write(input: &str) -> NodeResult {
+fn write(input: &str) -> NodeResult<'_> {
     let (rest, name) = preceded(tuple((tag_no_case("WRITE"), within_node)), identifier)(input)?;
     Ok((rest, Instruction::Write(name.into())))
 }
 
-fn arg_local_read(input: &str) -> NodeResult {
-    let (rest, index) =
-        preceded(tuple((tag_no_case("ARGLOCAL_READ"), within_node)), nom_u64)(input)?;
+fn arg_local_read(input: &str) -> NodeResult<'_> {
+    let (rest, index) = preceded(
+        tuple((tag_no_case("ARGLOCAL_READ"), within_node)),
+        unsigned_integer,
+    )(input)?;
     Ok((rest, Instruction::ArgLocalRead(index)))
 }
 
-fn arg_local_write(input: &str) -> NodeResult {
-    let (rest, index) =
-        preceded(tuple((tag_no_case("ARGLOCAL_WRITE"), within_node)), nom_u64)(input)?;
+fn arg_local_write(input: &str) -> NodeResult<'_> {
+    let (rest, index) = preceded(
+        tuple((tag_no_case("ARGLOCAL_WRITE"), within_node)),
+        unsigned_integer,
+    )(input)?;
     Ok((rest, Instruction::ArgLocalWrite(index)))
 }
 
-fn label(input: &str) -> NodeResult {
+fn label(input: &str) -> NodeResult<'_> {
     let (rest, name) = terminated(identifier, tag_no_case(":"))(input)?;
     Ok((rest, Instruction::Label(Label::named(name))))
 }
 
-fn jump(input: &str) -> NodeResult {
+fn jump(input: &str) -> NodeResult<'_> {
     let (rest, name) = preceded(tuple((tag_no_case("JUMP"), within_node)), identifier)(input)?;
     Ok((rest, Instruction::Jump(Label::named(name))))
 }
 
-fn branch_zero(input: &str) -> NodeResult {
+fn branch_zero(input: &str) -> NodeResult<'_> {
     let (rest, name) =
         preceded(tuple((tag_no_case("BRANCHZERO"), within_node)), identifier)(input)?;
     Ok((rest, Instruction::BranchZero(Label::named(name))))
 }
 
-fn function(input: &str) -> NodeResult {
-    let (rest, (name, num_locs)) = preceded(
+// An attribute's value is everything between its parentheses.
+fn attribute(input: &str) -> ParseResult<'_, Attribute> {
+    map(
+        preceded(
+            nom_char('@'),
+            pair(
+                identifier,
+                opt(delimited(
+                    nom_char('('),
+                    take_till(|c| c == ')' || c == '\n'),
+                    nom_char(')'),
+                )),
+            ),
+        ),
+        |(name, value)| Attribute {
+            name: name.into(),
+            value: value.map(|value| value.trim().into()),
+        },
+    )(input)
+}
+
+fn function_header(input: &str) -> ParseResult<'_, (&str, u64)> {
+    preceded(
         tuple((tag_no_case("FUNCTION"), within_node)),
-        tuple((identifier, preceded(within_node, nom_u64))),
-    )(input)?;
+        tuple((identifier, preceded(within_node, unsigned_integer))),
+    )(input)
+}
+
+fn function(input: &str) -> NodeResult<'_> {
+    let (rest, attributes) = many0(terminated(attribute, between_nodes))(input)?;
+    let (rest, (name, num_locs)) = if attributes.is_empty() {
+        function_header(rest)?
+    } else {
+        context(
+            "attributes can only go right before a FUNCTION",
+            cut(function_header),
+        )(rest)?
+    };
     Ok((
         rest,
         Instruction::Function {
             label: Label::named(name),
             num_locs,
+            attributes,
         },
     ))
 }
 
-fn call(input: &str) -> NodeResult {
+fn call(input: &str) -> NodeResult<'_> {
     let (rest, (name, num_args)) = preceded(
         tuple((tag_no_case("CALL"), within_node)),
-        tuple((identifier, preceded(within_node, nom_u64))),
+        tuple((identifier, preceded(within_node, unsigned_integer))),
     )(input)?;
     Ok((
         rest,
@@ -201,30 +319,26 @@ fn call(input: &str) -> NodeResult {
 
 noarg_node!(ret, "RET", Instruction::Ret);
 
-fn intrinsic(input: &str) -> NodeResult {
+fn intrinsic(input: &str) -> NodeResult<'_> {
     let (rest, intrinsic) = preceded(
         tuple((tag_no_case("INTRINSIC"), within_node)),
-        alt((
-            value(Intrinsic::PrintInt, tag_no_case("PRINT_INT")),
-            value(Intrinsic::PrintString, tag_no_case("PRINT_STRING")),
-            value(Intrinsic::Exit, tag_no_case("EXIT")),
-        )),
+        map_opt(identifier, Intrinsic::from_name),
     )(input)?;
 
     Ok((rest, Instruction::Intrinsic(intrinsic)))
 }
 
-fn push(input: &str) -> NodeResult {
-    let (rest, reg) = preceded(tuple((tag_no_case("PUSH"), within_node)), nom_i64)(input)?;
+fn push(input: &str) -> NodeResult<'_> {
+    let (rest, reg) = preceded(tuple((tag_no_case("PUSH"), within_node)), signed_integer)(input)?;
     Ok((rest, Instruction::Push { reg }))
 }
 
-fn pop(input: &str) -> NodeResult {
-    let (rest, reg) = preceded(tuple((tag_no_case("POP"), within_node)), nom_i64)(input)?;
+fn pop(input: &str) -> NodeResult<'_> {
+    let (rest, reg) = preceded(tuple((tag_no_case("POP"), within_node)), signed_integer)(input)?;
     Ok((rest, Instruction::Pop { reg }))
 }
 
-pub fn node(input: &str) -> NodeResult {
+pub fn node(input: &str) -> NodeResult<'_> {
     alt((
         alt((
             iconst, sconst, nop, add, sub, mul, div, mod_, bor, band, xor, or, and, eq, lt, gt, not,
@@ -236,17 +350,218 @@ pub fn node(input: &str) -> NodeResult {
     ))(input)
 }
 
-pub fn program(input: &str) -> Result<Vec<Instruction>, nom::Err<nom::error::Error<&str>>> {
+/// Parses a whole program in the textual format.
+///
+/// This never panics, whatever `input` is: anything that isn't a valid program
+/// is reported as an error. The regression corpus in the tests holds inputs
+/// that used to break that promise, or were found while fuzzing for it.
+pub fn program(input: &str) -> Result<Vec<Instruction>, nom::Err<VerboseError<&str>>> {
+    let spanned = spanned_program(input)?;
+    Ok(spanned.into_iter().map(|(node, _)| node).collect())
+}
+
+/// Like `program`, but also gives where in `input` each instruction is.
+pub fn program_with_source_map(
+    input: &str,
+) -> Result<(Vec<Instruction>, SourceMap), nom::Err<VerboseError<&str>>> {
+    let (prog, spans): (Vec<_>, Vec<_>) = spanned_program(input)?.into_iter().unzip();
+    let source_map = SourceMap::new(input, &spans);
+    Ok((prog, source_map))
+}
+
+// Each instruction with the byte range of `input` it was parsed from.
+fn spanned_program(input: &str) -> Result<Vec<SpannedNode>, nom::Err<VerboseError<&str>>> {
+    let offset = |rest: &str| input.len() - rest.len();
+    let spanned_node = |node_input| {
+        let (rest, node) = node(node_input)?;
+        Ok((rest, (node, offset(node_input)..offset(rest))))
+    };
     // TODO: Try doing this more simply. Do I need to consider the separators differently from the starting and ending whitespace?
-    let (rest, prog) = all_consuming(delimited(
+    // `all_consuming` guarantees there's nothing left over.
+    let (_, prog) = all_consuming(delimited(
         opt(between_nodes),
-        separated_list0(between_nodes, node),
+        separated_list0(between_nodes, spanned_node),
         opt(between_nodes),
     ))(input)?;
-    assert_eq!(rest, ""); // Surely this is redundant because of how all-consuming works.
     Ok(prog)
 }
 
+/// Renders an error returned by `program` as a human-readable diagnostic that
+/// points into `input`, which must be the text that was parsed.
+pub fn describe_error(input: &str, error: nom::Err<VerboseError<&str>>) -> String {
+    match error {
+        nom::Err::Error(error) | nom::Err::Failure(error) => convert_error(input, error),
+        // All of our parsers are complete parsers.
+        nom::Err::Incomplete(_) => unreachable!("Complete parsers asked for more input."),
+    }
+}
+
+/// An error from `stream`, already rendered like `describe_error` would.
+#[derive(Debug, PartialEq, Eq)]
+pub struct AssembleError {
+    /// The line the unparseable text starts on, counting from 1.
+    pub line: usize,
+    pub message: String,
+}
+
+impl std::fmt::Display for AssembleError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for AssembleError {}
+
+// Where a chunk of text ends, lexically: whether more lines are needed before
+// it can be parsed on its own.
+#[derive(Default)]
+struct ChunkState {
+    in_string: bool,
+    in_multi_line_comment: bool,
+    // Whether the last thing outside a comment was an attribute, which needs
+    // the FUNCTION after it, however many lines down that is.
+    in_attributes: bool,
+}
+
+impl ChunkState {
+    fn scan(&mut self, line: &str) {
+        let mut chars = line.chars().peekable();
+        while let Some(c) = chars.next() {
+            if self.in_string {
+                match c {
+                    '\\' => {
+                        chars.next();
+                    }
+                    '"' => self.in_string = false,
+                    _ => {}
+                }
+            } else if self.in_multi_line_comment {
+                if c == '*' && chars.peek() == Some(&'/') {
+                    chars.next();
+                    self.in_multi_line_comment = false;
+                }
+            } else {
+                match c {
+                    '"' => {
+                        self.in_string = true;
+                        self.in_attributes = false;
+                    }
+                    '/' if chars.peek() == Some(&'*') => {
+                        chars.next();
+                        self.in_multi_line_comment = true;
+                    }
+                    // A character literal, which might be '"'.
+                    '\'' => {
+                        if chars.next() == Some('\\') {
+                            chars.next();
+                        }
+                        chars.next();
+                        self.in_attributes = false;
+                    }
+                    '#' => return,
+                    // The name and value are skipped, since the value can
+                    // hold anything up to the ')'.
+                    '@' => {
+                        let in_name = |c: &char| c.is_alphanumeric() || *c == '$' || *c == '_';
+                        while chars.next_if(in_name).is_some() {}
+                        if chars.next_if_eq(&'(').is_some() {
+                            while chars.next_if(|&c| c != ')').is_some() {}
+                            chars.next_if_eq(&')');
+                        }
+                        self.in_attributes = true;
+                    }
+                    c if c.is_whitespace() => {}
+                    _ => self.in_attributes = false,
+                }
+            }
+        }
+    }
+
+    fn is_complete(&self) -> bool {
+        !self.in_string && !self.in_multi_line_comment && !self.in_attributes
+    }
+}
+
+struct Stream<R> {
+    reader: R,
+    next_line: usize,
+    parsed: std::collections::VecDeque<Instruction>,
+    finished: bool,
+}
+
+impl<R: std::io::BufRead> Stream<R> {
+    // Parses the next chunk of whole lines that can stand on its own.
+    // Usually that's one line, but strings and comments can hold newlines,
+    // and attributes go with the FUNCTION after them, even across blank
+    // lines and comments.
+    fn parse_chunk(&mut self) -> Result<(), AssembleError> {
+        let first_line = self.next_line;
+        let mut chunk = String::new();
+        let mut state = ChunkState::default();
+        loop {
+            let start = chunk.len();
+            let read = self.reader.read_line(&mut chunk).map_err(|error| AssembleError {
+                line: self.next_line,
+                message: format!("couldn't read the program: {error}\n"),
+            })?;
+            if read == 0 {
+                self.finished = true;
+                break;
+            }
+            self.next_line += 1;
+            state.scan(&chunk[start..]);
+            if state.is_complete() {
+                break;
+            }
+        }
+
+        match program(&chunk) {
+            Ok(prog) => {
+                self.parsed.extend(prog);
+                Ok(())
+            }
+            Err(_) => {
+                // Parsing again behind the lines that came before gives the
+                // diagnostic the right line numbers.
+                let padded = "\n".repeat(first_line - 1) + &chunk;
+                let error = program(&padded).expect_err("It didn't parse without padding.");
+                Err(AssembleError {
+                    line: first_line,
+                    message: describe_error(&padded, error),
+                })
+            }
+        }
+    }
+}
+
+impl<R: std::io::BufRead> Iterator for Stream<R> {
+    type Item = Result<Instruction, AssembleError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.parsed.is_empty() && !self.finished {
+            if let Err(error) = self.parse_chunk() {
+                self.finished = true;
+                return Some(Err(error));
+            }
+        }
+        self.parsed.pop_front().map(Ok)
+    }
+}
+
+/// Parses a program a few lines at a time, so the whole text never has to be
+/// in memory at once. It gives the same instructions as `program` would, and
+/// stops after the first error.
+pub fn stream(
+    reader: impl std::io::BufRead,
+) -> impl Iterator<Item = Result<Instruction, AssembleError>> {
+    Stream {
+        reader,
+        next_line: 1,
+        parsed: Default::default(),
+        finished: false,
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -325,6 +640,91 @@ mod tests {
         assert!(identifier("").is_err());
     }
 
+    #[test]
+    fn integer_literals() {
+        // Plain decimal, with and without signs:
+        assert_eq!(signed_integer("473"), Ok(("", 473)));
+        assert_eq!(signed_integer("-473"), Ok(("", -473)));
+        assert_eq!(signed_integer("+473"), Ok(("", 473)));
+
+        // Other radixes, with prefixes in either case:
+        assert_eq!(signed_integer("0x1F"), Ok(("", 31)));
+        assert_eq!(signed_integer("0XfF"), Ok(("", 255)));
+        assert_eq!(signed_integer("-0x10"), Ok(("", -16)));
+        assert_eq!(signed_integer("0b1010"), Ok(("", 10)));
+        assert_eq!(signed_integer("0o17"), Ok(("", 15)));
+
+        // Digit separators:
+        assert_eq!(signed_integer("1_000_000"), Ok(("", 1_000_000)));
+        assert_eq!(signed_integer("0xFF_FF"), Ok(("", 0xFFFF)));
+        assert_eq!(signed_integer("0b1111_0000"), Ok(("", 0b1111_0000)));
+        assert!(signed_integer("_1000").is_err()); // Separators can't come first.
+
+        // Character literals:
+        assert_eq!(signed_integer("'A'"), Ok(("", 65)));
+        assert_eq!(signed_integer("' '"), Ok(("", 32)));
+        assert_eq!(signed_integer(r"'\''"), Ok(("", 39)));
+        assert_eq!(signed_integer(r"'\\'"), Ok(("", 92)));
+        assert_eq!(signed_integer("-'A'"), Ok(("", -65)));
+        assert!(signed_integer("''").is_err());
+        assert!(signed_integer("'AB'").is_err());
+        assert!(signed_integer(r"'\n'").is_err()); // Like strings, there's no \n escape.
+
+        // The edges of the range:
+        assert_eq!(signed_integer("-9223372036854775808"), Ok(("", i64::MIN)));
+        assert_eq!(signed_integer("0x7FFF_FFFF_FFFF_FFFF"), Ok(("", i64::MAX)));
+        assert!(signed_integer("9223372036854775808").is_err());
+        assert!(signed_integer("0xFFFF_FFFF_FFFF_FFFF_FFFF_FFFF_FFFF_FFFF_F").is_err());
+        assert!(signed_integer("0x").is_err()); // Not a 0 followed by an x.
+
+        // Unsigned operands don't take signs at all:
+        assert_eq!(unsigned_integer("0x10"), Ok(("", 16)));
+        assert_eq!(unsigned_integer("18446744073709551615"), Ok(("", u64::MAX)));
+        assert!(unsigned_integer("-1").is_err());
+        assert!(unsigned_integer("+1").is_err());
+
+        // Everywhere a number is an operand:
+        assert_eq!(node("ICONST 0x2A"), Ok(("", Instruction::Iconst(42))));
+        assert_eq!(node("ICONST 'a'"), Ok(("", Instruction::Iconst(97))));
+        assert_eq!(
+            node("RESERVE buffer 0x10 \"\""),
+            Ok((
+                "",
+                Instruction::ReserveString {
+                    size: 16,
+                    name: "buffer".into(),
+                    initial_value: "".into()
+                }
+            ))
+        );
+        assert_eq!(
+            node("ARGLOCAL_READ 0b11"),
+            Ok(("", Instruction::ArgLocalRead(3)))
+        );
+        assert_eq!(
+            node("FUNCTION big 1_000"),
+            Ok((
+                "",
+                Instruction::Function {
+                    label: Label::named("big"),
+                    num_locs: 1000,
+                    attributes: vec![],
+                }
+            ))
+        );
+        assert_eq!(
+            node("CALL f 0o2"),
+            Ok((
+                "",
+                Instruction::Call {
+                    label: Label::named("f"),
+                    num_args: 2
+                }
+            ))
+        );
+        assert_eq!(node("POP -0x1"), Ok(("", Instruction::Pop { reg: -1 })));
+    }
+
     #[test]
     fn noarg_nodes() {
         // I never know how many tests to write...
@@ -435,7 +835,159 @@ mod tests {
         assert_eq!(
             node("RESERVE $_$ 4 (null)"),
             Ok(("", Instruction::ReserveInt { name: "$_$".into() }))
-        )
+        );
+
+        // (null) is case-insensitive, and can have comments around and inside it:
+        assert_eq!(
+            node("RESERVE x 4 /* an int */ (NULL) /* really */"),
+            Ok((
+                " /* really */",
+                Instruction::ReserveInt { name: "x".into() }
+            ))
+        );
+        assert_eq!(
+            node("RESERVE x 4 ( /* still */ Null )"),
+            Ok(("", Instruction::ReserveInt { name: "x".into() }))
+        );
+
+        // Missing or malformed initial values are errors, not panics, and say
+        // what was expected:
+        for bad_reserve in [
+            "RESERVE x 4",
+            "RESERVE x 4 ",
+            "RESERVE x 4 null",
+            "RESERVE x 4 (nul)",
+            "RESERVE x 4 \"unterminated",
+        ] {
+            let Err(nom::Err::Failure(error)) = node(bad_reserve) else {
+                panic!("{bad_reserve:?} should have failed to parse.");
+            };
+            assert!(error.errors.iter().any(|(_, kind)| matches!(
+                kind,
+                nom::error::VerboseErrorKind::Context(message) if message.contains("(null)")
+            )));
+        }
+        assert!(program("RESERVE x 4\nICONST 4").is_err());
+    }
+
+    #[test]
+    fn stream_matches_program() {
+        let texts = [
+            include_str!("../ir_samples/handwritten/strings_with_escapes.aves_text"),
+            "@inline\n@args(1)\nFUNCTION f 0 /* a comment\nover lines */ RET\nICONST '\"' # \"\nNOP",
+            "",
+            "\n\n# just a comment",
+            "@inline\n\nFUNCTION f 0\n",
+            "NOP @inline\nFUNCTION f 0\n",
+            "@inline # a comment\n/* and\nanother */\n@cold @args(#)\n\nFUNCTION f 0",
+        ];
+        for text in texts {
+            let streamed: Result<Vec<_>, _> = stream(text.as_bytes()).collect();
+            assert_eq!(streamed.unwrap(), program(text).unwrap(), "{text}");
+        }
+    }
+
+    #[test]
+    fn stream_matches_program_on_samples() {
+        use crate::{disassemble::disassemble, print_text::print_text};
+
+        fn check(path: &std::path::Path) {
+            if path.is_dir() {
+                for entry in std::fs::read_dir(path).unwrap() {
+                    check(&entry.unwrap().path());
+                }
+            } else if path
+                .extension()
+                .is_some_and(|extension| extension == "aves_bytecode")
+            {
+                let bytecode = std::fs::read(path).unwrap();
+                let text = print_text(&disassemble(&bytecode).unwrap());
+                let streamed: Result<Vec<_>, _> = stream(text.as_bytes()).collect();
+                assert_eq!(streamed.unwrap(), program(&text).unwrap(), "{path:?}");
+            }
+        }
+        check(&std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("ir_samples"));
+    }
+
+    #[test]
+    fn stream_and_program_fail_alike() {
+        for text in [
+            "@inline\n\n",
+            "@inline\nNOP\nFUNCTION f 0",
+            "@args(1\nFUNCTION f 0",
+        ] {
+            let streamed: Result<Vec<_>, _> = stream(text.as_bytes()).collect();
+            assert!(streamed.is_err() && program(text).is_err(), "{text}");
+        }
+    }
+
+    #[test]
+    fn stream_errors() {
+        let text = "NOP\nSCONST \"two\nlines\"\nICONST\nNOP";
+        let mut instructions = stream(text.as_bytes());
+        assert_eq!(instructions.next(), Some(Ok(Instruction::Nop)));
+        assert_eq!(
+            instructions.next(),
+            Some(Ok(Instruction::Sconst("two\nlines".into())))
+        );
+        let error = instructions.next().unwrap().unwrap_err();
+        assert_eq!(error.line, 4);
+        assert_eq!(error.message, describe_error(text, program(text).unwrap_err()));
+        assert_eq!(instructions.next(), None);
+    }
+
+    // Inputs that have made (or could plausibly make) the parser panic. Every
+    // one of them must come back as an `Err` that can be described.
+    const PANIC_REGRESSIONS: &[&str] = &[
+        // `reserve` used to index into whatever followed the size:
+        "RESERVE x 4",
+        "RESERVE x 4 ",
+        "RESERVE x 4 /* */",
+        "RESERVE x 4 (",
+        "RESERVE x 4 \"",
+        "RESERVE é 4 (nul",
+        // Unterminated things:
+        "\"",
+        "SCONST \"\\",
+        "SCONST \"\\\"",
+        "/*",
+        "ICONST 1 /* ",
+        "ICONST '",
+        "ICONST '\\",
+        // Numbers that don't fit, or aren't numbers:
+        "ICONST 0x",
+        "ICONST 0b2",
+        "ICONST 99999999999999999999999999999999999999999",
+        "ICONST -0xFFFF_FFFF_FFFF_FFFF_FFFF_FFFF_FFFF_FFFF_FFFF",
+        "ARGLOCAL_READ -1",
+        "CALL f -1",
+        "FUNCTION f 18446744073709551616",
+        // Multi-byte and odd characters, which `describe_error` has to slice around:
+        "é",
+        "💥:",
+        "ICONST 💥",
+        "SCONST \"💥\" 💥",
+        "L0:\r:",
+        "ICONST 1 #\r\r\nbad",
+        "\0",
+        "INTRINSIC",
+        "ret\n\n\nreturn",
+    ];
+
+    #[test]
+    fn never_panics() {
+        for input in PANIC_REGRESSIONS {
+            let error = program(input).expect_err(input);
+            assert!(!describe_error(input, error).is_empty());
+        }
+    }
+
+    #[test]
+    fn described_errors() {
+        let input = "ICONST 1\nRESERVE x 4 4\n";
+        let description = describe_error(input, program(input).unwrap_err());
+        assert!(description.contains("line 2"), "{description}");
+        assert!(description.contains("(null)"), "{description}");
     }
 
     #[test]
@@ -510,7 +1062,8 @@ mod tests {
                 "",
                 Instruction::Function {
                     label: Label::named("no_locals"),
-                    num_locs: 0
+                    num_locs: 0,
+                    attributes: vec![],
                 }
             ))
         );
@@ -521,10 +1074,38 @@ mod tests {
                 "",
                 Instruction::Function {
                     label: Label::named("some_locals"),
-                    num_locs: 3
+                    num_locs: 3,
+                    attributes: vec![],
+                }
+            ))
+        );
+
+        assert_eq!(
+            node("@inline @cold\n@key( some value )  FUNCTION annotated 1"),
+            Ok((
+                "",
+                Instruction::Function {
+                    label: Label::named("annotated"),
+                    num_locs: 1,
+                    attributes: vec![
+                        Attribute {
+                            name: "inline".into(),
+                            value: None
+                        },
+                        Attribute {
+                            name: "cold".into(),
+                            value: None
+                        },
+                        Attribute {
+                            name: "key".into(),
+                            value: Some("some value".into())
+                        },
+                    ],
                 }
             ))
         );
+        assert!(matches!(node("@inline RET"), Err(nom::Err::Failure(_))));
+        assert!(node("@key(unclosed FUNCTION f 0").is_err());
 
         assert!(node("function negative_locs -5050").is_err());
         assert!(node("function locs_not_specified ").is_err());
@@ -577,6 +1158,7 @@ mod tests {
         );
 
         assert!(node("intrinsic not_an_intrinsic").is_err());
+        assert!(node("intrinsic print_int_and_more").is_err()); // The whole name has to match.
 
         assert!(node("intrinsic").is_err()); // Intrinsic not specified.
     }
@@ -0,0 +1,191 @@
+// Structured views of a program, for seeing how a front-end laid it out: the
+// functions, the code between them, and where each label is used from.
+
+use std::{collections::HashMap, fmt::Write as _, ops::Range};
+
+use crate::{
+    analysis::function_spans,
+    ir_definition::{Instruction, Label},
+    stats::json_string,
+};
+
+/// A stretch of the program: one function, or the code between functions.
+struct Part<'a> {
+    function: Option<&'a Label>,
+    range: Range<usize>,
+}
+
+fn parts(program: &[Instruction]) -> Vec<Part<'_>> {
+    let mut parts = Vec::new();
+    let mut next = 0;
+    for span in function_spans(program) {
+        if span.range.start > next {
+            parts.push(Part {
+                function: None,
+                range: next..span.range.start,
+            });
+        }
+        next = span.range.end;
+        parts.push(Part {
+            function: Some(span.label),
+            range: span.range,
+        });
+    }
+    if next < program.len() {
+        parts.push(Part {
+            function: None,
+            range: next..program.len(),
+        });
+    }
+    parts
+}
+
+/// The indices of the instructions that jump to, branch to or call each
+/// label, by name.
+fn referrers(program: &[Instruction]) -> HashMap<&str, Vec<usize>> {
+    let mut referrers: HashMap<_, Vec<_>> = HashMap::new();
+    for (index, instruction) in program.iter().enumerate() {
+        if let Instruction::Jump(label)
+        | Instruction::BranchZero(label)
+        | Instruction::Call { label, .. } = instruction
+        {
+            referrers.entry(label.name()).or_default().push(index);
+        }
+    }
+    referrers
+}
+
+fn defined_label(instruction: &Instruction) -> Option<&Label> {
+    match instruction {
+        Instruction::Label(label) | Instruction::Function { label, .. } => Some(label),
+        _ => None,
+    }
+}
+
+fn list(indices: &[usize]) -> String {
+    let indices: Vec<_> = indices.iter().map(usize::to_string).collect();
+    indices.join(", ")
+}
+
+/// Each function, and each stretch of code between them, with its
+/// instructions nested under it. Every label and function says where it's
+/// used from.
+pub fn tree(program: &[Instruction]) -> String {
+    let referrers = referrers(program);
+    let width = program.len().saturating_sub(1).to_string().len();
+    let mut tree = String::new();
+    for part in parts(program) {
+        let (first, last) = (part.range.start, part.range.end - 1);
+        match part.function {
+            Some(label) => writeln!(tree, "function {} ({first}-{last})", label.name()),
+            None => writeln!(tree, "code outside functions ({first}-{last})"),
+        }
+        .unwrap();
+        for index in part.range {
+            let instruction = &program[index];
+            // Indented like `print_text`, so the places that can be jumped to
+            // stand out.
+            let indent = if defined_label(instruction).is_some() {
+                ""
+            } else {
+                "  "
+            };
+            write!(tree, "    {index:>width$}  {indent}{instruction}").unwrap();
+            if let Some(label) = defined_label(instruction) {
+                match referrers.get(label.name()) {
+                    Some(indices) => write!(tree, "  <- {}", list(indices)),
+                    None => write!(tree, "  <- nothing"),
+                }
+                .unwrap();
+            }
+            tree.push('\n');
+        }
+    }
+    tree
+}
+
+/// The same as `tree`, as a JSON object with a list of parts, each of which
+/// is a function (with a name) or not, with a list of instructions.
+/// Instructions that define labels have a list of referrers.
+pub fn json(program: &[Instruction]) -> String {
+    let referrers = referrers(program);
+    let parts: Vec<_> = parts(program)
+        .into_iter()
+        .map(|part| {
+            let instructions: Vec<_> = part
+                .range
+                .map(|index| {
+                    let instruction = &program[index];
+                    let mut object = format!(
+                        "{{\"index\": {index}, \"text\": {}",
+                        json_string(&instruction.to_string())
+                    );
+                    if let Some(label) = defined_label(instruction) {
+                        let indices = referrers.get(label.name()).map_or(&[][..], Vec::as_slice);
+                        write!(object, ", \"referrers\": [{}]", list(indices)).unwrap();
+                    }
+                    object.push('}');
+                    object
+                })
+                .collect();
+            let function = match part.function {
+                Some(label) => json_string(label.name()),
+                None => "null".to_string(),
+            };
+            format!(
+                "{{\"function\": {function}, \"instructions\": [{}]}}",
+                instructions.join(", ")
+            )
+        })
+        .collect();
+    format!("{{\"parts\": [{}]}}\n", parts.join(", "))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assemble;
+
+    const PROGRAM: &str = "JUMP main
+                           FUNCTION f 0
+                           ICONST 1
+                           RET
+                           main:
+                           ICONST 42
+                           CALL f 0
+                           JUMP main";
+
+    #[test]
+    fn tree_view() {
+        let program = assemble::program(PROGRAM).unwrap();
+        assert_eq!(
+            tree(&program),
+            "code outside functions (0-0)
+    0    JUMP main
+function f (1-3)
+    1  FUNCTION f 0  <- 6
+    2    ICONST 1
+    3    RET
+code outside functions (4-7)
+    4  main:  <- 0, 7
+    5    ICONST 42
+    6    CALL f 0
+    7    JUMP main
+"
+        );
+    }
+
+    #[test]
+    fn json_view() {
+        let program = assemble::program(PROGRAM).unwrap();
+        let json = json(&program);
+        assert!(json.starts_with(
+            "{\"parts\": [{\"function\": null, \"instructions\": \
+             [{\"index\": 0, \"text\": \"JUMP main\"}]}, \
+             {\"function\": \"f\", \"instructions\": \
+             [{\"index\": 1, \"text\": \"FUNCTION f 0\", \"referrers\": [6]}, "
+        ));
+        assert!(json.contains("{\"index\": 4, \"text\": \"main:\", \"referrers\": [0, 7]}"));
+        assert!(json.ends_with("]}]}\n"));
+    }
+}
@@ -0,0 +1,165 @@
+// Writing files so that they're never seen half-written. A run that's
+// interrupted while writing bytecode would otherwise leave a truncated file
+// behind, which the C code reads as a different, broken program.
+
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, BufWriter, Write},
+    ops::Deref,
+    path::{Path, PathBuf},
+    process,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+/// A file written next to where it's meant to go, which only takes the place
+/// of whatever is there on `commit`. If it's dropped without being committed,
+/// because writing it failed or panicked, it's removed and whatever was there
+/// is left alone. If the process is killed outright, the temporary file is
+/// left behind, but the file it was going to replace still is too.
+pub struct AtomicFile {
+    file: BufWriter<File>,
+    temp_path: TempPath,
+    path: PathBuf,
+}
+
+impl AtomicFile {
+    pub fn create(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        // Renaming is only atomic within a file system, so the temporary file
+        // goes in the same directory.
+        static NEXT: AtomicUsize = AtomicUsize::new(0);
+        let file_name = path
+            .file_name()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "not a file name"))?;
+        let mut temp_name = std::ffi::OsString::from(".");
+        temp_name.push(file_name);
+        temp_name.push(format!(
+            ".{}.{}.tmp",
+            process::id(),
+            NEXT.fetch_add(1, Ordering::Relaxed)
+        ));
+        let temp_path = path.with_file_name(temp_name);
+        let file = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&temp_path)?;
+        Ok(AtomicFile {
+            file: BufWriter::new(file),
+            temp_path: TempPath::new(temp_path),
+            path,
+        })
+    }
+
+    /// Puts the file in place, once everything written to it is on disk.
+    pub fn commit(self) -> io::Result<()> {
+        let file = self.file.into_inner().map_err(|error| error.into_error())?;
+        file.sync_all()?;
+        fs::rename(&self.temp_path, &self.path)?;
+        self.temp_path.keep();
+        Ok(())
+    }
+}
+
+impl Write for AtomicFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.file.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+/// Writes all of `contents` to `path`, like `fs::write`, but atomically.
+pub fn write_atomically(path: impl AsRef<Path>, contents: impl AsRef<[u8]>) -> io::Result<()> {
+    let mut file = AtomicFile::create(path)?;
+    file.write_all(contents.as_ref())?;
+    file.commit()
+}
+
+/// A path to a temporary file, which is removed when this is dropped, if
+/// there's anything there.
+#[derive(Debug)]
+pub struct TempPath {
+    path: Option<PathBuf>,
+}
+
+impl TempPath {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        TempPath {
+            path: Some(path.into()),
+        }
+    }
+
+    /// Keeps whatever is at the path, instead of removing it.
+    pub fn keep(mut self) -> PathBuf {
+        self.path.take().unwrap()
+    }
+}
+
+impl Deref for TempPath {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        self.path.as_ref().unwrap()
+    }
+}
+
+impl AsRef<Path> for TempPath {
+    fn as_ref(&self) -> &Path {
+        self
+    }
+}
+
+impl Drop for TempPath {
+    fn drop(&mut self) {
+        if let Some(path) = &self.path {
+            // It may never have been created, and there's nothing to be done
+            // if it can't be removed.
+            let _ = fs::remove_file(path);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entries(directory: &Path) -> Vec<String> {
+        let mut names: Vec<_> = fs::read_dir(directory)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn atomic_files() {
+        let directory =
+            std::env::temp_dir().join(format!("aves_atomic_file_test_{}", process::id()));
+        fs::create_dir(&directory).unwrap();
+        let path = directory.join("program.aves_bytecode");
+
+        write_atomically(&path, "old").unwrap();
+        let mut file = AtomicFile::create(&path).unwrap();
+        file.write_all(b"new").unwrap();
+        assert_eq!(entries(&directory).len(), 2);
+        assert_eq!(fs::read_to_string(&path).unwrap(), "old");
+        file.commit().unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "new");
+        assert_eq!(entries(&directory), ["program.aves_bytecode"]);
+
+        let mut file = AtomicFile::create(&path).unwrap();
+        file.write_all(b"abandoned").unwrap();
+        drop(file);
+        assert_eq!(fs::read_to_string(&path).unwrap(), "new");
+        assert_eq!(entries(&directory), ["program.aves_bytecode"]);
+
+        let temp_path = TempPath::new(&path);
+        assert!(temp_path.exists());
+        drop(temp_path);
+        assert!(entries(&directory).is_empty());
+        fs::remove_dir(&directory).unwrap();
+    }
+}
@@ -1,113 +1,1517 @@
 use std::{
-    fs::File,
-    io::{self, stdin, BufReader, BufWriter, Read},
-    os::fd::AsRawFd as _,
+    fs::{File, OpenOptions},
+    io::{self, stdin, stdout, BufRead, BufReader, BufWriter, Read, Write as _},
+    path::{Path, PathBuf},
     process::{self, Stdio},
+    str::FromStr,
+    thread,
+    time::{Duration, Instant},
 };
 
-use aves_ir::{assemble, bindings, write_bytecode::write_bytecode};
-use clap::Parser;
+use aves_ir::{
+    analysis::slice::{annotated_listing, backward_slice, reduced_program},
+    arithmetic::DivisionOverflow,
+    arity::check_arities,
+    assemble, ast_dump,
+    atomic_file::{write_atomically, AtomicFile, TempPath},
+    c_header::c_header,
+    c_interpreter::CIrList,
+    conformance::{run_rust, self_check, Outcome},
+    disassemble::{disassemble, looks_like_bytecode, read_bytecode},
+    explain::explain,
+    extract::extract,
+    fingerprint::Fingerprint,
+    frontend::{self, Lowered},
+    generate::{generate, GeneratorOptions},
+    interpret_rust::{self, debugger::Debugger, RunError, RunLimits, RunResult},
+    ir_definition::Instruction,
+    json::{read_json, write_json, JsonError},
+    legalize::{self, legalize},
+    optimize::peephole::Peephole,
+    output_sink::{OutputSink, Writer},
+    print_text::print_text,
+    reduce::reduce,
+    register_form::{self, linear_scan},
+    report::html_report,
+    resource_usage::{measure, measure_children, ResourceUsage},
+    signing::{SignatureError, SigningKey, VerifyingKey},
+    similarity::Similarity,
+    source_map::SourceMap,
+    stats::{ProgramStats, StatsComparison},
+    termination::analyze_loops,
+    timings::Timings,
+    trace::TraceWriter,
+    verify::{
+        division::check_division,
+        stack::{check_stack, StackProblem},
+        types::check_types,
+    },
+    write_bytecode::write_bytecode,
+};
+use clap::{Args, Parser, Subcommand, ValueEnum};
+
+// How many values from the top of the stack `run --trace` shows.
+const TRACE_VALUES: usize = 4;
 
-// TODO: This should have two mutually exclusive options: interpret and print.
-// These should be mutually exclusive since they both print to standard out.
-// Interpret prints the result of interpreting the program to standard out, and
-// print prints the human-readable form of the program to standard out. I should
-// also be able to do neither, and just produce the assembled output. deriving
-// Command on an enum, and making that part of the struct, should do the trick.
+// Everywhere a path is taken, "-" means standard in (or, for `assemble
+// --output`, standard out).
 #[derive(Parser)]
 struct CliOptions {
-    #[arg(short, long = "bytecode", required_unless_present("text_path"))]
-    bytecode_path: Option<std::path::PathBuf>,
-    #[arg(short, long = "text", required_unless_present("bytecode_path"))]
-    // TODO: Better name.
-    text_path: Option<std::path::PathBuf>,
-    #[arg(short, long = "output-bytecode", requires("text_path"))]
-    output_bytecode_path: Option<std::path::PathBuf>,
-    #[arg(short, long)]
-    print: bool,
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum AstFormat {
+    Flat,
+    Tree,
+    Json,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum ProgramFormat {
+    Text,
+    Json,
+    Bytecode,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum TimingsFormat {
+    Text,
+    Json,
+}
+
+/// What `build` makes a program into.
+#[derive(Clone, Copy, ValueEnum)]
+enum Target {
+    /// Bytecode for the C interpreter, as `assemble` writes.
+    InterpBytecode,
+}
+
+/// Where `build` can stop, to write the text program as it is then.
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Stage {
+    /// Once it's been read, before it's checked.
+    Parsed,
+    /// Once it's been checked and optimized.
+    Optimized,
+    /// Once it's been rewritten so the target can take every instruction (see
+    /// `aves_ir::legalize`).
+    Legalized,
+}
+
+/// A program to read, in one format or the other.
+#[derive(Args)]
+#[group(required = true, multiple = false)]
+struct Input {
+    #[arg(short, long = "bytecode", value_name = "PATH")]
+    bytecode_path: Option<PathBuf>,
+    #[arg(short, long = "text", value_name = "PATH")]
+    text_path: Option<PathBuf>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Assemble a text program into bytecode.
+    Assemble {
+        text_path: PathBuf,
+        /// Where to write the bytecode. It goes to standard out if this isn't given, or is "-".
+        /// Nothing else is ever written to standard out, so it can be piped into `run -b -`.
+        #[arg(
+            short,
+            long = "output",
+            visible_alias = "output-bytecode",
+            value_name = "PATH"
+        )]
+        output_path: Option<PathBuf>,
+    },
+    /// Turn a text program into something a target runs, in one go: read it, check that every
+    /// instruction is given the values it needs, of the types it needs (like `run --verify`),
+    /// optimize it (like the optimize subcommand), rewrite what the target can't take, then encode it for the
+    /// target.
+    Build {
+        text_path: PathBuf,
+        /// The only target so far is the C interpreter.
+        #[arg(long, value_enum, default_value = "interp-bytecode")]
+        target: Target,
+        /// Stop after this stage, and write the text program as it is then.
+        #[arg(long, value_enum, value_name = "STAGE")]
+        emit: Option<Stage>,
+        /// Skip optimizing.
+        #[arg(long)]
+        no_optimize: bool,
+        /// Where to write the result. It goes to standard out if this isn't given, or is "-".
+        #[arg(short, long = "output", value_name = "PATH")]
+        output_path: Option<PathBuf>,
+    },
+    /// Run a program. Without --rust, it's run by the C interpreter.
+    Run {
+        #[command(flatten)]
+        input: Input,
+        /// Interpret the program with the interpreter written in Rust, instead of the C one.
+        #[arg(long, conflicts_with_all(["record_expected", "twice"]))]
+        rust: bool,
+        /// Stop the Rust interpreter if a function runs more than this many of its own
+        /// instructions, not counting the ones of the functions it calls. Can be given more than
+        /// once.
+        #[arg(long, value_name = "FUNCTION=INSTRUCTIONS", value_parser = parse_budget, requires("rust"))]
+        budget: Vec<(String, u64)>,
+        /// Stop the Rust interpreter if the program prints more than this many bytes.
+        #[arg(long, value_name = "BYTES", requires("rust"))]
+        max_output: Option<u64>,
+        /// Stop the Rust interpreter if the program runs more than this many instructions.
+        #[arg(long, value_name = "N", requires("rust"))]
+        max_instructions: Option<u64>,
+        /// Stop the Rust interpreter if the operand stack would hold more than this many values.
+        #[arg(long, value_name = "N", requires("rust"))]
+        max_stack_depth: Option<usize>,
+        /// Stop the Rust interpreter if calls would nest more than this deep.
+        #[arg(long, value_name = "N", requires("rust"))]
+        max_call_depth: Option<usize>,
+        /// Print each instruction the Rust interpreter runs, with the top of the stack, to
+        /// standard error, or to the file given.
+        #[arg(long, value_name = "PATH", num_args = 0..=1, requires("rust"))]
+        trace: Option<Option<PathBuf>>,
+        /// Have the Rust interpreter trap on the smallest integer DIV or MOD -1, like the C
+        /// interpreter, instead of wrapping around.
+        #[arg(long, requires("rust"))]
+        c_division: bool,
+        /// Kill the C interpreter if the program runs for longer than this, and exit with 124.
+        /// The C interpreter is run in a child process when this is given.
+        #[arg(long, value_name = "SECONDS", value_parser = parse_seconds, conflicts_with("rust"))]
+        timeout: Option<Duration>,
+        /// Write what the program prints to a file next to it with the extension ".expected"
+        /// instead of to standard out, followed by a newline like the samples from the course's
+        /// tests, and the code it exits with to one with the extension ".expected_exit_code".
+        /// Nothing is written if the program doesn't exit with a code.
+        #[arg(long)]
+        record_expected: bool,
+        /// Run the program twice with each interpreter, and report any differences in what the
+        /// runs print, how they exit, or (for the Rust interpreter) what they leave on the stack.
+        #[arg(long, conflicts_with("record_expected"))]
+        twice: bool,
+        /// Print `RAN_OFF_THE_END` to standard error if the program runs off the end, so the
+        /// process that ran this one can tell that from the program exiting with 0.
+        #[arg(long, hide = true)]
+        report_end: bool,
+        /// Print to standard error how long loading the program took, then how much time and
+        /// memory running it took. The C interpreter is run in a child process to measure it.
+        #[arg(long, conflicts_with_all(["record_expected", "twice"]))]
+        time: bool,
+        /// Print to standard error how long each phase took: parsing the program, verifying it,
+        /// optimizing it, encoding it as bytecode for the C interpreter, and executing it. The C
+        /// interpreter is run in a child process to time it.
+        #[arg(
+            long,
+            value_enum,
+            value_name = "FORMAT",
+            num_args = 0..=1,
+            default_missing_value = "text",
+            conflicts_with_all(["record_expected", "twice"])
+        )]
+        timings: Option<TimingsFormat>,
+        /// Run the program through the peephole optimizer (see the optimize subcommand) first.
+        /// Traps can't say where they are in the text of an optimized program.
+        #[arg(long, conflicts_with_all(["record_expected", "twice"]))]
+        optimize: bool,
+        /// Print the program to standard error before running it: as text ("flat", the default),
+        /// as a tree of functions with where each label is used from, or as that tree in JSON.
+        /// With --optimize, it's the optimized program. The C interpreter is run in a child
+        /// process when this is given.
+        #[arg(
+            long,
+            value_enum,
+            value_name = "FORMAT",
+            num_args = 0..=1,
+            default_missing_value = "flat",
+            conflicts_with_all(["record_expected", "twice"])
+        )]
+        dump_ast: Option<AstFormat>,
+        /// Check that every instruction is given as many values as it needs, of the types it
+        /// needs (see the underflow and types warnings of the lint subcommand), first, and don't
+        /// run the program if one isn't. The C interpreter is run in a child process when this is
+        /// given.
+        #[arg(long, conflicts_with_all(["record_expected", "twice"]))]
+        verify: bool,
+    },
+    /// Print a program the way the C code prints it.
+    Print {
+        #[command(flatten)]
+        input: Input,
+    },
+    /// Print a bytecode program in the textual format. Function attributes aren't kept in
+    /// bytecode, so they can't be printed.
+    Disasm { bytecode_path: PathBuf },
+    /// Convert a program between the textual format, JSON (see `aves_ir::json`) and bytecode, and
+    /// print it. Function attributes are lost in bytecode.
+    Convert {
+        path: PathBuf,
+        #[arg(long, value_enum, value_name = "FORMAT")]
+        from: ProgramFormat,
+        #[arg(long, value_enum, value_name = "FORMAT")]
+        to: ProgramFormat,
+    },
+    /// Warn about calls with the wrong number of arguments, loops that may never terminate,
+    /// instructions that may find too few (or too many) values on the stack, instructions that
+    /// may be given a string where they need an integer, or the other way around, and divisions
+    /// that C leaves undefined.
+    Lint { text_path: PathBuf },
+    /// Print the reference entry for an instruction or intrinsic, like ARGLOCAL_READ or PRINT_INT.
+    Explain { name: String },
+    /// Compare the statistics (instruction counts, size, estimated cost, function sizes) of two
+    /// programs, each either text or bytecode. Text ones have to be ones that can be written as
+    /// bytecode.
+    CompareStats {
+        old: PathBuf,
+        new: PathBuf,
+        /// Print the comparison as JSON instead of a table.
+        #[arg(long)]
+        json: bool,
+    },
+    /// Shrink a text program while the command given to --check keeps failing on it, and print
+    /// the result.
+    Reduce {
+        text_path: PathBuf,
+        /// A shell command that exits with a nonzero status when the bug is there. The program
+        /// being tried is in the text file at "$1".
+        #[arg(long, value_name = "COMMAND")]
+        check: String,
+    },
+    /// Print a random, well-formed text program. The same seed always gives the same program.
+    Generate {
+        seed: u64,
+        /// How many statements to start each function (and the top-level code) with.
+        #[arg(long, value_name = "N")]
+        statements: Option<usize>,
+    },
+    /// EXPERIMENTAL: Print the text program that a program in the surface syntax (see
+    /// `aves_ir::frontend`) lowers to.
+    Lower {
+        path: PathBuf,
+        /// Verify the lowered program like `run --verify` does, then run it with the Rust
+        /// interpreter, instead of printing it. Problems and traps are reported at the lines of
+        /// the surface program they came from.
+        #[arg(long)]
+        run: bool,
+    },
+    /// EXPERIMENTAL: Print a text program in register form, with virtual registers instead of the
+    /// operand stack, and, with --allocate, where linear scan puts each virtual register.
+    Registers {
+        text_path: PathBuf,
+        /// How many real registers to allocate.
+        #[arg(long, value_name = "N")]
+        allocate: Option<u32>,
+    },
+    /// Print a text program after peephole optimization, and how many times each rule applied to
+    /// standard error.
+    Optimize { text_path: PathBuf },
+    /// Print a text program with just one function, the globals it uses and, with
+    /// --with-callees, the functions it calls, directly or not.
+    Extract {
+        text_path: PathBuf,
+        /// The name of the function to keep.
+        #[arg(long, value_name = "NAME")]
+        function: String,
+        #[arg(long)]
+        with_callees: bool,
+    },
+    /// Print a text program with the instructions that can affect the values an instruction uses
+    /// marked, or, with --reduced, just those instructions and the program's control flow.
+    Slice {
+        text_path: PathBuf,
+        /// The index of the instruction to slice from, counting from 0.
+        #[arg(long, value_name = "N")]
+        instruction: usize,
+        #[arg(long)]
+        reduced: bool,
+    },
+    /// Run small programs that check the C interpreter has the documented semantics on this
+    /// platform, like how DIV rounds and what overflowing does, and report which don't.
+    SelfCheck {
+        /// Check the Rust interpreter instead.
+        #[arg(long)]
+        rust: bool,
+        /// Count a probe as failed if the C interpreter runs it for longer than this.
+        #[arg(long, value_name = "SECONDS", value_parser = parse_seconds, conflicts_with("rust"))]
+        timeout: Option<Duration>,
+    },
+    /// Run a program in the Rust interpreter an instruction at a time, with breakpoints, taking
+    /// commands from standard in. Type "help" to list them.
+    Debug {
+        #[command(flatten)]
+        input: Input,
+    },
+    /// Print a C header with the numbers of the opcodes and intrinsics, and how each
+    /// instruction is laid out in the bytecode.
+    CHeader,
+    /// Print a text program's fingerprint: a hash that ignores what its labels are called, then a
+    /// hash of its bytecode. Programs that can't be written as bytecode don't have one.
+    Fingerprint { text_path: PathBuf },
+    /// Report how much of two text programs is the same, and which of their functions match,
+    /// ignoring what labels are called. Both have to be ones that can be written as bytecode.
+    Similar { first: PathBuf, second: PathBuf },
+    /// Write an HTML page with a text program's listing, its control-flow graph and the size of
+    /// each of its functions.
+    Report {
+        text_path: PathBuf,
+        /// Where to write the page. It goes to standard out if this isn't given.
+        #[arg(short, long = "output", value_name = "PATH")]
+        output_path: Option<PathBuf>,
+        /// Run the program with the Rust interpreter first, and color each instruction by how
+        /// many times it ran.
+        #[arg(long)]
+        run: bool,
+        /// Stop the program given to --run if it runs more than this many instructions, like
+        /// `run --max-instructions`, so one that never ends doesn't hang the report.
+        #[arg(long, value_name = "N", default_value_t = 100_000_000, requires("run"))]
+        max_instructions: u64,
+        /// Like `run --budget`, for the program given to --run.
+        #[arg(long, value_name = "FUNCTION=INSTRUCTIONS", value_parser = parse_budget, requires("run"))]
+        budget: Vec<(String, u64)>,
+    },
+    /// Make a new key pair for signing bytecode. The signing key goes in the file given, which
+    /// only you can read, and the verifying key, to hand out, in the same path with ".pub" on
+    /// the end.
+    Keygen { key_path: PathBuf },
+    /// Sign bytecode with a key from keygen. The signature goes next to it, with ".sig" on the
+    /// end of its path, unless --output is given.
+    Sign {
+        bytecode_path: PathBuf,
+        #[arg(long = "key", value_name = "PATH")]
+        key_path: PathBuf,
+        #[arg(short, long = "output", value_name = "PATH")]
+        output_path: Option<PathBuf>,
+    },
+    /// Check that bytecode is what was signed with the signing key that goes with a verifying
+    /// key, and exit with 1 if it isn't.
+    Verify {
+        bytecode_path: PathBuf,
+        #[arg(long = "key", value_name = "PATH")]
+        key_path: PathBuf,
+        /// Where the signature is, if it isn't next to the bytecode (see sign).
+        #[arg(long = "signature", value_name = "PATH")]
+        signature_path: Option<PathBuf>,
+    },
+}
+
+// `path` with `extension` added to the end, rather than in place of the one it has.
+fn with_added_extension(path: &Path, extension: &str) -> PathBuf {
+    let mut path = path.as_os_str().to_owned();
+    path.push(".");
+    path.push(extension);
+    path.into()
+}
+
+// Reads a key or signature written by keygen or sign.
+fn read_hex_file_or_exit<T: FromStr<Err = SignatureError>>(path: &Path) -> io::Result<T> {
+    match std::fs::read_to_string(path)?.parse() {
+        Ok(parsed) => Ok(parsed),
+        Err(error) => {
+            eprintln!("error: {}: {error}", path.display());
+            process::exit(1);
+        }
+    }
+}
+
+// Writes a file only its owner can read, and never over one that's there.
+fn write_secret(path: &Path, contents: &str) -> io::Result<()> {
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    options.open(path)?.write_all(contents.as_bytes())
+}
+
+fn parse_budget(budget: &str) -> Result<(String, u64), String> {
+    let (function, instructions) = budget
+        .split_once('=')
+        .ok_or("a budget looks like FUNCTION=INSTRUCTIONS")?;
+    let instructions = instructions
+        .parse()
+        .map_err(|_| format!("{instructions} isn't a number of instructions"))?;
+    Ok((function.to_string(), instructions))
+}
+
+fn parse_seconds(seconds: &str) -> Result<Duration, String> {
+    seconds
+        .parse()
+        .ok()
+        .and_then(|seconds| Duration::try_from_secs_f64(seconds).ok())
+        .ok_or_else(|| format!("{seconds} isn't a number of seconds"))
+}
+
+fn is_standard_stream(path: &Path) -> bool {
+    path == Path::new("-")
+}
+
+fn open(path: &Path) -> io::Result<Box<dyn BufRead>> {
+    if is_standard_stream(path) {
+        Ok(Box::new(stdin().lock()))
+    } else {
+        Ok(Box::new(BufReader::new(File::open(path)?)))
+    }
+}
+
+fn read_text_program(text_path: &Path) -> io::Result<String> {
+    let mut text_program = String::new();
+    open(text_path)?.read_to_string(&mut text_program)?;
+    Ok(text_program)
+}
+
+// Assembles the program as it's read, rather than reading all of it first.
+fn assemble_or_exit(text_path: &Path) -> io::Result<Vec<Instruction>> {
+    match assemble::stream(open(text_path)?).collect() {
+        Ok(prog) => Ok(prog),
+        Err(error) => {
+            eprint!("{error}");
+            process::exit(1);
+        }
+    }
+}
+
+fn read_bytecode_or_exit(bytecode_path: &Path) -> io::Result<Vec<Instruction>> {
+    match read_bytecode(open(bytecode_path)?) {
+        Ok(prog) => Ok(prog),
+        Err(error) => {
+            eprintln!("error: {error}");
+            process::exit(1);
+        }
+    }
+}
+
+// Encodes the program, to hand it to the C code or write it out. Programs
+// that can't be encoded are caught here, before anything has been written.
+fn bytecode_or_exit(prog: &[Instruction]) -> Vec<u8> {
+    let mut bytecode = Vec::new();
+    if let Err(error) = write_bytecode(prog, &mut bytecode) {
+        eprintln!("error: {error}");
+        process::exit(1);
+    }
+    bytecode
+}
+
+// Reads the program with the Rust code, whichever format it's in.
+fn load_or_exit(input: &Input) -> io::Result<Vec<Instruction>> {
+    match (&input.bytecode_path, &input.text_path) {
+        (Some(bytecode_path), None) => read_bytecode_or_exit(bytecode_path),
+        (None, Some(text_path)) => assemble_or_exit(text_path),
+        _ => unreachable!("Clap didn't do its job."),
+    }
+}
+
+// Reads a program that can be in either format, going by what's in it.
+fn load_either_or_exit(path: &Path) -> io::Result<Vec<Instruction>> {
+    let mut bytes = Vec::new();
+    open(path)?.read_to_end(&mut bytes)?;
+    if looks_like_bytecode(&bytes) {
+        match disassemble(&bytes) {
+            Ok(prog) => Ok(prog),
+            Err(error) => {
+                eprintln!("error: {}: {error}", path.display());
+                process::exit(1);
+            }
+        }
+    } else {
+        let text_program = String::from_utf8(bytes).expect("It looked like text.");
+        match assemble::program(&text_program) {
+            Ok(prog) => Ok(prog),
+            Err(error) => {
+                eprint!("{}", assemble::describe_error(&text_program, error));
+                process::exit(1);
+            }
+        }
+    }
+}
+
+// Has the C code read the bytecode straight from the file. Standard in is
+// read through `Stdin` and fed to the C code, rather than handing it fd 0,
+// which may not be what standard in is, or may have been read from already.
+fn load_c_ir_list(bytecode_path: &Path) -> io::Result<CIrList> {
+    if is_standard_stream(bytecode_path) {
+        CIrList::from_reader(stdin())
+    } else {
+        Ok(CIrList::from_file(File::open(bytecode_path)?))
+    }
+}
+
+// What the child the C interpreter runs in prints to standard error, last, if
+// the program runs off the end rather than exiting. It starts with a NUL so
+// nothing the C interpreter complains about is mistaken for it.
+const RAN_OFF_THE_END: &str = "\0ran off the end\n";
+
+// How the child the C interpreter ran in finished.
+struct ChildRun {
+    status: process::ExitStatus,
+    // Whether the program ran off the end. If it didn't, and `status` is
+    // still a success, the program exited with 0; otherwise the exit code is
+    // the program's, or the child died of something like SIGFPE.
+    ran_off_the_end: bool,
+    // What the child printed to standard error: the C interpreter's
+    // complaints, or how the child failed to load the program. Whatever isn't
+    // UTF-8 is replaced.
+    stderr: String,
+}
+
+// Interprets `bytecode` in a child process, which is the only way to capture
+// what the C code prints. That's passed on to `output` a line at a time, as
+// the child prints it, so a long run doesn't have to finish (or fit in memory)
+// before anything is seen. Whatever isn't UTF-8 is replaced. Standard error
+// is collected and returned, along with how the child finished.
+// If `timeout` is given and the child is still running after it, the child is
+// killed, and the error is of the kind `io::ErrorKind::TimedOut`.
+fn interpret_in_child(
+    bytecode: &[u8],
+    mut output: impl OutputSink + Send,
+    timeout: Option<Duration>,
+) -> io::Result<ChildRun> {
+    let mut child =
+        process::Command::new(std::env::current_exe().expect("Can't find current executable."))
+            .args(["run", "--report-end", "--bytecode", "-"])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()?;
+    // Dropping stdin closes it, so the child sees the end of the bytecode.
+    let mut child_stdin = child.stdin.take().expect("Could not get child's stdin.");
+    child_stdin.write_all(bytecode)?;
+    drop(child_stdin);
+    let mut child_stdout =
+        BufReader::new(child.stdout.take().expect("Could not get child's stdout."));
+    let mut child_stderr = child.stderr.take().expect("Could not get child's stderr.");
+    thread::scope(|scope| {
+        // What the program prints is passed on by another thread, so this one can watch the clock.
+        let reader = scope.spawn(move || -> io::Result<()> {
+            let mut line = Vec::new();
+            while child_stdout.read_until(b'\n', &mut line)? != 0 {
+                output.print(&String::from_utf8_lossy(&line));
+                line.clear();
+            }
+            output.finish();
+            Ok(())
+        });
+        // Standard error is read at the same time, so the child never blocks on a full pipe.
+        let errors_reader = scope.spawn(move || -> io::Result<Vec<u8>> {
+            let mut stderr = Vec::new();
+            child_stderr.read_to_end(&mut stderr)?;
+            Ok(stderr)
+        });
+        let status = match timeout {
+            Some(timeout) => wait_or_kill(&mut child, timeout),
+            None => child.wait(),
+        };
+        // Once the child is gone, its ends of the pipes are closed, so these don't block.
+        reader
+            .join()
+            .expect("The thread reading the interpreter's output panicked.")?;
+        let stderr = errors_reader
+            .join()
+            .expect("The error-reading thread panicked.")?;
+        let mut stderr = String::from_utf8_lossy(&stderr).into_owned();
+        let ran_off_the_end = stderr.ends_with(RAN_OFF_THE_END);
+        if ran_off_the_end {
+            stderr.truncate(stderr.len() - RAN_OFF_THE_END.len());
+        }
+        Ok(ChildRun {
+            status: status?,
+            ran_off_the_end,
+            stderr,
+        })
+    })
+}
+
+fn wait_or_kill(child: &mut process::Child, timeout: Duration) -> io::Result<process::ExitStatus> {
+    let deadline = Instant::now() + timeout;
+    loop {
+        if let Some(status) = child.try_wait()? {
+            return Ok(status);
+        }
+        if Instant::now() >= deadline {
+            child.kill()?;
+            child.wait()?;
+            return Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                format!("the C interpreter didn't finish within {timeout:?}"),
+            ));
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+}
+
+// Exits with 124, like timeout(1), if the C interpreter was killed for taking too long.
+fn exit_if_timed_out<T>(result: io::Result<T>) -> io::Result<T> {
+    match result {
+        Err(error) if error.kind() == io::ErrorKind::TimedOut => {
+            eprintln!("error: {error}");
+            process::exit(124);
+        }
+        result => result,
+    }
+}
+
+// Runs the `--check` command on a candidate for `reduce`.
+fn check_fails(check: &str, candidate_path: &Path, candidate: &[Instruction]) -> bool {
+    write_atomically(candidate_path, print_text(candidate))
+        .expect("Could not write the program being tried.");
+    let status = process::Command::new("sh")
+        .args(["-c", check, "sh"])
+        .arg(candidate_path)
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .expect("Could not run the check command.");
+    !status.success()
+}
+
+fn record_expected(input: &Input, timeout: Option<Duration>) -> io::Result<()> {
+    let input_path = input
+        .bytecode_path
+        .as_ref()
+        .or(input.text_path.as_ref())
+        .expect("Clap didn't do its job.");
+    if is_standard_stream(input_path) {
+        eprintln!("--record-expected needs a file to put the .expected file next to.");
+        process::exit(1);
+    }
+    let bytecode = match &input.bytecode_path {
+        Some(bytecode_path) => std::fs::read(bytecode_path)?,
+        None => bytecode_or_exit(&assemble_or_exit(input_path)?),
+    };
+    let mut expected = Writer::new(AtomicFile::create(input_path.with_extension("expected"))?);
+    // If it times out, nothing is recorded.
+    let run = exit_if_timed_out(interpret_in_child(&bytecode, &mut expected, timeout))?;
+    eprint!("{}", run.stderr);
+    let Some(exit_code) = run.status.code() else {
+        eprintln!(
+            "error: the program finished with {}, so nothing was recorded.",
+            run.status
+        );
+        drop(expected); // `process::exit` wouldn't remove the temporary file.
+        process::exit(1);
+    };
+    let mut expected = expected.into_inner()?;
+    writeln!(expected)?;
+    let mut expected_exit_code =
+        AtomicFile::create(input_path.with_extension("expected_exit_code"))?;
+    writeln!(expected_exit_code, "{exit_code}")?;
+    expected_exit_code.commit()?;
+    expected.commit()?;
+    Ok(())
+}
+
+fn run_twice(input: &Input, timeout: Option<Duration>) -> io::Result<()> {
+    let (prog, bytecode) = match &input.bytecode_path {
+        Some(bytecode_path) => {
+            let mut bytecode = Vec::new();
+            open(bytecode_path)?.read_to_end(&mut bytecode)?;
+            match disassemble(&bytecode) {
+                Ok(prog) => (prog, bytecode),
+                Err(error) => {
+                    eprintln!("error: {error}");
+                    process::exit(1);
+                }
+            }
+        }
+        None => {
+            let prog = load_or_exit(input)?;
+            let bytecode = bytecode_or_exit(&prog);
+            (prog, bytecode)
+        }
+    };
+
+    let mut differences = Vec::new();
+    // What a run took is different every time, so it's left out.
+    let rust_runs = [(), ()].map(|()| {
+        interpret_rust::interpret(&prog).map(|result| RunResult {
+            resource_usage: None,
+            ..result
+        })
+    });
+    if rust_runs[0] != rust_runs[1] {
+        differences.push("the Rust interpreter's two runs differ".to_string());
+    }
+    let mut c_outputs = [String::new(), String::new()];
+    let c_runs = [
+        exit_if_timed_out(interpret_in_child(&bytecode, &mut c_outputs[0], timeout))?,
+        exit_if_timed_out(interpret_in_child(&bytecode, &mut c_outputs[1], timeout))?,
+    ];
+    if c_outputs[0] != c_outputs[1] {
+        differences.push("the C interpreter's two runs print different things".into());
+    }
+    if c_runs[0].stderr != c_runs[1].stderr {
+        differences.push("the C interpreter's two runs print different errors".into());
+    }
+    if c_runs[0].status != c_runs[1].status {
+        differences.push(format!(
+            "the C interpreter's two runs finish differently: with {}, then with {}",
+            c_runs[0].status, c_runs[1].status
+        ));
+    } else if c_runs[0].ran_off_the_end != c_runs[1].ran_off_the_end {
+        differences.push("only one of the C interpreter's two runs runs off the end".into());
+    }
+    match &rust_runs[0] {
+        Ok(result) => {
+            if result.output != c_outputs[0] {
+                differences.push("the Rust and C interpreters print different things".into());
+            }
+            let c_run = &c_runs[0];
+            let c_finish = if c_run.ran_off_the_end {
+                "runs off the end".to_string()
+            } else {
+                format!("finishes with {}", c_run.status)
+            };
+            let exits_the_same =
+                |exit_code| !c_run.ran_off_the_end && c_run.status.code() == Some(exit_code);
+            match result.exit_code {
+                None if !c_run.ran_off_the_end => differences.push(format!(
+                    "the Rust interpreter runs off the end, but the C one {c_finish}"
+                )),
+                Some(exit_code) if !exits_the_same(exit_code) => differences.push(format!(
+                    "the Rust interpreter exits with {exit_code}, but the C one {c_finish}"
+                )),
+                _ => {}
+            }
+        }
+        Err(error) => differences.push(format!(
+            "the Rust interpreter traps {error}, so it can't be compared with the C one"
+        )),
+    }
+
+    if differences.is_empty() {
+        println!("All four runs agree.");
+    } else {
+        for difference in differences {
+            println!("{difference}");
+        }
+        process::exit(1);
+    }
+    Ok(())
+}
+
+fn print_times(load_time: Duration, usage: ResourceUsage) {
+    eprintln!("time: loading took {:.3}s", load_time.as_secs_f64());
+    eprintln!("time: running took {usage}");
+}
+
+fn dump(prog: &[Instruction], format: AstFormat) {
+    match format {
+        AstFormat::Flat => eprint!("{}", print_text(prog)),
+        AstFormat::Tree => eprint!("{}", ast_dump::tree(prog)),
+        AstFormat::Json => eprint!("{}", ast_dump::json(prog)),
+    }
+}
+
+// Goes through the stages of `build` up to `emit`, or to the end, and returns
+// what to write.
+fn build(
+    text_path: &Path,
+    target: Target,
+    emit: Option<Stage>,
+    optimize: bool,
+) -> io::Result<Vec<u8>> {
+    let mut prog = assemble_or_exit(text_path)?;
+    if emit == Some(Stage::Parsed) {
+        return Ok(print_text(&prog).into_bytes());
+    }
+    verify_or_exit(&prog);
+    if optimize {
+        prog = Peephole::standard().optimize(prog).program;
+    }
+    if emit == Some(Stage::Optimized) {
+        return Ok(print_text(&prog).into_bytes());
+    }
+    let legal_target = match target {
+        Target::InterpBytecode => legalize::Target::InterpBytecode,
+    };
+    let prog = match legalize(prog, legal_target) {
+        Ok(legalized) => legalized.program,
+        Err(errors) => {
+            for error in errors {
+                eprintln!("error: {error}");
+            }
+            process::exit(1);
+        }
+    };
+    if emit == Some(Stage::Legalized) {
+        return Ok(print_text(&prog).into_bytes());
+    }
+    Ok(match target {
+        Target::InterpBytecode => bytecode_or_exit(&prog),
+    })
+}
+
+// Exits if any instruction can be given a value of the wrong type, for
+// `run --verify` and `build`.
+fn verify_or_exit(prog: &[Instruction]) {
+    let problems = verification_errors(prog);
+    for (_, message) in &problems {
+        eprintln!("error: {message}");
+    }
+    if !problems.is_empty() {
+        process::exit(1);
+    }
+}
+
+// The problems `verify_or_exit` won't run a program with, with the index of
+// the instruction each is at.
+fn verification_errors(prog: &[Instruction]) -> Vec<(usize, String)> {
+    // The other stack problems don't stop a program running the way it means to.
+    let underflows = check_stack(prog)
+        .into_iter()
+        .filter(|problem| matches!(problem, StackProblem::Underflow { .. }))
+        .map(|problem| (problem.index(), problem.to_string()));
+    let type_errors = check_types(prog)
+        .into_iter()
+        .map(|problem| (problem.index, problem.to_string()));
+    underflows.chain(type_errors).collect()
+}
+
+// Where each call that was running when the program trapped was, and where
+// that is in the text, if it's known.
+fn print_backtrace(error: &RunError, source_map: Option<&SourceMap>) {
+    eprintln!("note: the calls that were running, innermost first:");
+    for frame in &error.backtrace {
+        let function = match &frame.function {
+            Some(function) => format!("in {function}"),
+            None => "outside of functions".to_string(),
+        };
+        match source_map.and_then(|map| map.position(frame.index)) {
+            Some(position) => eprintln!("  instruction {}, {function}, at {position}", frame.index),
+            None => eprintln!("  instruction {}, {function}", frame.index),
+        }
+    }
+}
+
+// `lower --run`, which reports everything at the surface lines it came from.
+fn run_lowered(source: &str, lowered: &Lowered) {
+    let problems = verification_errors(&lowered.program);
+    for (index, message) in &problems {
+        eprint!("{}", lowered.describe(source, *index, message));
+    }
+    if !problems.is_empty() {
+        process::exit(1);
+    }
+    let limits = RunLimits::default();
+    match interpret_rust::interpret_with_output(&lowered.program, &limits, stdout()) {
+        Ok(result) => {
+            if let Some(exit_code) = result.exit_code {
+                process::exit(exit_code);
+            }
+        }
+        Err(error) => {
+            eprint!("{}", lowered.describe(source, error.index, &error.trap));
+            // The innermost frame is where it trapped, which is described already.
+            for frame in &error.backtrace[1..] {
+                eprint!(
+                    "{}",
+                    lowered.describe(source, frame.index, "called from here")
+                );
+            }
+            process::exit(1);
+        }
+    }
+}
+
+fn print_timings(timings: &Timings, format: TimingsFormat) {
+    match format {
+        TimingsFormat::Text => eprint!("{timings}"),
+        TimingsFormat::Json => eprint!("{}", timings.to_json()),
+    }
+}
+
+// Reads the program with the Rust code for `run --rust`. Text is read whole
+// first, so that traps can say where in it they are.
+fn load_with_source_map(input: &Input) -> io::Result<(Vec<Instruction>, Option<SourceMap>)> {
+    match &input.text_path {
+        Some(text_path) => {
+            let text_program = read_text_program(text_path)?;
+            match assemble::program_with_source_map(&text_program) {
+                Ok((prog, source_map)) => Ok((prog, Some(source_map))),
+                Err(error) => {
+                    eprint!("{}", assemble::describe_error(&text_program, error));
+                    process::exit(1);
+                }
+            }
+        }
+        None => Ok((load_or_exit(input)?, None)),
+    }
+}
+
+// `run` with the C interpreter, when the program has to go through the Rust
+// code first, or the run has to be measured. The C code doesn't load programs
+// separately from running them, so executing includes it parsing the bytecode,
+// and bytecode that isn't optimized is only read, not parsed, beforehand.
+fn run_in_child(
+    input: &Input,
+    optimize: bool,
+    dump_ast: Option<AstFormat>,
+    verify: bool,
+    time: bool,
+    timings_format: Option<TimingsFormat>,
+    timeout: Option<Duration>,
+) -> io::Result<()> {
+    let mut timings = Timings::new();
+    let started = Instant::now();
+    let bytecode = match &input.bytecode_path {
+        Some(bytecode_path) if !optimize && dump_ast.is_none() && !verify => {
+            timings.time("read", || -> io::Result<_> {
+                let mut bytecode = Vec::new();
+                open(bytecode_path)?.read_to_end(&mut bytecode)?;
+                Ok(bytecode)
+            })?
+        }
+        _ => {
+            let mut prog = timings.time("parse", || load_or_exit(input))?;
+            if verify {
+                timings.time("verify", || verify_or_exit(&prog));
+            }
+            if optimize {
+                prog = timings.time("optimize", || Peephole::standard().optimize(prog).program);
+            }
+            if let Some(format) = dump_ast {
+                dump(&prog, format);
+            }
+            timings.time("encode", || bytecode_or_exit(&prog))
+        }
+    };
+    let load_time = started.elapsed();
+    let (run, usage) = timings.time("execute", || {
+        measure_children(|| interpret_in_child(&bytecode, stdout(), timeout))
+    });
+    let run = exit_if_timed_out(run)?;
+    eprint!("{}", run.stderr);
+    if time {
+        print_times(load_time, usage);
+    }
+    if let Some(format) = timings_format {
+        print_timings(&timings, format);
+    }
+    process::exit(run.status.code().unwrap_or(1));
 }
 
 fn main() -> io::Result<()> {
-    let options = CliOptions::parse();
+    match CliOptions::parse().command {
+        Command::Assemble {
+            text_path,
+            output_path,
+        } => {
+            let bytecode = bytecode_or_exit(&assemble_or_exit(&text_path)?);
+            match output_path {
+                Some(output_path) if !is_standard_stream(&output_path) => {
+                    write_atomically(output_path, bytecode)?;
+                }
+                _ => {
+                    let mut standard_out = stdout().lock();
+                    standard_out.write_all(&bytecode)?;
+                    standard_out.flush()?;
+                }
+            }
+        }
 
-    match options {
-        CliOptions {
-            bytecode_path: Some(_),
-            text_path: Some(_),
-            ..
+        Command::Build {
+            text_path,
+            target,
+            emit,
+            no_optimize,
+            output_path,
         } => {
-            panic!("Can't specify both formats to read from!");
+            let built = build(&text_path, target, emit, !no_optimize)?;
+            match output_path {
+                Some(output_path) if !is_standard_stream(&output_path) => {
+                    write_atomically(output_path, built)?;
+                }
+                _ => {
+                    let mut standard_out = stdout().lock();
+                    standard_out.write_all(&built)?;
+                    standard_out.flush()?;
+                }
+            }
         }
-        CliOptions {
-            bytecode_path: None,
-            text_path: None,
+
+        Command::Run {
+            input,
+            record_expected: true,
+            timeout,
+            ..
+        } => record_expected(&input, timeout)?,
+
+        Command::Run {
+            input,
+            twice: true,
+            timeout,
+            ..
+        } => run_twice(&input, timeout)?,
+
+        Command::Run {
+            input,
+            rust: true,
+            budget,
+            max_output,
+            max_instructions,
+            max_stack_depth,
+            max_call_depth,
+            trace,
+            c_division,
+            time,
+            timings: timings_format,
+            optimize,
+            dump_ast,
+            verify,
             ..
         } => {
-            unreachable!("Clap didn't do its job.")
+            let mut timings = Timings::new();
+            let started = Instant::now();
+            let (mut prog, mut source_map) =
+                timings.time("parse", || load_with_source_map(&input))?;
+            if verify {
+                timings.time("verify", || verify_or_exit(&prog));
+            }
+            if optimize {
+                prog = timings.time("optimize", || Peephole::standard().optimize(prog).program);
+                // The instructions have moved, so the map would point at the wrong lines.
+                source_map = None;
+            }
+            if let Some(format) = dump_ast {
+                dump(&prog, format);
+            }
+            let load_time = started.elapsed();
+            let limits = RunLimits {
+                function_budgets: budget.into_iter().collect(),
+                max_output,
+                max_instructions,
+                max_stack_depth,
+                max_call_depth,
+                division_overflow: if c_division {
+                    DivisionOverflow::Fail
+                } else {
+                    DivisionOverflow::Wrap
+                },
+            };
+            // What the program prints is shown as it's printed, so a long-running program can be
+            // watched.
+            let mut tracer = match trace {
+                None => None,
+                // Unbuffered, so the trace stays in step with what the program prints.
+                Some(None) => Some(TraceWriter::new(
+                    Box::new(io::stderr()) as Box<dyn io::Write>,
+                    TRACE_VALUES,
+                )),
+                Some(Some(path)) => Some(TraceWriter::new(
+                    Box::new(BufWriter::new(File::create(path)?)) as Box<dyn io::Write>,
+                    TRACE_VALUES,
+                )),
+            };
+            let (result, usage) = timings.time("execute", || {
+                measure(|| match &mut tracer {
+                    Some(tracer) => {
+                        interpret_rust::interpret_traced(&prog, &limits, stdout(), tracer)
+                    }
+                    None => interpret_rust::interpret_with_output(&prog, &limits, stdout()),
+                })
+            });
+            if let Some(tracer) = tracer {
+                tracer.into_inner()?;
+            }
+            if time {
+                print_times(load_time, usage);
+            }
+            if let Some(format) = timings_format {
+                print_timings(&timings, format);
+            }
+            match result {
+                Ok(result) => {
+                    if let Some(exit_code) = result.exit_code {
+                        process::exit(exit_code);
+                    }
+                }
+                Err(error) => {
+                    eprintln!("error: {error}");
+                    print_backtrace(&error, source_map.as_ref());
+                    process::exit(1);
+                }
+            }
         }
-        CliOptions {
-            bytecode_path: None,
-            text_path: Some(text_path),
-            output_bytecode_path,
-            print,
+
+        Command::Run {
+            input,
+            time,
+            timings,
+            optimize,
+            dump_ast,
+            verify,
+            timeout,
+            ..
+        } if time
+            || timings.is_some()
+            || optimize
+            || dump_ast.is_some()
+            || verify
+            || timeout.is_some() =>
+        {
+            run_in_child(&input, optimize, dump_ast, verify, time, timings, timeout)?
+        }
+
+        Command::Run {
+            input, report_end, ..
+        } => match &input.bytecode_path {
+            Some(bytecode_path) => {
+                // If the program exits, this process does too, and this is never printed.
+                load_c_ir_list(bytecode_path)?.run();
+                if report_end {
+                    eprint!("{RAN_OFF_THE_END}");
+                }
+            }
+            None => {
+                let bytecode = bytecode_or_exit(&load_or_exit(&input)?);
+                // Interpreting happens in a child, since the C code exits the whole process when
+                // the program does.
+                let mut child_cmd = process::Command::new(
+                    std::env::current_exe().expect("Can't find current executable."),
+                );
+                child_cmd.args(["run", "--bytecode", "-"]);
+                let mut child = child_cmd.stdin(Stdio::piped()).spawn()?;
+                let mut child_stdin = child.stdin.as_ref().expect("Could not get child's stdin.");
+                child_stdin
+                    .write_all(&bytecode)
+                    .expect("Could not write bytecode into child's stdin.");
+                let status = child.wait().expect("Child process (interpreter) failed.");
+                process::exit(status.code().unwrap_or(1));
+            }
+        },
+
+        Command::Print { input } => match &input.bytecode_path {
+            Some(bytecode_path) => load_c_ir_list(bytecode_path)?.print(),
+            None => {
+                CIrList::from_bytes(&bytecode_or_exit(&load_or_exit(&input)?))?.print();
+            }
+        },
+
+        Command::Disasm { bytecode_path } => {
+            print!("{}", print_text(&read_bytecode_or_exit(&bytecode_path)?));
+        }
+
+        Command::Convert { path, from, to } => {
+            let prog = match from {
+                ProgramFormat::Text => assemble_or_exit(&path)?,
+                ProgramFormat::Json => match read_json(&read_text_program(&path)?) {
+                    Ok(prog) => prog,
+                    // Printed like a syntax error in a text program.
+                    Err(JsonError::Syntax(message)) => {
+                        eprint!("{message}");
+                        process::exit(1);
+                    }
+                    Err(error) => {
+                        eprintln!("error: {error}");
+                        process::exit(1);
+                    }
+                },
+                ProgramFormat::Bytecode => read_bytecode_or_exit(&path)?,
+            };
+            match to {
+                ProgramFormat::Text => print!("{}", print_text(&prog)),
+                ProgramFormat::Json => print!("{}", write_json(&prog)),
+                ProgramFormat::Bytecode => {
+                    let mut standard_out = stdout().lock();
+                    standard_out.write_all(&bytecode_or_exit(&prog))?;
+                    standard_out.flush()?;
+                }
+            }
+        }
+
+        Command::Lint { text_path } => {
+            let prog = assemble_or_exit(&text_path)?;
+            for problem in check_arities(&prog) {
+                if problem.is_unknown_function() {
+                    println!("note: {problem}");
+                } else {
+                    println!("warning: {problem}");
+                }
+            }
+            for report in analyze_loops(&prog) {
+                if report.is_lint() {
+                    println!("warning: {report}");
+                }
+            }
+            for problem in check_stack(&prog) {
+                println!("warning: {problem}");
+            }
+            for problem in check_types(&prog) {
+                println!("warning: {problem}");
+            }
+            for problem in check_division(&prog) {
+                println!("warning: {problem}");
+            }
+        }
+
+        Command::Explain { name } => match explain(&name) {
+            Some(entry) => print!("{entry}"),
+            None => {
+                eprintln!("There's no instruction or intrinsic called {name}.");
+                process::exit(1);
+            }
+        },
+
+        Command::CompareStats { old, new, json } => {
+            let stats_or_exit = |path: &Path| -> io::Result<ProgramStats> {
+                match ProgramStats::of(&load_either_or_exit(path)?) {
+                    Ok(stats) => Ok(stats),
+                    Err(error) => {
+                        eprintln!("error: {}: {error}", path.display());
+                        process::exit(1);
+                    }
+                }
+            };
+            let comparison = StatsComparison {
+                old: stats_or_exit(&old)?,
+                new: stats_or_exit(&new)?,
+            };
+            if json {
+                print!("{}", comparison.to_json());
+            } else {
+                print!("{}", comparison.to_table());
+            }
+        }
+
+        Command::Reduce { text_path, check } => {
+            let prog = assemble_or_exit(&text_path)?;
+            let candidate_path = TempPath::new(
+                std::env::temp_dir().join(format!("aves_reduce_{}.aves_text", process::id())),
+            );
+            if !check_fails(&check, &candidate_path, &prog) {
+                eprintln!(
+                    "The check command succeeds on the original program, so there's nothing to reduce."
+                );
+                drop(candidate_path);
+                process::exit(1);
+            }
+            let reduced = reduce(&prog, |candidate| {
+                check_fails(&check, &candidate_path, candidate)
+            });
+            drop(candidate_path);
+            print!("{}", print_text(&reduced));
+        }
+
+        Command::Generate { seed, statements } => {
+            let defaults = GeneratorOptions::default();
+            let generator_options = GeneratorOptions {
+                seed,
+                statements: statements.unwrap_or(defaults.statements),
+                ..defaults
+            };
+            print!("{}", print_text(&generate(&generator_options)));
+        }
+
+        Command::Lower { path, run } => {
+            let source = read_text_program(&path)?;
+            let lowered = match frontend::lower(&source) {
+                Ok(lowered) => lowered,
+                Err(error) => {
+                    eprint!("{error}");
+                    process::exit(1);
+                }
+            };
+            if run {
+                run_lowered(&source, &lowered);
+            } else {
+                print!("{}", lowered.to_text());
+            }
+        }
+
+        Command::Registers {
+            text_path,
+            allocate: num_registers,
+        } => match register_form::lower(&assemble_or_exit(&text_path)?) {
+            Ok(lowered) => {
+                print!("{lowered}");
+                if let Some(num_registers) = num_registers {
+                    let allocation = linear_scan::allocate(&lowered, num_registers);
+                    println!();
+                    for (vreg, location) in allocation.locations.iter().enumerate() {
+                        if let Some(location) = location {
+                            println!("v{vreg}: {location}");
+                        }
+                    }
+                }
+            }
+            Err(problems) => {
+                for problem in problems {
+                    eprintln!("error: {problem}");
+                }
+                process::exit(1);
+            }
+        },
+
+        Command::Optimize { text_path } => {
+            let optimized = Peephole::standard().optimize(assemble_or_exit(&text_path)?);
+            print!("{}", print_text(&optimized.program));
+            for (rule, count) in optimized.rewrites {
+                eprintln!("{rule}: {count}");
+            }
+        }
+
+        Command::Extract {
+            text_path,
+            function,
+            with_callees,
+        } => match extract(&assemble_or_exit(&text_path)?, &function, with_callees) {
+            Ok(extracted) => print!("{}", print_text(&extracted)),
+            Err(error) => {
+                eprintln!("error: {error}");
+                process::exit(1);
+            }
+        },
+
+        Command::Slice {
+            text_path,
+            instruction,
+            reduced,
         } => {
-            // STRETCH: Make this streaming.
-            let mut text_program = String::new();
-            let text_program = if text_path == <&str as Into<std::path::PathBuf>>::into("-") {
-                stdin().read_to_string(&mut text_program)?;
-                text_program
+            let prog = assemble_or_exit(&text_path)?;
+            if instruction >= prog.len() {
+                eprintln!(
+                    "error: there's no instruction {instruction}; the program has {}",
+                    prog.len()
+                );
+                process::exit(1);
+            }
+            let slice = backward_slice(&prog, instruction);
+            if reduced {
+                print!("{}", print_text(&reduced_program(&prog, &slice)));
             } else {
-                let mut text_file = BufReader::new(File::open(text_path)?);
-                text_file.read_to_string(&mut text_program)?;
-                text_program
+                print!("{}", annotated_listing(&prog, &slice, instruction));
+            }
+        }
+
+        Command::SelfCheck { rust, timeout } => {
+            let report = if rust {
+                self_check(run_rust)
+            } else {
+                self_check(|prog| {
+                    let mut output = String::new();
+                    let run = interpret_in_child(&bytecode_or_exit(prog), &mut output, timeout)
+                        .map_err(|error| match error.kind() {
+                            io::ErrorKind::TimedOut => error.to_string(),
+                            _ => format!("couldn't run the C interpreter: {error}"),
+                        })?;
+                    match run.status.code() {
+                        Some(exit_code) => Ok(Outcome { output, exit_code }),
+                        None => Err(format!(
+                            "the C interpreter was killed ({}){}",
+                            run.status,
+                            match run.stderr.trim_end() {
+                                "" => String::new(),
+                                stderr => format!(": {stderr}"),
+                            }
+                        )),
+                    }
+                })
             };
+            print!("{report}");
+            if !report.all_passed() {
+                process::exit(1);
+            }
+        }
+
+        Command::Debug { input } => {
+            let prog = load_or_exit(&input)?;
+            let limits = RunLimits::default();
+            Debugger::new(&prog, &limits, stdout()).repl(stdin().lock(), stdout())?;
+        }
 
-            // It is not ideal that we're sometimes writing the bytecode twice when we could be doing so once.
-            let prog = assemble::program(&text_program).expect("Parsing error.");
-            if let Some(output_bytecode_path) = output_bytecode_path {
-                let mut output_bytecode_file = BufWriter::new(File::create(output_bytecode_path)?);
-                write_bytecode(&prog, &mut output_bytecode_file)?;
+        Command::CHeader => print!("{}", c_header()),
+
+        Command::Fingerprint { text_path } => {
+            match Fingerprint::of(&assemble_or_exit(&text_path)?) {
+                Ok(fingerprint) => println!("{fingerprint}"),
+                Err(error) => {
+                    eprintln!("error: {error}");
+                    process::exit(1);
+                }
             }
+        }
 
-            let mut child_cmd = process::Command::new(
-                std::env::current_exe().expect("Can't find current executable."),
-            );
-            if print {
-                child_cmd.arg("--print");
-            }
-            child_cmd.args(["--bytecode", "-"]);
-            let mut child = child_cmd.stdin(Stdio::piped()).spawn()?;
-            let mut child_stdin = child.stdin.as_ref().expect("Could not get child's stdin.");
-            write_bytecode(&prog, &mut child_stdin)
-                .expect("Could not write bytecode into child's stdin.");
-            child.wait().expect("Child process (interpreter) failed.");
-        }
-        CliOptions {
-            bytecode_path: Some(bytecode_path),
-            text_path: None,
-            print,
-            ..
+        Command::Similar { first, second } => {
+            let first = assemble_or_exit(&first)?;
+            let second = assemble_or_exit(&second)?;
+            match Similarity::between(&first, &second) {
+                Ok(similarity) => print!("{similarity}"),
+                Err(error) => {
+                    eprintln!("error: {error}");
+                    process::exit(1);
+                }
+            }
+        }
+
+        Command::Report {
+            text_path,
+            output_path,
+            run,
+            max_instructions,
+            budget,
         } => {
-            let bytecode_file;
-            // Why is it okay to turn a `File` into a raw fd with just an immutable
-            // reference to the file? You can definitely conceptually modify the
-            // file through the raw fd...
-            let bytecode_fd = if bytecode_path == <&str as Into<std::path::PathBuf>>::into("-") {
-                0
+            let prog = assemble_or_exit(&text_path)?;
+            let counts = if run {
+                let limits = RunLimits {
+                    function_budgets: budget.into_iter().collect(),
+                    max_instructions: Some(max_instructions),
+                    ..Default::default()
+                };
+                match interpret_rust::interpret_with_limits(&prog, &limits) {
+                    Ok(result) => Some(result.instruction_counts),
+                    Err(error) => {
+                        eprintln!("error: {error}");
+                        process::exit(1);
+                    }
+                }
             } else {
-                bytecode_file = File::open(bytecode_path)?;
-                bytecode_file.as_raw_fd()
+                None
             };
-            unsafe {
-                let c_ir_node = bindings::ir_list_read(bytecode_fd);
-                if print {
-                    bindings::ir_list_print(c_ir_node);
-                } else {
-                    bindings::interpret(c_ir_node);
+            let html = html_report(&text_path.to_string_lossy(), &prog, counts.as_deref());
+            match output_path {
+                Some(output_path) if !is_standard_stream(&output_path) => {
+                    write_atomically(output_path, html)?
                 }
-                bindings::free_list_ir(c_ir_node);
+                _ => print!("{html}"),
             }
         }
-    };
+
+        Command::Keygen { key_path } => {
+            let key = SigningKey::generate();
+            write_secret(&key_path, &format!("{key}\n"))?;
+            write_atomically(
+                with_added_extension(&key_path, "pub"),
+                format!("{}\n", key.verifying_key()),
+            )?;
+        }
+
+        Command::Sign {
+            bytecode_path,
+            key_path,
+            output_path,
+        } => {
+            let key: SigningKey = read_hex_file_or_exit(&key_path)?;
+            let signature = key.sign(&std::fs::read(&bytecode_path)?);
+            let output_path =
+                output_path.unwrap_or_else(|| with_added_extension(&bytecode_path, "sig"));
+            write_atomically(output_path, format!("{signature}\n"))?;
+        }
+
+        Command::Verify {
+            bytecode_path,
+            key_path,
+            signature_path,
+        } => {
+            let key: VerifyingKey = read_hex_file_or_exit(&key_path)?;
+            let signature_path =
+                signature_path.unwrap_or_else(|| with_added_extension(&bytecode_path, "sig"));
+            let signature = read_hex_file_or_exit(&signature_path)?;
+            match key.verify(&std::fs::read(&bytecode_path)?, &signature) {
+                Ok(()) => println!("The signature is good."),
+                Err(error) => {
+                    eprintln!("error: {error}");
+                    process::exit(1);
+                }
+            }
+        }
+    }
     Ok(())
 }
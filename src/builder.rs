@@ -0,0 +1,221 @@
+// Builds programs from Rust, for front-ends that target the IR without going
+// through the textual format.
+
+use crate::ir_definition::{Attribute, Instruction, Intrinsic, Label};
+
+/// Collects a program one instruction at a time. Each method adds one
+/// instruction (or, for the intrinsics, `INTRINSIC` with that intrinsic) and
+/// returns the builder, so they can be chained, like
+/// `builder.iconst(1).iconst(2).add()`. Labels can be given as `&str`s, or
+/// made with `fresh_label`.
+#[derive(Debug, Default)]
+pub struct IrBuilder {
+    program: Vec<Instruction>,
+    next_label: usize,
+}
+
+macro_rules! simple_instructions {
+    ($($method:ident => $instruction:expr,)*) => {
+        $(
+            pub fn $method(&mut self) -> &mut Self {
+                self.emit($instruction)
+            }
+        )*
+    };
+}
+
+impl IrBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A label that no other call to this makes. They all start with a `$`,
+    /// so they can't clash with labels of your own that don't.
+    pub fn fresh_label(&mut self) -> Label {
+        let label = Label::named(&format!("$L{}", self.next_label));
+        self.next_label += 1;
+        label
+    }
+
+    pub fn emit(&mut self, instruction: Instruction) -> &mut Self {
+        self.program.push(instruction);
+        self
+    }
+
+    simple_instructions! {
+        nop => Instruction::Nop,
+        add => Instruction::Add,
+        sub => Instruction::Sub,
+        mul => Instruction::Mul,
+        div => Instruction::Div,
+        mod_ => Instruction::Mod,
+        bor => Instruction::Bor,
+        band => Instruction::Band,
+        xor => Instruction::Xor,
+        or => Instruction::Or,
+        and => Instruction::And,
+        eq => Instruction::Eq,
+        lt => Instruction::Lt,
+        gt => Instruction::Gt,
+        not => Instruction::Not,
+        ret => Instruction::Ret,
+        print_int => Instruction::Intrinsic(Intrinsic::PrintInt),
+        print_string => Instruction::Intrinsic(Intrinsic::PrintString),
+        exit => Instruction::Intrinsic(Intrinsic::Exit),
+    }
+
+    pub fn iconst(&mut self, value: i64) -> &mut Self {
+        self.emit(Instruction::Iconst(value))
+    }
+
+    pub fn sconst(&mut self, value: impl Into<String>) -> &mut Self {
+        self.emit(Instruction::Sconst(value.into()))
+    }
+
+    pub fn reserve_int(&mut self, name: impl Into<String>) -> &mut Self {
+        self.emit(Instruction::ReserveInt { name: name.into() })
+    }
+
+    pub fn reserve_string(
+        &mut self,
+        name: impl Into<String>,
+        size: u64,
+        initial_value: impl Into<String>,
+    ) -> &mut Self {
+        self.emit(Instruction::ReserveString {
+            size,
+            name: name.into(),
+            initial_value: initial_value.into(),
+        })
+    }
+
+    pub fn read(&mut self, name: impl Into<String>) -> &mut Self {
+        self.emit(Instruction::Read(name.into()))
+    }
+
+    pub fn write(&mut self, name: impl Into<String>) -> &mut Self {
+        self.emit(Instruction::Write(name.into()))
+    }
+
+    pub fn arglocal_read(&mut self, index: u64) -> &mut Self {
+        self.emit(Instruction::ArgLocalRead(index))
+    }
+
+    pub fn arglocal_write(&mut self, index: u64) -> &mut Self {
+        self.emit(Instruction::ArgLocalWrite(index))
+    }
+
+    pub fn label(&mut self, label: impl Into<Label>) -> &mut Self {
+        self.emit(Instruction::Label(label.into()))
+    }
+
+    pub fn jump(&mut self, label: impl Into<Label>) -> &mut Self {
+        self.emit(Instruction::Jump(label.into()))
+    }
+
+    pub fn branch_zero(&mut self, label: impl Into<Label>) -> &mut Self {
+        self.emit(Instruction::BranchZero(label.into()))
+    }
+
+    pub fn function(&mut self, label: impl Into<Label>, num_locs: u64) -> &mut Self {
+        self.function_with_attributes(label, num_locs, vec![])
+    }
+
+    pub fn function_with_attributes(
+        &mut self,
+        label: impl Into<Label>,
+        num_locs: u64,
+        attributes: Vec<Attribute>,
+    ) -> &mut Self {
+        self.emit(Instruction::Function {
+            label: label.into(),
+            num_locs,
+            attributes,
+        })
+    }
+
+    pub fn call(&mut self, label: impl Into<Label>, num_args: u64) -> &mut Self {
+        self.emit(Instruction::Call {
+            label: label.into(),
+            num_args,
+        })
+    }
+
+    pub fn push(&mut self, reg: i64) -> &mut Self {
+        self.emit(Instruction::Push { reg })
+    }
+
+    pub fn pop(&mut self, reg: i64) -> &mut Self {
+        self.emit(Instruction::Pop { reg })
+    }
+
+    pub fn build(self) -> Vec<Instruction> {
+        self.program
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assemble;
+
+    #[test]
+    fn builds_what_the_text_would() {
+        let mut builder = IrBuilder::new();
+        let main = builder.fresh_label();
+        builder
+            .reserve_int("x")
+            .reserve_string("s", 3, "hi")
+            .jump(main.clone())
+            .function_with_attributes("double", 0, vec![Attribute::args(1)])
+            .arglocal_read(0)
+            .iconst(2)
+            .mul()
+            .arglocal_write(0)
+            .ret()
+            .label(main)
+            .iconst(42)
+            .iconst(-21)
+            .call("double", 1)
+            .write("x")
+            .read("s")
+            .print_string()
+            .push(1)
+            .pop(-1)
+            .iconst(0)
+            .exit();
+        assert_eq!(
+            builder.build(),
+            assemble::program(
+                "RESERVE x 4 (null)
+                 RESERVE s 3 \"hi\"
+                 JUMP $L0
+                 @args(1) FUNCTION double 0
+                 ARGLOCAL_READ 0
+                 ICONST 2
+                 MUL
+                 ARGLOCAL_WRITE 0
+                 RET
+                 $L0:
+                 ICONST 42
+                 ICONST -21
+                 CALL double 1
+                 WRITE x
+                 READ s
+                 INTRINSIC PRINT_STRING
+                 PUSH 1
+                 POP -1
+                 ICONST 0
+                 INTRINSIC EXIT"
+            )
+            .unwrap()
+        );
+    }
+
+    #[test]
+    fn fresh_labels_are_fresh() {
+        let mut builder = IrBuilder::new();
+        let labels: Vec<_> = (0..3).map(|_| builder.fresh_label()).collect();
+        assert_eq!(labels, ["$L0", "$L1", "$L2"].map(Label::named));
+    }
+}
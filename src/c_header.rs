@@ -0,0 +1,147 @@
+// Generates a C header describing the bytecode, for C programs that read or
+// write it without going through this crate. Everything in it comes from
+// `Opcode` and `Intrinsic`, so regenerating it is all it takes to keep it in
+// sync.
+
+use std::fmt::Write as _;
+
+use crate::{
+    ir_definition::Intrinsic,
+    opcode::{Opcode, OperandKind},
+};
+
+const PREAMBLE: &str = "\
+/* Generated by `aves_interpreter c-header`. Don't edit it by hand. */
+
+#ifndef AVES_BYTECODE_H
+#define AVES_BYTECODE_H
+
+/*
+ * A bytecode program is a sequence of instructions, with nothing before or
+ * after them. Each instruction is its opcode, then its operands.
+ *
+ * Opcodes and integer operands are 32-bit signed little-endian integers.
+ * Strings are their length (counting a null terminator) as an integer, then
+ * their bytes, then the null terminator.
+ */
+";
+
+// How an operand is laid out, and what it's called in the layout comments.
+fn encoding(operand: OperandKind) -> &'static str {
+    match operand {
+        OperandKind::Integer => "int32 value",
+        OperandKind::Count => "int32 count",
+        OperandKind::String => "string value",
+        OperandKind::Name => "string name",
+        OperandKind::Label => "string label",
+        OperandKind::Intrinsic => "int32 intrinsic",
+        OperandKind::Register => "int32 register",
+    }
+}
+
+// The operands of `opcode`, in the order they're written in the bytecode.
+fn bytecode_layout(opcode: Opcode) -> String {
+    if opcode == Opcode::Reserve {
+        // The size comes after the initial value in the bytecode, unlike in the
+        // textual format.
+        return "string name, string initial_value (length 0 and no bytes for (null)), \
+                int32 size"
+            .into();
+    }
+    let operands: Vec<_> = opcode
+        .info()
+        .operands
+        .iter()
+        .map(|&operand| encoding(operand))
+        .collect();
+    if operands.is_empty() {
+        "no operands".into()
+    } else {
+        operands.join(", ")
+    }
+}
+
+// Mnemonics are already valid C identifiers.
+fn macro_name(prefix: &str, name: &str) -> String {
+    format!("AVES_{prefix}_{}", name.to_ascii_uppercase())
+}
+
+/// The text of `aves_bytecode.h`, which defines the number of every opcode
+/// and intrinsic and documents how each instruction's operands are laid out.
+pub fn c_header() -> String {
+    let mut header = PREAMBLE.to_string();
+
+    header.push_str("\n/* Opcodes */\n");
+    for opcode in Opcode::ALL {
+        let info = opcode.info();
+        writeln!(header, "/* {}: {} */", info.syntax, bytecode_layout(opcode)).unwrap();
+        writeln!(
+            header,
+            "#define {} {}",
+            macro_name("OP", info.mnemonic),
+            opcode.number()
+        )
+        .unwrap();
+    }
+
+    header.push_str("\n/* Intrinsics, the operand of INTRINSIC */\n");
+    for intrinsic in Intrinsic::ALL {
+        writeln!(
+            header,
+            "#define {} {}",
+            macro_name("INTRINSIC", intrinsic.name()),
+            intrinsic.number()
+        )
+        .unwrap();
+    }
+    writeln!(
+        header,
+        "/* No intrinsic will ever be given a number from FIRST to LAST, so forks can use them. */\n\
+         #define AVES_INTRINSIC_EXPERIMENTAL_FIRST {}\n\
+         #define AVES_INTRINSIC_EXPERIMENTAL_LAST {}",
+        Intrinsic::EXPERIMENTAL.start(),
+        Intrinsic::EXPERIMENTAL.end()
+    )
+    .unwrap();
+
+    header.push_str("\n#endif /* AVES_BYTECODE_H */\n");
+    header
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn defines_every_opcode_and_intrinsic() {
+        let header = c_header();
+        for opcode in Opcode::ALL {
+            let definition = format!(
+                "#define AVES_OP_{} {}\n",
+                opcode.mnemonic(),
+                opcode.number()
+            );
+            assert!(header.contains(&definition), "missing {definition}");
+        }
+        for intrinsic in Intrinsic::ALL {
+            let definition = format!(
+                "#define AVES_INTRINSIC_{} {}\n",
+                intrinsic.name(),
+                intrinsic.number()
+            );
+            assert!(header.contains(&definition), "missing {definition}");
+        }
+        assert!(header.contains(
+            "#define AVES_INTRINSIC_EXPERIMENTAL_FIRST 32768\n\
+             #define AVES_INTRINSIC_EXPERIMENTAL_LAST 65535\n"
+        ));
+    }
+
+    #[test]
+    fn layouts() {
+        assert_eq!(bytecode_layout(Opcode::Nop), "no operands");
+        assert_eq!(bytecode_layout(Opcode::Call), "string label, int32 count");
+        assert!(c_header()
+            .contains("/* ARGLOCAL_READ <index>: int32 count */\n#define AVES_OP_ARGLOCAL_READ"));
+    }
+}
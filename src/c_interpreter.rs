@@ -0,0 +1,111 @@
+// A safe interface to the C code. This is the only module that calls into
+// `bindings`, or into C at all, so it's the only one with `unsafe`.
+
+use std::{
+    fs::File,
+    io::{self, Read},
+    mem::MaybeUninit,
+    os::fd::{AsFd as _, AsRawFd as _, BorrowedFd},
+    thread,
+};
+
+use crate::bindings;
+
+/// A program read by the C code, which frees it when this is dropped.
+///
+/// This is the only thing the C code allocates that Rust owns. There's no
+/// `CStack` to free with `free_stack`, since `interpret` never hands its
+/// stack over: a wrapper would have nothing to hold until it does.
+pub struct CIrList {
+    head: *mut bindings::ir_node,
+}
+
+impl CIrList {
+    /// Reads a bytecode program from `fd`, until the end of the file.
+    pub fn load(fd: BorrowedFd<'_>) -> Self {
+        // SAFETY: `fd` is open for as long as it's borrowed, which is longer
+        // than this call. ir_list_read only reads from it, and doesn't close it.
+        let head = unsafe { bindings::ir_list_read(fd.as_raw_fd()) };
+        CIrList { head }
+    }
+
+    /// Reads a bytecode program from `file`, which is closed afterwards.
+    pub fn from_file(file: File) -> Self {
+        Self::load(file.as_fd())
+    }
+
+    /// Reads a bytecode program from `source`, which needn't be a file. The
+    /// C code can only read from an fd, so the bytecode is fed to it through
+    /// a pipe by another thread.
+    pub fn from_reader(mut source: impl Read + Send) -> io::Result<Self> {
+        let (pipe_reader, mut pipe_writer) = io::pipe()?;
+        thread::scope(|scope| {
+            let feeder = scope.spawn(move || {
+                // Dropping the writer when this is done closes it, which is
+                // how the C code finds the end of the bytecode.
+                match io::copy(&mut source, &mut pipe_writer) {
+                    // The C code stopped reading early. It will have said why.
+                    Err(error) if error.kind() == io::ErrorKind::BrokenPipe => Ok(()),
+                    result => result.map(|_| ()),
+                }
+            });
+            let list = CIrList::load(pipe_reader.as_fd());
+            // If the C code stopped early, this is what unblocks the feeder.
+            drop(pipe_reader);
+            feeder
+                .join()
+                .expect("The thread feeding the C code panicked.")?;
+            Ok(list)
+        })
+    }
+
+    pub fn from_bytes(bytecode: &[u8]) -> io::Result<Self> {
+        Self::from_reader(bytecode)
+    }
+
+    /// Prints the program, in the C code's own format, to standard out.
+    pub fn print(&self) {
+        // SAFETY: `head` came from ir_list_read and hasn't been freed, since
+        // that only happens when `self` is dropped. Printing doesn't change it.
+        unsafe { bindings::ir_list_print(self.head) }
+    }
+
+    /// Runs the program. What it prints goes straight to standard out, and if
+    /// it exits, so does this process.
+    ///
+    /// The C interpreter returns nothing, and its stack is its own, so this
+    /// can't give back what was printed or the final stack. To capture what a
+    /// program prints, it has to be run in a child process, like
+    /// `run --record-expected` does.
+    ///
+    /// This takes `self` because the C interpreter is free to change the list
+    /// as it runs, so a list can't be trusted to run the same way twice.
+    pub fn run(self) {
+        // SAFETY: As in `print`. The list is still freed by `drop` afterwards.
+        unsafe { bindings::interpret(self.head) }
+    }
+}
+
+impl Drop for CIrList {
+    fn drop(&mut self) {
+        // SAFETY: `head` came from ir_list_read, and this is the only place
+        // that frees it.
+        unsafe { bindings::free_list_ir(self.head) }
+    }
+}
+
+/// The resource usage `getrusage` reports for `who`, which is one of
+/// `libc::RUSAGE_SELF` and `libc::RUSAGE_CHILDREN`.
+pub(crate) fn rusage(who: libc::c_int) -> libc::rusage {
+    let mut usage = MaybeUninit::uninit();
+    // SAFETY: getrusage only writes to `usage`, which is big enough for a
+    // `rusage`, and it fills all of it in when it succeeds.
+    unsafe {
+        assert_eq!(
+            libc::getrusage(who, usage.as_mut_ptr()),
+            0,
+            "Couldn't get resource usage."
+        );
+        usage.assume_init()
+    }
+}
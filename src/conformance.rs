@@ -0,0 +1,265 @@
+// Small programs that pin down the semantics the C interpreter is documented
+// to have (see `opcode` and `interpret_rust`), for checking that the C
+// interpreter built on some platform really has them. What C leaves up to the
+// platform, like dividing the smallest integer by -1, is where they differ.
+
+use std::fmt;
+
+use crate::{assemble, interpret_rust, ir_definition::Instruction};
+
+/// How a program finished. A program that runs off the end exits with 0.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Outcome {
+    pub output: String,
+    pub exit_code: i32,
+}
+
+pub struct Probe {
+    /// What the probe checks, as a statement that's true when it passes.
+    pub name: &'static str,
+    /// A program in the textual format.
+    pub program: &'static str,
+    pub expected_output: &'static str,
+    pub expected_exit_code: i32,
+}
+
+macro_rules! probe {
+    ($name:literal, $program:literal, $output:literal) => {
+        probe!($name, $program, $output, 0)
+    };
+    ($name:literal, $program:literal, $output:literal, $exit_code:literal) => {
+        Probe {
+            name: $name,
+            program: $program,
+            expected_output: $output,
+            expected_exit_code: $exit_code,
+        }
+    };
+}
+
+pub const PROBES: &[Probe] = &[
+    probe!(
+        "DIV rounds toward zero",
+        "ICONST -7 ICONST 2 DIV INTRINSIC PRINT_INT",
+        "-3"
+    ),
+    probe!(
+        "MOD has the sign of the dividend",
+        "ICONST -7 ICONST 2 MOD INTRINSIC PRINT_INT
+         SCONST \" \" INTRINSIC PRINT_STRING
+         ICONST 7 ICONST -2 MOD INTRINSIC PRINT_INT",
+        "-1 1"
+    ),
+    probe!(
+        "ADD wraps around at 32 bits",
+        "ICONST 2147483647 ICONST 1 ADD INTRINSIC PRINT_INT",
+        "-2147483648"
+    ),
+    probe!(
+        "SUB wraps around at 32 bits",
+        "ICONST -2147483648 ICONST 1 SUB INTRINSIC PRINT_INT",
+        "2147483647"
+    ),
+    probe!(
+        "MUL wraps around at 32 bits",
+        "ICONST 65536 ICONST 65536 MUL INTRINSIC PRINT_INT",
+        "0"
+    ),
+    probe!(
+        "the smallest integer DIV -1 is itself",
+        "ICONST -2147483648 ICONST -1 DIV INTRINSIC PRINT_INT",
+        "-2147483648"
+    ),
+    probe!(
+        "the smallest integer MOD -1 is 0",
+        "ICONST -2147483648 ICONST -1 MOD INTRINSIC PRINT_INT",
+        "0"
+    ),
+    probe!(
+        "comparisons are signed and give 1 or 0",
+        "ICONST -1 ICONST 1 LT INTRINSIC PRINT_INT
+         ICONST -1 ICONST 1 GT INTRINSIC PRINT_INT
+         ICONST 4 ICONST 4 EQ INTRINSIC PRINT_INT",
+        "101"
+    ),
+    probe!(
+        "NOT, AND and OR are logical and give 1 or 0",
+        "ICONST 5 NOT INTRINSIC PRINT_INT
+         ICONST 0 NOT INTRINSIC PRINT_INT
+         ICONST 2 ICONST 4 AND INTRINSIC PRINT_INT
+         ICONST 0 ICONST -3 OR INTRINSIC PRINT_INT",
+        "0111"
+    ),
+    probe!(
+        "BAND, BOR and XOR work on bits",
+        "ICONST 12 ICONST 10 BAND INTRINSIC PRINT_INT
+         SCONST \" \" INTRINSIC PRINT_STRING
+         ICONST 12 ICONST 10 BOR INTRINSIC PRINT_INT
+         SCONST \" \" INTRINSIC PRINT_STRING
+         ICONST 12 ICONST 10 XOR INTRINSIC PRINT_INT",
+        "8 14 6"
+    ),
+    probe!(
+        "PRINT_INT prints nothing but the number",
+        "ICONST 1 INTRINSIC PRINT_INT ICONST -2 INTRINSIC PRINT_INT",
+        "1-2"
+    ),
+    probe!(
+        "PRINT_STRING prints the string's bytes as they are",
+        "SCONST \"h\u{e9}llo,\tw\u{f6}rld
+\" INTRINSIC PRINT_STRING",
+        "h\u{e9}llo,\tw\u{f6}rld\n"
+    ),
+    probe!(
+        "an empty string prints nothing",
+        "SCONST \"\" INTRINSIC PRINT_STRING ICONST 1 INTRINSIC PRINT_INT",
+        "1"
+    ),
+    probe!(
+        "a string global starts as its initial value",
+        "RESERVE s 6 \"hello\" READ s INTRINSIC PRINT_STRING",
+        "hello"
+    ),
+    probe!(
+        "an integer global starts at 0",
+        "RESERVE i 4 (null) READ i INTRINSIC PRINT_INT",
+        "0"
+    ),
+    probe!(
+        "EXIT stops the program with its code",
+        "ICONST 3 INTRINSIC EXIT ICONST 1 INTRINSIC PRINT_INT",
+        "",
+        3
+    ),
+    probe!(
+        "running off the end exits with 0",
+        "ICONST 1 INTRINSIC PRINT_INT",
+        "1"
+    ),
+];
+
+impl Probe {
+    pub fn program(&self) -> Vec<Instruction> {
+        assemble::program(self.program).expect("A probe doesn't assemble.")
+    }
+
+    pub fn expected(&self) -> Outcome {
+        Outcome {
+            output: self.expected_output.to_string(),
+            exit_code: self.expected_exit_code,
+        }
+    }
+}
+
+pub struct ProbeResult {
+    pub probe: &'static Probe,
+    /// How the program finished, or why it couldn't be run, or didn't finish.
+    pub got: Result<Outcome, String>,
+}
+
+impl ProbeResult {
+    pub fn passed(&self) -> bool {
+        self.got.as_ref() == Ok(&self.probe.expected())
+    }
+}
+
+pub struct Report {
+    pub results: Vec<ProbeResult>,
+}
+
+impl Report {
+    pub fn all_passed(&self) -> bool {
+        self.results.iter().all(ProbeResult::passed)
+    }
+}
+
+/// A line for each probe, saying how it differed if it failed, then how many
+/// passed.
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for result in &self.results {
+            let probe = result.probe;
+            if result.passed() {
+                writeln!(f, "ok    {}", probe.name)?;
+                continue;
+            }
+            write!(f, "FAIL  {}: ", probe.name)?;
+            match &result.got {
+                Ok(got) if got.output != probe.expected_output => writeln!(
+                    f,
+                    "printed {:?} instead of {:?}",
+                    got.output, probe.expected_output
+                )?,
+                Ok(got) => writeln!(
+                    f,
+                    "exited with {} instead of {}",
+                    got.exit_code, probe.expected_exit_code
+                )?,
+                Err(error) => writeln!(f, "{error}")?,
+            }
+        }
+        let passed = self.results.iter().filter(|result| result.passed()).count();
+        writeln!(f, "{passed} of {} probes passed", self.results.len())
+    }
+}
+
+/// Runs every probe with `run`, which runs a program on the interpreter
+/// being checked.
+pub fn self_check(mut run: impl FnMut(&[Instruction]) -> Result<Outcome, String>) -> Report {
+    let results = PROBES
+        .iter()
+        .map(|probe| ProbeResult {
+            probe,
+            got: run(&probe.program()),
+        })
+        .collect();
+    Report { results }
+}
+
+/// Runs a program with the Rust interpreter, for `self_check`.
+pub fn run_rust(program: &[Instruction]) -> Result<Outcome, String> {
+    match interpret_rust::interpret(program) {
+        Ok(result) => Ok(Outcome {
+            output: result.output,
+            exit_code: result.exit_code.unwrap_or(0),
+        }),
+        Err(error) => Err(format!("trapped: {error}")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rust_interpreter_conforms() {
+        let report = self_check(run_rust);
+        assert!(report.all_passed(), "{report}");
+        assert!(report
+            .to_string()
+            .ends_with(&format!("{0} of {0} probes passed\n", PROBES.len())));
+    }
+
+    #[test]
+    fn failures() {
+        let report = self_check(|program| {
+            if program.contains(&Instruction::Div) {
+                Err("killed by a signal".into())
+            } else {
+                Ok(Outcome {
+                    output: "1".into(),
+                    exit_code: 0,
+                })
+            }
+        });
+        let report = report.to_string();
+        assert!(report.starts_with("FAIL  DIV rounds toward zero: killed by a signal\n"));
+        assert!(report.contains(
+            "FAIL  ADD wraps around at 32 bits: printed \"1\" instead of \"-2147483648\"\n"
+        ));
+        assert!(report.contains(
+            "FAIL  EXIT stops the program with its code: printed \"1\" instead of \"\"\n"
+        ));
+        assert!(report.contains("ok    running off the end exits with 0\n"));
+    }
+}
@@ -0,0 +1,436 @@
+// Reads bytecode back into `Instruction`s, the inverse of `write_bytecode`,
+// without going through the C code.
+
+use std::{fmt, io};
+
+use crate::{
+    ir_definition::{Instruction, Intrinsic, Label},
+    opcode::Opcode,
+    write_bytecode::{BytecodeOptions, Endianness, IntWidth},
+};
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct DisassembleError {
+    /// How many bytes into the bytecode the problem is.
+    pub offset: usize,
+    pub message: String,
+}
+
+impl fmt::Display for DisassembleError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "at byte {}: {}", self.offset, self.message)
+    }
+}
+
+impl std::error::Error for DisassembleError {}
+
+#[derive(Debug)]
+pub enum BytecodeError {
+    Io(io::Error),
+    Malformed(DisassembleError),
+}
+
+impl fmt::Display for BytecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BytecodeError::Io(error) => write!(f, "couldn't read the bytecode: {error}"),
+            BytecodeError::Malformed(error) => write!(f, "{error}"),
+        }
+    }
+}
+
+impl std::error::Error for BytecodeError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            BytecodeError::Io(error) => Some(error),
+            BytecodeError::Malformed(error) => Some(error),
+        }
+    }
+}
+
+impl From<io::Error> for BytecodeError {
+    fn from(error: io::Error) -> Self {
+        BytecodeError::Io(error)
+    }
+}
+
+impl From<DisassembleError> for BytecodeError {
+    fn from(error: DisassembleError) -> Self {
+        BytecodeError::Malformed(error)
+    }
+}
+
+struct Reader<'a> {
+    bytecode: &'a [u8],
+    offset: usize,
+    options: &'a BytecodeOptions,
+}
+
+impl Reader<'_> {
+    fn error<T>(&self, offset: usize, message: impl Into<String>) -> Result<T, DisassembleError> {
+        Err(DisassembleError {
+            offset,
+            message: message.into(),
+        })
+    }
+
+    fn bytes(&mut self, count: usize, what: &str) -> Result<&[u8], DisassembleError> {
+        let start = self.offset;
+        match self.bytecode.get(start..start + count) {
+            Some(bytes) => {
+                self.offset += count;
+                Ok(bytes)
+            }
+            None => self.error(start, format!("the bytecode ends in the middle of {what}")),
+        }
+    }
+
+    fn int(&mut self, what: &str) -> Result<i64, DisassembleError> {
+        let options = *self.options;
+        let bytes = self.bytes(options.int_width.bits() as usize / 8, what)?;
+        Ok(match (options.int_width, options.endianness) {
+            (IntWidth::W32, Endianness::Little) => {
+                i32::from_le_bytes(bytes.try_into().unwrap()).into()
+            }
+            (IntWidth::W32, Endianness::Big) => {
+                i32::from_be_bytes(bytes.try_into().unwrap()).into()
+            }
+            (IntWidth::W64, Endianness::Little) => i64::from_le_bytes(bytes.try_into().unwrap()),
+            (IntWidth::W64, Endianness::Big) => i64::from_be_bytes(bytes.try_into().unwrap()),
+        })
+    }
+
+    fn count(&mut self, what: &str) -> Result<u64, DisassembleError> {
+        let start = self.offset;
+        let value = self.int(what)?;
+        match u64::try_from(value) {
+            Ok(value) => Ok(value),
+            Err(_) => self.error(start, format!("{what} is negative ({value})")),
+        }
+    }
+
+    // `None` for a string with length 0, which is how RESERVE writes (null).
+    fn nullable_string(&mut self, what: &str) -> Result<Option<String>, DisassembleError> {
+        let start = self.offset;
+        let length = self.int(what)?;
+        let length = match usize::try_from(length) {
+            Ok(0) => return Ok(None),
+            Ok(length) => length,
+            Err(_) => return self.error(start, format!("{what} has a negative length")),
+        };
+        let bytes = self.bytes(length, what)?;
+        let Some((&0, text)) = bytes.split_last() else {
+            return self.error(start, format!("{what} isn't null-terminated"));
+        };
+        match String::from_utf8(text.to_vec()) {
+            Ok(text) => Ok(Some(text)),
+            Err(_) => self.error(start, format!("{what} isn't valid UTF-8")),
+        }
+    }
+
+    fn string(&mut self, what: &str) -> Result<String, DisassembleError> {
+        let start = self.offset;
+        match self.nullable_string(what)? {
+            Some(text) => Ok(text),
+            None => self.error(
+                start,
+                format!("{what} has length 0, so it has no null terminator"),
+            ),
+        }
+    }
+
+    fn label(&mut self) -> Result<Label, DisassembleError> {
+        Ok(Label::named(&self.string("a label")?))
+    }
+
+    fn instruction(&mut self) -> Result<Instruction, DisassembleError> {
+        let start = self.offset;
+        let number = self.int("an opcode")?;
+        let Some(opcode) = u32::try_from(number).ok().and_then(Opcode::from_number) else {
+            return self.error(start, format!("{number} isn't an opcode"));
+        };
+        let instruction = match opcode {
+            Opcode::Nop => Instruction::Nop,
+            Opcode::Iconst => Instruction::Iconst(self.int("an integer constant")?),
+            Opcode::Sconst => Instruction::Sconst(self.string("a string constant")?),
+            Opcode::Add => Instruction::Add,
+            Opcode::Sub => Instruction::Sub,
+            Opcode::Mul => Instruction::Mul,
+            Opcode::Div => Instruction::Div,
+            Opcode::Mod => Instruction::Mod,
+            Opcode::Bor => Instruction::Bor,
+            Opcode::Band => Instruction::Band,
+            Opcode::Xor => Instruction::Xor,
+            Opcode::Or => Instruction::Or,
+            Opcode::And => Instruction::And,
+            Opcode::Eq => Instruction::Eq,
+            Opcode::Lt => Instruction::Lt,
+            Opcode::Gt => Instruction::Gt,
+            Opcode::Not => Instruction::Not,
+            Opcode::Reserve => {
+                let name = self.string("a global's name")?;
+                let initial_value = self.nullable_string("a global's initial value")?;
+                let size = self.count("a global's size")?;
+                match initial_value {
+                    Some(initial_value) => Instruction::ReserveString {
+                        size,
+                        name,
+                        initial_value,
+                    },
+                    // Integers are always 4 bytes, so the size isn't kept.
+                    None => Instruction::ReserveInt { name },
+                }
+            }
+            Opcode::Read => Instruction::Read(self.string("a global's name")?),
+            Opcode::Write => Instruction::Write(self.string("a global's name")?),
+            Opcode::ArgLocalRead => Instruction::ArgLocalRead(self.count("an index")?),
+            Opcode::ArgLocalWrite => Instruction::ArgLocalWrite(self.count("an index")?),
+            Opcode::Label => Instruction::Label(self.label()?),
+            Opcode::Jump => Instruction::Jump(self.label()?),
+            Opcode::BranchZero => Instruction::BranchZero(self.label()?),
+            Opcode::Function => Instruction::Function {
+                label: self.label()?,
+                num_locs: self.count("a number of locals")?,
+                // Attributes aren't kept in bytecode.
+                attributes: vec![],
+            },
+            Opcode::Call => Instruction::Call {
+                label: self.label()?,
+                num_args: self.count("a number of arguments")?,
+            },
+            Opcode::Ret => Instruction::Ret,
+            Opcode::Intrinsic => {
+                let start = self.offset;
+                let number = self.int("an intrinsic")?;
+                let unsigned = u32::try_from(number).ok();
+                match unsigned.and_then(Intrinsic::from_number) {
+                    Some(intrinsic) => Instruction::Intrinsic(intrinsic),
+                    None if unsigned.is_some_and(|n| Intrinsic::EXPERIMENTAL.contains(&n)) => {
+                        return self.error(
+                            start,
+                            format!(
+                                "{number} is an experimental intrinsic, which only a fork knows"
+                            ),
+                        )
+                    }
+                    None => return self.error(start, format!("{number} isn't an intrinsic")),
+                }
+            }
+            Opcode::Push => Instruction::Push {
+                reg: self.int("a register")?,
+            },
+            Opcode::Pop => Instruction::Pop {
+                reg: self.int("a register")?,
+            },
+        };
+        Ok(instruction)
+    }
+}
+
+/// Reads a whole program from its bytecode. Everything but function
+/// attributes, which bytecode doesn't keep, comes back as it was written.
+pub fn disassemble(bytecode: &[u8]) -> Result<Vec<Instruction>, DisassembleError> {
+    disassemble_with(bytecode, &BytecodeOptions::default())
+}
+
+/// Like `disassemble`, for bytecode written by `write_bytecode_with` with
+/// `options`.
+pub fn disassemble_with(
+    bytecode: &[u8],
+    options: &BytecodeOptions,
+) -> Result<Vec<Instruction>, DisassembleError> {
+    let mut reader = Reader {
+        bytecode,
+        offset: 0,
+        options,
+    };
+    let mut program = Vec::new();
+    while reader.offset < bytecode.len() {
+        program.push(reader.instruction()?);
+    }
+    Ok(program)
+}
+
+/// Whether `bytes` look like bytecode rather than a text program. Text
+/// programs are UTF-8 with no NUL bytes in them, and bytecode never is, since
+/// each instruction starts with an opcode much smaller than its word.
+pub fn looks_like_bytecode(bytes: &[u8]) -> bool {
+    bytes.contains(&0) || std::str::from_utf8(bytes).is_err()
+}
+
+/// Reads a whole program from bytecode in `reader`, like `ir_list_read` does
+/// in the C code, but without needing an fd or the C library.
+pub fn read_bytecode(reader: impl io::Read) -> Result<Vec<Instruction>, BytecodeError> {
+    read_bytecode_with(reader, &BytecodeOptions::default())
+}
+
+/// Like `read_bytecode`, for bytecode written with `options`.
+pub fn read_bytecode_with(
+    mut reader: impl io::Read,
+    options: &BytecodeOptions,
+) -> Result<Vec<Instruction>, BytecodeError> {
+    let mut bytecode = Vec::new();
+    reader.read_to_end(&mut bytecode)?;
+    Ok(disassemble_with(&bytecode, options)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        assemble,
+        write_bytecode::{write_bytecode, write_bytecode_with},
+    };
+
+    fn bytecode(program: &[Instruction]) -> Vec<u8> {
+        let mut bytecode = Vec::new();
+        write_bytecode(program, &mut bytecode).unwrap();
+        bytecode
+    }
+
+    #[test]
+    fn round_trips() {
+        let program = assemble::program(
+            "RESERVE counter 4 (null)
+             RESERVE greeting 6 \"Hello\"
+             JUMP main
+             FUNCTION f 2
+             ARGLOCAL_READ 0
+             ARGLOCAL_WRITE 2
+             ICONST -5
+             RET
+             main:
+             ICONST 42
+             SCONST \"with \\\" and \\\\\"
+             CALL f 1
+             BRANCHZERO main
+             READ counter
+             WRITE counter
+             PUSH 3
+             POP -1
+             ADD SUB MUL DIV MOD BOR BAND XOR OR AND EQ LT GT NOT NOP
+             INTRINSIC PRINT_INT
+             INTRINSIC PRINT_STRING
+             INTRINSIC EXIT",
+        )
+        .unwrap();
+        assert_eq!(disassemble(&bytecode(&program)), Ok(program));
+    }
+
+    #[test]
+    fn round_trips_with_options() {
+        let program = assemble::program(
+            "RESERVE greeting 6 \"Hello\"
+             FUNCTION f 2
+             ICONST -4294967296
+             ICONST 2147483647
+             CALL f 1
+             POP -1",
+        )
+        .unwrap();
+        for endianness in [Endianness::Little, Endianness::Big] {
+            let options = BytecodeOptions {
+                endianness,
+                int_width: IntWidth::W64,
+            };
+            let mut bytecode = Vec::new();
+            write_bytecode_with(&program, &mut bytecode, &options).unwrap();
+            assert_eq!(disassemble_with(&bytecode, &options), Ok(program.clone()));
+        }
+
+        let options = BytecodeOptions {
+            endianness: Endianness::Big,
+            int_width: IntWidth::W32,
+        };
+        let mut bytecode = Vec::new();
+        write_bytecode_with(&program[3..], &mut bytecode, &options).unwrap();
+        assert_eq!(
+            disassemble_with(&bytecode, &options),
+            Ok(program[3..].to_vec())
+        );
+        // Read the other way, the opcode is far too big.
+        assert!(disassemble(&bytecode).is_err());
+    }
+
+    #[test]
+    fn attributes_are_lost() {
+        let program = assemble::program("@inline FUNCTION f 0").unwrap();
+        assert_eq!(
+            disassemble(&bytecode(&program)),
+            Ok(vec![Instruction::Function {
+                label: Label::named("f"),
+                num_locs: 0,
+                attributes: vec![]
+            }])
+        );
+    }
+
+    #[test]
+    fn matches_the_text_sample() {
+        let bytecode =
+            include_bytes!("../ir_samples/handwritten/strings_with_escapes.aves_bytecode");
+        let text = include_str!("../ir_samples/handwritten/strings_with_escapes.aves_text");
+        assert_eq!(disassemble(bytecode), Ok(assemble::program(text).unwrap()));
+    }
+
+    #[test]
+    fn tells_bytecode_from_text() {
+        let bytecode =
+            include_bytes!("../ir_samples/handwritten/strings_with_escapes.aves_bytecode");
+        let text = include_str!("../ir_samples/handwritten/strings_with_escapes.aves_text");
+        assert!(looks_like_bytecode(bytecode));
+        assert!(!looks_like_bytecode(text.as_bytes()));
+        assert!(!looks_like_bytecode(b""));
+    }
+
+    #[test]
+    fn reads_from_readers() {
+        let program =
+            assemble::program("JUMP main main: SCONST \"hi\" INTRINSIC PRINT_STRING").unwrap();
+        let bytecode = bytecode(&program);
+        // A reader that only gives a few bytes at a time.
+        let chunked = io::Read::chain(
+            &bytecode[..5],
+            io::Read::chain(&bytecode[5..11], &bytecode[11..]),
+        );
+        assert_eq!(read_bytecode(chunked).unwrap(), program);
+
+        assert!(matches!(
+            read_bytecode(&bytecode[..bytecode.len() - 2]),
+            Err(BytecodeError::Malformed(_))
+        ));
+    }
+
+    #[test]
+    fn errors() {
+        let sconst = bytecode(&[Instruction::Sconst("hi".into())]);
+        assert_eq!(
+            disassemble(&sconst[..sconst.len() - 1]).unwrap_err(),
+            DisassembleError {
+                offset: 8,
+                message: "the bytecode ends in the middle of a string constant".into()
+            }
+        );
+        assert_eq!(
+            disassemble(&[1, 2, 3, 4, 5, 6]).unwrap_err().to_string(),
+            "at byte 0: 67305985 isn't an opcode"
+        );
+        let mut call = bytecode(&[Instruction::Call {
+            label: Label::named("f"),
+            num_args: 0,
+        }]);
+        let length = call.len();
+        call[length - 4..].copy_from_slice(&(-1i32).to_le_bytes());
+        assert_eq!(
+            disassemble(&call).unwrap_err().message,
+            "a number of arguments is negative (-1)"
+        );
+        let mut intrinsic = bytecode(&[Instruction::Intrinsic(Intrinsic::Exit)]);
+        let length = intrinsic.len();
+        intrinsic[length - 4..].copy_from_slice(&0x8000i32.to_le_bytes());
+        assert_eq!(
+            disassemble(&intrinsic).unwrap_err().message,
+            "32768 is an experimental intrinsic, which only a fork knows"
+        );
+    }
+}
@@ -0,0 +1,104 @@
+// Renders the reference entries printed by `aves_interpreter explain`, from
+// the tables in `opcode`.
+
+use std::fmt::Write as _;
+
+use crate::{assemble, ir_definition::Intrinsic, opcode::Opcode, write_bytecode::write_bytecode};
+
+/// The reference entry for an instruction's mnemonic or an intrinsic's name
+/// (ignoring case), or `None` if `name` is neither.
+pub fn explain(name: &str) -> Option<String> {
+    match Opcode::from_mnemonic(name) {
+        Some(opcode) => Some(explain_opcode(opcode)),
+        None => Intrinsic::from_name(name).map(explain_intrinsic),
+    }
+}
+
+fn explain_opcode(opcode: Opcode) -> String {
+    let info = opcode.info();
+    let operands = if info.operands.is_empty() {
+        "none".to_string()
+    } else {
+        info.operands
+            .iter()
+            .map(|operand| operand.name())
+            .collect::<Vec<_>>()
+            .join(", ")
+    };
+    let traps = if info.traps.is_empty() {
+        "none"
+    } else {
+        info.traps
+    };
+
+    // Writing to a `String` can't fail.
+    let mut entry = String::new();
+    writeln!(entry, "{}\n", info.syntax).unwrap();
+    writeln!(entry, "{}\n", info.description).unwrap();
+    writeln!(entry, "Operands:     {operands}").unwrap();
+    writeln!(entry, "Stack effect: {}", info.stack_effect).unwrap();
+    writeln!(entry, "Traps:        {traps}").unwrap();
+    write_example(&mut entry, info.example);
+    entry
+}
+
+fn explain_intrinsic(intrinsic: Intrinsic) -> String {
+    let info = intrinsic.info();
+
+    let mut entry = String::new();
+    writeln!(entry, "INTRINSIC {}\n", intrinsic.name()).unwrap();
+    writeln!(entry, "{}\n", info.description).unwrap();
+    writeln!(entry, "Stack effect: {}", info.stack_effect).unwrap();
+    write_example(&mut entry, info.example);
+    entry
+}
+
+// Every example has one instruction per line, so each line is shown next to
+// the bytes it's encoded as.
+fn write_example(entry: &mut String, example: &str) {
+    let instructions = assemble::program(example).expect("Examples always assemble.");
+    let width = example.lines().map(str::len).max().unwrap_or(0);
+
+    writeln!(entry, "\nExample and encoding:").unwrap();
+    for (line, instruction) in example.lines().zip(&instructions) {
+        let mut bytes = Vec::new();
+        write_bytecode(std::slice::from_ref(instruction), &mut bytes)
+            .expect("Examples can always be written as bytecode.");
+        let hex: Vec<_> = bytes.iter().map(|byte| format!("{byte:02x}")).collect();
+        writeln!(entry, "    {line:width$}    {}", hex.join(" ")).unwrap();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn explains_opcodes_and_intrinsics() {
+        let entry = explain("arglocal_read").unwrap();
+        assert!(entry.starts_with("ARGLOCAL_READ <index>\n"));
+        assert!(entry.contains("Stack effect: -- value"));
+        assert!(entry.contains("    ARGLOCAL_READ 0    "));
+
+        let entry = explain("Print_Int").unwrap();
+        assert!(entry.starts_with("INTRINSIC PRINT_INT\n"));
+        assert!(entry.contains("ICONST 473"));
+
+        assert_eq!(explain("FROBNICATE"), None);
+    }
+
+    #[test]
+    fn every_example_has_one_instruction_per_line() {
+        let examples = Opcode::ALL
+            .map(|opcode| opcode.info().example)
+            .into_iter()
+            .chain(Intrinsic::ALL.map(|intrinsic| intrinsic.info().example));
+        for example in examples {
+            assert_eq!(
+                assemble::program(example).unwrap().len(),
+                example.lines().count(),
+                "{example:?}"
+            );
+        }
+    }
+}
@@ -0,0 +1,181 @@
+// Cutting a function out of a program, with what it needs, to isolate a bug
+// from a huge generated program.
+
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    fmt,
+};
+
+use crate::{
+    analysis::function_spans,
+    ir_definition::{Instruction, Label},
+};
+
+#[derive(Debug, PartialEq)]
+pub struct UnknownFunction(pub String);
+
+impl fmt::Display for UnknownFunction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "there's no function called {}", self.0)
+    }
+}
+
+impl std::error::Error for UnknownFunction {}
+
+/// A program with just `function` (and, if `with_callees`, every function it
+/// calls, directly or not) and the globals they read or write.
+///
+/// The globals' `RESERVE`s come first, in the order they were in, then a jump
+/// over the functions, which are also in the order they were in. So the
+/// program runs without doing anything, and a call can be added to the end to
+/// try the function out. Calls to functions that weren't extracted are left
+/// as they are.
+pub fn extract(
+    program: &[Instruction],
+    function: &str,
+    with_callees: bool,
+) -> Result<Vec<Instruction>, UnknownFunction> {
+    let spans: HashMap<_, _> = function_spans(program)
+        .into_iter()
+        .rev() // So the first definition wins, like everywhere else.
+        .map(|span| (span.label.name(), span.range))
+        .collect();
+    if !spans.contains_key(function) {
+        return Err(UnknownFunction(function.to_string()));
+    }
+
+    // By where they start, to keep them in order.
+    let mut extracted = BTreeMap::new();
+    let mut to_visit = vec![function];
+    while let Some(name) = to_visit.pop() {
+        let Some(range) = spans.get(name) else {
+            continue;
+        };
+        if extracted.insert(range.start, range.clone()).is_some() {
+            continue;
+        }
+        if with_callees {
+            for instruction in &program[range.clone()] {
+                if let Instruction::Call { label, .. } = instruction {
+                    to_visit.push(label.name());
+                }
+            }
+        }
+    }
+    let ranges: Vec<_> = extracted.into_values().collect();
+
+    let globals: BTreeSet<_> = ranges
+        .iter()
+        .flat_map(|range| &program[range.clone()])
+        .filter_map(|instruction| match instruction {
+            Instruction::Read(name) | Instruction::Write(name) => Some(name.as_str()),
+            _ => None,
+        })
+        .collect();
+    let mut result: Vec<_> = program
+        .iter()
+        .filter(|instruction| match instruction {
+            Instruction::ReserveInt { name } | Instruction::ReserveString { name, .. } => {
+                globals.contains(name.as_str())
+            }
+            _ => false,
+        })
+        .cloned()
+        .collect();
+
+    // Labels starting with `$` are left to tools like this one.
+    let mut end = String::from("$end");
+    while program.iter().any(|instruction| match instruction {
+        Instruction::Label(label) | Instruction::Function { label, .. } => label.name() == end,
+        _ => false,
+    }) {
+        end.insert(0, '$');
+    }
+    result.push(Instruction::Jump(Label::named(&end)));
+    for range in ranges {
+        result.extend_from_slice(&program[range]);
+    }
+    result.push(Instruction::Label(Label::named(&end)));
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assemble;
+
+    const PROGRAM: &str = "RESERVE unused 4 (null)
+                           RESERVE counter 4 (null)
+                           RESERVE greeting 6 \"hello\"
+                           JUMP main
+                           FUNCTION helper 0
+                           READ greeting
+                           INTRINSIC PRINT_STRING
+                           ICONST 0
+                           RET
+                           FUNCTION other 0
+                           READ unused
+                           RET
+                           FUNCTION f 0
+                           READ counter
+                           ICONST 42
+                           CALL helper 0
+                           ADD
+                           RET
+                           main:
+                           ICONST 42
+                           CALL f 0
+                           INTRINSIC PRINT_INT";
+
+    #[test]
+    fn with_and_without_callees() {
+        let program = assemble::program(PROGRAM).unwrap();
+        assert_eq!(
+            extract(&program, "f", true).unwrap(),
+            assemble::program(
+                "RESERVE counter 4 (null)
+                 RESERVE greeting 6 \"hello\"
+                 JUMP $end
+                 FUNCTION helper 0
+                 READ greeting
+                 INTRINSIC PRINT_STRING
+                 ICONST 0
+                 RET
+                 FUNCTION f 0
+                 READ counter
+                 ICONST 42
+                 CALL helper 0
+                 ADD
+                 RET
+                 $end:"
+            )
+            .unwrap()
+        );
+        assert_eq!(
+            extract(&program, "f", false).unwrap(),
+            assemble::program(
+                "RESERVE counter 4 (null)
+                 JUMP $end
+                 FUNCTION f 0
+                 READ counter
+                 ICONST 42
+                 CALL helper 0
+                 ADD
+                 RET
+                 $end:"
+            )
+            .unwrap()
+        );
+        assert_eq!(
+            extract(&program, "main", true),
+            Err(UnknownFunction("main".into()))
+        );
+    }
+
+    #[test]
+    fn end_label_is_fresh() {
+        let program = assemble::program("FUNCTION f 0 $end: ICONST 0 RET").unwrap();
+        let extracted = extract(&program, "f", true).unwrap();
+        assert_eq!(extracted[0], Instruction::Jump(Label::named("$$end")));
+    }
+}
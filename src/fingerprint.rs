@@ -0,0 +1,128 @@
+// Hashes of programs that stay the same from one build to the next, for
+// telling whether two programs are the same without keeping both around.
+
+use std::{collections::HashMap, fmt};
+
+use crate::{
+    ir_definition::{Instruction, Label},
+    write_bytecode::{write_bytecode, BytecodeWriteError},
+};
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash)]
+pub struct Fingerprint {
+    /// The same for programs that differ only in what their labels (including
+    /// functions) are called.
+    pub structural: u64,
+    /// A hash of the program's bytecode, so it changes with any change that
+    /// the bytecode would show.
+    pub exact: u64,
+}
+
+impl Fingerprint {
+    /// Function attributes aren't part of either hash, since the bytecode
+    /// doesn't keep them. A program that can't be written as bytecode has no
+    /// fingerprint.
+    pub fn of(program: &[Instruction]) -> Result<Self, BytecodeWriteError> {
+        Ok(Fingerprint {
+            structural: fnv1a(&bytecode(&with_canonical_labels(program))?),
+            exact: fnv1a(&bytecode(program)?),
+        })
+    }
+}
+
+impl fmt::Display for Fingerprint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:016x}-{:016x}", self.structural, self.exact)
+    }
+}
+
+fn bytecode(program: &[Instruction]) -> Result<Vec<u8>, BytecodeWriteError> {
+    let mut bytecode = Vec::new();
+    write_bytecode(program, &mut bytecode)?;
+    Ok(bytecode)
+}
+
+// `std`'s hashers are allowed to change between Rust versions, and these
+// hashes are meant to be stored, so this is FNV-1a instead.
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, &byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x100000001b3)
+    })
+}
+
+// Renames every label to `L0`, `L1`, ..., in the order they're first mentioned.
+fn with_canonical_labels(program: &[Instruction]) -> Vec<Instruction> {
+    let mut names = HashMap::new();
+    let mut rename = |label: &Label| {
+        let next = names.len();
+        let name = names
+            .entry(label.name().to_string())
+            .or_insert_with(|| format!("L{next}"));
+        Label::named(name)
+    };
+    program
+        .iter()
+        .map(|instruction| match instruction {
+            Instruction::Label(label) => Instruction::Label(rename(label)),
+            Instruction::Jump(label) => Instruction::Jump(rename(label)),
+            Instruction::BranchZero(label) => Instruction::BranchZero(rename(label)),
+            Instruction::Function {
+                label,
+                num_locs,
+                attributes,
+            } => Instruction::Function {
+                label: rename(label),
+                num_locs: *num_locs,
+                attributes: attributes.clone(),
+            },
+            Instruction::Call { label, num_args } => Instruction::Call {
+                label: rename(label),
+                num_args: *num_args,
+            },
+            other => other.clone(),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assemble;
+
+    fn fingerprint(text: &str) -> Fingerprint {
+        Fingerprint::of(&assemble::program(text).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn label_names_only_change_the_exact_hash() {
+        let original = fingerprint("JUMP main FUNCTION f 0 RET main: CALL f 0 BRANCHZERO main");
+        let renamed = fingerprint("JUMP start FUNCTION g 0 RET start: CALL g 0 BRANCHZERO start");
+        assert_eq!(original.structural, renamed.structural);
+        assert_ne!(original.exact, renamed.exact);
+
+        // Which label is used where still matters.
+        let swapped = fingerprint("JUMP main FUNCTION f 0 RET main: CALL f 0 BRANCHZERO f");
+        assert_ne!(original.structural, swapped.structural);
+    }
+
+    #[test]
+    fn is_stable() {
+        // If this changes, so does every stored fingerprint.
+        assert_eq!(fnv1a(b""), 0xcbf29ce484222325);
+        assert_eq!(fnv1a(b"a"), 0xaf63dc4c8601ec8c);
+        assert_eq!(
+            fingerprint("ICONST 1 INTRINSIC PRINT_INT"),
+            fingerprint("ICONST 1\n\tINTRINSIC PRINT_INT # with a comment")
+        );
+        assert_ne!(fingerprint("ICONST 1"), fingerprint("ICONST 2"));
+    }
+
+    #[test]
+    fn programs_without_bytecode() {
+        let program = assemble::program("ICONST -9223372036854775808").unwrap();
+        assert!(matches!(
+            Fingerprint::of(&program),
+            Err(BytecodeWriteError::Overflow { index: 0, .. })
+        ));
+    }
+}
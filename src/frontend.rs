@@ -0,0 +1,1028 @@
+// EXPERIMENTAL: A surface syntax that's quicker to write test programs in than
+// the IR, and lowers to it. It looks like this:
+//
+//     var total = 0;
+//
+//     fn square(n) {
+//         return n * n;
+//     }
+//
+//     var i = 1;
+//     while i <= 10 {
+//         total = total + square(i);
+//         i = i + 1;
+//     }
+//     print total;
+//
+// Values are integers, except for string literals, which can be printed and
+// stored in globals. `print` prints a string if it's given a string literal or
+// a global that was declared with one, and an integer otherwise. `||` and `&&`
+// are OR and AND, so both sides are always evaluated. Variables declared
+// without a value start at 0. `//` starts a comment.
+//
+// Globals are declared at the top level, and locals anywhere in a function.
+// Locals are visible from their declaration to the end of the function.
+
+use std::{collections::HashMap, fmt};
+
+use nom::{
+    branch::alt,
+    bytes::complete::{tag, take_while},
+    character::complete::{char as nom_char, digit1, multispace1, not_line_ending, satisfy},
+    combinator::{all_consuming, cut, eof, map, map_res, not, opt, recognize, value, verify},
+    error::{context, convert_error, VerboseError},
+    multi::{many0, many0_count, separated_list0},
+    sequence::{delimited, pair, preceded, terminated, tuple},
+    IResult,
+};
+
+use crate::{
+    assemble::string_literal,
+    ir_definition::{Attribute, Instruction, Intrinsic, Label},
+};
+
+type ParseResult<'a, O> = IResult<&'a str, O, VerboseError<&'a str>>;
+
+/// The IR for a surface program, and the (1-based) line of the surface
+/// program that each instruction came from.
+#[derive(Debug, PartialEq)]
+pub struct Lowered {
+    pub program: Vec<Instruction>,
+    pub lines: Vec<usize>,
+}
+
+impl Lowered {
+    /// Describes a problem found with the instruction at `index`, like a
+    /// verifier problem or a trap, at the surface line the instruction came
+    /// from, the way errors from `lower` are. `source` has to be the surface
+    /// program that was lowered.
+    pub fn describe(&self, source: &str, index: usize, message: impl fmt::Display) -> String {
+        match self.lines.get(index) {
+            Some(&line) => {
+                let line_text = source.lines().nth(line - 1).unwrap_or("");
+                format!("at line {line}: {message}\n{line_text}\n")
+            }
+            None => format!("{message}\n"),
+        }
+    }
+
+    /// The program in the textual IR format, with a comment giving the
+    /// surface line before each run of instructions that came from it.
+    pub fn to_text(&self) -> String {
+        let mut text = String::new();
+        let mut last_line = None;
+        for (instruction, &line) in self.program.iter().zip(&self.lines) {
+            if last_line != Some(line) {
+                text.push_str(&format!("# line {line}\n"));
+                last_line = Some(line);
+            }
+            text.push_str(&format!("{instruction}\n"));
+        }
+        text
+    }
+}
+
+/// Lowers a program in the surface syntax to the IR. Errors are described in
+/// terms of the surface program, ready to show to whoever wrote it.
+pub fn lower(source: &str) -> Result<Lowered, String> {
+    let items = match program(source) {
+        Ok((_, items)) => items,
+        Err(nom::Err::Error(error) | nom::Err::Failure(error)) => {
+            return Err(convert_error(source, error))
+        }
+        Err(nom::Err::Incomplete(_)) => unreachable!("Complete parsers asked for more input."),
+    };
+    let mut lowerer = Lowerer {
+        source,
+        functions: HashMap::new(),
+        globals: HashMap::new(),
+        scope: None,
+        lowered: Lowered {
+            program: Vec::new(),
+            lines: Vec::new(),
+        },
+        line: 1,
+        next_label: 0,
+    };
+    lowerer.program(&items)?;
+    Ok(lowerer.lowered)
+}
+
+// The syntax tree. Every node keeps the input that starts with it (`at`), so
+// errors and line numbers can point back into the source.
+
+struct Expression<'a> {
+    at: &'a str,
+    kind: ExpressionKind<'a>,
+}
+
+enum ExpressionKind<'a> {
+    Integer(i64),
+    String(String),
+    Variable(&'a str),
+    Call(&'a str, Vec<Expression<'a>>),
+    Negate(Box<Expression<'a>>),
+    Not(Box<Expression<'a>>),
+    Binary(BinaryOperator, Box<Expression<'a>>, Box<Expression<'a>>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum BinaryOperator {
+    Or,
+    And,
+    Bor,
+    Xor,
+    Band,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Mod,
+}
+
+struct Statement<'a> {
+    at: &'a str,
+    kind: StatementKind<'a>,
+}
+
+enum StatementKind<'a> {
+    Var(&'a str, Option<Expression<'a>>),
+    Assign(&'a str, Expression<'a>),
+    Print(Expression<'a>),
+    If(Expression<'a>, Vec<Statement<'a>>, Vec<Statement<'a>>),
+    While(Expression<'a>, Vec<Statement<'a>>),
+    Return(Expression<'a>),
+    Exit(Expression<'a>),
+    Expression(Expression<'a>),
+}
+
+struct Function<'a> {
+    at: &'a str,
+    name: &'a str,
+    params: Vec<&'a str>,
+    body: Vec<Statement<'a>>,
+}
+
+enum Item<'a> {
+    Function(Function<'a>),
+    Statement(Statement<'a>),
+}
+
+// Parsing. Every parser skips the whitespace before what it parses, but not
+// after.
+
+fn ws(input: &str) -> ParseResult<'_, ()> {
+    value(
+        (),
+        many0_count(alt((multispace1, preceded(tag("//"), not_line_ending)))),
+    )(input)
+}
+
+fn symbol<'a>(text: &'static str) -> impl FnMut(&'a str) -> ParseResult<'a, &'a str> {
+    preceded(ws, tag(text))
+}
+
+fn is_name_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_'
+}
+
+fn keyword<'a>(word: &'static str) -> impl FnMut(&'a str) -> ParseResult<'a, &'a str> {
+    preceded(ws, terminated(tag(word), not(satisfy(is_name_char))))
+}
+
+const KEYWORDS: [&str; 8] = [
+    "var", "fn", "if", "else", "while", "return", "print", "exit",
+];
+
+fn name(input: &str) -> ParseResult<'_, &str> {
+    preceded(
+        ws,
+        context(
+            "a name",
+            verify(
+                recognize(pair(
+                    satisfy(|c| c.is_ascii_alphabetic() || c == '_'),
+                    take_while(is_name_char),
+                )),
+                |name: &str| !KEYWORDS.contains(&name),
+            ),
+        ),
+    )(input)
+}
+
+// From the loosest binding to the tightest.
+const PRECEDENCE: [&[(&str, BinaryOperator)]; 9] = [
+    &[("||", BinaryOperator::Or)],
+    &[("&&", BinaryOperator::And)],
+    &[("|", BinaryOperator::Bor)],
+    &[("^", BinaryOperator::Xor)],
+    &[("&", BinaryOperator::Band)],
+    &[("==", BinaryOperator::Eq), ("!=", BinaryOperator::Ne)],
+    &[
+        ("<=", BinaryOperator::Le),
+        (">=", BinaryOperator::Ge),
+        ("<", BinaryOperator::Lt),
+        (">", BinaryOperator::Gt),
+    ],
+    &[("+", BinaryOperator::Add), ("-", BinaryOperator::Sub)],
+    &[
+        ("*", BinaryOperator::Mul),
+        ("/", BinaryOperator::Div),
+        ("%", BinaryOperator::Mod),
+    ],
+];
+
+// The longest operator `input` starts with, and its precedence level, so that
+// `||` is never read as two `|`s.
+fn operator(input: &str) -> Option<(usize, &'static str, BinaryOperator)> {
+    PRECEDENCE
+        .iter()
+        .enumerate()
+        .flat_map(|(level, operators)| {
+            operators
+                .iter()
+                .map(move |&(symbol, operator)| (level, symbol, operator))
+        })
+        .filter(|(_, symbol, _)| input.starts_with(symbol))
+        .max_by_key(|(_, symbol, _)| symbol.len())
+}
+
+fn expression(input: &str) -> ParseResult<'_, Expression<'_>> {
+    binary(0, input)
+}
+
+fn binary<'a>(level: usize, input: &'a str) -> ParseResult<'a, Expression<'a>> {
+    if level == PRECEDENCE.len() {
+        return unary(input);
+    }
+    let (mut input, mut left) = binary(level + 1, input)?;
+    loop {
+        let (rest, _) = ws(input)?;
+        let Some((_, symbol, operator)) = operator(rest).filter(|&(at, ..)| at == level) else {
+            return Ok((input, left));
+        };
+        let (rest, right) = cut(|input: &'a str| binary(level + 1, input))(&rest[symbol.len()..])?;
+        left = Expression {
+            at: left.at,
+            kind: ExpressionKind::Binary(operator, Box::new(left), Box::new(right)),
+        };
+        input = rest;
+    }
+}
+
+fn unary(input: &str) -> ParseResult<'_, Expression<'_>> {
+    let (input, _) = ws(input)?;
+    let at = input;
+    alt((
+        map(preceded(nom_char('-'), unary), move |operand| Expression {
+            at,
+            kind: ExpressionKind::Negate(Box::new(operand)),
+        }),
+        map(preceded(nom_char('!'), unary), move |operand| Expression {
+            at,
+            kind: ExpressionKind::Not(Box::new(operand)),
+        }),
+        primary,
+    ))(input)
+}
+
+fn primary(input: &str) -> ParseResult<'_, Expression<'_>> {
+    let (input, _) = ws(input)?;
+    let at = input;
+    let arguments = delimited(
+        symbol("("),
+        separated_list0(symbol(","), expression),
+        cut(symbol(")")),
+    );
+    context(
+        "an expression",
+        alt((
+            map(map_res(digit1, str::parse), move |i| Expression {
+                at,
+                kind: ExpressionKind::Integer(i),
+            }),
+            map(string_literal, move |text| Expression {
+                at,
+                kind: ExpressionKind::String(text),
+            }),
+            map(pair(name, opt(arguments)), move |(name, arguments)| {
+                let kind = match arguments {
+                    Some(arguments) => ExpressionKind::Call(name, arguments),
+                    None => ExpressionKind::Variable(name),
+                };
+                Expression { at, kind }
+            }),
+            delimited(symbol("("), expression, cut(symbol(")"))),
+        )),
+    )(input)
+}
+
+fn block(input: &str) -> ParseResult<'_, Vec<Statement<'_>>> {
+    preceded(
+        symbol("{"),
+        cut(terminated(many0(statement), context("a '}'", symbol("}")))),
+    )(input)
+}
+
+fn if_statement(input: &str) -> ParseResult<'_, Statement<'_>> {
+    let (input, _) = ws(input)?;
+    let at = input;
+    let (rest, (condition, then, otherwise)) = preceded(
+        keyword("if"),
+        cut(tuple((
+            expression,
+            block,
+            opt(preceded(
+                keyword("else"),
+                cut(alt((block, map(if_statement, |statement| vec![statement])))),
+            )),
+        ))),
+    )(input)?;
+    let kind = StatementKind::If(condition, then, otherwise.unwrap_or_default());
+    Ok((rest, Statement { at, kind }))
+}
+
+fn statement(input: &str) -> ParseResult<'_, Statement<'_>> {
+    let (input, _) = ws(input)?;
+    let at = input;
+    let semicolon = || context("a ';'", cut(symbol(";")));
+    let (rest, kind) = alt((
+        map(
+            preceded(
+                keyword("var"),
+                cut(pair(
+                    name,
+                    terminated(opt(preceded(symbol("="), expression)), semicolon()),
+                )),
+            ),
+            |(name, value)| StatementKind::Var(name, value),
+        ),
+        map(
+            preceded(keyword("print"), cut(terminated(expression, semicolon()))),
+            StatementKind::Print,
+        ),
+        map(if_statement, |statement| statement.kind),
+        map(
+            preceded(keyword("while"), cut(pair(expression, block))),
+            |(condition, body)| StatementKind::While(condition, body),
+        ),
+        map(
+            preceded(keyword("return"), cut(terminated(expression, semicolon()))),
+            StatementKind::Return,
+        ),
+        map(
+            preceded(keyword("exit"), cut(terminated(expression, semicolon()))),
+            StatementKind::Exit,
+        ),
+        map(
+            pair(
+                name,
+                preceded(
+                    terminated(symbol("="), not(nom_char('='))),
+                    cut(terminated(expression, semicolon())),
+                ),
+            ),
+            |(name, value)| StatementKind::Assign(name, value),
+        ),
+        map(
+            terminated(expression, semicolon()),
+            StatementKind::Expression,
+        ),
+    ))(input)?;
+    Ok((rest, Statement { at, kind }))
+}
+
+fn function(input: &str) -> ParseResult<'_, Function<'_>> {
+    let (input, _) = ws(input)?;
+    let at = input;
+    let (rest, (name, params, body)) = preceded(
+        keyword("fn"),
+        cut(tuple((
+            name,
+            delimited(
+                symbol("("),
+                separated_list0(symbol(","), name),
+                context("a ')'", symbol(")")),
+            ),
+            block,
+        ))),
+    )(input)?;
+    Ok((
+        rest,
+        Function {
+            at,
+            name,
+            params,
+            body,
+        },
+    ))
+}
+
+fn program(input: &str) -> ParseResult<'_, Vec<Item<'_>>> {
+    all_consuming(terminated(
+        many0(alt((
+            map(function, Item::Function),
+            map(statement, Item::Statement),
+        ))),
+        pair(ws, context("a statement or function", eof)),
+    ))(input)
+}
+
+// Lowering.
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Kind {
+    Integer,
+    String,
+}
+
+struct Scope<'a> {
+    arglocals: HashMap<&'a str, u64>,
+    num_args: u64,
+    num_locs: u64,
+}
+
+struct Lowerer<'a> {
+    source: &'a str,
+    /// Every function's number of parameters.
+    functions: HashMap<&'a str, u64>,
+    globals: HashMap<&'a str, Kind>,
+    /// The function being lowered, if any.
+    scope: Option<Scope<'a>>,
+    lowered: Lowered,
+    /// The line that instructions are currently coming from.
+    line: usize,
+    next_label: usize,
+}
+
+type LowerResult = Result<(), String>;
+
+impl<'a> Lowerer<'a> {
+    fn error(&self, at: &str, message: &str) -> String {
+        let offset = self.source.len() - at.len();
+        let before = &self.source[..offset];
+        let line_start = before.rfind('\n').map_or(0, |newline| newline + 1);
+        let line_text = self.source[line_start..].lines().next().unwrap_or("");
+        let column = before[line_start..].chars().count() + 1;
+        format!(
+            "at line {}, column {column}: {message}\n{line_text}\n{:>column$}\n",
+            self.line_of(at),
+            "^"
+        )
+    }
+
+    fn line_of(&self, at: &str) -> usize {
+        let offset = self.source.len() - at.len();
+        self.source[..offset].matches('\n').count() + 1
+    }
+
+    fn emit(&mut self, instruction: Instruction) {
+        self.lowered.program.push(instruction);
+        self.lowered.lines.push(self.line);
+    }
+
+    fn fresh_label(&mut self) -> Label {
+        // Names in the surface syntax can't have a '$' in them, so these
+        // can't clash with functions.
+        let label = Label::named(&format!("$L{}", self.next_label));
+        self.next_label += 1;
+        label
+    }
+
+    fn program(&mut self, items: &[Item<'a>]) -> LowerResult {
+        // Functions can be called, and globals used, before they're declared,
+        // so find them all first.
+        for item in items {
+            match item {
+                Item::Function(function) => {
+                    let num_params = function.params.len() as u64;
+                    if self.functions.insert(function.name, num_params).is_some() {
+                        let message = format!("{} is already a function", function.name);
+                        return Err(self.error(function.at, &message));
+                    }
+                }
+                Item::Statement(Statement {
+                    at,
+                    kind: StatementKind::Var(name, value),
+                }) => {
+                    let kind = match value {
+                        Some(Expression {
+                            kind: ExpressionKind::String(_),
+                            ..
+                        }) => Kind::String,
+                        _ => Kind::Integer,
+                    };
+                    if self.globals.insert(name, kind).is_some() {
+                        return Err(self.error(at, &format!("{name} is already declared")));
+                    }
+                }
+                Item::Statement(_) => {}
+            }
+        }
+
+        // Globals are reserved before anything else runs, so that functions
+        // can use them whenever they're called.
+        for item in items {
+            if let Item::Statement(Statement {
+                at,
+                kind: StatementKind::Var(name, value),
+            }) = item
+            {
+                self.line = self.line_of(at);
+                let reservation = match value {
+                    Some(Expression {
+                        kind: ExpressionKind::String(text),
+                        ..
+                    }) => Instruction::ReserveString {
+                        size: text.len() as u64 + 1,
+                        name: name.to_string(),
+                        initial_value: text.clone(),
+                    },
+                    _ => Instruction::ReserveInt {
+                        name: name.to_string(),
+                    },
+                };
+                self.emit(reservation);
+            }
+        }
+
+        // Like front-ends do, jump over the functions to the top-level code.
+        let has_functions = items.iter().any(|item| matches!(item, Item::Function(_)));
+        let main = has_functions.then(|| self.fresh_label());
+        self.line = 1;
+        if let Some(main) = &main {
+            self.emit(Instruction::Jump(main.clone()));
+        }
+        for item in items {
+            if let Item::Function(function) = item {
+                self.function(function)?;
+            }
+        }
+        if let Some(main) = main {
+            self.emit(Instruction::Label(main));
+        }
+        for item in items {
+            if let Item::Statement(statement) = item {
+                self.statement(statement)?;
+            }
+        }
+        self.line = self.line_of(&self.source[self.source.len()..]);
+        self.emit(Instruction::Iconst(0));
+        self.emit(Instruction::Intrinsic(Intrinsic::Exit));
+        Ok(())
+    }
+
+    fn function(&mut self, function: &Function<'a>) -> LowerResult {
+        self.line = self.line_of(function.at);
+        let mut arglocals = HashMap::new();
+        for (index, param) in function.params.iter().enumerate() {
+            if arglocals.insert(*param, index as u64).is_some() {
+                let message = format!("{} has two parameters called {param}", function.name);
+                return Err(self.error(function.at, &message));
+            }
+        }
+        self.scope = Some(Scope {
+            arglocals,
+            num_args: function.params.len() as u64,
+            num_locs: 0,
+        });
+
+        let function_index = self.lowered.program.len();
+        self.emit(Instruction::Function {
+            label: Label::named(function.name),
+            num_locs: 0,
+            attributes: Vec::new(),
+        });
+        for statement in &function.body {
+            self.statement(statement)?;
+        }
+        if !matches!(
+            function.body.last(),
+            Some(Statement {
+                kind: StatementKind::Return(_),
+                ..
+            })
+        ) {
+            self.emit(Instruction::Iconst(0));
+            self.emit(Instruction::Ret);
+        }
+
+        let scope = self.scope.take().expect("The scope was set above.");
+        self.lowered.program[function_index] = Instruction::Function {
+            label: Label::named(function.name),
+            num_locs: scope.num_locs,
+            attributes: vec![Attribute::args(scope.num_args)],
+        };
+        Ok(())
+    }
+
+    fn statement(&mut self, statement: &Statement<'a>) -> LowerResult {
+        self.line = self.line_of(statement.at);
+        match &statement.kind {
+            StatementKind::Var(name, value) => {
+                if self.scope.is_some() {
+                    self.local(name, value.as_ref(), statement.at)?;
+                } else if self.globals[name] == Kind::Integer {
+                    // String globals get their value when they're reserved.
+                    self.value_or_zero(value.as_ref())?;
+                    self.emit(Instruction::Write(name.to_string()));
+                }
+            }
+            StatementKind::Assign(name, value) => {
+                self.expression(value)?;
+                self.line = self.line_of(statement.at);
+                let write = self.write(name, statement.at)?;
+                self.emit(write);
+            }
+            StatementKind::Print(value) => {
+                self.expression(value)?;
+                let intrinsic = match self.kind(value) {
+                    Kind::Integer => Intrinsic::PrintInt,
+                    Kind::String => Intrinsic::PrintString,
+                };
+                self.emit(Instruction::Intrinsic(intrinsic));
+            }
+            StatementKind::If(condition, then, otherwise) => {
+                let (otherwise_label, end) = (self.fresh_label(), self.fresh_label());
+                self.expression(condition)?;
+                if otherwise.is_empty() {
+                    self.emit(Instruction::BranchZero(end.clone()));
+                    self.statements(then)?;
+                } else {
+                    self.emit(Instruction::BranchZero(otherwise_label.clone()));
+                    self.statements(then)?;
+                    self.emit(Instruction::Jump(end.clone()));
+                    self.emit(Instruction::Label(otherwise_label));
+                    self.statements(otherwise)?;
+                }
+                self.emit(Instruction::Label(end));
+            }
+            StatementKind::While(condition, body) => {
+                let (top, end) = (self.fresh_label(), self.fresh_label());
+                self.emit(Instruction::Label(top.clone()));
+                self.expression(condition)?;
+                self.emit(Instruction::BranchZero(end.clone()));
+                self.statements(body)?;
+                self.line = self.line_of(statement.at);
+                self.emit(Instruction::Jump(top));
+                self.emit(Instruction::Label(end));
+            }
+            StatementKind::Return(value) => {
+                if self.scope.is_none() {
+                    return Err(self.error(statement.at, "return outside of a function"));
+                }
+                self.expression(value)?;
+                self.emit(Instruction::Ret);
+            }
+            StatementKind::Exit(value) => {
+                self.expression(value)?;
+                self.emit(Instruction::Intrinsic(Intrinsic::Exit));
+            }
+            StatementKind::Expression(value) => {
+                self.expression(value)?;
+                // Discard the value, like front-ends do.
+                self.emit(Instruction::Pop { reg: -1 });
+            }
+        }
+        Ok(())
+    }
+
+    fn statements(&mut self, statements: &[Statement<'a>]) -> LowerResult {
+        for statement in statements {
+            if self.scope.is_none() {
+                if let StatementKind::Var(..) = statement.kind {
+                    let message = "globals have to be declared at the top level";
+                    return Err(self.error(statement.at, message));
+                }
+            }
+            self.statement(statement)?;
+        }
+        Ok(())
+    }
+
+    fn local(&mut self, name: &'a str, value: Option<&Expression<'a>>, at: &str) -> LowerResult {
+        let scope = self
+            .scope
+            .as_ref()
+            .expect("Locals are only declared in functions.");
+        if scope.arglocals.contains_key(name) {
+            return Err(self.error(at, &format!("{name} is already declared")));
+        }
+        let index = scope.num_args + scope.num_locs;
+        // Declared after its value, so `var x = x;` reads a global.
+        self.value_or_zero(value)?;
+        let scope = self
+            .scope
+            .as_mut()
+            .expect("Locals are only declared in functions.");
+        scope.num_locs += 1;
+        scope.arglocals.insert(name, index);
+        self.emit(Instruction::ArgLocalWrite(index));
+        Ok(())
+    }
+
+    fn value_or_zero(&mut self, value: Option<&Expression<'a>>) -> LowerResult {
+        match value {
+            Some(value) => self.expression(value),
+            None => {
+                self.emit(Instruction::Iconst(0));
+                Ok(())
+            }
+        }
+    }
+
+    fn read(&self, name: &str, at: &str) -> Result<Instruction, String> {
+        if let Some(&index) = self
+            .scope
+            .as_ref()
+            .and_then(|scope| scope.arglocals.get(name))
+        {
+            Ok(Instruction::ArgLocalRead(index))
+        } else if self.globals.contains_key(name) {
+            Ok(Instruction::Read(name.to_string()))
+        } else {
+            Err(self.error(at, &format!("there's no variable called {name}")))
+        }
+    }
+
+    fn write(&self, name: &str, at: &str) -> Result<Instruction, String> {
+        Ok(match self.read(name, at)? {
+            Instruction::ArgLocalRead(index) => Instruction::ArgLocalWrite(index),
+            _ => Instruction::Write(name.to_string()),
+        })
+    }
+
+    fn kind(&self, expression: &Expression) -> Kind {
+        match &expression.kind {
+            ExpressionKind::String(_) => Kind::String,
+            ExpressionKind::Variable(name) => {
+                let is_local = self
+                    .scope
+                    .as_ref()
+                    .is_some_and(|scope| scope.arglocals.contains_key(name));
+                match self.globals.get(name) {
+                    Some(&kind) if !is_local => kind,
+                    _ => Kind::Integer,
+                }
+            }
+            _ => Kind::Integer,
+        }
+    }
+
+    fn expression(&mut self, expression: &Expression<'a>) -> LowerResult {
+        self.line = self.line_of(expression.at);
+        match &expression.kind {
+            ExpressionKind::Integer(i) => self.emit(Instruction::Iconst(*i)),
+            ExpressionKind::String(text) => self.emit(Instruction::Sconst(text.clone())),
+            ExpressionKind::Variable(name) => {
+                let read = self.read(name, expression.at)?;
+                self.emit(read);
+            }
+            ExpressionKind::Call(name, arguments) => {
+                let Some(&num_params) = self.functions.get(name) else {
+                    return Err(
+                        self.error(expression.at, &format!("there's no function called {name}"))
+                    );
+                };
+                if num_params != arguments.len() as u64 {
+                    let message = format!(
+                        "{name} takes {num_params} arguments, but is given {}",
+                        arguments.len()
+                    );
+                    return Err(self.error(expression.at, &message));
+                }
+                // The slot the return value ends up in.
+                self.emit(Instruction::Iconst(42));
+                for argument in arguments {
+                    self.expression(argument)?;
+                }
+                self.line = self.line_of(expression.at);
+                self.emit(Instruction::Call {
+                    label: Label::named(name),
+                    num_args: num_params,
+                });
+            }
+            ExpressionKind::Negate(operand) => {
+                self.emit(Instruction::Iconst(0));
+                self.expression(operand)?;
+                self.emit(Instruction::Sub);
+            }
+            ExpressionKind::Not(operand) => {
+                self.expression(operand)?;
+                self.emit(Instruction::Not);
+            }
+            ExpressionKind::Binary(operator, left, right) => {
+                self.expression(left)?;
+                self.expression(right)?;
+                self.line = self.line_of(expression.at);
+                let (instruction, negated) = match operator {
+                    BinaryOperator::Or => (Instruction::Or, false),
+                    BinaryOperator::And => (Instruction::And, false),
+                    BinaryOperator::Bor => (Instruction::Bor, false),
+                    BinaryOperator::Xor => (Instruction::Xor, false),
+                    BinaryOperator::Band => (Instruction::Band, false),
+                    BinaryOperator::Eq => (Instruction::Eq, false),
+                    BinaryOperator::Ne => (Instruction::Eq, true),
+                    BinaryOperator::Lt => (Instruction::Lt, false),
+                    BinaryOperator::Le => (Instruction::Gt, true),
+                    BinaryOperator::Gt => (Instruction::Gt, false),
+                    BinaryOperator::Ge => (Instruction::Lt, true),
+                    BinaryOperator::Add => (Instruction::Add, false),
+                    BinaryOperator::Sub => (Instruction::Sub, false),
+                    BinaryOperator::Mul => (Instruction::Mul, false),
+                    BinaryOperator::Div => (Instruction::Div, false),
+                    BinaryOperator::Mod => (Instruction::Mod, false),
+                };
+                self.emit(instruction);
+                if negated {
+                    self.emit(Instruction::Not);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        assemble,
+        interpret_rust::interpret,
+        termination::{analyze_loops, Verdict},
+        verify::division::check_division,
+    };
+
+    fn lowered_text(source: &str) -> String {
+        lower(source)
+            .unwrap()
+            .program
+            .iter()
+            .map(|instruction| format!("{instruction}\n"))
+            .collect()
+    }
+
+    #[test]
+    fn top_level_code() {
+        assert_eq!(
+            lowered_text("var x = 1 + 2 * 3;\nprint x;\nprint \"hi\";"),
+            "RESERVE x 4 (null)\n\
+             ICONST 1\nICONST 2\nICONST 3\nMUL\nADD\nWRITE x\n\
+             READ x\nINTRINSIC PRINT_INT\n\
+             SCONST \"hi\"\nINTRINSIC PRINT_STRING\n\
+             ICONST 0\nINTRINSIC EXIT\n"
+        );
+    }
+
+    #[test]
+    fn functions_and_calls() {
+        assert_eq!(
+            lowered_text(
+                "fn add(a, b) { var sum = a + b; return sum; }
+                 print add(1, -2);"
+            ),
+            "JUMP $L0\n\
+             @args(2) FUNCTION add 1\n\
+             ARGLOCAL_READ 0\nARGLOCAL_READ 1\nADD\nARGLOCAL_WRITE 2\n\
+             ARGLOCAL_READ 2\nRET\n\
+             $L0:\n\
+             ICONST 42\nICONST 1\nICONST 0\nICONST 2\nSUB\nCALL add 2\n\
+             INTRINSIC PRINT_INT\n\
+             ICONST 0\nINTRINSIC EXIT\n"
+        );
+    }
+
+    #[test]
+    fn control_flow() {
+        assert_eq!(
+            lowered_text(
+                "var i = 0;
+                 while i < 3 {
+                     if i != 1 { print i; } else { i; }
+                     i = i + 1;
+                 }"
+            ),
+            "RESERVE i 4 (null)\nICONST 0\nWRITE i\n\
+             $L0:\nREAD i\nICONST 3\nLT\nBRANCHZERO $L1\n\
+             READ i\nICONST 1\nEQ\nNOT\nBRANCHZERO $L2\n\
+             READ i\nINTRINSIC PRINT_INT\nJUMP $L3\n\
+             $L2:\nREAD i\nPOP -1\n$L3:\n\
+             READ i\nICONST 1\nADD\nWRITE i\n\
+             JUMP $L0\n$L1:\n\
+             ICONST 0\nINTRINSIC EXIT\n"
+        );
+    }
+
+    #[test]
+    fn string_globals() {
+        assert_eq!(
+            lowered_text("var greeting = \"hello\";\nprint greeting;"),
+            "RESERVE greeting 6 \"hello\"\n\
+             READ greeting\nINTRINSIC PRINT_STRING\n\
+             ICONST 0\nINTRINSIC EXIT\n"
+        );
+    }
+
+    #[test]
+    fn operators_bind_like_c() {
+        let text = lowered_text("print 1 || 2 && 3 | 4 ^ 5 & 6 == 7 <= 8 - 9 % 10;");
+        let operators: Vec<_> = text
+            .lines()
+            .filter(|line| !line.starts_with("ICONST") && !line.starts_with("INTRINSIC"))
+            .collect();
+        assert_eq!(
+            operators,
+            ["MOD", "SUB", "GT", "NOT", "EQ", "BAND", "XOR", "BOR", "AND", "OR"]
+        );
+    }
+
+    #[test]
+    fn lowered_programs_assemble() {
+        let lowered = lower(
+            "var total = 0;
+             fn square(n) { return n * n; }
+             var i = 1;
+             while i <= 10 {
+                 total = total + square(i);
+                 i = i + 1;
+             }
+             print total;",
+        )
+        .unwrap();
+        assert_eq!(
+            assemble::program(&lowered.to_text()).unwrap(),
+            lowered.program
+        );
+    }
+
+    #[test]
+    fn lines() {
+        let lowered = lower("var x;\n\nx = 2;\nprint x;").unwrap();
+        assert_eq!(lowered.lines, vec![1, 1, 1, 3, 3, 4, 4, 4, 4]);
+        assert!(lowered
+            .to_text()
+            .starts_with("# line 1\nRESERVE x 4 (null)\n"));
+    }
+
+    #[test]
+    fn problems_point_into_the_source() {
+        let source = "var x = 1;\nfn f(a) {\n    return a / 0;\n}\nprint f(x);";
+        let lowered = lower(source).unwrap();
+        let problems = check_division(&lowered.program);
+        assert_eq!(
+            lowered.describe(source, problems[0].index(), "it divides by zero"),
+            "at line 3: it divides by zero\n    return a / 0;\n"
+        );
+        let error = interpret(&lowered.program).unwrap_err();
+        assert_eq!(
+            lowered.describe(source, error.index, &error.trap),
+            format!("at line 3: {}\n    return a / 0;\n", error.trap)
+        );
+    }
+
+    #[test]
+    fn counting_loops_terminate() {
+        let lowered = lower("var i = 0;\nwhile i < 10 { print i; i = i + 1; }").unwrap();
+        let reports = analyze_loops(&lowered.program);
+        assert_eq!(reports[0].verdict, Verdict::Terminates);
+        assert_eq!(lowered.lines[*reports[0].body.start()], 2);
+    }
+
+    #[test]
+    fn errors_point_into_the_source() {
+        assert_eq!(
+            lower("var x = 1;\nprint y + x;").unwrap_err(),
+            "at line 2, column 7: there's no variable called y\nprint y + x;\n      ^\n"
+        );
+        assert!(lower("fn f(a) { return a; }\nf(1, 2);")
+            .unwrap_err()
+            .starts_with("at line 2, column 1: f takes 1 arguments, but is given 2"));
+        assert!(lower("return 1;")
+            .unwrap_err()
+            .contains("return outside of a function"));
+        assert!(lower("if 1 { var x; }")
+            .unwrap_err()
+            .contains("globals have to be declared at the top level"));
+        assert!(lower("var x; var x;")
+            .unwrap_err()
+            .contains("x is already declared"));
+        assert!(lower("g();")
+            .unwrap_err()
+            .contains("there's no function called g"));
+    }
+
+    #[test]
+    fn syntax_errors_point_into_the_source() {
+        let error = lower("var x = 1;\nprint x +;\n").unwrap_err();
+        assert!(error.contains("line 2"), "{error}");
+        assert!(error.contains("an expression"), "{error}");
+        let error = lower("while 1 { print 1;").unwrap_err();
+        assert!(error.contains("a '}'"), "{error}");
+    }
+}
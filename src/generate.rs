@@ -0,0 +1,430 @@
+// Random programs for fuzzing interpreters. Unlike random text, these are
+// programs a front-end could have produced, so they get past the parser and
+// actually exercise the interpreter.
+
+use crate::ir_definition::{Attribute, Instruction, Intrinsic, Label};
+
+/// The knobs for `generate`. The same options always generate the same program.
+#[derive(Debug, Clone)]
+pub struct GeneratorOptions {
+    pub seed: u64,
+    /// How many functions there are, besides the top-level code.
+    pub functions: usize,
+    /// How many statements each function, and the top-level code, starts with.
+    /// Statements nested in ifs and loops come on top of these.
+    pub statements: usize,
+    /// How deeply expressions and statements nest.
+    pub max_depth: usize,
+    /// The most times any one loop runs.
+    pub max_loop_iterations: u64,
+    /// How many integer globals there are.
+    pub globals: usize,
+    pub strings: bool,
+    pub loops: bool,
+    pub calls: bool,
+}
+
+impl Default for GeneratorOptions {
+    fn default() -> Self {
+        GeneratorOptions {
+            seed: 0,
+            functions: 3,
+            statements: 8,
+            max_depth: 3,
+            max_loop_iterations: 5,
+            globals: 2,
+            strings: true,
+            loops: true,
+            calls: true,
+        }
+    }
+}
+
+// SplitMix64. It's tiny, and good enough for picking instructions.
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    }
+
+    /// A number in `0..bound`, which must not be empty.
+    fn below(&mut self, bound: u64) -> u64 {
+        self.next() % bound
+    }
+
+    fn pick<'a, T>(&mut self, choices: &'a [T]) -> &'a T {
+        &choices[self.below(choices.len() as u64) as usize]
+    }
+}
+
+struct FunctionSignature {
+    name: String,
+    num_args: u64,
+}
+
+const BINARY_OPERATIONS: [Instruction; 13] = [
+    Instruction::Add,
+    Instruction::Sub,
+    Instruction::Mul,
+    Instruction::Div,
+    Instruction::Mod,
+    Instruction::Bor,
+    Instruction::Band,
+    Instruction::Xor,
+    Instruction::Or,
+    Instruction::And,
+    Instruction::Eq,
+    Instruction::Lt,
+    Instruction::Gt,
+];
+
+struct Generator<'a> {
+    options: &'a GeneratorOptions,
+    rng: Rng,
+    program: Vec<Instruction>,
+    functions: Vec<FunctionSignature>,
+    /// The globals expressions may read and statements may write. Loop
+    /// counters aren't in here, so nothing but their own loop touches them.
+    globals: Vec<String>,
+    next_label: usize,
+    next_counter: usize,
+}
+
+/// Generates a random program that stays well-formed when run: every
+/// expression leaves exactly one integer on the stack and every statement
+/// leaves the stack as it found it, every label that's jumped to is defined,
+/// every variable is written before it's read, and the program exits with
+/// `EXIT`.
+///
+/// It also always terminates. Loops count a counter of their own down from at
+/// most `max_loop_iterations`, and functions only call functions defined
+/// before them, so there's no recursion. Division and modulo are only ever by
+/// nonzero constants, but arithmetic can overflow.
+pub fn generate(options: &GeneratorOptions) -> Vec<Instruction> {
+    let mut generator = Generator {
+        options,
+        rng: Rng(options.seed),
+        program: Vec::new(),
+        functions: Vec::new(),
+        globals: Vec::new(),
+        next_label: 0,
+        next_counter: 0,
+    };
+    generator.program();
+    generator.program
+}
+
+impl Generator<'_> {
+    fn emit(&mut self, instruction: Instruction) {
+        self.program.push(instruction);
+    }
+
+    fn fresh_label(&mut self) -> Label {
+        let label = Label::named(&format!("L{}", self.next_label));
+        self.next_label += 1;
+        label
+    }
+
+    fn program(&mut self) {
+        for i in 0..self.options.globals {
+            let name = format!("g{i}");
+            self.emit(Instruction::ReserveInt { name: name.clone() });
+            self.constant();
+            self.emit(Instruction::Write(name.clone()));
+            self.globals.push(name);
+        }
+
+        // Loop counters are reserved as their loops are generated, so they
+        // can't all be reserved up front. Instead, the functions are jumped
+        // over, like front-ends do, and the counters are reserved first thing
+        // in the top-level code.
+        let main = self.fresh_label();
+        self.emit(Instruction::Jump(main.clone()));
+        for i in 0..self.options.functions {
+            self.function(format!("f{i}"));
+        }
+        self.emit(Instruction::Label(main));
+        let reservations_at = self.program.len();
+        self.statements(self.options.statements, 0, 0);
+        self.emit(Instruction::Iconst(0));
+        self.emit(Instruction::Intrinsic(Intrinsic::Exit));
+
+        let counters = (0..self.next_counter).map(|i| Instruction::ReserveInt {
+            name: format!("c{i}"),
+        });
+        self.program
+            .splice(reservations_at..reservations_at, counters);
+    }
+
+    fn function(&mut self, name: String) {
+        let num_args = self.rng.below(3);
+        let num_locs = self.rng.below(3);
+        self.emit(Instruction::Function {
+            label: Label::named(&name),
+            num_locs,
+            attributes: vec![Attribute::args(num_args)],
+        });
+        for local in num_args..num_args + num_locs {
+            self.constant();
+            self.emit(Instruction::ArgLocalWrite(local));
+        }
+        let arglocals = num_args + num_locs;
+        self.statements(self.options.statements, 0, arglocals);
+        self.expression(0, arglocals);
+        self.emit(Instruction::Ret);
+        // Only added now, so the function can't call itself.
+        self.functions.push(FunctionSignature { name, num_args });
+    }
+
+    fn statements(&mut self, count: usize, depth: usize, arglocals: u64) {
+        for _ in 0..count {
+            self.statement(depth, arglocals);
+        }
+    }
+
+    fn statement(&mut self, depth: usize, arglocals: u64) {
+        let nested = depth < self.options.max_depth;
+        match self.rng.below(6) {
+            0 if nested => self.if_statement(depth, arglocals),
+            1 if nested && self.options.loops => self.loop_statement(depth, arglocals),
+            2 if self.options.strings => {
+                let text = format!("s{}\n", self.rng.below(100));
+                self.emit(Instruction::Sconst(text));
+                self.emit(Instruction::Intrinsic(Intrinsic::PrintString));
+            }
+            3 if !self.globals.is_empty() => {
+                self.expression(0, arglocals);
+                let global = self.rng.pick(&self.globals).clone();
+                self.emit(Instruction::Write(global));
+            }
+            4 if arglocals > 0 => {
+                self.expression(0, arglocals);
+                let index = self.rng.below(arglocals);
+                self.emit(Instruction::ArgLocalWrite(index));
+            }
+            _ => {
+                self.expression(0, arglocals);
+                self.emit(Instruction::Intrinsic(Intrinsic::PrintInt));
+            }
+        }
+    }
+
+    fn if_statement(&mut self, depth: usize, arglocals: u64) {
+        let (otherwise, end) = (self.fresh_label(), self.fresh_label());
+        self.expression(0, arglocals);
+        self.emit(Instruction::BranchZero(otherwise.clone()));
+        let count = 1 + self.rng.below(2) as usize;
+        self.statements(count, depth + 1, arglocals);
+        self.emit(Instruction::Jump(end.clone()));
+        self.emit(Instruction::Label(otherwise));
+        let count = self.rng.below(2) as usize;
+        self.statements(count, depth + 1, arglocals);
+        self.emit(Instruction::Label(end));
+    }
+
+    fn loop_statement(&mut self, depth: usize, arglocals: u64) {
+        let counter = format!("c{}", self.next_counter);
+        self.next_counter += 1;
+        let (top, end) = (self.fresh_label(), self.fresh_label());
+
+        let iterations = self.rng.below(self.options.max_loop_iterations + 1);
+        self.emit(Instruction::Iconst(iterations as i64));
+        self.emit(Instruction::Write(counter.clone()));
+        self.emit(Instruction::Label(top.clone()));
+        self.emit(Instruction::Read(counter.clone()));
+        self.emit(Instruction::BranchZero(end.clone()));
+        let count = 1 + self.rng.below(2) as usize;
+        self.statements(count, depth + 1, arglocals);
+        self.emit(Instruction::Read(counter.clone()));
+        self.emit(Instruction::Iconst(1));
+        self.emit(Instruction::Sub);
+        self.emit(Instruction::Write(counter));
+        self.emit(Instruction::Jump(top));
+        self.emit(Instruction::Label(end));
+    }
+
+    fn constant(&mut self) {
+        let value = self.rng.below(201) as i64 - 100;
+        self.emit(Instruction::Iconst(value));
+    }
+
+    fn expression(&mut self, depth: usize, arglocals: u64) {
+        let nested = depth < self.options.max_depth;
+        match self.rng.below(6) {
+            0 | 1 if nested => {
+                self.expression(depth + 1, arglocals);
+                let operation = self.rng.pick(&BINARY_OPERATIONS).clone();
+                if matches!(operation, Instruction::Div | Instruction::Mod) {
+                    let divisor = 1 + self.rng.below(9) as i64;
+                    self.emit(Instruction::Iconst(divisor));
+                } else {
+                    self.expression(depth + 1, arglocals);
+                }
+                self.emit(operation);
+            }
+            2 if nested => {
+                self.expression(depth + 1, arglocals);
+                self.emit(Instruction::Not);
+            }
+            3 if nested && self.options.calls && !self.functions.is_empty() => {
+                let callee = self.rng.below(self.functions.len() as u64) as usize;
+                let (name, num_args) = {
+                    let signature = &self.functions[callee];
+                    (signature.name.clone(), signature.num_args)
+                };
+                // The slot the return value ends up in, like front-ends emit.
+                self.emit(Instruction::Iconst(42));
+                for _ in 0..num_args {
+                    self.expression(depth + 1, arglocals);
+                }
+                self.emit(Instruction::Call {
+                    label: Label::named(&name),
+                    num_args,
+                });
+            }
+            4 if !self.globals.is_empty() => {
+                let global = self.rng.pick(&self.globals).clone();
+                self.emit(Instruction::Read(global));
+            }
+            5 if arglocals > 0 => {
+                let index = self.rng.below(arglocals);
+                self.emit(Instruction::ArgLocalRead(index));
+            }
+            _ => self.constant(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{HashMap, HashSet};
+
+    use super::*;
+    use crate::{assemble, print_text::print_text};
+
+    // Checks what `generate` promises about the stack, by walking the program
+    // the way it's laid out: straight-line code, with every jump target
+    // reached with the same stack depth it was first reached with.
+    fn check_stack_depths(program: &[Instruction]) {
+        let mut depth: i64 = 0;
+        let mut depth_at = HashMap::new();
+        for (index, instruction) in program.iter().enumerate() {
+            depth += match instruction {
+                Instruction::Iconst(_) | Instruction::Sconst(_) | Instruction::Read(_) => 1,
+                Instruction::ArgLocalRead(_) => 1,
+                Instruction::Write(_) | Instruction::ArgLocalWrite(_) => -1,
+                Instruction::BranchZero(_) | Instruction::Ret => -1,
+                Instruction::Intrinsic(_) => -1,
+                Instruction::Call { num_args, .. } => -(*num_args as i64),
+                Instruction::Not => 0,
+                Instruction::Function { .. } => {
+                    depth = 0;
+                    0
+                }
+                _ if BINARY_OPERATIONS.contains(instruction) => -1,
+                _ => 0,
+            };
+            assert!(depth >= 0, "stack underflow at {index}: {instruction}");
+            if let Instruction::Jump(label) | Instruction::BranchZero(label) = instruction {
+                let expected = depth_at.entry(label.name().to_string()).or_insert(depth);
+                assert_eq!(*expected, depth, "at {index}: {instruction}");
+            }
+            if let Instruction::Label(label) = instruction {
+                let expected = depth_at.entry(label.name().to_string()).or_insert(depth);
+                assert_eq!(*expected, depth, "at {index}: {instruction}");
+            }
+            if *instruction == Instruction::Ret {
+                assert_eq!(depth, 0, "at {index}: {instruction}");
+            }
+        }
+        assert_eq!(depth, 0);
+    }
+
+    #[test]
+    fn same_seed_same_program() {
+        let options = GeneratorOptions {
+            seed: 7,
+            ..Default::default()
+        };
+        assert_eq!(generate(&options), generate(&options));
+        let other = GeneratorOptions {
+            seed: 8,
+            ..Default::default()
+        };
+        assert_ne!(generate(&options), generate(&other));
+    }
+
+    #[test]
+    fn programs_are_well_formed() {
+        for seed in 0..200 {
+            let options = GeneratorOptions {
+                seed,
+                ..Default::default()
+            };
+            let program = generate(&options);
+
+            let defined: HashSet<_> = program
+                .iter()
+                .filter_map(|instruction| match instruction {
+                    Instruction::Label(label) | Instruction::Function { label, .. } => {
+                        Some(label.name())
+                    }
+                    _ => None,
+                })
+                .collect();
+            for instruction in &program {
+                if let Instruction::Jump(label)
+                | Instruction::BranchZero(label)
+                | Instruction::Call { label, .. } = instruction
+                {
+                    assert!(defined.contains(label.name()), "seed {seed}: {instruction}");
+                }
+            }
+
+            assert_eq!(
+                program.last(),
+                Some(&Instruction::Intrinsic(Intrinsic::Exit)),
+                "seed {seed}"
+            );
+            check_stack_depths(&program);
+
+            // And it survives being printed and parsed.
+            assert_eq!(
+                assemble::program(&print_text(&program)).unwrap(),
+                program,
+                "seed {seed}"
+            );
+        }
+    }
+
+    #[test]
+    fn features_can_be_turned_off() {
+        let options = GeneratorOptions {
+            seed: 3,
+            statements: 40,
+            strings: false,
+            loops: false,
+            calls: false,
+            globals: 0,
+            ..Default::default()
+        };
+        for instruction in generate(&options) {
+            assert!(
+                !matches!(
+                    instruction,
+                    Instruction::Sconst(_)
+                        | Instruction::Call { .. }
+                        | Instruction::Read(_)
+                        | Instruction::Write(_)
+                        | Instruction::ReserveInt { .. }
+                ),
+                "{instruction}"
+            );
+        }
+    }
+}
@@ -0,0 +1,1158 @@
+// An interpreter for programs as `Instruction`s, written in Rust so it can be
+// used as a library: it needs no child process and no C code, and it returns
+// what happened instead of printing it.
+//
+// It's meant to behave like the C interpreter on every program the C
+// interpreter handles well. Where the C interpreter's behaviour is undefined,
+// like popping from an empty stack or dividing by zero, this one traps.
+
+pub mod debugger;
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt,
+};
+
+use crate::{
+    analysis::stack_effect,
+    arithmetic::{self, ArithmeticError, DivisionOverflow},
+    ir_definition::{Instruction, Intrinsic},
+    output_sink::OutputSink,
+    resource_usage::{measure, ResourceUsage},
+    trace::{Event, Tracer},
+};
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum Value {
+    /// Integers are 32 bits wide, like in the bytecode, and arithmetic on them
+    /// wraps.
+    Int(i32),
+    Str(String),
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Value::Int(value) => write!(f, "{value}"),
+            Value::Str(value) => write!(f, "{value:?}"),
+        }
+    }
+}
+
+/// What running a program did.
+#[derive(Debug, PartialEq, Eq)]
+pub struct RunResult {
+    /// Everything the program printed.
+    pub output: String,
+    /// The code the program gave to `INTRINSIC EXIT`, or `None` if it ran off
+    /// the end instead.
+    pub exit_code: Option<i32>,
+    /// What was left on the operand stack, from the bottom up.
+    pub stack: Vec<Value>,
+    /// How many instructions ran in each function that was called, not
+    /// counting the instructions of the functions it called.
+    pub function_steps: BTreeMap<String, u64>,
+    /// How many times each instruction ran, by index.
+    pub instruction_counts: Vec<u64>,
+    /// The time and memory the run took, as `resource_usage::measure` gives
+    /// them, or `None` for a result that didn't come from a run. Unlike
+    /// everything else here, it's different every time, so leave it out when
+    /// comparing runs.
+    pub resource_usage: Option<ResourceUsage>,
+}
+
+/// Limits on how much of its time a program can take, and on what it can do.
+#[derive(Debug, Default, Clone)]
+pub struct RunLimits {
+    /// How many instructions each function may run, counted like
+    /// `RunResult::function_steps`. Functions that aren't here are unlimited,
+    /// which is useful for capping one untrusted function while the code
+    /// around it runs freely.
+    pub function_budgets: HashMap<String, u64>,
+    /// How many bytes the program may print in all, or `None` for no limit.
+    /// The print that would go over it is left out entirely, so what was
+    /// printed before the trap is never more than this.
+    pub max_output: Option<u64>,
+    /// How many instructions the program may run in all, or `None` for no
+    /// limit. This is what stops a program that loops forever.
+    pub max_instructions: Option<u64>,
+    /// How many values the operand stack may hold, counting every function's
+    /// and the arguments and locals of every function that's running, or
+    /// `None` for no limit. The instruction that would go over it traps
+    /// before it runs.
+    pub max_stack_depth: Option<usize>,
+    /// How many calls may be running at once, or `None` for no limit. The
+    /// `CALL` that would go over it traps.
+    pub max_call_depth: Option<usize>,
+    /// What DIV and MOD do with the smallest integer and -1. With
+    /// `DivisionOverflow::Fail`, it traps, where the C interpreter is usually
+    /// killed by SIGFPE.
+    pub division_overflow: DivisionOverflow,
+}
+
+/// A limit from `RunLimits` that a program went over.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum Limit {
+    FunctionBudget { function: String, budget: u64 },
+    Output { max_bytes: u64 },
+    Instructions { max: u64 },
+    StackDepth { max: usize },
+    CallDepth { max: usize },
+}
+
+impl fmt::Display for Limit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Limit::FunctionBudget { function, budget } => {
+                write!(
+                    f,
+                    "{function} ran more than its budget of {budget} instructions"
+                )
+            }
+            Limit::Output { max_bytes } => {
+                write!(f, "the program printed more than {max_bytes} bytes")
+            }
+            Limit::Instructions { max } => {
+                write!(f, "the program ran more than {max} instructions")
+            }
+            Limit::StackDepth { max } => {
+                write!(f, "the stack would have held more than {max} values")
+            }
+            Limit::CallDepth { max } => {
+                write!(f, "calls would have nested more than {max} deep")
+            }
+        }
+    }
+}
+
+/// Why a program was stopped.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum Trap {
+    StackUnderflow,
+    /// The value on top of the stack was the wrong type for the instruction.
+    TypeMismatch {
+        expected: &'static str,
+        found: Value,
+    },
+    DivisionByZero,
+    /// The smallest integer divided by -1, with `RunLimits::division_overflow`
+    /// set to `DivisionOverflow::Fail`.
+    DivisionOverflow,
+    UnknownGlobal(String),
+    UnknownLabel(String),
+    UnknownFunction(String),
+    ArgLocalOutOfRange {
+        index: u64,
+        num_slots: usize,
+    },
+    /// An ARGLOCAL or RET outside of any function.
+    NotInFunction,
+    /// A PUSH from a register nothing was popped into.
+    EmptyRegister(i64),
+    /// A CALL of a function with more locals than there's memory for.
+    FrameTooBig {
+        function: String,
+        num_locs: u64,
+    },
+    LimitExceeded(Limit),
+}
+
+impl fmt::Display for Trap {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Trap::StackUnderflow => write!(f, "the stack is empty"),
+            Trap::TypeMismatch { expected, found } => {
+                write!(f, "expected {expected} on the stack, but found {found}")
+            }
+            Trap::DivisionByZero => write!(f, "division by zero"),
+            Trap::DivisionOverflow => write!(f, "the smallest integer divided by -1 doesn't fit"),
+            Trap::UnknownGlobal(name) => write!(f, "there's no global called {name}"),
+            Trap::UnknownLabel(name) => write!(f, "there's no label called {name}"),
+            Trap::UnknownFunction(name) => write!(f, "there's no function called {name}"),
+            Trap::ArgLocalOutOfRange { index, num_slots } => write!(
+                f,
+                "argument or local {index} doesn't exist; the function only has {num_slots}"
+            ),
+            Trap::NotInFunction => write!(f, "the program isn't inside a function"),
+            Trap::EmptyRegister(reg) => write!(f, "register {reg} is empty"),
+            Trap::FrameTooBig { function, num_locs } => {
+                write!(f, "{function}'s {num_locs} locals don't fit in memory")
+            }
+            Trap::LimitExceeded(limit) => write!(f, "{limit}"),
+        }
+    }
+}
+
+impl From<ArithmeticError> for Trap {
+    fn from(error: ArithmeticError) -> Self {
+        match error {
+            ArithmeticError::DivisionByZero => Trap::DivisionByZero,
+            ArithmeticError::DivisionOverflow => Trap::DivisionOverflow,
+        }
+    }
+}
+
+/// A trap, and where in the program it happened.
+#[derive(Debug, PartialEq, Eq)]
+pub struct RunError {
+    /// The index of the instruction that trapped.
+    pub index: usize,
+    pub trap: Trap,
+    /// The calls that were running, innermost first.
+    pub backtrace: Vec<BacktraceFrame>,
+    /// What the program printed before it trapped.
+    pub output: String,
+}
+
+/// One of the calls that were running when a program stopped.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct BacktraceFrame {
+    /// The function, or `None` for the code outside of functions, which is
+    /// always the last frame.
+    pub function: Option<String>,
+    /// The instruction it was at: the next one to run, for the innermost
+    /// frame, and the CALL it's waiting on, for the others.
+    pub index: usize,
+}
+
+impl fmt::Display for RunError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "at instruction {}: {}", self.index, self.trap)
+    }
+}
+
+impl std::error::Error for RunError {}
+
+struct Frame<'a> {
+    function: &'a str,
+    /// Where to go back to.
+    return_index: usize,
+    /// The arguments, then the locals.
+    slots: Vec<Value>,
+    /// How tall the operand stack was when the function was called, not
+    /// counting the arguments. The top of it is the placeholder for the result.
+    stack_height: usize,
+}
+
+/// What running one instruction did.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum StepResult {
+    /// The program can go on.
+    Continue,
+    /// It ran `INTRINSIC EXIT` with this code.
+    Exited(i32),
+    /// It ran off the end.
+    Finished,
+    /// The instruction trapped, and `Vm::pc` is still where it is.
+    Trapped(Trap),
+}
+
+/// A program being run, one instruction at a time, for looking at what the
+/// machine is like between instructions. `interpret` and the functions like
+/// it run one of these to the end.
+pub struct Vm<'a, S = String, T = ()> {
+    program: &'a [Instruction],
+    /// Where each label and function starts.
+    labels: HashMap<&'a str, usize>,
+    index: usize,
+    stack: Vec<Value>,
+    globals: HashMap<String, Value>,
+    registers: HashMap<i64, Value>,
+    frames: Vec<Frame<'a>>,
+    /// How many slots all of `frames` have, for `RunLimits::max_stack_depth`.
+    slots_held: usize,
+    output: S,
+    tracer: T,
+    /// How many bytes have been printed, for `RunLimits::max_output`.
+    output_len: u64,
+    limits: RunLimits,
+    function_steps: HashMap<&'a str, u64>,
+    instruction_counts: Vec<u64>,
+    /// How many instructions have run, for `RunLimits::max_instructions`.
+    steps: u64,
+    /// How the program stopped, once it has.
+    stopped: Option<StepResult>,
+}
+
+impl<'a> Vm<'a> {
+    /// Ready to run the first instruction of `program`, with no limits, and
+    /// with what the program prints kept in `output`.
+    pub fn new(program: &'a [Instruction]) -> Self {
+        Vm::with_output(program, &RunLimits::default(), String::new(), ())
+    }
+}
+
+impl<'a, S: OutputSink, T: Tracer> Vm<'a, S, T> {
+    /// Like `new`, but with `limits`, with what the program prints going to
+    /// `output`, and with `tracer` told about each instruction before it runs.
+    pub fn with_output(
+        program: &'a [Instruction],
+        limits: &RunLimits,
+        output: S,
+        tracer: T,
+    ) -> Self {
+        let mut labels = HashMap::new();
+        for (index, instruction) in program.iter().enumerate() {
+            if let Instruction::Label(label) | Instruction::Function { label, .. } = instruction {
+                labels.entry(label.name()).or_insert(index);
+            }
+        }
+        Vm {
+            program,
+            labels,
+            index: 0,
+            stack: Vec::new(),
+            globals: HashMap::new(),
+            registers: HashMap::new(),
+            frames: Vec::new(),
+            slots_held: 0,
+            output,
+            tracer,
+            output_len: 0,
+            limits: limits.clone(),
+            function_steps: HashMap::new(),
+            instruction_counts: vec![0; program.len()],
+            steps: 0,
+            stopped: None,
+        }
+    }
+
+    /// The index of the instruction that runs next.
+    pub fn pc(&self) -> usize {
+        self.index
+    }
+
+    /// The operand stack, from the bottom up.
+    pub fn stack(&self) -> &[Value] {
+        &self.stack
+    }
+
+    /// The globals that have been reserved, with their values.
+    pub fn globals(&self) -> &HashMap<String, Value> {
+        &self.globals
+    }
+
+    /// Where what the program prints goes.
+    pub fn output(&self) -> &S {
+        &self.output
+    }
+
+    /// The calls that are running, innermost first.
+    pub fn backtrace(&self) -> Vec<BacktraceFrame> {
+        let mut backtrace = Vec::new();
+        let mut index = self.index;
+        for frame in self.frames.iter().rev() {
+            backtrace.push(BacktraceFrame {
+                function: Some(frame.function.into()),
+                index,
+            });
+            index = frame.return_index - 1;
+        }
+        backtrace.push(BacktraceFrame {
+            function: None,
+            index,
+        });
+        backtrace
+    }
+
+    /// Runs the next instruction. Once the program has stopped, stepping
+    /// again does nothing, and gives how it stopped again.
+    pub fn step(&mut self) -> StepResult {
+        if let Some(stopped) = &self.stopped {
+            return stopped.clone();
+        }
+        let result = self.execute().unwrap_or_else(StepResult::Trapped);
+        if result != StepResult::Continue {
+            self.output.finish();
+            self.tracer.finish();
+            self.stopped = Some(result.clone());
+        }
+        result
+    }
+
+    fn pop(&mut self) -> Result<Value, Trap> {
+        self.stack.pop().ok_or(Trap::StackUnderflow)
+    }
+
+    fn pop_int(&mut self) -> Result<i32, Trap> {
+        match self.pop()? {
+            Value::Int(value) => Ok(value),
+            found => Err(Trap::TypeMismatch {
+                expected: "an integer",
+                found,
+            }),
+        }
+    }
+
+    fn pop_str(&mut self) -> Result<String, Trap> {
+        match self.pop()? {
+            Value::Str(value) => Ok(value),
+            found => Err(Trap::TypeMismatch {
+                expected: "a string",
+                found,
+            }),
+        }
+    }
+
+    fn binary(&mut self, instruction: &Instruction) -> Result<(), Trap> {
+        let b = self.pop_int()?;
+        let a = self.pop_int()?;
+        let overflow = self.limits.division_overflow;
+        let result = arithmetic::binary(instruction, a, b, overflow)
+            .expect("The instruction doesn't take two integers.");
+        self.stack.push(Value::Int(result?));
+        Ok(())
+    }
+
+    fn print(&mut self, text: &str) -> Result<(), Trap> {
+        let output_len = self.output_len + text.len() as u64;
+        if let Some(max_bytes) = self.limits.max_output {
+            if output_len > max_bytes {
+                return Err(Trap::LimitExceeded(Limit::Output { max_bytes }));
+            }
+        }
+        self.output_len = output_len;
+        self.output.print(text);
+        Ok(())
+    }
+
+    fn label(&self, name: &str) -> Result<usize, Trap> {
+        self.labels
+            .get(name)
+            .copied()
+            .ok_or_else(|| Trap::UnknownLabel(name.into()))
+    }
+
+    fn slot(&mut self, index: u64) -> Result<&mut Value, Trap> {
+        let frame = self.frames.last_mut().ok_or(Trap::NotInFunction)?;
+        let num_slots = frame.slots.len();
+        usize::try_from(index)
+            .ok()
+            .and_then(|index| frame.slots.get_mut(index))
+            .ok_or(Trap::ArgLocalOutOfRange { index, num_slots })
+    }
+
+    /// Checks that running `instruction` stays within the limits that apply
+    /// to the whole program.
+    fn check_limits(&mut self, instruction: &Instruction) -> Result<(), Trap> {
+        self.steps += 1;
+        if let Some(max) = self.limits.max_instructions {
+            if self.steps > max {
+                return Err(Trap::LimitExceeded(Limit::Instructions { max }));
+            }
+        }
+        if let Some(max) = self.limits.max_stack_depth {
+            let (pops, pushes) = stack_effect(instruction);
+            if self.stack.len().saturating_sub(pops) + pushes + self.slots_held > max {
+                return Err(Trap::LimitExceeded(Limit::StackDepth { max }));
+            }
+        }
+        if let Some(max) = self.limits.max_call_depth {
+            if let Instruction::Call { .. } = instruction {
+                if self.frames.len() >= max {
+                    return Err(Trap::LimitExceeded(Limit::CallDepth { max }));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Runs the instruction at `self.index`.
+    fn execute(&mut self) -> Result<StepResult, Trap> {
+        let program = self.program;
+        let Some(instruction) = program.get(self.index) else {
+            return Ok(StepResult::Finished);
+        };
+        self.tracer
+            .before(self.index, instruction, &self.stack, self.frames.len());
+        if let Some(frame) = self.frames.last() {
+            let steps = self.function_steps.entry(frame.function).or_default();
+            *steps += 1;
+            if let Some(&budget) = self.limits.function_budgets.get(frame.function) {
+                if *steps > budget {
+                    return Err(Trap::LimitExceeded(Limit::FunctionBudget {
+                        function: frame.function.into(),
+                        budget,
+                    }));
+                }
+            }
+        }
+        self.check_limits(instruction)?;
+        self.instruction_counts[self.index] += 1;
+        let mut next = self.index + 1;
+        match instruction {
+            Instruction::Nop | Instruction::Label(_) | Instruction::Function { .. } => {}
+            Instruction::Iconst(value) => {
+                // The bytecode only has room for 32 bits.
+                self.stack.push(Value::Int(*value as i32))
+            }
+            Instruction::Sconst(value) => self.stack.push(Value::Str(value.clone())),
+            Instruction::Add
+            | Instruction::Sub
+            | Instruction::Mul
+            | Instruction::Div
+            | Instruction::Mod
+            | Instruction::Bor
+            | Instruction::Band
+            | Instruction::Xor
+            | Instruction::Or
+            | Instruction::And
+            | Instruction::Eq
+            | Instruction::Lt
+            | Instruction::Gt => self.binary(instruction)?,
+            Instruction::Not => {
+                let a = self.pop_int()?;
+                self.stack.push(Value::Int((a == 0).into()));
+            }
+            Instruction::ReserveString {
+                name,
+                initial_value,
+                ..
+            } => {
+                self.globals
+                    .insert(name.clone(), Value::Str(initial_value.clone()));
+            }
+            Instruction::ReserveInt { name } => {
+                self.globals.insert(name.clone(), Value::Int(0));
+            }
+            Instruction::Read(name) => {
+                let value = self
+                    .globals
+                    .get(name)
+                    .ok_or_else(|| Trap::UnknownGlobal(name.clone()))?;
+                self.stack.push(value.clone());
+            }
+            Instruction::Write(name) => {
+                let value = self.pop()?;
+                let global = self
+                    .globals
+                    .get_mut(name)
+                    .ok_or_else(|| Trap::UnknownGlobal(name.clone()))?;
+                *global = value;
+                let event = Event::GlobalWrite {
+                    name,
+                    value: global,
+                };
+                self.tracer.event(self.index, event);
+            }
+            Instruction::ArgLocalRead(index) => {
+                let value = self.slot(*index)?.clone();
+                self.stack.push(value);
+            }
+            Instruction::ArgLocalWrite(index) => {
+                let value = self.pop()?;
+                *self.slot(*index)? = value;
+            }
+            Instruction::Jump(label) => next = self.label(label.name())?,
+            Instruction::BranchZero(label) => {
+                if self.pop_int()? == 0 {
+                    next = self.label(label.name())?;
+                }
+            }
+            Instruction::Call { label, num_args } => {
+                let Some(&start) = self.labels.get(label.name()) else {
+                    return Err(Trap::UnknownFunction(label.name().into()));
+                };
+                let Instruction::Function { num_locs, .. } = &self.program[start] else {
+                    return Err(Trap::UnknownFunction(label.name().into()));
+                };
+                // The placeholder for the result has to be there too.
+                let num_args = usize::try_from(*num_args).unwrap_or(usize::MAX);
+                if self.stack.len() <= num_args {
+                    return Err(Trap::StackUnderflow);
+                }
+                // The arguments only move from the stack into the frame, so
+                // the locals are all the call adds.
+                let locals = usize::try_from(*num_locs).unwrap_or(usize::MAX);
+                if let Some(max) = self.limits.max_stack_depth {
+                    let held = (self.stack.len() + self.slots_held).saturating_add(locals);
+                    if held > max {
+                        return Err(Trap::LimitExceeded(Limit::StackDepth { max }));
+                    }
+                }
+                let num_slots = num_args.saturating_add(locals);
+                let mut slots = Vec::new();
+                if slots.try_reserve_exact(num_slots).is_err() {
+                    return Err(Trap::FrameTooBig {
+                        function: label.name().into(),
+                        num_locs: *num_locs,
+                    });
+                }
+                slots.extend(self.stack.drain(self.stack.len() - num_args..));
+                let event = Event::Call {
+                    function: label.name(),
+                    args: &slots,
+                };
+                self.tracer.event(self.index, event);
+                slots.resize(num_slots, Value::Int(0));
+                self.slots_held += slots.len();
+                self.frames.push(Frame {
+                    function: label.name(),
+                    return_index: next,
+                    slots,
+                    stack_height: self.stack.len(),
+                });
+                next = start + 1;
+            }
+            Instruction::Ret => {
+                let frame = self.frames.last().ok_or(Trap::NotInFunction)?;
+                // The result has to be one the function pushed, not one of
+                // its caller's.
+                if self.stack.len() <= frame.stack_height {
+                    return Err(Trap::StackUnderflow);
+                }
+                let result = self.pop()?;
+                let frame = self.frames.pop().ok_or(Trap::NotInFunction)?;
+                self.slots_held -= frame.slots.len();
+                // Anything the function left on the stack goes away with it.
+                self.stack.truncate(frame.stack_height);
+                let slot = self.stack.last_mut().ok_or(Trap::StackUnderflow)?;
+                *slot = result;
+                let event = Event::Return {
+                    function: frame.function,
+                    result: slot,
+                };
+                self.tracer.event(self.index, event);
+                next = frame.return_index;
+            }
+            Instruction::Intrinsic(intrinsic) => {
+                let argument = match intrinsic {
+                    Intrinsic::PrintInt => {
+                        let value = self.pop_int()?;
+                        self.print(&value.to_string())?;
+                        Value::Int(value)
+                    }
+                    Intrinsic::PrintString => {
+                        let value = self.pop_str()?;
+                        self.print(&value)?;
+                        Value::Str(value)
+                    }
+                    Intrinsic::Exit => Value::Int(self.pop_int()?),
+                };
+                let event = Event::Intrinsic {
+                    intrinsic: *intrinsic,
+                    argument: &argument,
+                };
+                self.tracer.event(self.index, event);
+                if let (Intrinsic::Exit, Value::Int(exit_code)) = (intrinsic, argument) {
+                    return Ok(StepResult::Exited(exit_code));
+                }
+            }
+            Instruction::Push { reg } => {
+                let value = self
+                    .registers
+                    .get(reg)
+                    .ok_or(Trap::EmptyRegister(*reg))?
+                    .clone();
+                self.stack.push(value);
+            }
+            Instruction::Pop { reg } => {
+                let value = self.pop()?;
+                // Register -1 is where unused values go to be thrown away.
+                if *reg != -1 {
+                    self.registers.insert(*reg, value);
+                }
+            }
+        }
+        self.index = next;
+        Ok(StepResult::Continue)
+    }
+}
+
+/// Runs `program` until it exits or runs off the end.
+pub fn interpret(program: &[Instruction]) -> Result<RunResult, RunError> {
+    interpret_with_limits(program, &RunLimits::default())
+}
+
+/// Like `interpret`, but traps if `program` goes over one of `limits`.
+pub fn interpret_with_limits(
+    program: &[Instruction],
+    limits: &RunLimits,
+) -> Result<RunResult, RunError> {
+    let (result, output) = run(program, limits, String::new(), ());
+    match result {
+        Ok(result) => Ok(RunResult { output, ..result }),
+        Err(error) => Err(RunError { output, ..error }),
+    }
+}
+
+/// Like `interpret_with_limits`, but what the program prints goes to `output`
+/// as it's printed. The `output` of the result or error is left empty.
+pub fn interpret_with_output(
+    program: &[Instruction],
+    limits: &RunLimits,
+    output: impl OutputSink,
+) -> Result<RunResult, RunError> {
+    run(program, limits, output, ()).0
+}
+
+/// Like `interpret_with_output`, but `tracer` is told about each instruction
+/// before it runs.
+pub fn interpret_traced(
+    program: &[Instruction],
+    limits: &RunLimits,
+    output: impl OutputSink,
+    tracer: impl Tracer,
+) -> Result<RunResult, RunError> {
+    run(program, limits, output, tracer).0
+}
+
+fn run<S: OutputSink>(
+    program: &[Instruction],
+    limits: &RunLimits,
+    output: S,
+    tracer: impl Tracer,
+) -> (Result<RunResult, RunError>, S) {
+    let mut vm = Vm::with_output(program, limits, output, tracer);
+    let (exit_code, usage) = measure(|| loop {
+        match vm.step() {
+            StepResult::Continue => {}
+            StepResult::Exited(exit_code) => break Ok(Some(exit_code)),
+            StepResult::Finished => break Ok(None),
+            StepResult::Trapped(trap) => break Err(trap),
+        }
+    });
+    let result = match exit_code {
+        Ok(exit_code) => Ok(RunResult {
+            output: String::new(),
+            exit_code,
+            stack: vm.stack,
+            function_steps: vm
+                .function_steps
+                .into_iter()
+                .map(|(function, steps)| (function.to_string(), steps))
+                .collect(),
+            instruction_counts: vm.instruction_counts,
+            resource_usage: Some(usage),
+        }),
+        Err(trap) => Err(RunError {
+            index: vm.index,
+            trap,
+            backtrace: vm.backtrace(),
+            output: String::new(),
+        }),
+    };
+    (result, vm.output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{assemble, frontend, generate};
+
+    fn run(text: &str) -> Result<RunResult, RunError> {
+        interpret(&assemble::program(text).unwrap())
+    }
+
+    #[test]
+    fn arithmetic_wraps() {
+        let result = run("ICONST 2147483647
+             ICONST 1
+             ADD
+             INTRINSIC PRINT_INT
+             ICONST -7
+             ICONST 2
+             DIV
+             INTRINSIC PRINT_INT
+             ICONST -7
+             ICONST 2
+             MOD
+             INTRINSIC PRINT_INT
+             ICONST 3
+             INTRINSIC EXIT")
+        .unwrap();
+        assert_eq!(result.output, "-2147483648-3-1");
+        assert_eq!(result.exit_code, Some(3));
+        assert_eq!(result.stack, vec![]);
+        assert!(result.resource_usage.is_some());
+    }
+
+    #[test]
+    fn calls_replace_the_placeholder() {
+        let result = run("JUMP main
+             FUNCTION f 1
+             ARGLOCAL_READ 0
+             ARGLOCAL_READ 1
+             ADD
+             ICONST 99
+             ARGLOCAL_WRITE 2
+             ARGLOCAL_READ 2
+             ADD
+             RET
+             main:
+             RESERVE greeting 3 \"hi\"
+             READ greeting
+             ICONST 42
+             ICONST 1
+             ICONST 2
+             CALL f 2
+             INTRINSIC PRINT_INT
+             INTRINSIC PRINT_STRING")
+        .unwrap();
+        assert_eq!(result.output, "102hi");
+        assert_eq!(result.exit_code, None);
+    }
+
+    #[test]
+    fn stepping() {
+        let program = assemble::program(
+            "RESERVE x 4 (null)
+             ICONST 7
+             WRITE x
+             ICONST 2
+             INTRINSIC PRINT_INT
+             ICONST 3
+             INTRINSIC EXIT",
+        )
+        .unwrap();
+        let mut vm = Vm::new(&program);
+        assert_eq!((vm.pc(), vm.step()), (0, StepResult::Continue));
+        assert_eq!(vm.globals()["x"], Value::Int(0));
+        vm.step();
+        assert_eq!(vm.stack(), [Value::Int(7)]);
+        vm.step();
+        assert_eq!(vm.globals()["x"], Value::Int(7));
+        assert!(vm.stack().is_empty());
+        vm.step();
+        vm.step();
+        assert_eq!(vm.output(), "2");
+        assert_eq!(vm.step(), StepResult::Continue);
+        assert_eq!(vm.step(), StepResult::Exited(3));
+        assert_eq!(vm.step(), StepResult::Exited(3));
+        assert_eq!(vm.pc(), 6);
+
+        let program = assemble::program("ICONST 1 ADD").unwrap();
+        let mut vm = Vm::new(&program);
+        vm.step();
+        assert_eq!(vm.step(), StepResult::Trapped(Trap::StackUnderflow));
+        assert_eq!(vm.pc(), 1);
+    }
+
+    #[test]
+    fn traps() {
+        let trap = |text| run(text).unwrap_err().trap;
+        assert_eq!(trap("ADD"), Trap::StackUnderflow);
+        assert_eq!(trap("ICONST 1\nICONST 0\nDIV"), Trap::DivisionByZero);
+        assert_eq!(trap("READ x"), Trap::UnknownGlobal("x".into()));
+        assert_eq!(trap("JUMP nowhere"), Trap::UnknownLabel("nowhere".into()));
+        assert_eq!(
+            trap("ICONST 42\nCALL f 0"),
+            Trap::UnknownFunction("f".into())
+        );
+        assert_eq!(trap("ICONST 1\nRET"), Trap::NotInFunction);
+        let error = run("JUMP main
+             FUNCTION f 0
+             RET
+             main:
+             ICONST 7
+             ICONST 42
+             CALL f 0
+             INTRINSIC PRINT_INT
+             INTRINSIC PRINT_INT")
+        .unwrap_err();
+        assert_eq!((error.index, error.trap), (2, Trap::StackUnderflow));
+        assert_eq!(error.output, "");
+        assert_eq!(
+            trap("JUMP main\nFUNCTION f 18446744073709551615\nRET\nmain:\nICONST 0\nCALL f 0"),
+            Trap::FrameTooBig {
+                function: "f".into(),
+                num_locs: u64::MAX
+            }
+        );
+        assert_eq!(
+            trap("SCONST \"1\"\nINTRINSIC PRINT_INT"),
+            Trap::TypeMismatch {
+                expected: "an integer",
+                found: Value::Str("1".into())
+            }
+        );
+
+        let error = run("SCONST \"before\"\nINTRINSIC PRINT_STRING\nARGLOCAL_READ 0").unwrap_err();
+        assert_eq!(error.index, 2);
+        assert_eq!(error.output, "before");
+        assert_eq!(
+            error.to_string(),
+            "at instruction 2: the program isn't inside a function"
+        );
+
+        let program = assemble::program("ICONST -2147483648\nICONST -1\nMOD").unwrap();
+        let limits = RunLimits {
+            division_overflow: DivisionOverflow::Fail,
+            ..Default::default()
+        };
+        let error = interpret_with_limits(&program, &limits).unwrap_err();
+        assert_eq!(error.trap, Trap::DivisionOverflow);
+        assert_eq!(interpret(&program).unwrap().stack, [Value::Int(0)]);
+    }
+
+    #[test]
+    fn backtraces() {
+        let error = run("JUMP main
+             FUNCTION inner 0
+             ICONST 1
+             ICONST 0
+             DIV
+             RET
+             FUNCTION outer 0
+             ICONST 0
+             CALL inner 0
+             RET
+             main:
+             ICONST 0
+             CALL outer 0")
+        .unwrap_err();
+        let frame = |function: Option<&str>, index| BacktraceFrame {
+            function: function.map(String::from),
+            index,
+        };
+        assert_eq!(
+            error.backtrace,
+            [
+                frame(Some("inner"), 4),
+                frame(Some("outer"), 8),
+                frame(None, 12)
+            ]
+        );
+        assert_eq!(
+            run("ICONST 1 ICONST 0 DIV").unwrap_err().backtrace,
+            [frame(None, 2)]
+        );
+    }
+
+    #[test]
+    fn function_budgets() {
+        let program = assemble::program(
+            "JUMP main
+             FUNCTION helper 0
+             ICONST 1
+             RET
+             FUNCTION callback 0
+             ICONST 42
+             CALL helper 0
+             RET
+             main:
+             ICONST 42
+             CALL callback 0
+             ICONST 42
+             CALL callback 0
+             ICONST 0
+             INTRINSIC EXIT",
+        )
+        .unwrap();
+        let result = interpret(&program).unwrap();
+        assert_eq!(
+            result.function_steps,
+            BTreeMap::from([("callback".into(), 6), ("helper".into(), 4)])
+        );
+        assert_eq!(
+            result.instruction_counts,
+            [1, 0, 2, 2, 0, 2, 2, 2, 1, 1, 1, 1, 1, 1, 1]
+        );
+
+        let limits = |budget| RunLimits {
+            function_budgets: HashMap::from([("callback".into(), budget)]),
+            ..Default::default()
+        };
+        assert!(interpret_with_limits(&program, &limits(6)).is_ok());
+        let error = interpret_with_limits(&program, &limits(5)).unwrap_err();
+        assert_eq!(
+            error.trap,
+            Trap::LimitExceeded(Limit::FunctionBudget {
+                function: "callback".into(),
+                budget: 5
+            })
+        );
+        assert_eq!(
+            error.to_string(),
+            "at instruction 7: callback ran more than its budget of 5 instructions"
+        );
+    }
+
+    #[test]
+    fn max_output() {
+        let program = assemble::program(
+            "loop:
+             SCONST \"yes
+\"
+             INTRINSIC PRINT_STRING
+             JUMP loop",
+        )
+        .unwrap();
+        let limits = RunLimits {
+            max_output: Some(10),
+            ..Default::default()
+        };
+        let error = interpret_with_limits(&program, &limits).unwrap_err();
+        assert_eq!(error.index, 2);
+        assert_eq!(
+            error.trap,
+            Trap::LimitExceeded(Limit::Output { max_bytes: 10 })
+        );
+        assert_eq!(error.output, "yes\nyes\n");
+
+        let limits = RunLimits {
+            max_output: Some(2),
+            ..Default::default()
+        };
+        let program = assemble::program("ICONST 42 INTRINSIC PRINT_INT").unwrap();
+        assert!(interpret_with_limits(&program, &limits).is_ok());
+    }
+
+    #[test]
+    fn machine_limits() {
+        let trap = |text, limits: RunLimits| {
+            let program = assemble::program(text).unwrap();
+            let error = interpret_with_limits(&program, &limits).unwrap_err();
+            (error.index, error.trap)
+        };
+        assert_eq!(
+            trap(
+                "loop: JUMP loop",
+                RunLimits {
+                    max_instructions: Some(5),
+                    ..Default::default()
+                }
+            ),
+            (1, Trap::LimitExceeded(Limit::Instructions { max: 5 }))
+        );
+        assert_eq!(
+            trap(
+                "ICONST 1 ICONST 2 ADD ICONST 3 loop: ICONST 4 JUMP loop",
+                RunLimits {
+                    max_stack_depth: Some(3),
+                    ..Default::default()
+                }
+            ),
+            (5, Trap::LimitExceeded(Limit::StackDepth { max: 3 }))
+        );
+        // Locals count too, and are counted before they're made.
+        assert_eq!(
+            trap(
+                "JUMP main
+                 FUNCTION f 2
+                 ICONST 1
+                 RET
+                 main:
+                 ICONST 0
+                 CALL f 0",
+                RunLimits {
+                    max_stack_depth: Some(3),
+                    ..Default::default()
+                }
+            ),
+            (2, Trap::LimitExceeded(Limit::StackDepth { max: 3 }))
+        );
+        assert_eq!(
+            trap(
+                "JUMP main
+                 FUNCTION f 4000000000
+                 RET
+                 main:
+                 ICONST 0
+                 CALL f 0",
+                RunLimits {
+                    max_stack_depth: Some(100),
+                    ..Default::default()
+                }
+            ),
+            (5, Trap::LimitExceeded(Limit::StackDepth { max: 100 }))
+        );
+        assert_eq!(
+            trap(
+                "JUMP main
+                 FUNCTION f 0
+                 ICONST 42
+                 CALL f 0
+                 RET
+                 main:
+                 ICONST 42
+                 CALL f 0",
+                RunLimits {
+                    max_call_depth: Some(2),
+                    ..Default::default()
+                }
+            ),
+            (3, Trap::LimitExceeded(Limit::CallDepth { max: 2 }))
+        );
+    }
+
+    #[test]
+    fn runs_lowered_programs() {
+        let lowered = frontend::lower(
+            "var total = 0;
+             fn triangle(n) {
+                 var sum = 0;
+                 while n > 0 { sum = sum + n; n = n - 1; }
+                 return sum;
+             }
+             total = triangle(10);
+             print total;
+             print \" done\";
+             exit total % 7;",
+        )
+        .unwrap();
+        let result = interpret(&lowered.program).unwrap();
+        assert_eq!(result.output, "55 done");
+        assert_eq!(result.exit_code, Some(6));
+    }
+
+    #[test]
+    fn generated_programs_run_to_the_end() {
+        let without_usage = |result: RunResult| RunResult {
+            resource_usage: None,
+            ..result
+        };
+        for seed in 0..20 {
+            let program = generate::generate(&generate::GeneratorOptions {
+                seed,
+                ..Default::default()
+            });
+            let result = interpret(&program).unwrap_or_else(|error| panic!("seed {seed}: {error}"));
+            assert_eq!(result.exit_code, Some(0), "seed {seed}");
+            // Nothing about a run, like the order of the globals in a
+            // HashMap, should leak into its result, apart from what it took.
+            assert_eq!(
+                interpret(&program).map(without_usage),
+                Ok(without_usage(result)),
+                "seed {seed}"
+            );
+        }
+    }
+
+    // Each sample with an .expected file has to print what's in it, followed
+    // by a newline, and exit with what's in its .expected_exit_code file, or
+    // 0 if there isn't one.
+    #[test]
+    fn samples_match_what_was_recorded() {
+        use std::{fs, path::Path};
+
+        fn check(path: &Path) -> usize {
+            if path.is_dir() {
+                return fs::read_dir(path)
+                    .unwrap()
+                    .map(|entry| check(&entry.unwrap().path()))
+                    .sum();
+            }
+            let is_bytecode = path
+                .extension()
+                .is_some_and(|extension| extension == "aves_bytecode");
+            let expected = fs::read_to_string(path.with_extension("expected"));
+            let (true, Ok(expected)) = (is_bytecode, expected) else {
+                return 0;
+            };
+            let expected_exit_code = fs::read_to_string(path.with_extension("expected_exit_code"))
+                .map_or(0, |code| code.trim().parse().unwrap());
+            let program = crate::disassemble::disassemble(&fs::read(path).unwrap()).unwrap();
+            let result = interpret(&program).unwrap_or_else(|error| panic!("{path:?}: {error}"));
+            assert_eq!(result.output + "\n", expected, "{path:?}");
+            assert_eq!(
+                result.exit_code.unwrap_or(0),
+                expected_exit_code,
+                "{path:?}"
+            );
+            1
+        }
+        let samples = Path::new(env!("CARGO_MANIFEST_DIR")).join("ir_samples");
+        assert!(check(&samples) > 0);
+    }
+}
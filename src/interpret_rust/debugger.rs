@@ -0,0 +1,404 @@
+// Running a program an instruction at a time, stopping at breakpoints, and
+// looking at the stack, globals and slots on the way, from commands typed in
+// a REPL.
+
+use std::{
+    collections::BTreeSet,
+    fmt::Write as _,
+    io::{self, BufRead, Write},
+};
+
+use super::{RunLimits, StepResult, Value, Vm};
+use crate::{
+    ir_definition::Instruction,
+    output_sink::OutputSink,
+    trace::{Event, Tracer},
+};
+
+const HELP: &str = "\
+break LOCATION     stop before the instruction at LOCATION, an index or a label (b)
+delete LOCATION    remove a breakpoint
+breakpoints        list the breakpoints
+watch GLOBAL       stop after a WRITE to GLOBAL
+unwatch GLOBAL     stop watching GLOBAL
+step [N]           run N instructions, or 1 (s)
+continue           run until a breakpoint, or the program stops (c)
+where              show the next instruction and the calls it's in (w)
+list               show the instructions around the next one (l)
+stack              show the operand stack, from the bottom up
+globals            show the globals
+locals             show the current function's arguments and locals
+registers          show the registers
+quit               stop debugging (q)
+";
+
+pub struct Debugger<'a, S> {
+    vm: Vm<'a, S, Watches>,
+    breakpoints: BTreeSet<usize>,
+}
+
+// The globals being watched, and the last write to one of them that hasn't
+// been shown yet.
+#[derive(Default)]
+struct Watches {
+    names: BTreeSet<String>,
+    hit: Option<(usize, String, Value)>,
+}
+
+impl Tracer for Watches {
+    fn before(&mut self, _: usize, _: &Instruction, _: &[Value], _: usize) {}
+
+    fn event(&mut self, index: usize, event: Event) {
+        if let Event::GlobalWrite { name, value } = event {
+            if self.names.contains(name) {
+                self.hit = Some((index, name.into(), value.clone()));
+            }
+        }
+    }
+}
+
+impl<'a, S: OutputSink> Debugger<'a, S> {
+    /// A debugger stopped before the program's first instruction. What the
+    /// program prints goes to `output`.
+    pub fn new(program: &'a [Instruction], limits: &RunLimits, output: S) -> Self {
+        Debugger {
+            vm: Vm::with_output(program, limits, output, Watches::default()),
+            breakpoints: BTreeSet::new(),
+        }
+    }
+
+    /// An index, or the name of a label or function.
+    fn location(&self, location: &str) -> Result<usize, String> {
+        if let Ok(index) = location.parse::<usize>() {
+            if index < self.vm.program.len() {
+                return Ok(index);
+            }
+            return Err(format!(
+                "there's no instruction {index}; the program has {}",
+                self.vm.program.len()
+            ));
+        }
+        let location = location.strip_suffix(':').unwrap_or(location);
+        self.vm
+            .labels
+            .get(location)
+            .copied()
+            .ok_or_else(|| format!("there's no label called {location}"))
+    }
+
+    /// Runs one instruction, unless the program has stopped, and says whether
+    /// it can go on without stopping for a watched global.
+    fn step_once(&mut self) -> bool {
+        self.vm.step() == StepResult::Continue && self.vm.tracer.hit.is_none()
+    }
+
+    /// Where the program is, after the write to a watched global that stopped
+    /// it, if one did.
+    fn describe_stop(&mut self) -> String {
+        let mut text = String::new();
+        if let Some((index, name, value)) = self.vm.tracer.hit.take() {
+            writeln!(text, "{name} was set to {value} at {index}").unwrap();
+        }
+        text + &self.describe_position()
+    }
+
+    fn describe_position(&self) -> String {
+        match &self.vm.stopped {
+            None => match self.vm.program.get(self.vm.index) {
+                Some(instruction) => format!("at {}: {instruction}\n", self.vm.index),
+                // It stops when it tries to run past the end.
+                None => "at the end of the program\n".to_string(),
+            },
+            Some(StepResult::Exited(exit_code)) => {
+                format!("the program exited with {exit_code}\n")
+            }
+            Some(StepResult::Finished) => "the program ran off the end\n".to_string(),
+            Some(StepResult::Trapped(trap)) => {
+                let index = self.vm.index;
+                format!("the program trapped at instruction {index}: {trap}\n")
+            }
+            Some(StepResult::Continue) => unreachable!("A program that can go on hasn't stopped."),
+        }
+    }
+
+    fn where_(&self) -> String {
+        let mut text = self.describe_position();
+        let backtrace = self.vm.backtrace();
+        for (frame, caller) in backtrace.iter().zip(&backtrace[1..]) {
+            if let Some(function) = &frame.function {
+                writeln!(text, "  in {function}, called from {}", caller.index).unwrap();
+            }
+        }
+        text
+    }
+
+    fn list(&self) -> String {
+        let program = self.vm.program;
+        let around = self.vm.index.saturating_sub(3)..(self.vm.index + 4).min(program.len());
+        let width = program.len().saturating_sub(1).to_string().len();
+        let mut text = String::new();
+        for index in around {
+            let marker = if index == self.vm.index { "=>" } else { "  " };
+            let breakpoint = if self.breakpoints.contains(&index) {
+                '*'
+            } else {
+                ' '
+            };
+            writeln!(
+                text,
+                "{marker}{breakpoint}{index:>width$}  {}",
+                program[index]
+            )
+            .unwrap();
+        }
+        text
+    }
+
+    fn values<'v>(values: impl IntoIterator<Item = (String, &'v Value)>) -> String {
+        let mut text = String::new();
+        for (name, value) in values {
+            writeln!(text, "{name} = {value}").unwrap();
+        }
+        if text.is_empty() {
+            text.push_str("(none)\n");
+        }
+        text
+    }
+
+    /// Runs one command, and gives what to show for it.
+    pub fn command(&mut self, line: &str) -> String {
+        let mut words = line.split_whitespace();
+        let Some(command) = words.next() else {
+            return String::new();
+        };
+        let argument = words.next();
+        match (command, argument) {
+            ("break" | "b", Some(location)) => match self.location(location) {
+                Ok(index) => {
+                    self.breakpoints.insert(index);
+                    format!("breakpoint at {index}: {}\n", self.vm.program[index])
+                }
+                Err(error) => format!("error: {error}\n"),
+            },
+            ("delete", Some(location)) => match self.location(location) {
+                Ok(index) if self.breakpoints.remove(&index) => {
+                    format!("deleted the breakpoint at {index}\n")
+                }
+                Ok(index) => format!("error: there's no breakpoint at {index}\n"),
+                Err(error) => format!("error: {error}\n"),
+            },
+            ("breakpoints", None) => {
+                let mut text = String::new();
+                for &index in &self.breakpoints {
+                    writeln!(text, "{index}: {}", self.vm.program[index]).unwrap();
+                }
+                if text.is_empty() {
+                    text.push_str("(none)\n");
+                }
+                text
+            }
+            ("step" | "s", count) => {
+                let count = match count.map(str::parse::<u64>) {
+                    None => 1,
+                    Some(Ok(count)) => count,
+                    Some(Err(_)) => return "error: step takes a number of instructions\n".into(),
+                };
+                if self.vm.stopped.is_some() {
+                    return "error: the program isn't running\n".into();
+                }
+                for _ in 0..count {
+                    if !self.step_once() {
+                        break;
+                    }
+                }
+                self.describe_stop()
+            }
+            ("continue" | "c", None) => {
+                if self.vm.stopped.is_some() {
+                    return "error: the program isn't running\n".into();
+                }
+                // The first step leaves the breakpoint it's stopped at, if any.
+                while self.step_once() && !self.breakpoints.contains(&self.vm.index) {}
+                self.describe_stop()
+            }
+            ("watch", Some(name)) => {
+                self.vm.tracer.names.insert(name.into());
+                format!("watching {name}\n")
+            }
+            ("unwatch", Some(name)) => {
+                if self.vm.tracer.names.remove(name) {
+                    format!("stopped watching {name}\n")
+                } else {
+                    format!("error: {name} isn't being watched\n")
+                }
+            }
+            ("where" | "w", None) => self.where_(),
+            ("list" | "l", None) => self.list(),
+            ("stack", None) => {
+                let values = self.vm.stack.iter().enumerate();
+                Self::values(values.map(|(depth, value)| (depth.to_string(), value)))
+            }
+            ("globals", None) => {
+                let mut globals: Vec<_> = self.vm.globals.iter().collect();
+                globals.sort_by_key(|(name, _)| *name);
+                Self::values(
+                    globals
+                        .into_iter()
+                        .map(|(name, value)| (name.clone(), value)),
+                )
+            }
+            ("locals", None) => match self.vm.frames.last() {
+                Some(frame) => {
+                    let slots = frame.slots.iter().enumerate();
+                    Self::values(slots.map(|(slot, value)| (slot.to_string(), value)))
+                }
+                None => "error: the program isn't inside a function\n".into(),
+            },
+            ("registers", None) => {
+                let mut registers: Vec<_> = self.vm.registers.iter().collect();
+                registers.sort_by_key(|(reg, _)| **reg);
+                Self::values(
+                    registers
+                        .into_iter()
+                        .map(|(reg, value)| (reg.to_string(), value)),
+                )
+            }
+            ("help" | "h", None) => HELP.into(),
+            _ => format!("error: I don't know \"{}\"; try help\n", line.trim()),
+        }
+    }
+
+    /// Reads commands from `input` until it ends or says to quit, writing a
+    /// prompt before each and what it shows after.
+    pub fn repl(&mut self, mut input: impl BufRead, mut output: impl Write) -> io::Result<()> {
+        output.write_all(self.describe_position().as_bytes())?;
+        let mut line = String::new();
+        loop {
+            write!(output, "(debug) ")?;
+            output.flush()?;
+            line.clear();
+            if input.read_line(&mut line)? == 0 {
+                // Leave the terminal on a line of its own.
+                return writeln!(output);
+            }
+            if matches!(line.trim(), "quit" | "q") {
+                return Ok(());
+            }
+            output.write_all(self.command(&line).as_bytes())?;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assemble;
+
+    const PROGRAM: &str = "RESERVE total 4 (null)
+                           JUMP main
+                           FUNCTION add 1
+                           ARGLOCAL_READ 0
+                           ARGLOCAL_READ 1
+                           ADD
+                           ARGLOCAL_WRITE 2
+                           ARGLOCAL_READ 2
+                           RET
+                           main:
+                           ICONST 42
+                           ICONST 1
+                           ICONST 2
+                           CALL add 2
+                           WRITE total
+                           READ total
+                           INTRINSIC PRINT_INT";
+
+    #[test]
+    fn breakpoints_and_inspection() {
+        let program = assemble::program(PROGRAM).unwrap();
+        let limits = RunLimits::default();
+        let mut output = String::new();
+        let mut debugger = Debugger::new(&program, &limits, &mut output);
+        assert_eq!(
+            debugger.command("b add"),
+            "breakpoint at 2: FUNCTION add 1\n"
+        );
+        assert_eq!(
+            debugger.command("break 7"),
+            "breakpoint at 7: ARGLOCAL_READ 2\n"
+        );
+        assert_eq!(
+            debugger.command("delete 2"),
+            "deleted the breakpoint at 2\n"
+        );
+        assert_eq!(debugger.command("c"), "at 7: ARGLOCAL_READ 2\n");
+        assert_eq!(
+            debugger.command("where"),
+            "at 7: ARGLOCAL_READ 2\n  in add, called from 13\n"
+        );
+        assert_eq!(debugger.command("locals"), "0 = 1\n1 = 2\n2 = 3\n");
+        assert_eq!(debugger.command("stack"), "0 = 42\n");
+        assert_eq!(debugger.command("globals"), "total = 0\n");
+        assert_eq!(
+            debugger.command("list"),
+            "    4  ARGLOCAL_READ 1
+    5  ADD
+    6  ARGLOCAL_WRITE 2
+=>* 7  ARGLOCAL_READ 2
+    8  RET
+    9  main:
+   10  ICONST 42
+"
+        );
+        assert_eq!(debugger.command("s 2"), "at 14: WRITE total\n");
+        assert_eq!(debugger.command("stack"), "0 = 3\n");
+        assert_eq!(debugger.command("c"), "the program ran off the end\n");
+        assert_eq!(debugger.command("s"), "error: the program isn't running\n");
+        assert_eq!(
+            debugger.command("b nowhere"),
+            "error: there's no label called nowhere\n"
+        );
+        assert_eq!(
+            debugger.command("fly"),
+            "error: I don't know \"fly\"; try help\n"
+        );
+        drop(debugger);
+        assert_eq!(output, "3");
+    }
+
+    #[test]
+    fn watchpoints() {
+        let program = assemble::program(PROGRAM).unwrap();
+        let limits = RunLimits::default();
+        let mut debugger = Debugger::new(&program, &limits, String::new());
+        assert_eq!(debugger.command("watch total"), "watching total\n");
+        assert_eq!(
+            debugger.command("c"),
+            "total was set to 3 at 14\nat 15: READ total\n"
+        );
+        assert_eq!(
+            debugger.command("unwatch total"),
+            "stopped watching total\n"
+        );
+        assert_eq!(
+            debugger.command("unwatch total"),
+            "error: total isn't being watched\n"
+        );
+        assert_eq!(debugger.command("c"), "the program ran off the end\n");
+    }
+
+    #[test]
+    fn repl() {
+        let program = assemble::program("ICONST 1\nICONST 0\nDIV").unwrap();
+        let limits = RunLimits::default();
+        let mut debugger = Debugger::new(&program, &limits, String::new());
+        let mut output = Vec::new();
+        debugger
+            .repl("s\nc\nq\nstack\n".as_bytes(), &mut output)
+            .unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "at 0: ICONST 1\n(debug) at 1: ICONST 0\n\
+             (debug) the program trapped at instruction 2: division by zero\n(debug) "
+        );
+    }
+}
@@ -1,5 +1,7 @@
+use std::fmt;
+
 // TODO: Make all String's &str. Requires lifetime shenanigans.
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Clone)]
 pub struct Label(String);
 
 impl Label {
@@ -12,6 +14,42 @@ impl Label {
     }
 }
 
+impl From<&str> for Label {
+    fn from(name: &str) -> Self {
+        Label::named(name)
+    }
+}
+
+/// Extra information about a function, written `@name` or `@name(value)`
+/// before it in the textual format. Some have meanings, like `@args` and
+/// `@no_verify_stack`, but any attribute is kept. The bytecode has nowhere to
+/// put them, so they're lost when a program is written as bytecode.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Attribute {
+    pub name: String,
+    pub value: Option<String>,
+}
+
+impl Attribute {
+    /// `@args(n)`, which declares that a function takes `n` arguments.
+    pub fn args(num_args: u64) -> Self {
+        Attribute {
+            name: "args".into(),
+            value: Some(num_args.to_string()),
+        }
+    }
+}
+
+impl fmt::Display for Attribute {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "@{}", self.name)?;
+        match &self.value {
+            Some(value) => write!(f, "({value})"),
+            None => Ok(()),
+        }
+    }
+}
+
 #[derive(Debug, PartialEq, Clone, Copy)]
 pub enum Intrinsic {
     PrintInt,
@@ -19,7 +57,28 @@ pub enum Intrinsic {
     Exit,
 }
 
-#[derive(Debug, PartialEq)]
+impl Intrinsic {
+    /// Every intrinsic, in the order the C code numbers them.
+    pub const ALL: [Intrinsic; 3] = [Intrinsic::PrintInt, Intrinsic::PrintString, Intrinsic::Exit];
+
+    /// The name used for this intrinsic in the textual format.
+    pub fn name(self) -> &'static str {
+        match self {
+            Intrinsic::PrintInt => "PRINT_INT",
+            Intrinsic::PrintString => "PRINT_STRING",
+            Intrinsic::Exit => "EXIT",
+        }
+    }
+
+    /// Looks an intrinsic up by name, ignoring case like the rest of the textual format.
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|intrinsic| intrinsic.name().eq_ignore_ascii_case(name))
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
 pub enum Instruction {
     Nop,
 
@@ -64,6 +123,7 @@ pub enum Instruction {
     Function {
         label: Label,
         num_locs: u64,
+        attributes: Vec<Attribute>,
     },
     Call {
         label: Label,
@@ -80,3 +140,114 @@ pub enum Instruction {
         reg: i64,
     },
 }
+
+// Writes a string literal that `assemble` reads back as `text`. Only
+// backslashes and double quotes have escapes; everything else, newlines
+// included, goes in as-is.
+fn write_string_literal(f: &mut fmt::Formatter, text: &str) -> fmt::Result {
+    write!(f, "\"{}\"", text.replace('\\', r"\\").replace('"', r#"\""#))
+}
+
+/// The textual form of an instruction, which `assemble::node` parses back into
+/// the same instruction.
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Instruction::Label(label) => return write!(f, "{}:", label.name()),
+            Instruction::Function { attributes, .. } => {
+                for attribute in attributes {
+                    write!(f, "{attribute} ")?;
+                }
+            }
+            _ => {}
+        }
+        write!(f, "{}", self.opcode().mnemonic())?;
+        match self {
+            Instruction::Iconst(i) => write!(f, " {i}"),
+            Instruction::Sconst(text) => {
+                write!(f, " ")?;
+                write_string_literal(f, text)
+            }
+            Instruction::ReserveString {
+                size,
+                name,
+                initial_value,
+            } => {
+                write!(f, " {name} {size} ")?;
+                write_string_literal(f, initial_value)
+            }
+            Instruction::ReserveInt { name } => write!(f, " {name} 4 (null)"),
+            Instruction::Read(name) | Instruction::Write(name) => write!(f, " {name}"),
+            Instruction::ArgLocalRead(index) | Instruction::ArgLocalWrite(index) => {
+                write!(f, " {index}")
+            }
+            Instruction::Jump(label) | Instruction::BranchZero(label) => {
+                write!(f, " {}", label.name())
+            }
+            Instruction::Function {
+                label, num_locs, ..
+            } => {
+                write!(f, " {} {num_locs}", label.name())
+            }
+            Instruction::Call { label, num_args } => write!(f, " {} {num_args}", label.name()),
+            Instruction::Intrinsic(intrinsic) => write!(f, " {}", intrinsic.name()),
+            Instruction::Push { reg } | Instruction::Pop { reg } => write!(f, " {reg}"),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assemble;
+
+    #[test]
+    fn display_round_trips() {
+        let program = assemble::program(
+            r#"NOP
+               ICONST -12
+               SCONST "quote \" backslash \\ newline
+"
+               RESERVE s 16 "init"
+               RESERVE i 4 (null)
+               READ i
+               WRITE s
+               ARGLOCAL_READ 0
+               ARGLOCAL_WRITE 1
+               L0:
+               JUMP L0
+               BRANCHZERO L0
+               FUNCTION f 2
+               @inline @key(some value) FUNCTION g 0
+               CALL f 3
+               RET
+               INTRINSIC PRINT_STRING
+               PUSH 1
+               POP -1
+               MOD"#,
+        )
+        .unwrap();
+        for instruction in program {
+            let text = instruction.to_string();
+            assert_eq!(assemble::node(&text), Ok(("", instruction)), "{text}");
+        }
+    }
+
+    #[test]
+    fn display() {
+        assert_eq!(Instruction::Label(Label::named("L3")).to_string(), "L3:");
+        assert_eq!(
+            Instruction::Call {
+                label: Label::named("f"),
+                num_args: 2
+            }
+            .to_string(),
+            "CALL f 2"
+        );
+        assert_eq!(
+            Instruction::Sconst("a\"b".into()).to_string(),
+            r#"SCONST "a\"b""#
+        );
+    }
+}
@@ -0,0 +1,624 @@
+// Programs as JSON, so tools in other languages can make and read them
+// without knowing the bytecode format or the textual grammar.
+//
+// A program is an object with a list of instructions. Each instruction is an
+// object with its mnemonic as "op" and its operands by name:
+//
+//     {"instructions": [
+//       {"op": "RESERVE", "name": "greeting", "size": 6, "value": "hello"},
+//       {"op": "RESERVE", "name": "count", "size": 4, "value": null},
+//       {"op": "FUNCTION", "label": "f", "num_locs": 0, "attributes": []},
+//       {"op": "LABEL", "label": "L0"},
+//       {"op": "CALL", "label": "f", "num_args": 0},
+//       {"op": "INTRINSIC", "intrinsic": "PRINT_INT"},
+//       ...
+//     ]}
+//
+// The rest are ICONST and SCONST with a "value", READ and WRITE with a
+// "name", ARGLOCAL_READ and ARGLOCAL_WRITE with an "index", JUMP and
+// BRANCHZERO with a "label", PUSH and POP with a "reg", and the instructions
+// without operands, with just an "op".
+
+use std::fmt;
+
+use nom::{
+    branch::alt,
+    bytes::complete::{tag, take_while1},
+    character::complete::{char as nom_char, multispace0, none_of, one_of, satisfy},
+    combinator::{all_consuming, cut, map, map_opt, map_res, not, opt, recognize, value, verify},
+    error::{context, convert_error, VerboseError, VerboseErrorKind},
+    multi::{count, many0, separated_list0},
+    sequence::{delimited, pair, preceded, separated_pair, terminated},
+    IResult,
+};
+
+use crate::{
+    ir_definition::{Attribute, Instruction, Intrinsic, Label},
+    opcode::Opcode,
+    stats::json_string,
+};
+
+type ParseResult<'a, O> = IResult<&'a str, O, VerboseError<&'a str>>;
+
+/// The program as a JSON object, with one instruction on each line.
+pub fn write_json(program: &[Instruction]) -> String {
+    let instructions: Vec<_> = program
+        .iter()
+        .map(|instruction| format!("  {}", instruction_json(instruction)))
+        .collect();
+    if instructions.is_empty() {
+        return "{\"instructions\": []}\n".to_string();
+    }
+    format!("{{\"instructions\": [\n{}\n]}}\n", instructions.join(",\n"))
+}
+
+fn instruction_json(instruction: &Instruction) -> String {
+    let op = json_string(instruction.opcode().mnemonic());
+    let operands = match instruction {
+        Instruction::Iconst(value) => format!(", \"value\": {value}"),
+        Instruction::Sconst(value) => format!(", \"value\": {}", json_string(value)),
+        Instruction::ReserveString {
+            size,
+            name,
+            initial_value,
+        } => format!(
+            ", \"name\": {}, \"size\": {size}, \"value\": {}",
+            json_string(name),
+            json_string(initial_value)
+        ),
+        Instruction::ReserveInt { name } => {
+            format!(
+                ", \"name\": {}, \"size\": 4, \"value\": null",
+                json_string(name)
+            )
+        }
+        Instruction::Read(name) | Instruction::Write(name) => {
+            format!(", \"name\": {}", json_string(name))
+        }
+        Instruction::ArgLocalRead(index) | Instruction::ArgLocalWrite(index) => {
+            format!(", \"index\": {index}")
+        }
+        Instruction::Label(label) | Instruction::Jump(label) | Instruction::BranchZero(label) => {
+            format!(", \"label\": {}", json_string(label.name()))
+        }
+        Instruction::Function {
+            label,
+            num_locs,
+            attributes,
+        } => {
+            let attributes: Vec<_> = attributes
+                .iter()
+                .map(|attribute| {
+                    let value = match &attribute.value {
+                        Some(value) => json_string(value),
+                        None => "null".to_string(),
+                    };
+                    format!(
+                        "{{\"name\": {}, \"value\": {value}}}",
+                        json_string(&attribute.name)
+                    )
+                })
+                .collect();
+            format!(
+                ", \"label\": {}, \"num_locs\": {num_locs}, \"attributes\": [{}]",
+                json_string(label.name()),
+                attributes.join(", ")
+            )
+        }
+        Instruction::Call { label, num_args } => format!(
+            ", \"label\": {}, \"num_args\": {num_args}",
+            json_string(label.name())
+        ),
+        Instruction::Intrinsic(intrinsic) => {
+            format!(", \"intrinsic\": {}", json_string(intrinsic.name()))
+        }
+        Instruction::Push { reg } | Instruction::Pop { reg } => format!(", \"reg\": {reg}"),
+        _ => String::new(),
+    };
+    format!("{{\"op\": {op}{operands}}}")
+}
+
+#[derive(Debug, PartialEq)]
+pub enum JsonError {
+    /// The text isn't JSON (or has a number that isn't an integer), rendered
+    /// like `assemble::describe_error` renders errors in the textual format.
+    Syntax(String),
+    /// The JSON isn't a program.
+    NotAProgram(String),
+    /// An instruction that isn't one.
+    Instruction { index: usize, message: String },
+}
+
+impl fmt::Display for JsonError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            JsonError::Syntax(message) => write!(f, "{message}"),
+            JsonError::NotAProgram(message) => write!(f, "{message}"),
+            JsonError::Instruction { index, message } => {
+                write!(f, "instruction {index}: {message}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for JsonError {}
+
+#[derive(Debug, PartialEq, Clone)]
+enum Json {
+    Null,
+    Bool(bool),
+    /// Only integers are needed, so they're all that's read.
+    Integer(i128),
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+
+impl Json {
+    fn kind(&self) -> &'static str {
+        match self {
+            Json::Null => "null",
+            Json::Bool(_) => "a boolean",
+            Json::Integer(_) => "an integer",
+            Json::String(_) => "a string",
+            Json::Array(_) => "a list",
+            Json::Object(_) => "an object",
+        }
+    }
+}
+
+fn hex_digits(input: &str) -> ParseResult<'_, u32> {
+    map_res(
+        recognize(count(satisfy(|c| c.is_ascii_hexdigit()), 4)),
+        |digits| u32::from_str_radix(digits, 16),
+    )(input)
+}
+
+// A `\u` escape, or two for a character outside the Basic Multilingual Plane.
+fn unicode_escape(input: &str) -> ParseResult<'_, char> {
+    let high_surrogate = verify(hex_digits, |high| (0xd800..0xdc00).contains(high));
+    let low_surrogate = verify(hex_digits, |low| (0xdc00..0xe000).contains(low));
+    context(
+        "a \\u escape needs four hex digits that make a character",
+        map_opt(
+            alt((
+                map(
+                    pair(
+                        preceded(nom_char('u'), high_surrogate),
+                        preceded(tag("\\u"), low_surrogate),
+                    ),
+                    |(high, low)| 0x10000 + ((high - 0xd800) << 10) + (low - 0xdc00),
+                ),
+                preceded(nom_char('u'), hex_digits),
+            )),
+            char::from_u32,
+        ),
+    )(input)
+}
+
+fn string_character(input: &str) -> ParseResult<'_, char> {
+    alt((
+        preceded(
+            nom_char('\\'),
+            cut(alt((
+                one_of("\"\\/"),
+                value('\u{8}', nom_char('b')),
+                value('\u{c}', nom_char('f')),
+                value('\n', nom_char('n')),
+                value('\r', nom_char('r')),
+                value('\t', nom_char('t')),
+                unicode_escape,
+            ))),
+        ),
+        none_of("\"\\"),
+    ))(input)
+}
+
+fn string(input: &str) -> ParseResult<'_, String> {
+    map(
+        preceded(
+            nom_char('"'),
+            cut(terminated(many0(string_character), nom_char('"'))),
+        ),
+        |characters| characters.into_iter().collect(),
+    )(input)
+}
+
+fn integer(input: &str) -> ParseResult<'_, i128> {
+    let digits = take_while1(|c: char| c.is_ascii_digit());
+    let (rest, text) = recognize(pair(opt(nom_char('-')), digits))(input)?;
+    // A fraction or an exponent would make it a number this can't hold.
+    let (rest, _) = context("numbers have to be integers", cut(not(one_of(".eE"))))(rest)?;
+    match text.parse() {
+        Ok(value) => Ok((rest, value)),
+        Err(_) => Err(nom::Err::Failure(VerboseError {
+            errors: vec![(input, VerboseErrorKind::Context("this number is too big"))],
+        })),
+    }
+}
+
+fn array(input: &str) -> ParseResult<'_, Vec<Json>> {
+    preceded(
+        nom_char('['),
+        cut(terminated(
+            separated_list0(nom_char(','), json),
+            preceded(multispace0, nom_char(']')),
+        )),
+    )(input)
+}
+
+fn object(input: &str) -> ParseResult<'_, Vec<(String, Json)>> {
+    let member = separated_pair(
+        delimited(multispace0, string, multispace0),
+        cut(nom_char(':')),
+        json,
+    );
+    preceded(
+        nom_char('{'),
+        cut(terminated(
+            separated_list0(nom_char(','), member),
+            preceded(multispace0, nom_char('}')),
+        )),
+    )(input)
+}
+
+fn json(input: &str) -> ParseResult<'_, Json> {
+    delimited(
+        multispace0,
+        context(
+            "expected a JSON value",
+            alt((
+                value(Json::Null, tag("null")),
+                value(Json::Bool(true), tag("true")),
+                value(Json::Bool(false), tag("false")),
+                map(integer, Json::Integer),
+                map(string, Json::String),
+                map(array, Json::Array),
+                map(object, Json::Object),
+            )),
+        ),
+        multispace0,
+    )(input)
+}
+
+/// The fields of an instruction's object, which are checked off as they're
+/// used, so that a misspelled one is an error rather than being ignored.
+struct Fields<'a> {
+    op: &'a str,
+    fields: Vec<(&'a str, Option<&'a Json>)>,
+}
+
+impl<'a> Fields<'a> {
+    fn field(&mut self, name: &str) -> Result<&'a Json, String> {
+        self.fields
+            .iter_mut()
+            .find(|(field, _)| *field == name)
+            .and_then(|(_, value)| value.take())
+            .ok_or_else(|| format!("{} needs a \"{name}\"", self.op))
+    }
+
+    fn integer<T: TryFrom<i128>>(&mut self, name: &str) -> Result<T, String> {
+        match self.field(name)? {
+            Json::Integer(value) => T::try_from(*value)
+                .map_err(|_| format!("{value} is out of range for the \"{name}\" of {}", self.op)),
+            other => Err(format!(
+                "the \"{name}\" of {} has to be an integer, not {}",
+                self.op,
+                other.kind()
+            )),
+        }
+    }
+
+    fn string(&mut self, name: &str) -> Result<&'a str, String> {
+        match self.field(name)? {
+            Json::String(value) => Ok(value),
+            other => Err(format!(
+                "the \"{name}\" of {} has to be a string, not {}",
+                self.op,
+                other.kind()
+            )),
+        }
+    }
+
+    /// A string that has to be written as one word in the textual format.
+    fn name(&mut self, name: &str) -> Result<&'a str, String> {
+        let value = self.string(name)?;
+        let is_identifier = !value.is_empty()
+            && value
+                .chars()
+                .all(|c| c.is_alphanumeric() || c == '$' || c == '_');
+        if !is_identifier {
+            return Err(format!(
+                "the \"{name}\" of {} has to be letters, digits, $ and _, not {}",
+                self.op,
+                json_string(value)
+            ));
+        }
+        Ok(value)
+    }
+
+    fn label(&mut self) -> Result<Label, String> {
+        self.name("label").map(Label::named)
+    }
+
+    fn finish(self) -> Result<(), String> {
+        match self.fields.iter().find(|(_, value)| value.is_some()) {
+            Some((field, _)) => Err(format!("{} doesn't have a \"{field}\"", self.op)),
+            None => Ok(()),
+        }
+    }
+}
+
+fn attribute(json: &Json) -> Result<Attribute, String> {
+    let Json::Object(members) = json else {
+        return Err(format!(
+            "an attribute has to be an object, not {}",
+            json.kind()
+        ));
+    };
+    let mut fields = Fields {
+        op: "an attribute",
+        fields: members.iter().map(|(k, v)| (k.as_str(), Some(v))).collect(),
+    };
+    let name = fields.name("name")?.to_string();
+    let value = match fields.field("value")? {
+        Json::Null => None,
+        Json::String(value) => Some(value.clone()),
+        other => {
+            return Err(format!(
+                "an attribute's \"value\" has to be a string or null, not {}",
+                other.kind()
+            ))
+        }
+    };
+    fields.finish()?;
+    Ok(Attribute { name, value })
+}
+
+fn instruction(json: &Json) -> Result<Instruction, String> {
+    let Json::Object(members) = json else {
+        return Err(format!("has to be an object, not {}", json.kind()));
+    };
+    let mut fields = Fields {
+        op: "an instruction",
+        fields: members.iter().map(|(k, v)| (k.as_str(), Some(v))).collect(),
+    };
+    let mnemonic = fields.string("op")?;
+    let opcode = Opcode::from_mnemonic(mnemonic)
+        .ok_or_else(|| format!("there's no instruction called {}", json_string(mnemonic)))?;
+    fields.op = opcode.mnemonic();
+
+    let instruction = match opcode {
+        Opcode::Nop => Instruction::Nop,
+        Opcode::Iconst => Instruction::Iconst(fields.integer("value")?),
+        Opcode::Sconst => Instruction::Sconst(fields.string("value")?.to_string()),
+        Opcode::Add => Instruction::Add,
+        Opcode::Sub => Instruction::Sub,
+        Opcode::Mul => Instruction::Mul,
+        Opcode::Div => Instruction::Div,
+        Opcode::Mod => Instruction::Mod,
+        Opcode::Bor => Instruction::Bor,
+        Opcode::Band => Instruction::Band,
+        Opcode::Xor => Instruction::Xor,
+        Opcode::Or => Instruction::Or,
+        Opcode::And => Instruction::And,
+        Opcode::Eq => Instruction::Eq,
+        Opcode::Lt => Instruction::Lt,
+        Opcode::Gt => Instruction::Gt,
+        Opcode::Not => Instruction::Not,
+        Opcode::Reserve => {
+            let name = fields.name("name")?.to_string();
+            let size = fields.integer("size")?;
+            match fields.field("value")? {
+                Json::Null => Instruction::ReserveInt { name },
+                Json::String(initial_value) => Instruction::ReserveString {
+                    size,
+                    name,
+                    initial_value: initial_value.clone(),
+                },
+                other => {
+                    return Err(format!(
+                        "the \"value\" of RESERVE has to be a string or null, not {}",
+                        other.kind()
+                    ))
+                }
+            }
+        }
+        Opcode::Read => Instruction::Read(fields.name("name")?.to_string()),
+        Opcode::Write => Instruction::Write(fields.name("name")?.to_string()),
+        Opcode::ArgLocalRead => Instruction::ArgLocalRead(fields.integer("index")?),
+        Opcode::ArgLocalWrite => Instruction::ArgLocalWrite(fields.integer("index")?),
+        Opcode::Label => Instruction::Label(fields.label()?),
+        Opcode::Jump => Instruction::Jump(fields.label()?),
+        Opcode::BranchZero => Instruction::BranchZero(fields.label()?),
+        Opcode::Function => {
+            let label = fields.label()?;
+            let num_locs = fields.integer("num_locs")?;
+            // Most functions don't have any, so they can be left out.
+            let attributes = match fields.field("attributes") {
+                Ok(Json::Array(attributes)) => {
+                    attributes.iter().map(attribute).collect::<Result<_, _>>()?
+                }
+                Ok(other) => {
+                    return Err(format!(
+                        "the \"attributes\" of FUNCTION have to be a list, not {}",
+                        other.kind()
+                    ))
+                }
+                Err(_) => Vec::new(),
+            };
+            Instruction::Function {
+                label,
+                num_locs,
+                attributes,
+            }
+        }
+        Opcode::Call => Instruction::Call {
+            label: fields.label()?,
+            num_args: fields.integer("num_args")?,
+        },
+        Opcode::Ret => Instruction::Ret,
+        Opcode::Intrinsic => {
+            let name = fields.string("intrinsic")?;
+            let intrinsic = Intrinsic::from_name(name)
+                .ok_or_else(|| format!("there's no intrinsic called {}", json_string(name)))?;
+            Instruction::Intrinsic(intrinsic)
+        }
+        Opcode::Push => Instruction::Push {
+            reg: fields.integer("reg")?,
+        },
+        Opcode::Pop => Instruction::Pop {
+            reg: fields.integer("reg")?,
+        },
+    };
+    fields.finish()?;
+    Ok(instruction)
+}
+
+/// Reads a program written like `write_json` writes it. Mnemonics and
+/// intrinsics can be in any case, like in the textual format.
+pub fn read_json(input: &str) -> Result<Vec<Instruction>, JsonError> {
+    let parsed = all_consuming(json)(input).map_err(|error| match error {
+        nom::Err::Error(error) | nom::Err::Failure(error) => {
+            JsonError::Syntax(convert_error(input, error))
+        }
+        // All of these parsers are complete parsers.
+        nom::Err::Incomplete(_) => unreachable!("Complete parsers asked for more input."),
+    })?;
+    let instructions = match parsed.1 {
+        Json::Object(members) => match members.into_iter().find(|(key, _)| key == "instructions") {
+            Some((_, Json::Array(instructions))) => instructions,
+            Some((_, other)) => {
+                return Err(JsonError::NotAProgram(format!(
+                    "\"instructions\" has to be a list, not {}",
+                    other.kind()
+                )))
+            }
+            None => {
+                return Err(JsonError::NotAProgram(
+                    "a program needs a list of \"instructions\"".to_string(),
+                ))
+            }
+        },
+        other => {
+            return Err(JsonError::NotAProgram(format!(
+                "a program has to be an object, not {}",
+                other.kind()
+            )))
+        }
+    };
+    instructions
+        .iter()
+        .enumerate()
+        .map(|(index, json)| {
+            instruction(json).map_err(|message| JsonError::Instruction { index, message })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assemble;
+
+    #[test]
+    fn round_trips() {
+        let program = assemble::program(
+            r#"NOP
+               ICONST -12
+               SCONST "quote \" backslash \\ newline
+"
+               RESERVE s 16 "init"
+               RESERVE i 4 (null)
+               READ i
+               WRITE s
+               ARGLOCAL_READ 0
+               ARGLOCAL_WRITE 1
+               L0:
+               JUMP L0
+               BRANCHZERO L0
+               FUNCTION f 2
+               @inline @key(some value) FUNCTION g 0
+               CALL f 3
+               RET
+               INTRINSIC PRINT_STRING
+               PUSH 1
+               POP -1
+               MOD"#,
+        )
+        .unwrap();
+        let json = write_json(&program);
+        assert!(json.contains(
+            "\n  {\"op\": \"FUNCTION\", \"label\": \"g\", \"num_locs\": 0, \"attributes\": \
+             [{\"name\": \"inline\", \"value\": null}, {\"name\": \"key\", \"value\": \"some value\"}]},\n"
+        ));
+        assert_eq!(read_json(&json), Ok(program));
+        assert_eq!(read_json(&write_json(&[])), Ok(vec![]));
+    }
+
+    #[test]
+    fn reads_what_tools_write() {
+        let json = r#"{ "instructions" : [
+            {"op": "iconst", "value": 42},
+            {"value": "caf\u00e9 \ud83d\udc26\n", "op": "SCONST"},
+            {"op": "FUNCTION", "label": "f", "num_locs": 1},
+            {"op": "intrinsic", "intrinsic": "print_int"}
+        ] }"#;
+        assert_eq!(
+            read_json(json),
+            Ok(vec![
+                Instruction::Iconst(42),
+                Instruction::Sconst("café 🐦\n".into()),
+                Instruction::Function {
+                    label: Label::named("f"),
+                    num_locs: 1,
+                    attributes: vec![],
+                },
+                Instruction::Intrinsic(Intrinsic::PrintInt),
+            ])
+        );
+    }
+
+    #[test]
+    fn errors() {
+        let program = |instruction: &str| {
+            format!("{{\"instructions\": [{{\"op\": \"NOP\"}}, {instruction}]}}")
+        };
+        let error = |instruction: &str| match read_json(&program(instruction)) {
+            Err(JsonError::Instruction { index: 1, message }) => message,
+            other => panic!("{other:?}"),
+        };
+        assert_eq!(error(r#"{"op": "ICONST"}"#), "ICONST needs a \"value\"");
+        assert_eq!(
+            error(r#"{"op": "ICONST", "vlaue": 1, "value": 1}"#),
+            "ICONST doesn't have a \"vlaue\""
+        );
+        assert_eq!(
+            error(r#"{"op": "ARGLOCAL_READ", "index": -1}"#),
+            "-1 is out of range for the \"index\" of ARGLOCAL_READ"
+        );
+        assert_eq!(
+            error(r#"{"op": "JUMP", "label": "two words"}"#),
+            "the \"label\" of JUMP has to be letters, digits, $ and _, not \"two words\""
+        );
+        assert_eq!(
+            error(r#"{"op": "FLY"}"#),
+            "there's no instruction called \"FLY\""
+        );
+        assert_eq!(error("[]"), "has to be an object, not a list");
+        assert_eq!(
+            read_json("[]"),
+            Err(JsonError::NotAProgram(
+                "a program has to be an object, not a list".into()
+            ))
+        );
+        assert!(matches!(
+            read_json(&program(r#"{"op": "ICONST", "value": 1.5}"#)),
+            Err(JsonError::Syntax(_))
+        ));
+        assert!(matches!(
+            read_json("{\"instructions\": [}"),
+            Err(JsonError::Syntax(_))
+        ));
+    }
+}
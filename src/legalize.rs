@@ -0,0 +1,145 @@
+// Rewrites a program so a target can take every instruction in it, just
+// before it's encoded for that target. What can't be rewritten is reported,
+// with the instruction and the target, instead of turning up as an encoding
+// error (or worse) later.
+//
+// The only target so far is the C interpreter's bytecode, where every number
+// is 32 bits. An ICONST too big for that is wrapped around to the value the
+// Rust interpreter would push for it. Strings with a NUL in them and other
+// numbers that don't fit can't be made legal.
+
+use std::{fmt, io};
+
+use crate::{
+    ir_definition::Instruction,
+    write_bytecode::{write_bytecode, BytecodeWriteError},
+};
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Target {
+    /// Bytecode for the C interpreter.
+    InterpBytecode,
+}
+
+impl fmt::Display for Target {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Target::InterpBytecode => write!(f, "interp-bytecode"),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct LegalizeError {
+    pub index: usize,
+    pub instruction: Instruction,
+    pub target: Target,
+    pub reason: String,
+}
+
+impl fmt::Display for LegalizeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "instruction {} ({}) can't be made legal for {}: {}",
+            self.index, self.instruction, self.target, self.reason
+        )
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Legalized {
+    pub program: Vec<Instruction>,
+    /// How many instructions were rewritten.
+    pub rewrites: usize,
+}
+
+/// Makes `program` legal for `target`, or finds every instruction that
+/// can't be, in the order they appear.
+pub fn legalize(
+    program: Vec<Instruction>,
+    target: Target,
+) -> Result<Legalized, Vec<LegalizeError>> {
+    match target {
+        Target::InterpBytecode => legalize_for_bytecode(program),
+    }
+}
+
+fn legalize_for_bytecode(mut program: Vec<Instruction>) -> Result<Legalized, Vec<LegalizeError>> {
+    let mut rewrites = 0;
+    let mut errors = Vec::new();
+    for (index, instruction) in program.iter_mut().enumerate() {
+        if let Instruction::Iconst(value) = instruction {
+            let wrapped = *value as i32;
+            if i64::from(wrapped) != *value {
+                *value = wrapped.into();
+                rewrites += 1;
+            }
+        }
+        let reason = match write_bytecode(std::slice::from_ref(instruction), &mut io::sink()) {
+            Ok(()) => continue,
+            Err(BytecodeWriteError::Overflow { value, .. }) => {
+                format!("{value} doesn't fit in the bytecode's 32-bit integers")
+            }
+            Err(BytecodeWriteError::InteriorNul { text, .. }) => {
+                format!("{text:?} has a NUL in it, which bytecode strings can't")
+            }
+            Err(BytecodeWriteError::Io(_)) => unreachable!("Writing to a sink can't fail."),
+        };
+        errors.push(LegalizeError {
+            index,
+            instruction: instruction.clone(),
+            target: Target::InterpBytecode,
+            reason,
+        });
+    }
+    if errors.is_empty() {
+        Ok(Legalized { program, rewrites })
+    } else {
+        Err(errors)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{assemble, interpret_rust::interpret};
+
+    #[test]
+    fn wraps_constants() {
+        let program = assemble::program(
+            "ICONST 4294967297
+             ICONST -2147483649
+             ADD
+             INTRINSIC PRINT_INT",
+        )
+        .unwrap();
+        let legalized = legalize(program.clone(), Target::InterpBytecode).unwrap();
+        assert_eq!(legalized.rewrites, 2);
+        assert_eq!(legalized.program[0], Instruction::Iconst(1));
+        assert_eq!(legalized.program[1], Instruction::Iconst(i32::MAX.into()));
+        assert_eq!(
+            interpret(&legalized.program).unwrap().output,
+            interpret(&program).unwrap().output
+        );
+    }
+
+    #[test]
+    fn reports_what_cant_be_legal() {
+        let program = vec![
+            Instruction::Sconst("a\0b".into()),
+            Instruction::ArgLocalRead(1 << 31),
+            Instruction::Iconst(1 << 40),
+        ];
+        let errors = legalize(program, Target::InterpBytecode).unwrap_err();
+        assert_eq!(
+            errors.iter().map(ToString::to_string).collect::<Vec<_>>(),
+            [
+                "instruction 0 (SCONST \"a\0b\") can't be made legal for interp-bytecode: \
+                 \"a\\0b\" has a NUL in it, which bytecode strings can't",
+                "instruction 1 (ARGLOCAL_READ 2147483648) can't be made legal for \
+                 interp-bytecode: 2147483648 doesn't fit in the bytecode's 32-bit integers",
+            ]
+        );
+    }
+}
@@ -1,4 +1,38 @@
+pub mod analysis;
+pub mod arithmetic;
+pub mod arity;
 pub mod assemble;
+pub mod ast_dump;
+pub mod atomic_file;
 pub mod bindings;
+pub mod builder;
+pub mod c_header;
+pub mod c_interpreter;
+pub mod conformance;
+pub mod disassemble;
+pub mod explain;
+pub mod extract;
+pub mod fingerprint;
+pub mod frontend;
+pub mod generate;
+pub mod interpret_rust;
 pub mod ir_definition;
+pub mod json;
+pub mod legalize;
+pub mod opcode;
+pub mod optimize;
+pub mod output_sink;
+pub mod print_text;
+pub mod reduce;
+pub mod register_form;
+pub mod report;
+pub mod resource_usage;
+pub mod signing;
+pub mod similarity;
+pub mod source_map;
+pub mod stats;
+pub mod termination;
+pub mod timings;
+pub mod trace;
+pub mod verify;
 pub mod write_bytecode;
@@ -0,0 +1,599 @@
+// The reference for the instruction set lives here, so tools (and people) can
+// ask the crate what an instruction does instead of reading the C code.
+
+use std::ops::RangeInclusive;
+
+use crate::{
+    bindings::*,
+    ir_definition::{Instruction, Intrinsic},
+};
+
+/// Every kind of instruction, without its operands. `Instruction::ReserveInt`
+/// and `Instruction::ReserveString` are both `Opcode::Reserve`, like in the
+/// bytecode.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash, PartialOrd, Ord)]
+pub enum Opcode {
+    Nop,
+    Iconst,
+    Sconst,
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Mod,
+    Bor,
+    Band,
+    Xor,
+    Or,
+    And,
+    Eq,
+    Lt,
+    Gt,
+    Not,
+    Reserve,
+    Read,
+    Write,
+    ArgLocalRead,
+    ArgLocalWrite,
+    Label,
+    Jump,
+    BranchZero,
+    Function,
+    Call,
+    Ret,
+    Intrinsic,
+    Push,
+    Pop,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum OperandKind {
+    /// A signed integer constant.
+    Integer,
+    /// An unsigned count, size, or index.
+    Count,
+    /// A double-quoted string literal, or `(null)` for `RESERVE`.
+    String,
+    /// The name of a global variable.
+    Name,
+    /// The name of a label or function.
+    Label,
+    /// One of the intrinsics, like `PRINT_INT`.
+    Intrinsic,
+    /// A register number.
+    Register,
+}
+
+impl OperandKind {
+    pub fn name(self) -> &'static str {
+        match self {
+            OperandKind::Integer => "integer",
+            OperandKind::Count => "count",
+            OperandKind::String => "string",
+            OperandKind::Name => "global name",
+            OperandKind::Label => "label",
+            OperandKind::Intrinsic => "intrinsic",
+            OperandKind::Register => "register",
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct OpcodeInfo {
+    /// How the instruction is spelled in the textual format (case doesn't matter).
+    pub mnemonic: &'static str,
+    pub syntax: &'static str,
+    pub operands: &'static [OperandKind],
+    pub description: &'static str,
+    /// In Forth's notation: the values popped, then `--`, then the values pushed.
+    pub stack_effect: &'static str,
+    /// What can go wrong at runtime, if anything.
+    pub traps: &'static str,
+    pub example: &'static str,
+}
+
+macro_rules! binary_op_info {
+    ($mnemonic:literal, $description:literal, $result:literal, $traps:literal) => {
+        OpcodeInfo {
+            mnemonic: $mnemonic,
+            syntax: $mnemonic,
+            operands: &[],
+            description: $description,
+            stack_effect: concat!("a b -- ", $result),
+            traps: $traps,
+            example: concat!("ICONST 6\nICONST 3\n", $mnemonic),
+        }
+    };
+}
+
+impl Opcode {
+    pub const ALL: [Opcode; 31] = [
+        Opcode::Nop,
+        Opcode::Iconst,
+        Opcode::Sconst,
+        Opcode::Add,
+        Opcode::Sub,
+        Opcode::Mul,
+        Opcode::Div,
+        Opcode::Mod,
+        Opcode::Bor,
+        Opcode::Band,
+        Opcode::Xor,
+        Opcode::Or,
+        Opcode::And,
+        Opcode::Eq,
+        Opcode::Lt,
+        Opcode::Gt,
+        Opcode::Not,
+        Opcode::Reserve,
+        Opcode::Read,
+        Opcode::Write,
+        Opcode::ArgLocalRead,
+        Opcode::ArgLocalWrite,
+        Opcode::Label,
+        Opcode::Jump,
+        Opcode::BranchZero,
+        Opcode::Function,
+        Opcode::Call,
+        Opcode::Ret,
+        Opcode::Intrinsic,
+        Opcode::Push,
+        Opcode::Pop,
+    ];
+
+    pub fn mnemonic(self) -> &'static str {
+        self.info().mnemonic
+    }
+
+    /// Looks an opcode up by its mnemonic, ignoring case like the assembler does.
+    pub fn from_mnemonic(mnemonic: &str) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|opcode| opcode.mnemonic().eq_ignore_ascii_case(mnemonic))
+    }
+
+    /// The number that stands for this opcode in the bytecode. The numbers come
+    /// from the C code's headers, through the bindings.
+    pub fn number(self) -> u32 {
+        match self {
+            Opcode::Nop => ir_op_ir_nop,
+            Opcode::Iconst => ir_op_ir_iconst,
+            Opcode::Sconst => ir_op_ir_sconst,
+            Opcode::Add => ir_op_ir_add,
+            Opcode::Sub => ir_op_ir_sub,
+            Opcode::Mul => ir_op_ir_mul,
+            Opcode::Div => ir_op_ir_div,
+            Opcode::Mod => ir_op_ir_mod,
+            Opcode::Bor => ir_op_ir_bor,
+            Opcode::Band => ir_op_ir_band,
+            Opcode::Xor => ir_op_ir_xor,
+            Opcode::Or => ir_op_ir_or,
+            Opcode::And => ir_op_ir_and,
+            Opcode::Eq => ir_op_ir_eq,
+            Opcode::Lt => ir_op_ir_lt,
+            Opcode::Gt => ir_op_ir_gt,
+            Opcode::Not => ir_op_ir_not,
+            Opcode::Reserve => ir_op_ir_reserve,
+            Opcode::Read => ir_op_ir_read,
+            Opcode::Write => ir_op_ir_write,
+            Opcode::ArgLocalRead => ir_op_ir_arglocal_read,
+            Opcode::ArgLocalWrite => ir_op_ir_arglocal_write,
+            Opcode::Label => ir_op_ir_lbl,
+            Opcode::Jump => ir_op_ir_jump,
+            Opcode::BranchZero => ir_op_ir_branchzero,
+            Opcode::Function => ir_op_ir_function,
+            Opcode::Call => ir_op_ir_call,
+            Opcode::Ret => ir_op_ir_ret,
+            Opcode::Intrinsic => ir_op_ir_intrinsic,
+            Opcode::Push => ir_op_ir_push,
+            Opcode::Pop => ir_op_ir_pop,
+        }
+    }
+
+    /// Looks an opcode up by the number that stands for it in the bytecode.
+    pub fn from_number(number: u32) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|opcode| opcode.number() == number)
+    }
+
+    pub fn info(self) -> &'static OpcodeInfo {
+        use OperandKind::*;
+        match self {
+            Opcode::Nop => &OpcodeInfo {
+                mnemonic: "NOP",
+                syntax: "NOP",
+                operands: &[],
+                description: "Does nothing.",
+                stack_effect: "--",
+                traps: "",
+                example: "NOP",
+            },
+            Opcode::Iconst => &OpcodeInfo {
+                mnemonic: "ICONST",
+                syntax: "ICONST <integer>",
+                operands: &[Integer],
+                description: "Pushes an integer constant. The bytecode only has room for 32-bit \
+                              constants.",
+                stack_effect: "-- n",
+                traps: "",
+                example: "ICONST 0x2A",
+            },
+            Opcode::Sconst => &OpcodeInfo {
+                mnemonic: "SCONST",
+                syntax: "SCONST \"<string>\"",
+                operands: &[String],
+                description: "Pushes a string constant. Inside the quotes, \\\" is a double quote \
+                              and \\\\ is a backslash; everything else, newlines included, is \
+                              taken literally.",
+                stack_effect: "-- s",
+                traps: "",
+                example: "SCONST \"Hello, world!\"",
+            },
+            Opcode::Add => &binary_op_info!("ADD", "Adds two integers.", "a+b", ""),
+            Opcode::Sub => &binary_op_info!(
+                "SUB",
+                "Subtracts the top integer from the one below it.",
+                "a-b",
+                ""
+            ),
+            Opcode::Mul => &binary_op_info!("MUL", "Multiplies two integers.", "a*b", ""),
+            Opcode::Div => &binary_op_info!(
+                "DIV",
+                "Divides the integer below the top by the top one, rounding towards zero.",
+                "a/b",
+                "Division by zero. The smallest integer DIV -1 is itself in the Rust interpreter, but \
+                 usually kills the C one."
+            ),
+            Opcode::Mod => &binary_op_info!(
+                "MOD",
+                "The remainder of dividing the integer below the top by the top one. It has the \
+                 sign of the dividend.",
+                "a%b",
+                "Division by zero. The smallest integer MOD -1 is 0 in the Rust interpreter, but \
+                 usually kills the C one."
+            ),
+            Opcode::Bor => &binary_op_info!("BOR", "Bitwise or of two integers.", "a|b", ""),
+            Opcode::Band => &binary_op_info!("BAND", "Bitwise and of two integers.", "a&b", ""),
+            Opcode::Xor => &binary_op_info!("XOR", "Bitwise xor of two integers.", "a^b", ""),
+            Opcode::Or => &binary_op_info!(
+                "OR",
+                "Logical or: pushes 1 if either integer is nonzero, and 0 otherwise.",
+                "a||b",
+                ""
+            ),
+            Opcode::And => &binary_op_info!(
+                "AND",
+                "Logical and: pushes 1 if both integers are nonzero, and 0 otherwise.",
+                "a&&b",
+                ""
+            ),
+            Opcode::Eq => &binary_op_info!(
+                "EQ",
+                "Pushes 1 if the two integers are equal, and 0 otherwise.",
+                "a==b",
+                ""
+            ),
+            Opcode::Lt => &binary_op_info!(
+                "LT",
+                "Pushes 1 if the integer below the top is less than the top one, and 0 otherwise.",
+                "a<b",
+                ""
+            ),
+            Opcode::Gt => &binary_op_info!(
+                "GT",
+                "Pushes 1 if the integer below the top is greater than the top one, and 0 \
+                 otherwise.",
+                "a>b",
+                ""
+            ),
+            Opcode::Not => &OpcodeInfo {
+                mnemonic: "NOT",
+                syntax: "NOT",
+                operands: &[],
+                description: "Logical not: pushes 1 if the integer is 0, and 0 otherwise.",
+                stack_effect: "a -- !a",
+                traps: "",
+                example: "ICONST 0\nNOT",
+            },
+            Opcode::Reserve => &OpcodeInfo {
+                mnemonic: "RESERVE",
+                syntax: "RESERVE <name> <size> \"<initial value>\" | RESERVE <name> 4 (null)",
+                operands: &[Name, Count, String],
+                description:
+                    "Declares a global variable. Strings are given a size in bytes and an \
+                              initial value; integers are given an initial value of (null), and \
+                              always take 4 bytes.",
+                stack_effect: "--",
+                traps: "",
+                example: "RESERVE greeting 6 \"Hello\"\nRESERVE counter 4 (null)",
+            },
+            Opcode::Read => &OpcodeInfo {
+                mnemonic: "READ",
+                syntax: "READ <name>",
+                operands: &[Name],
+                description: "Pushes the value of a global variable.",
+                stack_effect: "-- value",
+                traps: "The global was never reserved.",
+                example: "READ counter",
+            },
+            Opcode::Write => &OpcodeInfo {
+                mnemonic: "WRITE",
+                syntax: "WRITE <name>",
+                operands: &[Name],
+                description: "Pops a value into a global variable.",
+                stack_effect: "value --",
+                traps: "The global was never reserved.",
+                example: "ICONST 0\nWRITE counter",
+            },
+            Opcode::ArgLocalRead => &OpcodeInfo {
+                mnemonic: "ARGLOCAL_READ",
+                syntax: "ARGLOCAL_READ <index>",
+                operands: &[Count],
+                description: "Pushes an argument or local of the current function. Indices start \
+                              at 0 with the arguments, in the order the caller pushed them, and \
+                              continue with the function's locals.",
+                stack_effect: "-- value",
+                traps: "The index is past the function's arguments and locals, or the program is \
+                        not inside a function.",
+                example: "ARGLOCAL_READ 0",
+            },
+            Opcode::ArgLocalWrite => &OpcodeInfo {
+                mnemonic: "ARGLOCAL_WRITE",
+                syntax: "ARGLOCAL_WRITE <index>",
+                operands: &[Count],
+                description: "Pops a value into an argument or local of the current function, \
+                              indexed like ARGLOCAL_READ.",
+                stack_effect: "value --",
+                traps: "The index is past the function's arguments and locals, or the program is \
+                        not inside a function.",
+                example: "ICONST 1\nARGLOCAL_WRITE 0",
+            },
+            Opcode::Label => &OpcodeInfo {
+                mnemonic: "LABEL",
+                syntax: "<label>:",
+                operands: &[Label],
+                description: "Marks a place in the program that can be jumped to. Does nothing \
+                              when executed.",
+                stack_effect: "--",
+                traps: "",
+                example: "L0:",
+            },
+            Opcode::Jump => &OpcodeInfo {
+                mnemonic: "JUMP",
+                syntax: "JUMP <label>",
+                operands: &[Label],
+                description: "Continues execution at a label.",
+                stack_effect: "--",
+                traps: "The label doesn't exist.",
+                example: "JUMP L0",
+            },
+            Opcode::BranchZero => &OpcodeInfo {
+                mnemonic: "BRANCHZERO",
+                syntax: "BRANCHZERO <label>",
+                operands: &[Label],
+                description: "Pops an integer, and continues execution at a label if it was 0.",
+                stack_effect: "condition --",
+                traps: "The label doesn't exist.",
+                example: "ICONST 0\nBRANCHZERO L0",
+            },
+            Opcode::Function => &OpcodeInfo {
+                mnemonic: "FUNCTION",
+                syntax: "FUNCTION <label> <number of locals>",
+                operands: &[Label, Count],
+                description: "Marks the start of a function that has the given number of locals \
+                              after its arguments. Programs jump over function bodies; like a \
+                              label, it does nothing when executed. Attributes, like @inline or \
+                              @key(value), can go right before it, but aren't kept in bytecode. \
+                              @args(n) declares that the function takes n arguments.",
+                stack_effect: "--",
+                traps: "",
+                example: "FUNCTION square 0",
+            },
+            Opcode::Call => &OpcodeInfo {
+                mnemonic: "CALL",
+                syntax: "CALL <label> <number of arguments>",
+                operands: &[Label, Count],
+                description: "Calls a function. The caller first pushes a placeholder for the \
+                              return value, then the arguments. When the function returns, the \
+                              arguments are gone and the placeholder has been replaced by the \
+                              return value.",
+                stack_effect: "placeholder arg_0 ... arg_n-1 -- result",
+                traps: "The function doesn't exist.",
+                example: "ICONST 0\nICONST 7\nCALL square 1",
+            },
+            Opcode::Ret => &OpcodeInfo {
+                mnemonic: "RET",
+                syntax: "RET",
+                operands: &[],
+                description: "Pops the return value and returns from the current function.",
+                stack_effect: "result --",
+                traps: "The program is not inside a function.",
+                example: "ARGLOCAL_READ 0\nARGLOCAL_READ 0\nMUL\nRET",
+            },
+            Opcode::Intrinsic => &OpcodeInfo {
+                mnemonic: "INTRINSIC",
+                syntax: "INTRINSIC <PRINT_INT | PRINT_STRING | EXIT>",
+                operands: &[Intrinsic],
+                description: "Runs one of the routines built into the interpreter. PRINT_INT and \
+                              PRINT_STRING print a value without a trailing newline; EXIT ends \
+                              the program with an exit code.",
+                stack_effect: "value --",
+                traps: "",
+                example: "SCONST \"Hello\"\nINTRINSIC PRINT_STRING",
+            },
+            Opcode::Push => &OpcodeInfo {
+                mnemonic: "PUSH",
+                syntax: "PUSH <register>",
+                operands: &[Register],
+                description: "Pushes the value of a register. Front-ends shouldn't need this.",
+                stack_effect: "-- value",
+                traps: "",
+                example: "PUSH 1",
+            },
+            Opcode::Pop => &OpcodeInfo {
+                mnemonic: "POP",
+                syntax: "POP <register>",
+                operands: &[Register],
+                description: "Pops a value into a register. Register -1 throws the value away, \
+                              which is how unused return values get discarded.",
+                stack_effect: "value --",
+                traps: "",
+                example: "POP -1",
+            },
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct IntrinsicInfo {
+    pub description: &'static str,
+    /// In the same notation as `OpcodeInfo::stack_effect`.
+    pub stack_effect: &'static str,
+    pub example: &'static str,
+}
+
+impl Intrinsic {
+    /// Numbers no intrinsic here will ever have, so a fork can give its own
+    /// intrinsics numbers from it without colliding with ones added later.
+    pub const EXPERIMENTAL: RangeInclusive<u32> = 0x8000..=0xFFFF;
+
+    /// The number that stands for this intrinsic in the bytecode, like
+    /// `Opcode::number`.
+    pub fn number(self) -> u32 {
+        match self {
+            Intrinsic::PrintInt => intrinsic_intrinsic_print_int,
+            Intrinsic::PrintString => intrinsic_intrinsic_print_string,
+            Intrinsic::Exit => intrinsic_intrinsic_exit,
+        }
+    }
+
+    /// Looks an intrinsic up by the number that stands for it in the bytecode.
+    pub fn from_number(number: u32) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|intrinsic| intrinsic.number() == number)
+    }
+
+    pub fn info(self) -> &'static IntrinsicInfo {
+        match self {
+            Intrinsic::PrintInt => &IntrinsicInfo {
+                description: "Pops an integer and prints it in decimal, without a newline.",
+                stack_effect: "n --",
+                example: "ICONST 473\nINTRINSIC PRINT_INT",
+            },
+            Intrinsic::PrintString => &IntrinsicInfo {
+                description: "Pops a string and prints it, without adding a newline.",
+                stack_effect: "s --",
+                example: "SCONST \"Hello\"\nINTRINSIC PRINT_STRING",
+            },
+            Intrinsic::Exit => &IntrinsicInfo {
+                description: "Pops an integer and ends the program, using it as the exit code.",
+                stack_effect: "code --",
+                example: "ICONST 0\nINTRINSIC EXIT",
+            },
+        }
+    }
+}
+
+impl Instruction {
+    pub fn opcode(&self) -> Opcode {
+        match self {
+            Instruction::Nop => Opcode::Nop,
+            Instruction::Iconst(_) => Opcode::Iconst,
+            Instruction::Sconst(_) => Opcode::Sconst,
+            Instruction::Add => Opcode::Add,
+            Instruction::Sub => Opcode::Sub,
+            Instruction::Mul => Opcode::Mul,
+            Instruction::Div => Opcode::Div,
+            Instruction::Mod => Opcode::Mod,
+            Instruction::Bor => Opcode::Bor,
+            Instruction::Band => Opcode::Band,
+            Instruction::Xor => Opcode::Xor,
+            Instruction::Or => Opcode::Or,
+            Instruction::And => Opcode::And,
+            Instruction::Eq => Opcode::Eq,
+            Instruction::Lt => Opcode::Lt,
+            Instruction::Gt => Opcode::Gt,
+            Instruction::Not => Opcode::Not,
+            Instruction::ReserveString { .. } | Instruction::ReserveInt { .. } => Opcode::Reserve,
+            Instruction::Read(_) => Opcode::Read,
+            Instruction::Write(_) => Opcode::Write,
+            Instruction::ArgLocalRead(_) => Opcode::ArgLocalRead,
+            Instruction::ArgLocalWrite(_) => Opcode::ArgLocalWrite,
+            Instruction::Label(_) => Opcode::Label,
+            Instruction::Jump(_) => Opcode::Jump,
+            Instruction::BranchZero(_) => Opcode::BranchZero,
+            Instruction::Function { .. } => Opcode::Function,
+            Instruction::Call { .. } => Opcode::Call,
+            Instruction::Ret => Opcode::Ret,
+            Instruction::Intrinsic(_) => Opcode::Intrinsic,
+            Instruction::Push { .. } => Opcode::Push,
+            Instruction::Pop { .. } => Opcode::Pop,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assemble;
+
+    #[test]
+    fn mnemonics_round_trip() {
+        for opcode in Opcode::ALL {
+            assert_eq!(Opcode::from_mnemonic(opcode.mnemonic()), Some(opcode));
+            assert_eq!(
+                Opcode::from_mnemonic(&opcode.mnemonic().to_lowercase()),
+                Some(opcode)
+            );
+        }
+        assert_eq!(Opcode::from_mnemonic("NOT_AN_OPCODE"), None);
+    }
+
+    #[test]
+    fn numbers_round_trip() {
+        for opcode in Opcode::ALL {
+            assert_eq!(Opcode::from_number(opcode.number()), Some(opcode));
+        }
+        for intrinsic in Intrinsic::ALL {
+            assert_eq!(Intrinsic::from_number(intrinsic.number()), Some(intrinsic));
+            assert!(!Intrinsic::EXPERIMENTAL.contains(&intrinsic.number()));
+        }
+    }
+
+    #[test]
+    fn examples_assemble_and_end_in_their_opcode() {
+        for opcode in Opcode::ALL {
+            let info = opcode.info();
+            let example = assemble::program(info.example).unwrap_or_else(|e| {
+                panic!("The example for {} didn't parse: {e:?}", info.mnemonic)
+            });
+            assert!(
+                example
+                    .iter()
+                    .any(|instruction| instruction.opcode() == opcode),
+                "The example for {} doesn't use it.",
+                info.mnemonic
+            );
+        }
+    }
+
+    #[test]
+    fn intrinsic_examples_assemble_and_use_their_intrinsic() {
+        for intrinsic in Intrinsic::ALL {
+            let example = assemble::program(intrinsic.info().example).unwrap();
+            assert!(example.contains(&Instruction::Intrinsic(intrinsic)));
+        }
+    }
+
+    #[test]
+    fn syntax_starts_with_mnemonic() {
+        for opcode in Opcode::ALL {
+            let info = opcode.info();
+            if opcode != Opcode::Label {
+                assert!(info.syntax.starts_with(info.mnemonic));
+            }
+        }
+    }
+}
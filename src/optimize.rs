@@ -0,0 +1,4 @@
+// Rewrites of programs that keep what they do, but make them smaller or
+// faster.
+
+pub mod peephole;
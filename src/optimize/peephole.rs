@@ -0,0 +1,350 @@
+// Peephole optimization: replacing short runs of instructions with shorter
+// ones that do the same thing, one run at a time.
+
+use crate::{
+    arithmetic::{self, DivisionOverflow},
+    ir_definition::{Instruction, Intrinsic},
+};
+
+/// A rewrite of a short run of instructions.
+///
+/// Rules may assume that the program doesn't trap, so `ICONST 0 ADD` can go
+/// even though it traps when there's a string on the stack. Since labels are
+/// instructions too, a run that doesn't contain one can't be jumped into.
+pub trait Rule {
+    /// What the rule is called in `Optimized::rewrites`.
+    fn name(&self) -> &str;
+
+    /// Looks at the start of `instructions`, which runs to the end of the
+    /// program. If the rule applies, returns how many instructions it replaces
+    /// and what with. The replacement has to be shorter, so that optimizing
+    /// always finishes.
+    fn rewrite(&self, instructions: &[Instruction]) -> Option<(usize, Vec<Instruction>)>;
+}
+
+/// A rule that replaces exactly `find` with `replace`.
+#[derive(Debug, Clone)]
+pub struct Pattern {
+    pub name: String,
+    pub find: Vec<Instruction>,
+    pub replace: Vec<Instruction>,
+}
+
+impl Rule for Pattern {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn rewrite(&self, instructions: &[Instruction]) -> Option<(usize, Vec<Instruction>)> {
+        instructions
+            .starts_with(&self.find)
+            .then(|| (self.find.len(), self.replace.clone()))
+    }
+}
+
+/// `PUSH r POP r` puts register `r` back where it was.
+struct PushPop;
+
+impl Rule for PushPop {
+    fn name(&self) -> &str {
+        "push then pop"
+    }
+
+    fn rewrite(&self, instructions: &[Instruction]) -> Option<(usize, Vec<Instruction>)> {
+        match instructions {
+            [Instruction::Push { reg: pushed }, Instruction::Pop { reg: popped }, ..]
+                if pushed == popped =>
+            {
+                Some((2, vec![]))
+            }
+            _ => None,
+        }
+    }
+}
+
+/// `NOT NOT` turns any nonzero integer into 1, so it isn't a no-op by itself,
+/// but it is when all that's done with the result is to test it for zero.
+struct DoubleNotBranch;
+
+impl Rule for DoubleNotBranch {
+    fn name(&self) -> &str {
+        "double not before a branch"
+    }
+
+    fn rewrite(&self, instructions: &[Instruction]) -> Option<(usize, Vec<Instruction>)> {
+        match instructions {
+            [Instruction::Not, Instruction::Not, branch @ Instruction::BranchZero(_), ..] => {
+                Some((3, vec![branch.clone()]))
+            }
+            _ => None,
+        }
+    }
+}
+
+/// `ICONST a ICONST b` and an instruction that takes two integers is the
+/// constant it gives, computed like the interpreter would. Dividing by zero,
+/// or the smallest integer by -1, is left alone, so the program still does
+/// the same on both interpreters. So are constants that don't fit in 32 bits,
+/// which the bytecode can't hold.
+struct FoldConstants;
+
+impl Rule for FoldConstants {
+    fn name(&self) -> &str {
+        "fold constants"
+    }
+
+    fn rewrite(&self, instructions: &[Instruction]) -> Option<(usize, Vec<Instruction>)> {
+        let [Instruction::Iconst(a), Instruction::Iconst(b), operation, ..] = instructions else {
+            return None;
+        };
+        let (a, b) = (i32::try_from(*a).ok()?, i32::try_from(*b).ok()?);
+        let result = arithmetic::binary(operation, a, b, DivisionOverflow::Fail)?.ok()?;
+        Some((3, vec![Instruction::Iconst(result.into())]))
+    }
+}
+
+/// A set of rules, and the loop that applies them.
+pub struct Peephole {
+    rules: Vec<Box<dyn Rule>>,
+}
+
+/// What `Peephole::optimize` did.
+#[derive(Debug, PartialEq)]
+pub struct Optimized {
+    pub program: Vec<Instruction>,
+    /// How many times each rule applied, by name, in the order the rules were
+    /// added. Rules that never applied aren't here.
+    pub rewrites: Vec<(String, usize)>,
+}
+
+impl Peephole {
+    /// No rules at all, for building up a set of your own.
+    pub fn empty() -> Self {
+        Peephole { rules: Vec::new() }
+    }
+
+    /// The rules that come with the crate.
+    pub fn standard() -> Self {
+        let pattern = |name: &str, find: Vec<Instruction>, replace: Vec<Instruction>| Pattern {
+            name: name.to_string(),
+            find,
+            replace,
+        };
+        Peephole::empty()
+            .with_rule(pattern(
+                "add zero",
+                vec![Instruction::Iconst(0), Instruction::Add],
+                vec![],
+            ))
+            .with_rule(pattern(
+                "subtract zero",
+                vec![Instruction::Iconst(0), Instruction::Sub],
+                vec![],
+            ))
+            .with_rule(pattern(
+                "multiply by one",
+                vec![Instruction::Iconst(1), Instruction::Mul],
+                vec![],
+            ))
+            .with_rule(pattern(
+                "triple not",
+                vec![Instruction::Not, Instruction::Not, Instruction::Not],
+                vec![Instruction::Not],
+            ))
+            .with_rule(DoubleNotBranch)
+            .with_rule(PushPop)
+            .with_rule(FoldConstants)
+            .with_rule(pattern(
+                "print nothing",
+                vec![
+                    Instruction::Sconst(String::new()),
+                    Instruction::Intrinsic(Intrinsic::PrintString),
+                ],
+                vec![],
+            ))
+    }
+
+    /// Adds `rule` after the others. Where more than one rule applies, the
+    /// one added first wins.
+    pub fn with_rule(mut self, rule: impl Rule + 'static) -> Self {
+        self.rules.push(Box::new(rule));
+        self
+    }
+
+    /// Applies the rules until none of them apply anywhere. A replacement can
+    /// make a rule apply where it didn't before, including across where the
+    /// replaced instructions were, so passes go on until one changes nothing.
+    pub fn optimize(&self, mut program: Vec<Instruction>) -> Optimized {
+        let mut rewrites = vec![0; self.rules.len()];
+        loop {
+            let mut optimized = Vec::with_capacity(program.len());
+            let mut changed = false;
+            let mut index = 0;
+            while index < program.len() {
+                let rewrite = self.rules.iter().enumerate().find_map(|(which, rule)| {
+                    let (len, replacement) = rule.rewrite(&program[index..])?;
+                    assert!(
+                        replacement.len() < len && len <= program.len() - index,
+                        "The peephole rule \"{}\" didn't shorten the program.",
+                        rule.name()
+                    );
+                    Some((which, len, replacement))
+                });
+                match rewrite {
+                    Some((which, len, replacement)) => {
+                        rewrites[which] += 1;
+                        optimized.extend(replacement);
+                        index += len;
+                        changed = true;
+                    }
+                    None => {
+                        optimized.push(program[index].clone());
+                        index += 1;
+                    }
+                }
+            }
+            program = optimized;
+            if !changed {
+                break;
+            }
+        }
+        Optimized {
+            program,
+            rewrites: self
+                .rules
+                .iter()
+                .zip(rewrites)
+                .filter(|&(_, count)| count > 0)
+                .map(|(rule, count)| (rule.name().to_string(), count))
+                .collect(),
+        }
+    }
+}
+
+impl Default for Peephole {
+    fn default() -> Self {
+        Self::standard()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        assemble,
+        generate::{generate, GeneratorOptions},
+        interpret_rust::interpret,
+    };
+
+    #[test]
+    fn standard_rules() {
+        let program = assemble::program(
+            "PUSH 5
+             ICONST 1 MUL
+             ICONST 0 ADD
+             PUSH 1 POP 1
+             NOT NOT NOT NOT NOT
+             POP 1
+             PUSH 1
+             PUSH 1
+             NOT NOT
+             BRANCHZERO end
+             SCONST \"\" INTRINSIC PRINT_STRING
+             ICONST 0 end: ADD",
+        )
+        .unwrap();
+        let optimized = Peephole::standard().optimize(program);
+        assert_eq!(
+            optimized.program,
+            assemble::program(
+                "PUSH 5
+                 NOT
+                 POP 1
+                 PUSH 1
+                 PUSH 1
+                 BRANCHZERO end
+                 ICONST 0 end: ADD"
+            )
+            .unwrap()
+        );
+        assert_eq!(
+            optimized.rewrites,
+            [
+                ("add zero".to_string(), 1),
+                ("multiply by one".to_string(), 1),
+                ("triple not".to_string(), 2),
+                ("double not before a branch".to_string(), 1),
+                ("push then pop".to_string(), 1),
+                ("print nothing".to_string(), 1),
+            ]
+        );
+    }
+
+    #[test]
+    fn folding_constants() {
+        let program = assemble::program(
+            "ICONST -7 ICONST 2 MOD
+             ICONST 3 MUL
+             ICONST 1 ICONST 0 DIV
+             ICONST -2147483648 ICONST -1 DIV
+             ICONST 4294967296 ICONST 1 ADD",
+        )
+        .unwrap();
+        let optimized = Peephole::standard().optimize(program);
+        assert_eq!(
+            optimized.program,
+            assemble::program(
+                "ICONST -3
+                 ICONST 1 ICONST 0 DIV
+                 ICONST -2147483648 ICONST -1 DIV
+                 ICONST 4294967296 ICONST 1 ADD"
+            )
+            .unwrap()
+        );
+        assert_eq!(optimized.rewrites, [("fold constants".to_string(), 2)]);
+    }
+
+    struct DropNops;
+
+    impl Rule for DropNops {
+        fn name(&self) -> &str {
+            "drop nops"
+        }
+
+        fn rewrite(&self, instructions: &[Instruction]) -> Option<(usize, Vec<Instruction>)> {
+            let len = instructions
+                .iter()
+                .take_while(|&instruction| *instruction == Instruction::Nop)
+                .count();
+            (len > 0).then(|| (len, vec![]))
+        }
+    }
+
+    #[test]
+    fn rules_of_your_own() {
+        let program = assemble::program("NOP NOP ICONST 0 NOP ADD").unwrap();
+        let optimized = Peephole::standard().with_rule(DropNops).optimize(program);
+        assert!(optimized.program.is_empty());
+        assert_eq!(
+            optimized.rewrites,
+            [("add zero".to_string(), 1), ("drop nops".to_string(), 2)]
+        );
+    }
+
+    #[test]
+    fn generated_programs_run_the_same() {
+        for seed in 0..20 {
+            let program = generate(&GeneratorOptions {
+                seed,
+                ..Default::default()
+            });
+            let optimized = Peephole::standard().optimize(program.clone()).program;
+            let (before, after) = (interpret(&program), interpret(&optimized));
+            assert_eq!(
+                before.map(|result| (result.output, result.exit_code)),
+                after.map(|result| (result.output, result.exit_code)),
+                "seed {seed}"
+            );
+        }
+    }
+}
@@ -0,0 +1,177 @@
+// Where what a program prints goes, when it's run by `interpret_rust`.
+
+use std::{
+    io::{self, Write as _},
+    sync::mpsc,
+};
+
+/// Takes what a program prints, as it prints it. Each call to `print` is one
+/// `PRINT_INT` or `PRINT_STRING`, which doesn't have to end in a newline.
+pub trait OutputSink {
+    fn print(&mut self, text: &str);
+
+    /// Called once the program has stopped, however it stopped, for sinks
+    /// that hold on to some of what's printed.
+    fn finish(&mut self) {}
+}
+
+impl OutputSink for String {
+    fn print(&mut self, text: &str) {
+        self.push_str(text);
+    }
+}
+
+impl OutputSink for Vec<u8> {
+    fn print(&mut self, text: &str) {
+        self.extend_from_slice(text.as_bytes());
+    }
+}
+
+impl OutputSink for io::Stdout {
+    fn print(&mut self, text: &str) {
+        self.write_all(text.as_bytes())
+            .expect("Couldn't write to standard out.");
+    }
+
+    fn finish(&mut self) {
+        self.flush().expect("Couldn't write to standard out.");
+    }
+}
+
+/// Sends each print on its own. If the receiver has gone away, what's printed
+/// is dropped, like output nobody is reading.
+impl OutputSink for mpsc::Sender<String> {
+    fn print(&mut self, text: &str) {
+        let _ = self.send(text.to_string());
+    }
+}
+
+impl<S: OutputSink + ?Sized> OutputSink for &mut S {
+    fn print(&mut self, text: &str) {
+        (**self).print(text);
+    }
+
+    fn finish(&mut self) {
+        (**self).finish();
+    }
+}
+
+/// Writes what's printed to any writer, like a file. Printing can't fail, so
+/// the first error writing is kept for `into_inner`, and nothing more is
+/// written after it.
+pub struct Writer<W: io::Write> {
+    writer: W,
+    error: Option<io::Error>,
+}
+
+impl<W: io::Write> Writer<W> {
+    pub fn new(writer: W) -> Self {
+        Writer {
+            writer,
+            error: None,
+        }
+    }
+
+    /// The writer back, or the first error writing to it.
+    pub fn into_inner(self) -> io::Result<W> {
+        match self.error {
+            Some(error) => Err(error),
+            None => Ok(self.writer),
+        }
+    }
+
+    fn attempt(&mut self, write: impl FnOnce(&mut W) -> io::Result<()>) {
+        if self.error.is_none() {
+            self.error = write(&mut self.writer).err();
+        }
+    }
+}
+
+impl<W: io::Write> OutputSink for Writer<W> {
+    fn print(&mut self, text: &str) {
+        self.attempt(|writer| writer.write_all(text.as_bytes()));
+    }
+
+    fn finish(&mut self) {
+        self.attempt(|writer| writer.flush());
+    }
+}
+
+/// Calls a function with each whole line that's printed, without its newline.
+/// If the program stops partway through a line, that part is passed on too.
+pub struct Lines<F: FnMut(&str)> {
+    callback: F,
+    line: String,
+}
+
+impl<F: FnMut(&str)> Lines<F> {
+    pub fn new(callback: F) -> Self {
+        Lines {
+            callback,
+            line: String::new(),
+        }
+    }
+}
+
+impl<F: FnMut(&str)> OutputSink for Lines<F> {
+    fn print(&mut self, text: &str) {
+        let mut rest = text;
+        while let Some((before, after)) = rest.split_once('\n') {
+            self.line.push_str(before);
+            (self.callback)(&self.line);
+            self.line.clear();
+            rest = after;
+        }
+        self.line.push_str(rest);
+    }
+
+    fn finish(&mut self) {
+        if !self.line.is_empty() {
+            (self.callback)(&self.line);
+            self.line.clear();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{assemble, interpret_rust::interpret_with_output, interpret_rust::RunLimits};
+
+    const PROGRAM: &str = "SCONST \"one\ntw\" INTRINSIC PRINT_STRING
+                           SCONST \"o\nthree\" INTRINSIC PRINT_STRING
+                           ICONST 4 INTRINSIC PRINT_INT";
+
+    #[test]
+    fn lines() {
+        let program = assemble::program(PROGRAM).unwrap();
+        let mut lines = Vec::new();
+        let result = interpret_with_output(
+            &program,
+            &RunLimits::default(),
+            Lines::new(|line: &str| lines.push(line.to_string())),
+        )
+        .unwrap();
+        assert_eq!(lines, ["one", "two", "three4"]);
+        assert_eq!(result.output, "");
+    }
+
+    #[test]
+    fn channels_and_bytes() {
+        let program = assemble::program(PROGRAM).unwrap();
+        let (sender, receiver) = mpsc::channel();
+        interpret_with_output(&program, &RunLimits::default(), sender).unwrap();
+        assert_eq!(
+            receiver.iter().collect::<Vec<_>>(),
+            ["one\ntw", "o\nthree", "4"]
+        );
+
+        let mut bytes = Vec::new();
+        interpret_with_output(&program, &RunLimits::default(), &mut bytes).unwrap();
+        assert_eq!(bytes, b"one\ntwo\nthree4");
+
+        let mut writer = Writer::new(io::Cursor::new(Vec::new()));
+        interpret_with_output(&program, &RunLimits::default(), &mut writer).unwrap();
+        assert_eq!(writer.into_inner().unwrap().into_inner(), bytes);
+    }
+}
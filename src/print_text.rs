@@ -0,0 +1,74 @@
+// Prints whole programs in the textual format. Each instruction's `Display`
+// does the real work; this only decides how they're laid out.
+
+use std::fmt::Write as _;
+
+use crate::ir_definition::Instruction;
+
+/// The canonical textual form of `program`: one instruction per line, with
+/// everything but labels and functions indented by a tab, so the places that
+/// can be jumped to stand out. Assembling it gives back `program`.
+pub fn print_text(program: &[Instruction]) -> String {
+    let mut text = String::new();
+    for instruction in program {
+        if !matches!(
+            instruction,
+            Instruction::Label(_) | Instruction::Function { .. }
+        ) {
+            text.push('\t');
+        }
+        writeln!(text, "{instruction}").unwrap();
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        assemble,
+        disassemble::disassemble,
+        generate::{generate, GeneratorOptions},
+        write_bytecode::write_bytecode,
+    };
+
+    #[test]
+    fn layout() {
+        let program =
+            assemble::program("JUMP main @args(1) FUNCTION f 0 ARGLOCAL_READ 0 RET main: NOP")
+                .unwrap();
+        assert_eq!(
+            print_text(&program),
+            "\tJUMP main\n@args(1) FUNCTION f 0\n\tARGLOCAL_READ 0\n\tRET\nmain:\n\tNOP\n"
+        );
+    }
+
+    #[test]
+    fn text_and_bytecode_round_trip() {
+        for seed in 0..20 {
+            let program = generate(&GeneratorOptions {
+                seed,
+                ..Default::default()
+            });
+            let text = print_text(&program);
+            assert_eq!(assemble::program(&text).unwrap(), program, "seed {seed}");
+
+            // Bytecode doesn't keep attributes, so the text comes back without
+            // them.
+            let mut bytecode = Vec::new();
+            write_bytecode(&program, &mut bytecode).unwrap();
+            let without_attributes: String = text
+                .lines()
+                .map(|line| match line.split_once(" FUNCTION ") {
+                    Some((_, rest)) => format!("FUNCTION {rest}\n"),
+                    None => format!("{line}\n"),
+                })
+                .collect();
+            assert_eq!(
+                print_text(&disassemble(&bytecode).unwrap()),
+                without_attributes,
+                "seed {seed}"
+            );
+        }
+    }
+}
@@ -0,0 +1,138 @@
+// Shrinks a program that triggers a bug down to a small one that still does,
+// by delta debugging (Zeller and Hildebrandt's ddmin, removing chunks only).
+
+use crate::{analysis::function_spans, ir_definition::Instruction};
+
+/// Returns a smaller version of `program` for which `still_fails` still holds.
+/// `still_fails` must hold for `program` itself.
+///
+/// Whole functions are removed first, since they're the largest pieces that
+/// are likely to be irrelevant. Then chunks of instructions are removed, with
+/// the chunks getting smaller whenever no chunk can be removed, until no single
+/// instruction can be. The result isn't necessarily the smallest program that
+/// fails, but removing any one instruction from it makes it pass.
+pub fn reduce(
+    program: &[Instruction],
+    mut still_fails: impl FnMut(&[Instruction]) -> bool,
+) -> Vec<Instruction> {
+    let mut program = program.to_vec();
+    remove_functions(&mut program, &mut still_fails);
+    remove_chunks(&mut program, &mut still_fails);
+    program
+}
+
+fn remove_functions(
+    program: &mut Vec<Instruction>,
+    still_fails: &mut impl FnMut(&[Instruction]) -> bool,
+) {
+    // Going from the last function to the first means removing one doesn't
+    // move the ones we have yet to try.
+    let spans: Vec<_> = function_spans(program)
+        .into_iter()
+        .map(|span| span.range)
+        .collect();
+    for range in spans.into_iter().rev() {
+        let candidate = without(program, range.clone());
+        if still_fails(&candidate) {
+            *program = candidate;
+        }
+    }
+}
+
+fn remove_chunks(
+    program: &mut Vec<Instruction>,
+    still_fails: &mut impl FnMut(&[Instruction]) -> bool,
+) {
+    let mut chunks = 2;
+    while !program.is_empty() {
+        let chunk_size = program.len().div_ceil(chunks);
+        let mut removed_any = false;
+        let mut start = 0;
+        while start < program.len() {
+            let end = (start + chunk_size).min(program.len());
+            let candidate = without(program, start..end);
+            if still_fails(&candidate) {
+                *program = candidate;
+                removed_any = true;
+            } else {
+                start = end;
+            }
+        }
+
+        if removed_any {
+            chunks = (chunks - 1).max(2);
+        } else if chunk_size == 1 {
+            break;
+        } else {
+            chunks = (chunks * 2).min(program.len());
+        }
+    }
+}
+
+fn without(program: &[Instruction], range: std::ops::Range<usize>) -> Vec<Instruction> {
+    let mut remaining = program[..range.start].to_vec();
+    remaining.extend_from_slice(&program[range.end..]);
+    remaining
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{assemble, ir_definition::Intrinsic};
+
+    fn prints_string(program: &[Instruction]) -> bool {
+        program.contains(&Instruction::Intrinsic(Intrinsic::PrintString))
+    }
+
+    #[test]
+    fn reduces_to_what_matters() {
+        let program = assemble::program(
+            "JUMP main
+             FUNCTION f 0
+             ICONST 1
+             RET
+             main:
+             ICONST 1
+             ICONST 2
+             ADD
+             INTRINSIC PRINT_INT
+             SCONST \"hi\"
+             INTRINSIC PRINT_STRING
+             ICONST 0
+             INTRINSIC EXIT",
+        )
+        .unwrap();
+        assert_eq!(
+            reduce(&program, prints_string),
+            vec![Instruction::Intrinsic(Intrinsic::PrintString)]
+        );
+    }
+
+    #[test]
+    fn keeps_instructions_that_only_fail_together() {
+        let program = assemble::program("NOP\nICONST 1\nNOP\nNOP\nICONST 2\nNOP\nADD").unwrap();
+        let adds_two_constants = |program: &[Instruction]| {
+            program
+                .iter()
+                .filter(|instruction| matches!(instruction, Instruction::Iconst(_)))
+                .count()
+                == 2
+        };
+        assert_eq!(
+            reduce(&program, adds_two_constants),
+            vec![Instruction::Iconst(1), Instruction::Iconst(2)]
+        );
+    }
+
+    #[test]
+    fn stops_at_one_minimal_program() {
+        let program = assemble::program("NOP\nNOP\nNOP").unwrap();
+        let mut checks = 0;
+        let reduced = reduce(&program, |_| {
+            checks += 1;
+            true
+        });
+        assert_eq!(reduced, vec![]);
+        assert!(checks <= 3, "{checks} checks");
+    }
+}
@@ -0,0 +1,296 @@
+// Lowering to a register form: the operand stack replaced by as many virtual
+// registers as are needed, so each instruction names where its values come
+// from and where its result goes. It's the first step of compiling for a
+// machine with registers, before `linear_scan` gives the virtual registers
+// real ones.
+//
+// Within a basic block, each value pushed gets a fresh virtual register. A
+// block that starts with values on the stack finds them in registers 0, 1,
+// and so on, from the bottom up, so whatever's left on the stack at the end of
+// a block is moved there first.
+
+pub mod linear_scan;
+
+use std::fmt;
+
+use crate::{
+    analysis::{cfg::Cfg, stack_effect},
+    ir_definition::{Instruction, Intrinsic},
+    verify::stack::{check_stack, StackProblem},
+};
+
+/// A virtual register. There are as many as the program needs.
+pub type VReg = u32;
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum RegInstruction {
+    /// A stack instruction, with the registers it pops and pushes instead.
+    /// `CALL` doesn't pop its placeholder; the result just goes to `dest`.
+    Op {
+        instruction: Instruction,
+        dest: Option<VReg>,
+        /// In the order they were pushed, so `a` comes before `b` in `a b SUB`.
+        sources: Vec<VReg>,
+    },
+    Move {
+        dest: VReg,
+        src: VReg,
+    },
+}
+
+impl RegInstruction {
+    /// The registers it reads.
+    pub fn uses(&self) -> &[VReg] {
+        match self {
+            RegInstruction::Op { sources, .. } => sources,
+            RegInstruction::Move { src, .. } => std::slice::from_ref(src),
+        }
+    }
+
+    /// The register it writes, if any.
+    pub fn def(&self) -> Option<VReg> {
+        match self {
+            RegInstruction::Op { dest, .. } => *dest,
+            RegInstruction::Move { dest, .. } => Some(*dest),
+        }
+    }
+}
+
+/// Like `v2 = ADD v0 v1`, `WRITE x v2` and `v3 = v2`.
+impl fmt::Display for RegInstruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RegInstruction::Op {
+                instruction,
+                dest,
+                sources,
+            } => {
+                if let Some(dest) = dest {
+                    write!(f, "v{dest} = ")?;
+                }
+                write!(f, "{instruction}")?;
+                for source in sources {
+                    write!(f, " v{source}")?;
+                }
+                Ok(())
+            }
+            RegInstruction::Move { dest, src } => write!(f, "v{dest} = v{src}"),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct RegisterProgram {
+    pub instructions: Vec<RegInstruction>,
+    /// How many virtual registers it uses, numbered from 0.
+    pub num_vregs: u32,
+}
+
+impl fmt::Display for RegisterProgram {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for instruction in &self.instructions {
+            match instruction {
+                RegInstruction::Op {
+                    instruction: Instruction::Label(_) | Instruction::Function { .. },
+                    ..
+                } => writeln!(f, "{instruction}")?,
+                _ => writeln!(f, "\t{instruction}")?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// How deep the stack is at the start of each block: 0 at the start of the
+/// program and of each function, and otherwise what the first way into the
+/// block found. `check_stack` makes sure every way agrees. Blocks that can't
+/// be reached start empty.
+fn entry_depths(program: &[Instruction], cfg: &Cfg) -> Vec<usize> {
+    let mut depths: Vec<Option<usize>> = vec![None; cfg.blocks.len()];
+    let mut to_visit = Vec::new();
+    let roots = cfg.function_entries.iter().map(|(_, block)| *block);
+    for root in roots.chain((!cfg.blocks.is_empty()).then_some(0)) {
+        depths[root] = Some(0);
+        to_visit.push(root);
+    }
+    while let Some(block) = to_visit.pop() {
+        let mut depth = depths[block].unwrap();
+        for index in cfg.blocks[block].range.clone() {
+            let (pops, pushes) = stack_effect(&program[index]);
+            depth = depth.saturating_sub(pops) + pushes;
+        }
+        for &successor in &cfg.blocks[block].successors {
+            if depths[successor].is_none() {
+                depths[successor] = Some(depth);
+                to_visit.push(successor);
+            }
+        }
+    }
+    depths.into_iter().map(Option::unwrap_or_default).collect()
+}
+
+/// Lowers `program`, which mustn't have any stack underflows or mismatches
+/// (see `check_stack`); if it does, they're what's returned.
+pub fn lower(program: &[Instruction]) -> Result<RegisterProgram, Vec<StackProblem>> {
+    let problems: Vec<_> = check_stack(program)
+        .into_iter()
+        .filter(|problem| {
+            matches!(
+                problem,
+                StackProblem::Underflow { .. } | StackProblem::Mismatch { .. }
+            )
+        })
+        .collect();
+    if !problems.is_empty() {
+        return Err(problems);
+    }
+
+    let cfg = Cfg::new(program);
+    let depths = entry_depths(program, &cfg);
+    let mut next = depths.iter().copied().max().unwrap_or(0) as VReg;
+    let mut instructions = Vec::new();
+    for (block, depth) in cfg.blocks.iter().zip(depths) {
+        let mut stack: Vec<VReg> = (0..depth as VReg).collect();
+        // Puts what's left on the stack where the next block expects it.
+        // Only the bottom values start in those registers, and they stay
+        // put, so no move overwrites what another one reads.
+        let settle = |stack: &[VReg], instructions: &mut Vec<RegInstruction>| {
+            for (depth, &src) in stack.iter().enumerate() {
+                if src != depth as VReg {
+                    let dest = depth as VReg;
+                    instructions.push(RegInstruction::Move { dest, src });
+                }
+            }
+        };
+        for index in block.range.clone() {
+            let instruction = &program[index];
+            let (pops, pushes) = stack_effect(instruction);
+            // Only code that can't be reached can pop what isn't there; it
+            // gets registers nothing writes. A call with more arguments than
+            // there could ever be registers can't have them, so it's treated
+            // like an underflow where it could be reached.
+            if pops > stack.len() + (VReg::MAX - next) as usize {
+                return Err(vec![StackProblem::Underflow {
+                    index,
+                    needs: pops,
+                    depth: stack.len(),
+                }]);
+            }
+            while stack.len() < pops {
+                stack.insert(0, next);
+                next += 1;
+            }
+            let mut sources = stack.split_off(stack.len() - pops);
+            if let Instruction::Call { .. } = instruction {
+                sources.remove(0);
+            }
+            let dest = (pushes == 1).then(|| {
+                next += 1;
+                next - 1
+            });
+            let last = index == block.range.end - 1;
+            let jumps = matches!(
+                instruction,
+                Instruction::Jump(_) | Instruction::BranchZero(_)
+            );
+            if last && jumps {
+                settle(&stack, &mut instructions);
+            }
+            instructions.push(RegInstruction::Op {
+                instruction: instruction.clone(),
+                dest,
+                sources,
+            });
+            stack.extend(dest);
+            let stops = matches!(
+                instruction,
+                Instruction::Ret | Instruction::Intrinsic(Intrinsic::Exit)
+            );
+            if last && !jumps && !stops {
+                settle(&stack, &mut instructions);
+            }
+        }
+    }
+    Ok(RegisterProgram {
+        instructions,
+        num_vregs: next,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assemble;
+
+    #[test]
+    fn lowering() {
+        let program = assemble::program(
+            "JUMP main
+             FUNCTION twice 0
+             ARGLOCAL_READ 0
+             ICONST 2
+             MUL
+             RET
+             main:
+             ICONST 1
+             ICONST 42
+             ICONST 5
+             CALL twice 1
+             READ x
+             BRANCHZERO other
+             ICONST 3
+             ADD
+             other:
+             INTRINSIC PRINT_INT",
+        )
+        .unwrap();
+        assert_eq!(
+            lower(&program).unwrap().to_string(),
+            "\tJUMP main
+FUNCTION twice 0
+\tv2 = ARGLOCAL_READ 0
+\tv3 = ICONST 2
+\tv4 = MUL v2 v3
+\tRET v4
+main:
+\tv5 = ICONST 1
+\tv6 = ICONST 42
+\tv7 = ICONST 5
+\tv8 = CALL twice 1 v7
+\tv9 = READ x
+\tv0 = v5
+\tv1 = v8
+\tBRANCHZERO other v9
+\tv10 = ICONST 3
+\tv11 = ADD v1 v10
+\tv1 = v11
+other:
+\tINTRINSIC PRINT_INT v1
+"
+        );
+    }
+
+    #[test]
+    fn needs_a_verified_program() {
+        let program = assemble::program("ICONST 1 ADD").unwrap();
+        assert_eq!(
+            lower(&program),
+            Err(vec![StackProblem::Underflow {
+                index: 1,
+                needs: 2,
+                depth: 1
+            }])
+        );
+        // Where it can't be reached, only an impossible call is a problem.
+        let program =
+            assemble::program("JUMP end ICONST 1 ADD CALL f 18446744073709551615 end:").unwrap();
+        assert_eq!(
+            lower(&program),
+            Err(vec![StackProblem::Underflow {
+                index: 3,
+                needs: usize::MAX,
+                depth: 1
+            }])
+        );
+    }
+}
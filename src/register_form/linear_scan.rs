@@ -0,0 +1,251 @@
+// Linear-scan register allocation (Poletto and Sarkar's): each virtual
+// register is live over one range of the program, and the ranges are handed
+// real registers in the order they start. When there aren't enough, the range
+// that goes on longest is spilled to memory.
+
+use std::{
+    collections::{BTreeSet, HashMap},
+    fmt,
+};
+
+use super::{RegInstruction, RegisterProgram, VReg};
+use crate::ir_definition::{Instruction, Intrinsic, Label};
+
+/// Where a virtual register is live: from the first instruction it's written
+/// or needed at to the last. In a loop, that's the whole loop.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct Interval {
+    pub vreg: VReg,
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Interval {
+    /// An interval that ends where another starts doesn't overlap it, since
+    /// the instruction there reads the one before it writes the other.
+    pub fn overlaps(&self, other: &Interval) -> bool {
+        self.start < other.end && other.start < self.end
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Location {
+    Register(u32),
+    /// A slot in memory, one for each spilled virtual register.
+    Spilled(u32),
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Location::Register(register) => write!(f, "r{register}"),
+            Location::Spilled(slot) => write!(f, "spill slot {slot}"),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Allocation {
+    /// By virtual register. Virtual registers that are never used or written
+    /// are `None`.
+    pub locations: Vec<Option<Location>>,
+    pub num_spill_slots: u32,
+}
+
+/// The instructions control can go to after each one.
+fn successors(program: &RegisterProgram) -> Vec<Vec<usize>> {
+    let instructions = &program.instructions;
+    let mut labels = HashMap::new();
+    for (index, instruction) in instructions.iter().enumerate() {
+        if let RegInstruction::Op {
+            instruction: Instruction::Label(label) | Instruction::Function { label, .. },
+            ..
+        } = instruction
+        {
+            labels.entry(label.name()).or_insert(index);
+        }
+    }
+    let next = |index: usize| (index + 1 < instructions.len()).then_some(index + 1);
+    (0..instructions.len())
+        .map(|index| {
+            let target = |label: &Label| labels.get(label.name()).copied();
+            match &instructions[index] {
+                RegInstruction::Op { instruction, .. } => match instruction {
+                    Instruction::Jump(label) => target(label).into_iter().collect(),
+                    Instruction::BranchZero(label) => {
+                        target(label).into_iter().chain(next(index)).collect()
+                    }
+                    Instruction::Ret | Instruction::Intrinsic(Intrinsic::Exit) => Vec::new(),
+                    _ => next(index).into_iter().collect(),
+                },
+                RegInstruction::Move { .. } => next(index).into_iter().collect(),
+            }
+        })
+        .collect()
+}
+
+/// The interval of each virtual register that's used or written, in the
+/// order they start.
+pub fn live_intervals(program: &RegisterProgram) -> Vec<Interval> {
+    let instructions = &program.instructions;
+    let successors = successors(program);
+    let mut live_in: Vec<BTreeSet<VReg>> = vec![BTreeSet::new(); instructions.len()];
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for index in (0..instructions.len()).rev() {
+            let mut live: BTreeSet<VReg> = successors[index]
+                .iter()
+                .flat_map(|&successor| live_in[successor].iter().copied())
+                .collect();
+            if let Some(def) = instructions[index].def() {
+                live.remove(&def);
+            }
+            live.extend(instructions[index].uses());
+            if live != live_in[index] {
+                live_in[index] = live;
+                changed = true;
+            }
+        }
+    }
+
+    let mut ranges: Vec<Option<(usize, usize)>> = vec![None; program.num_vregs as usize];
+    for (index, instruction) in instructions.iter().enumerate() {
+        let def = instruction.def();
+        let here = live_in[index]
+            .iter()
+            .chain(def.iter())
+            .chain(instruction.uses());
+        for &vreg in here {
+            let range = ranges[vreg as usize].get_or_insert((index, index));
+            range.0 = range.0.min(index);
+            range.1 = range.1.max(index);
+        }
+    }
+    let mut intervals: Vec<_> = ranges
+        .into_iter()
+        .enumerate()
+        .filter_map(|(vreg, range)| {
+            range.map(|(start, end)| Interval {
+                vreg: vreg as VReg,
+                start,
+                end,
+            })
+        })
+        .collect();
+    intervals.sort_by_key(|interval| (interval.start, interval.vreg));
+    intervals
+}
+
+/// Gives each virtual register one of `num_registers` real ones, or a spill
+/// slot, so that no two that are live at once share a register.
+pub fn allocate(program: &RegisterProgram, num_registers: u32) -> Allocation {
+    let mut locations = vec![None; program.num_vregs as usize];
+    let mut num_spill_slots = 0;
+    let mut spill = |locations: &mut Vec<Option<Location>>, vreg: VReg| {
+        locations[vreg as usize] = Some(Location::Spilled(num_spill_slots));
+        num_spill_slots += 1;
+    };
+    let mut free: BTreeSet<u32> = (0..num_registers).collect();
+    // The intervals that have a register, with the register.
+    let mut active: Vec<(Interval, u32)> = Vec::new();
+    for interval in live_intervals(program) {
+        active.retain(|&(other, register)| {
+            let expired = other.end <= interval.start;
+            if expired {
+                free.insert(register);
+            }
+            !expired
+        });
+        if let Some(register) = free.pop_first() {
+            locations[interval.vreg as usize] = Some(Location::Register(register));
+            active.push((interval, register));
+            continue;
+        }
+        let longest = active
+            .iter()
+            .enumerate()
+            .max_by_key(|(_, (other, _))| other.end)
+            .map(|(position, _)| position);
+        match longest {
+            Some(position) if active[position].0.end > interval.end => {
+                let (other, register) = active.swap_remove(position);
+                spill(&mut locations, other.vreg);
+                locations[interval.vreg as usize] = Some(Location::Register(register));
+                active.push((interval, register));
+            }
+            _ => spill(&mut locations, interval.vreg),
+        }
+    }
+    Allocation {
+        locations,
+        num_spill_slots,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        assemble,
+        generate::{generate, GeneratorOptions},
+        register_form::lower,
+    };
+
+    #[test]
+    fn spills_the_longest() {
+        let program = lower(
+            &assemble::program(
+                "ICONST 1
+                 ICONST 2
+                 ICONST 3
+                 ADD
+                 ADD
+                 INTRINSIC PRINT_INT",
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        let allocation = allocate(&program, 2);
+        assert_eq!(
+            allocation.locations,
+            [
+                Some(Location::Spilled(0)),
+                Some(Location::Register(1)),
+                Some(Location::Register(0)),
+                Some(Location::Register(0)),
+                Some(Location::Register(0)),
+            ]
+        );
+        assert_eq!(allocation.num_spill_slots, 1);
+    }
+
+    #[test]
+    fn live_at_once_means_different_registers() {
+        for seed in 0..20 {
+            let program = lower(&generate(&GeneratorOptions {
+                seed,
+                ..Default::default()
+            }))
+            .unwrap();
+            let intervals = live_intervals(&program);
+            let allocation = allocate(&program, 4);
+            for (position, first) in intervals.iter().enumerate() {
+                for second in &intervals[position + 1..] {
+                    let locations = (
+                        allocation.locations[first.vreg as usize],
+                        allocation.locations[second.vreg as usize],
+                    );
+                    if let (Some(Location::Register(a)), Some(Location::Register(b))) = locations {
+                        assert!(
+                            a != b || !first.overlaps(second),
+                            "seed {seed}: v{} and v{} are both in r{a}",
+                            first.vreg,
+                            second.vreg
+                        );
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,210 @@
+// A single HTML page about a program, meant to be passed around: the listing,
+// how often each instruction ran (if the program was run), the control-flow
+// graph, and the size of each function.
+
+use std::fmt::Write as _;
+
+use crate::{
+    analysis::{cfg::Cfg, function_spans},
+    ir_definition::Instruction,
+};
+
+const STYLE: &str = "body { font-family: sans-serif; margin: 2em; }
+table { border-collapse: collapse; }
+td, th { padding: 0 0.75em; text-align: left; }
+.listing td { font-family: monospace; white-space: pre; }
+.listing td.count, .stats td.number, .cfg td.number { text-align: right; }
+.listing tr.never td.instruction { color: #999; }
+.listing tr.function td { padding-top: 0.5em; font-weight: bold; }
+";
+
+/// Renders the report for `program`. `counts`, if there are any, are how many
+/// times each instruction ran, like `RunResult::instruction_counts`. They
+/// color the listing: the more an instruction ran, the redder it is, and the
+/// instructions that never ran are grayed out.
+pub fn html_report(title: &str, program: &[Instruction], counts: Option<&[u64]>) -> String {
+    let mut html = String::new();
+    writeln!(
+        html,
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{}</title>\n\
+         <style>\n{STYLE}</style>\n</head>\n<body>\n<h1>{}</h1>",
+        escape(title),
+        escape(title)
+    )
+    .unwrap();
+
+    let spans = function_spans(program);
+    html.push_str("<h2>Functions</h2>\n<table class=\"stats\">\n<tr><th>function</th>");
+    html.push_str("<th>instructions</th>");
+    if counts.is_some() {
+        html.push_str("<th>instructions run</th>");
+    }
+    html.push_str("</tr>\n");
+    for span in &spans {
+        write!(
+            html,
+            "<tr><td><a href=\"#{0}\">{0}</a></td><td class=\"number\">{1}</td>",
+            escape(span.label.name()),
+            span.range.len()
+        )
+        .unwrap();
+        if let Some(counts) = counts {
+            let run: u64 = counts[span.range.clone()].iter().sum();
+            write!(html, "<td class=\"number\">{run}</td>").unwrap();
+        }
+        html.push_str("</tr>\n");
+    }
+    html.push_str("</table>\n");
+
+    // A table of the graph, since drawing it would take Graphviz or a script.
+    // The Graphviz source is there too, for whoever wants the picture.
+    let cfg = Cfg::new(program);
+    html.push_str("<h2>Control flow</h2>\n<table class=\"cfg\">\n<tr><th>block</th>");
+    html.push_str("<th>instructions</th>");
+    if counts.is_some() {
+        html.push_str("<th>runs</th>");
+    }
+    html.push_str("<th>goes to</th></tr>\n");
+    for (id, block) in cfg.blocks.iter().enumerate() {
+        write!(
+            html,
+            "<tr id=\"b{id}\"><td>b{id}</td><td class=\"number\">{}&ndash;{}</td>",
+            block.range.start,
+            block.range.end - 1
+        )
+        .unwrap();
+        if let Some(counts) = counts {
+            write!(
+                html,
+                "<td class=\"number\">{}</td>",
+                counts[block.range.start]
+            )
+            .unwrap();
+        }
+        let successors: Vec<_> = block
+            .successors
+            .iter()
+            .map(|successor| format!("<a href=\"#b{successor}\">b{successor}</a>"))
+            .collect();
+        writeln!(html, "<td>{}</td></tr>", successors.join(", ")).unwrap();
+    }
+    writeln!(
+        html,
+        "</table>\n<details><summary>In Graphviz's format</summary>\n<pre>{}</pre>\n</details>",
+        escape(&cfg.to_dot(program))
+    )
+    .unwrap();
+
+    // Log scale, so that a hot loop doesn't wash out everything else.
+    let max = counts
+        .and_then(|counts| counts.iter().max().copied())
+        .unwrap_or(0);
+    let heat = |count: u64| ((count as f64).ln_1p() / (max as f64).ln_1p() * 0.6).min(0.6);
+
+    html.push_str("<h2>Listing</h2>\n<table class=\"listing\">\n<tr><th>index</th>");
+    if counts.is_some() {
+        html.push_str("<th>runs</th>");
+    }
+    html.push_str("<th>instruction</th></tr>\n");
+    for (index, instruction) in program.iter().enumerate() {
+        let mut classes = Vec::new();
+        let mut attributes = String::new();
+        if let Instruction::Function { label, .. } = instruction {
+            classes.push("function");
+            write!(attributes, " id=\"{}\"", escape(label.name())).unwrap();
+        }
+        let count = counts.map(|counts| counts[index]);
+        match count {
+            Some(0) => classes.push("never"),
+            Some(count) => write!(
+                attributes,
+                " style=\"background: rgba(255, 0, 0, {:.2})\"",
+                heat(count)
+            )
+            .unwrap(),
+            None => {}
+        }
+        if !classes.is_empty() {
+            write!(attributes, " class=\"{}\"", classes.join(" ")).unwrap();
+        }
+
+        let indent = match instruction {
+            Instruction::Label(_) | Instruction::Function { .. } => "",
+            _ => "    ",
+        };
+        write!(html, "<tr{attributes}><td class=\"count\">{index}</td>").unwrap();
+        if let Some(count) = count {
+            write!(html, "<td class=\"count\">{count}</td>").unwrap();
+        }
+        writeln!(
+            html,
+            "<td class=\"instruction\">{indent}{}</td></tr>",
+            escape(&instruction.to_string())
+        )
+        .unwrap();
+    }
+    html.push_str("</table>\n</body>\n</html>\n");
+    html
+}
+
+fn escape(text: &str) -> String {
+    let mut escaped = String::new();
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{assemble, interpret_rust::interpret};
+
+    #[test]
+    fn report() {
+        let program = assemble::program(
+            "JUMP main
+             FUNCTION f 0
+             ICONST 1
+             RET
+             FUNCTION unused 0
+             SCONST \"<&>\"
+             RET
+             main:
+             ICONST 42
+             CALL f 0
+             POP -1",
+        )
+        .unwrap();
+        let counts = interpret(&program).unwrap().instruction_counts;
+        let html = html_report("a <test>", &program, Some(&counts));
+
+        assert!(html.contains("<title>a &lt;test&gt;</title>"));
+        assert!(html.contains(
+            "<tr><td><a href=\"#f\">f</a></td><td class=\"number\">3</td>\
+             <td class=\"number\">2</td></tr>"
+        ));
+        assert!(html.contains(
+            "<tr class=\"never\"><td class=\"count\">5</td><td class=\"count\">0</td>\
+             <td class=\"instruction\">    SCONST &quot;&lt;&amp;&gt;&quot;</td></tr>"
+        ));
+        assert!(html.contains(
+            "<tr style=\"background: rgba(255, 0, 0, 0.60)\"><td class=\"count\">0</td>"
+        ));
+        assert!(html.contains(
+            "<tr id=\"b0\"><td>b0</td><td class=\"number\">0&ndash;0</td>\
+             <td class=\"number\">1</td><td><a href=\"#b3\">b3</a></td></tr>"
+        ));
+        assert!(html.contains("b0 -&gt; b3;"));
+
+        let without_counts = html_report("a <test>", &program, None);
+        assert!(!without_counts.contains("instructions run"));
+        assert!(!without_counts.contains("class=\"never\""));
+    }
+}
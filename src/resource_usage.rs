@@ -0,0 +1,92 @@
+// How much time and memory running something took, for benchmarking without
+// an external `time`, which can't tell assembling a program from running it.
+
+use std::{
+    fmt,
+    time::{Duration, Instant},
+};
+
+use crate::c_interpreter::rusage;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ResourceUsage {
+    pub wall: Duration,
+    /// CPU time spent in the program itself.
+    pub user: Duration,
+    /// CPU time spent in the kernel on the program's behalf.
+    pub system: Duration,
+    /// The most memory that was resident at once, in bytes. This is a peak
+    /// for a whole process, so it can't be narrowed down to part of one: see
+    /// `measure` and `measure_children`.
+    pub peak_rss: u64,
+}
+
+impl fmt::Display for ResourceUsage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{:.3}s wall, {:.3}s user, {:.3}s system, {} KiB peak RSS",
+            self.wall.as_secs_f64(),
+            self.user.as_secs_f64(),
+            self.system.as_secs_f64(),
+            self.peak_rss / 1024
+        )
+    }
+}
+
+/// Runs `f` and measures it. The CPU times are this whole process's, so other
+/// threads running at the same time are counted too, and the peak RSS is the
+/// process's peak so far, which may have been before `f` started.
+pub fn measure<T>(f: impl FnOnce() -> T) -> (T, ResourceUsage) {
+    measure_with(libc::RUSAGE_SELF, f)
+}
+
+/// Runs `f` and measures the child processes it waits for. The peak RSS is
+/// that of the largest child waited for so far, even before `f` started.
+pub fn measure_children<T>(f: impl FnOnce() -> T) -> (T, ResourceUsage) {
+    measure_with(libc::RUSAGE_CHILDREN, f)
+}
+
+fn measure_with<T>(who: libc::c_int, f: impl FnOnce() -> T) -> (T, ResourceUsage) {
+    let before = rusage(who);
+    let started = Instant::now();
+    let value = f();
+    let wall = started.elapsed();
+    let after = rusage(who);
+    let usage = ResourceUsage {
+        wall,
+        user: duration(after.ru_utime).saturating_sub(duration(before.ru_utime)),
+        system: duration(after.ru_stime).saturating_sub(duration(before.ru_stime)),
+        // Linux counts this in KiB, and macOS in bytes.
+        peak_rss: after.ru_maxrss as u64 * if cfg!(target_os = "macos") { 1 } else { 1024 },
+    };
+    (value, usage)
+}
+
+fn duration(time: libc::timeval) -> Duration {
+    Duration::new(time.tv_sec as u64, time.tv_usec as u32 * 1000)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{process, thread};
+
+    #[test]
+    fn measures() {
+        let (value, usage) = measure(|| {
+            thread::sleep(Duration::from_millis(20));
+            42
+        });
+        assert_eq!(value, 42);
+        assert!(usage.wall >= Duration::from_millis(20));
+        assert!(usage.peak_rss > 0);
+
+        let (status, usage) = measure_children(|| process::Command::new("true").status());
+        assert!(status.unwrap().success());
+        assert!(usage.peak_rss > 0);
+        assert!(usage
+            .to_string()
+            .ends_with(&format!("{} KiB peak RSS", usage.peak_rss / 1024)));
+    }
+}
@@ -0,0 +1,209 @@
+// Ed25519 signatures over bytecode, so whoever runs a program someone else
+// handed out (a reference solution, or a grader's tests) can check it's the
+// one that was signed, and that nothing has changed it since. The bytecode is
+// signed byte for byte, and the signature is kept apart from it, so the C
+// interpreter reads signed bytecode like any other.
+//
+// Keys and signatures are written as hex, so each fits on a line of a text
+// file.
+
+use std::{fmt, str::FromStr};
+
+use ed25519_compact::{KeyPair, PublicKey, Seed, Signature};
+
+/// The secret half of a key pair, which signs. It's written as its 32-byte
+/// seed.
+pub struct SigningKey(KeyPair);
+
+/// The public half of a key pair, which checks signatures.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct VerifyingKey(PublicKey);
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct BytecodeSignature(Signature);
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum SignatureError {
+    /// A key or signature that isn't the hex of the right number of bytes.
+    Malformed { what: &'static str, bytes: usize },
+    /// The right number of bytes, but not a key.
+    InvalidKey { what: &'static str },
+    /// The signature isn't the key's signature of the bytecode.
+    Mismatch,
+}
+
+impl fmt::Display for SignatureError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SignatureError::Malformed { what, bytes } => {
+                write!(f, "{what} should be {bytes} bytes, written in hex")
+            }
+            SignatureError::InvalidKey { what } => write!(f, "{what} isn't a valid Ed25519 key"),
+            SignatureError::Mismatch => write!(
+                f,
+                "the signature doesn't match: the bytecode was changed, or signed with another key"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for SignatureError {}
+
+impl SigningKey {
+    /// A new key pair, from the operating system's randomness.
+    pub fn generate() -> SigningKey {
+        SigningKey(KeyPair::generate())
+    }
+
+    pub fn verifying_key(&self) -> VerifyingKey {
+        VerifyingKey(self.0.pk)
+    }
+
+    /// The same bytecode and key always give the same signature.
+    pub fn sign(&self, bytecode: &[u8]) -> BytecodeSignature {
+        BytecodeSignature(self.0.sk.sign(bytecode, None))
+    }
+}
+
+impl VerifyingKey {
+    pub fn verify(
+        &self,
+        bytecode: &[u8],
+        signature: &BytecodeSignature,
+    ) -> Result<(), SignatureError> {
+        self.0
+            .verify(bytecode, &signature.0)
+            .map_err(|_| SignatureError::Mismatch)
+    }
+}
+
+fn write_hex(f: &mut fmt::Formatter, bytes: &[u8]) -> fmt::Result {
+    for byte in bytes {
+        write!(f, "{byte:02x}")?;
+    }
+    Ok(())
+}
+
+// Surrounding whitespace, like the newline at the end of a file, is ignored.
+fn parse_hex(text: &str, what: &'static str, bytes: usize) -> Result<Vec<u8>, SignatureError> {
+    let malformed = SignatureError::Malformed { what, bytes };
+    let text = text.trim();
+    if text.len() != bytes * 2 || !text.is_ascii() {
+        return Err(malformed);
+    }
+    (0..text.len())
+        .step_by(2)
+        .map(|start| u8::from_str_radix(&text[start..start + 2], 16))
+        .collect::<Result<_, _>>()
+        .map_err(|_| malformed)
+}
+
+impl fmt::Display for SigningKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write_hex(f, self.0.sk.seed().as_ref())
+    }
+}
+
+impl FromStr for SigningKey {
+    type Err = SignatureError;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let what = "a signing key";
+        let seed = parse_hex(text, what, Seed::BYTES)?;
+        // All zeros is the one seed `KeyPair` won't take, since no random
+        // one would ever be that.
+        if seed.iter().all(|&byte| byte == 0) {
+            return Err(SignatureError::InvalidKey { what });
+        }
+        Ok(SigningKey(KeyPair::from_seed(
+            Seed::from_slice(&seed).unwrap(),
+        )))
+    }
+}
+
+impl fmt::Display for VerifyingKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write_hex(f, self.0.as_ref())
+    }
+}
+
+impl FromStr for VerifyingKey {
+    type Err = SignatureError;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let what = "a verifying key";
+        let key = parse_hex(text, what, PublicKey::BYTES)?;
+        PublicKey::from_slice(&key)
+            .map(VerifyingKey)
+            .map_err(|_| SignatureError::InvalidKey { what })
+    }
+}
+
+impl fmt::Display for BytecodeSignature {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write_hex(f, self.0.as_ref())
+    }
+}
+
+impl FromStr for BytecodeSignature {
+    type Err = SignatureError;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let signature = parse_hex(text, "a signature", Signature::BYTES)?;
+        Ok(BytecodeSignature(
+            Signature::from_slice(&signature).unwrap(),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn signing() {
+        let key: SigningKey = "01".repeat(32).parse().unwrap();
+        let verifying_key = key.verifying_key();
+        let bytecode = [1, 0, 0, 0, 42, 0, 0, 0];
+        let signature = key.sign(&bytecode);
+        assert_eq!(verifying_key.verify(&bytecode, &signature), Ok(()));
+        let mut changed = bytecode;
+        changed[4] = 43;
+        assert_eq!(
+            verifying_key.verify(&changed, &signature),
+            Err(SignatureError::Mismatch)
+        );
+        let other = SigningKey::generate().verifying_key();
+        assert_eq!(
+            other.verify(&bytecode, &signature),
+            Err(SignatureError::Mismatch)
+        );
+
+        // Everything survives being written out and read back.
+        assert_eq!(
+            key.to_string()
+                .parse::<SigningKey>()
+                .unwrap()
+                .sign(&bytecode),
+            signature
+        );
+        assert_eq!(
+            format!("{verifying_key}\n").parse::<VerifyingKey>(),
+            Ok(verifying_key)
+        );
+        assert_eq!(signature.to_string().parse(), Ok(signature));
+        assert_eq!(
+            "00".repeat(32).parse::<SigningKey>().err(),
+            Some(SignatureError::InvalidKey {
+                what: "a signing key"
+            })
+        );
+        assert_eq!(
+            "abc".parse::<BytecodeSignature>(),
+            Err(SignatureError::Malformed {
+                what: "a signature",
+                bytes: 64
+            })
+        );
+    }
+}
@@ -0,0 +1,196 @@
+// How much of one program is also in another, function by function, for
+// finding copies that have had their labels renamed.
+
+use std::fmt;
+
+use crate::{
+    analysis::function_spans, fingerprint::Fingerprint, ir_definition::Instruction,
+    write_bytecode::BytecodeWriteError,
+};
+
+#[derive(Debug, PartialEq)]
+pub struct FunctionMatch {
+    pub first: String,
+    pub second: String,
+    /// How many instructions the function spans, which is the same in both.
+    pub instructions: usize,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Similarity {
+    /// The fraction of the two programs' instructions that are in matched
+    /// code, from 0 to 1.
+    pub score: f64,
+    /// Functions of the first program that are also in the second, apart from
+    /// what labels are called, in the order they are in the first.
+    pub matches: Vec<FunctionMatch>,
+    /// Whether the code outside of all functions matches as well.
+    pub outside_functions_match: bool,
+}
+
+// A program cut into its functions and whatever is left over, each with the
+// structural hash of its instructions.
+struct Parts {
+    functions: Vec<(String, usize, u64)>,
+    outside_functions: (usize, u64),
+}
+
+impl Parts {
+    fn of(program: &[Instruction]) -> Result<Self, BytecodeWriteError> {
+        // Fingerprinting the whole program first means a problem is found
+        // where it is in the program, not where it is in a function.
+        Fingerprint::of(program)?;
+        let spans = function_spans(program);
+        let outside_functions: Vec<_> = program
+            .iter()
+            .enumerate()
+            .filter(|(index, _)| !spans.iter().any(|span| span.range.contains(index)))
+            .map(|(_, instruction)| instruction.clone())
+            .collect();
+        Ok(Parts {
+            functions: spans
+                .iter()
+                .map(|span| {
+                    let body = &program[span.range.clone()];
+                    Ok((
+                        span.label.name().to_string(),
+                        body.len(),
+                        Fingerprint::of(body)?.structural,
+                    ))
+                })
+                .collect::<Result<_, BytecodeWriteError>>()?,
+            outside_functions: (
+                outside_functions.len(),
+                Fingerprint::of(&outside_functions)?.structural,
+            ),
+        })
+    }
+}
+
+impl Similarity {
+    /// Matches each function of `first` with a function of `second` that's
+    /// the same once labels are renamed, if there is one. Calls count as the
+    /// same whatever they call, since the callee may have been renamed too.
+    /// Fails where either program can't be written as bytecode, like
+    /// `Fingerprint::of`.
+    pub fn between(
+        first: &[Instruction],
+        second: &[Instruction],
+    ) -> Result<Self, BytecodeWriteError> {
+        let first_parts = Parts::of(first)?;
+        let second_parts = Parts::of(second)?;
+
+        let mut unmatched: Vec<_> = second_parts.functions.iter().collect();
+        let mut matches = Vec::new();
+        for (name, instructions, hash) in &first_parts.functions {
+            if let Some(position) = unmatched.iter().position(|(_, _, other)| other == hash) {
+                let (other_name, _, _) = unmatched.remove(position);
+                matches.push(FunctionMatch {
+                    first: name.clone(),
+                    second: other_name.clone(),
+                    instructions: *instructions,
+                });
+            }
+        }
+
+        let outside_functions_match =
+            first_parts.outside_functions.1 == second_parts.outside_functions.1;
+        let mut matched = matches.iter().map(|m| m.instructions).sum::<usize>();
+        if outside_functions_match {
+            matched += first_parts.outside_functions.0;
+        }
+        let total = first.len() + second.len();
+        let score = if total == 0 {
+            1.0
+        } else {
+            (2 * matched) as f64 / total as f64
+        };
+
+        Ok(Similarity {
+            score,
+            matches,
+            outside_functions_match,
+        })
+    }
+}
+
+impl fmt::Display for Similarity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "similarity: {:.2}", self.score)?;
+        for m in &self.matches {
+            writeln!(
+                f,
+                "{} matches {} ({} instructions)",
+                m.first, m.second, m.instructions
+            )?;
+        }
+        if self.outside_functions_match {
+            writeln!(f, "the code outside of functions matches")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assemble;
+
+    fn similarity(first: &str, second: &str) -> Similarity {
+        Similarity::between(
+            &assemble::program(first).unwrap(),
+            &assemble::program(second).unwrap(),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn renamed_copies_are_identical() {
+        let result = similarity(
+            "JUMP main FUNCTION f 0 ICONST 1 RET FUNCTION g 0 CALL f 0 RET main: CALL g 0",
+            "JUMP start FUNCTION b 0 CALL a 0 RET FUNCTION a 0 ICONST 1 RET start: CALL b 0",
+        );
+        assert_eq!(result.score, 1.0);
+        assert!(result.outside_functions_match);
+        assert_eq!(
+            result.matches,
+            [
+                FunctionMatch {
+                    first: "f".into(),
+                    second: "a".into(),
+                    instructions: 3
+                },
+                FunctionMatch {
+                    first: "g".into(),
+                    second: "b".into(),
+                    instructions: 3
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn partial_matches() {
+        let result = similarity(
+            "FUNCTION f 0 ICONST 1 RET FUNCTION g 0 ICONST 2 RET",
+            "FUNCTION h 0 ICONST 1 RET FUNCTION i 0 ICONST 3 RET ICONST 4",
+        );
+        assert_eq!(result.score, 6.0 / 13.0);
+        assert!(!result.outside_functions_match);
+        assert_eq!(
+            result.to_string(),
+            "similarity: 0.46\nf matches h (3 instructions)\n"
+        );
+    }
+
+    #[test]
+    fn programs_without_bytecode() {
+        let first = assemble::program("FUNCTION f 0 ICONST 1 RET").unwrap();
+        let second =
+            assemble::program("FUNCTION f 0 ICONST 1 RET POP 9223372036854775807").unwrap();
+        assert!(matches!(
+            Similarity::between(&first, &second),
+            Err(BytecodeWriteError::Overflow { index: 3, .. })
+        ));
+    }
+}
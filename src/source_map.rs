@@ -0,0 +1,127 @@
+// Where each instruction of a text program came from, for tools that need to
+// point back into the text.
+
+use std::{fmt, ops::Range};
+
+/// A place in the text. Both numbers count from 1, and columns count
+/// characters, not bytes.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, PartialOrd, Ord)]
+pub struct SourcePosition {
+    pub line: usize,
+    pub column: usize,
+}
+
+impl fmt::Display for SourcePosition {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}, column {}", self.line, self.column)
+    }
+}
+
+/// Maps the instructions of a program to the text they were assembled from,
+/// and back. Build one with `assemble::program_with_source_map`.
+#[derive(Debug, PartialEq)]
+pub struct SourceMap {
+    /// For each instruction, where it starts and where its last character is.
+    spans: Vec<(SourcePosition, SourcePosition)>,
+}
+
+impl SourceMap {
+    /// `spans` are the byte ranges of each instruction in `text`, in order.
+    /// None of them may be empty.
+    pub(crate) fn new(text: &str, spans: &[Range<usize>]) -> Self {
+        let line_starts: Vec<_> = std::iter::once(0)
+            .chain(text.match_indices('\n').map(|(offset, _)| offset + 1))
+            .collect();
+        let position = |offset: usize| {
+            let line = line_starts.partition_point(|&start| start <= offset);
+            let line_start = line_starts[line - 1];
+            SourcePosition {
+                line,
+                column: text[line_start..offset].chars().count() + 1,
+            }
+        };
+        let spans = spans
+            .iter()
+            .map(|span| {
+                // Where the last character starts, since `span.end` is just
+                // past the instruction.
+                let last = text[..span.end]
+                    .char_indices()
+                    .next_back()
+                    .map_or(0, |(i, _)| i);
+                (position(span.start), position(last))
+            })
+            .collect();
+        SourceMap { spans }
+    }
+
+    /// Where the instruction at `index` starts.
+    pub fn position(&self, index: usize) -> Option<SourcePosition> {
+        self.spans.get(index).map(|&(start, _)| start)
+    }
+
+    /// The instructions that are at least partly on `line`. Since
+    /// instructions never overlap, they're always next to each other.
+    pub fn instructions_on_line(&self, line: usize) -> Range<usize> {
+        let first = self.spans.partition_point(|(_, end)| end.line < line);
+        let after_last = self.spans.partition_point(|(start, _)| start.line <= line);
+        first..after_last.max(first)
+    }
+
+    /// How many instructions there are.
+    pub fn len(&self) -> usize {
+        self.spans.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.spans.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::assemble::program_with_source_map;
+
+    use super::*;
+
+    #[test]
+    fn both_directions() {
+        let text = "JUMP main # comment\n\
+                    main: ICONST 1 /* a\n\
+                    long comment */ SCONST \"two\n\
+                    lines\" NOP\n\
+                    \n\
+                    \tRET";
+        let (program, map) = program_with_source_map(text).unwrap();
+        assert_eq!(map.len(), program.len());
+
+        let positions: Vec<_> = (0..map.len())
+            .map(|index| {
+                let position = map.position(index).unwrap();
+                (position.line, position.column)
+            })
+            .collect();
+        assert_eq!(positions, [(1, 1), (2, 1), (2, 7), (3, 17), (4, 8), (6, 2)]);
+        assert_eq!(map.position(6), None);
+
+        assert_eq!(map.instructions_on_line(1), 0..1);
+        assert_eq!(map.instructions_on_line(2), 1..3);
+        assert_eq!(map.instructions_on_line(3), 3..4);
+        assert_eq!(map.instructions_on_line(4), 3..5);
+        assert!(map.instructions_on_line(5).is_empty());
+        assert_eq!(map.instructions_on_line(6), 5..6);
+        assert!(map.instructions_on_line(7).is_empty());
+    }
+
+    #[test]
+    fn columns_count_characters() {
+        let (_, map) = program_with_source_map("SCONST \"é\" NOP").unwrap();
+        assert_eq!(
+            map.position(1),
+            Some(SourcePosition {
+                line: 1,
+                column: 12
+            })
+        );
+    }
+}
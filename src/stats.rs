@@ -0,0 +1,379 @@
+// Summaries of programs, and how they change from one version of a program to
+// the next.
+
+use std::{collections::BTreeMap, fmt::Write as _};
+
+use crate::{
+    analysis::function_spans,
+    ir_definition::Instruction,
+    opcode::Opcode,
+    write_bytecode::{write_bytecode, BytecodeWriteError},
+};
+
+#[derive(Debug, PartialEq, Default)]
+pub struct ProgramStats {
+    pub instructions: usize,
+    pub bytecode_bytes: usize,
+    /// What running each instruction once would cost, by `estimated_cost`.
+    pub estimated_cost: usize,
+    /// Only the opcodes that appear in the program are present.
+    pub opcode_counts: BTreeMap<Opcode, usize>,
+    /// How many instructions each function spans, by name. See
+    /// `analysis::function_spans` for where functions are taken to end.
+    pub function_sizes: BTreeMap<String, usize>,
+}
+
+/// A rough cost of running `opcode` once, where the cheapest instructions
+/// cost 1. Labels and functions only mark places, so they're free; globals
+/// are looked up by name; calls and returns make and throw away frames; and
+/// intrinsics do I/O. It's only good for seeing which way a change went.
+pub fn estimated_cost(opcode: Opcode) -> usize {
+    match opcode {
+        Opcode::Label | Opcode::Function => 0,
+        Opcode::Div | Opcode::Mod | Opcode::Read | Opcode::Write | Opcode::Reserve => 2,
+        Opcode::Call | Opcode::Ret => 4,
+        Opcode::Intrinsic => 8,
+        _ => 1,
+    }
+}
+
+impl ProgramStats {
+    /// Fails where the program can't be written as bytecode, since then it
+    /// has no bytecode size.
+    pub fn of(program: &[Instruction]) -> Result<Self, BytecodeWriteError> {
+        let mut bytecode = Vec::new();
+        write_bytecode(program, &mut bytecode)?;
+
+        let mut opcode_counts = BTreeMap::new();
+        for instruction in program {
+            *opcode_counts.entry(instruction.opcode()).or_default() += 1;
+        }
+
+        let function_sizes = function_spans(program)
+            .into_iter()
+            .map(|span| (span.label.name().to_string(), span.range.len()))
+            .collect();
+
+        let estimated_cost = opcode_counts
+            .iter()
+            .map(|(&opcode, count)| estimated_cost(opcode) * count)
+            .sum();
+
+        Ok(ProgramStats {
+            instructions: program.len(),
+            bytecode_bytes: bytecode.len(),
+            estimated_cost,
+            opcode_counts,
+            function_sizes,
+        })
+    }
+}
+
+/// The stats of two versions of a program, side by side. Everything is
+/// reported in a fixed order (opcodes in the order of `Opcode::ALL`, functions
+/// by name), so comparisons of the same programs always render the same way.
+#[derive(Debug, PartialEq)]
+pub struct StatsComparison {
+    pub old: ProgramStats,
+    pub new: ProgramStats,
+}
+
+// One row of a comparison. `None` means the thing doesn't exist in that
+// version, which is different from it being there with a count of 0.
+struct Row {
+    name: String,
+    old: Option<usize>,
+    new: Option<usize>,
+}
+
+impl StatsComparison {
+    pub fn new(old: &[Instruction], new: &[Instruction]) -> Result<Self, BytecodeWriteError> {
+        Ok(StatsComparison {
+            old: ProgramStats::of(old)?,
+            new: ProgramStats::of(new)?,
+        })
+    }
+
+    fn totals(&self) -> Vec<Row> {
+        vec![
+            Row {
+                name: "instructions".into(),
+                old: Some(self.old.instructions),
+                new: Some(self.new.instructions),
+            },
+            Row {
+                name: "bytecode bytes".into(),
+                old: Some(self.old.bytecode_bytes),
+                new: Some(self.new.bytecode_bytes),
+            },
+            Row {
+                name: "estimated cost".into(),
+                old: Some(self.old.estimated_cost),
+                new: Some(self.new.estimated_cost),
+            },
+        ]
+    }
+
+    // Opcodes missing from a program were used 0 times, so they're never `None`.
+    fn opcodes(&self) -> Vec<Row> {
+        Opcode::ALL
+            .into_iter()
+            .filter_map(|opcode| {
+                let old = self.old.opcode_counts.get(&opcode).copied();
+                let new = self.new.opcode_counts.get(&opcode).copied();
+                (old.is_some() || new.is_some()).then(|| Row {
+                    name: opcode.mnemonic().into(),
+                    old: Some(old.unwrap_or(0)),
+                    new: Some(new.unwrap_or(0)),
+                })
+            })
+            .collect()
+    }
+
+    fn functions(&self) -> Vec<Row> {
+        let names: std::collections::BTreeSet<_> = self
+            .old
+            .function_sizes
+            .keys()
+            .chain(self.new.function_sizes.keys())
+            .collect();
+        names
+            .into_iter()
+            .map(|name| Row {
+                name: name.clone(),
+                old: self.old.function_sizes.get(name).copied(),
+                new: self.new.function_sizes.get(name).copied(),
+            })
+            .collect()
+    }
+
+    pub fn to_table(&self) -> String {
+        let sections = [
+            ("", self.totals()),
+            ("opcode", self.opcodes()),
+            ("function", self.functions()),
+        ];
+        let name_width = sections
+            .iter()
+            .flat_map(|(heading, rows)| {
+                std::iter::once(heading.len()).chain(rows.iter().map(|row| row.name.len()))
+            })
+            .max()
+            .unwrap_or(0);
+
+        let count = |count: Option<usize>| count.map_or("-".to_string(), |count| count.to_string());
+        let mut table = String::new();
+        for (heading, rows) in sections {
+            if rows.is_empty() {
+                continue;
+            }
+            if !table.is_empty() {
+                table.push('\n');
+            }
+            writeln!(
+                table,
+                "{heading:name_width$}  {:>8}  {:>8}  {:>8}",
+                "old", "new", "change"
+            )
+            .unwrap();
+            for row in rows {
+                let change = row.new.unwrap_or(0) as i64 - row.old.unwrap_or(0) as i64;
+                writeln!(
+                    table,
+                    "{:name_width$}  {:>8}  {:>8}  {:>+8}",
+                    row.name,
+                    count(row.old),
+                    count(row.new),
+                    change
+                )
+                .unwrap();
+            }
+        }
+        table
+    }
+
+    pub fn to_json(&self) -> String {
+        let count =
+            |count: Option<usize>| count.map_or("null".to_string(), |count| count.to_string());
+        let object = |rows: Vec<Row>| {
+            let members: Vec<_> = rows
+                .into_iter()
+                .map(|row| {
+                    format!(
+                        "{}: {{\"old\": {}, \"new\": {}}}",
+                        json_string(&row.name),
+                        count(row.old),
+                        count(row.new)
+                    )
+                })
+                .collect();
+            format!("{{{}}}", members.join(", "))
+        };
+
+        let mut totals = self.totals().into_iter();
+        let instructions = totals.next().unwrap();
+        let bytecode_bytes = totals.next().unwrap();
+        let estimated_cost = totals.next().unwrap();
+        format!(
+            "{{\"instructions\": {{\"old\": {}, \"new\": {}}}, \
+             \"bytecode_bytes\": {{\"old\": {}, \"new\": {}}}, \
+             \"estimated_cost\": {{\"old\": {}, \"new\": {}}}, \
+             \"opcodes\": {}, \"functions\": {}}}\n",
+            count(instructions.old),
+            count(instructions.new),
+            count(bytecode_bytes.old),
+            count(bytecode_bytes.new),
+            count(estimated_cost.old),
+            count(estimated_cost.new),
+            object(self.opcodes()),
+            object(self.functions()),
+        )
+    }
+}
+
+// Labels can be any alphanumeric characters, so escape everything JSON needs
+// escaped, even though it's unlikely to come up.
+pub(crate) fn json_string(text: &str) -> String {
+    let mut escaped = String::from("\"");
+    for c in text.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            c if c.is_control() => write!(escaped, "\\u{:04x}", c as u32).unwrap(),
+            c => escaped.push(c),
+        }
+    }
+    escaped.push('"');
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{assemble, disassemble::disassemble};
+
+    fn comparison() -> StatsComparison {
+        let old = assemble::program(
+            "JUMP main
+             FUNCTION f 0
+             ICONST 1
+             ICONST 1
+             ADD
+             RET
+             main:
+             ICONST 42
+             CALL f 0
+             INTRINSIC EXIT",
+        )
+        .unwrap();
+        let new = assemble::program(
+            "JUMP main
+             FUNCTION g 0
+             ICONST 2
+             RET
+             main:
+             ICONST 42
+             CALL g 0
+             INTRINSIC EXIT",
+        )
+        .unwrap();
+        StatsComparison::new(&old, &new).unwrap()
+    }
+
+    #[test]
+    fn stats_of_a_program() {
+        let stats = comparison().old;
+        assert_eq!(stats.instructions, 10);
+        assert_eq!(stats.opcode_counts[&Opcode::Iconst], 3);
+        assert_eq!(stats.opcode_counts.get(&Opcode::Sub), None);
+        assert_eq!(stats.function_sizes["f"], 5);
+        assert!(stats.bytecode_bytes > 0);
+        // The ICONSTs, ADD and JUMP, then the CALL and RET, then the EXIT.
+        assert_eq!(stats.estimated_cost, 5 + 4 + 4 + 8);
+    }
+
+    #[test]
+    fn bytecode_programs() {
+        let old = include_bytes!("../ir_samples/from_a4/07-function-noargs/pass1.aves_bytecode");
+        let new = include_bytes!(
+            "../ir_samples/from_a4/08-function-args-shortcircuit/pass1.aves_bytecode"
+        );
+        let comparison =
+            StatsComparison::new(&disassemble(old).unwrap(), &disassemble(new).unwrap()).unwrap();
+        assert_eq!(comparison.old.bytecode_bytes, old.len());
+        assert_eq!(comparison.new.bytecode_bytes, new.len());
+    }
+
+    #[test]
+    fn programs_without_bytecode() {
+        for text in ["ICONST -9223372036854775808", "POP 9223372036854775807"] {
+            let program = assemble::program(text).unwrap();
+            assert!(
+                matches!(
+                    ProgramStats::of(&program),
+                    Err(BytecodeWriteError::Overflow { index: 0, .. })
+                ),
+                "{text}"
+            );
+        }
+    }
+
+    #[test]
+    fn table() {
+        let table = comparison().to_table();
+        assert!(
+            table.contains("instructions          10         8        -2"),
+            "{table}"
+        );
+        assert!(
+            table.contains("estimated cost        21        19        -2"),
+            "{table}"
+        );
+        assert!(
+            table.contains("ADD                    1         0        -1"),
+            "{table}"
+        );
+        assert!(
+            table.contains("f                      5         -        -5"),
+            "{table}"
+        );
+        assert!(
+            table.contains("g                      -         3        +3"),
+            "{table}"
+        );
+        // Opcodes are in the order of the table in `opcode`, not alphabetical.
+        assert!(table.find("ICONST").unwrap() < table.find("ADD").unwrap());
+    }
+
+    #[test]
+    fn json() {
+        let json = comparison().to_json();
+        assert!(json.starts_with("{\"instructions\": {\"old\": 10, \"new\": 8}, "));
+        assert!(json.contains("\"estimated_cost\": {\"old\": 21, \"new\": 19}, "));
+        assert!(json.contains("\"ADD\": {\"old\": 1, \"new\": 0}"));
+        assert!(json.contains(
+            "\"functions\": {\"f\": {\"old\": 5, \"new\": null}, \"g\": {\"old\": null, \"new\": 3}}"
+        ));
+        assert_eq!(comparison().to_json(), json); // Always the same.
+    }
+
+    #[test]
+    fn functions_are_reported_by_name() {
+        // The same functions, defined in the opposite order.
+        let old = assemble::program("FUNCTION b 0\nRET\nFUNCTION a 0\nICONST 1\nRET").unwrap();
+        let new = assemble::program("FUNCTION a 0\nICONST 1\nRET\nFUNCTION b 0\nRET").unwrap();
+        let comparison = StatsComparison::new(&old, &new).unwrap();
+        let json = comparison.to_json();
+        assert!(json.ends_with(
+            "\"functions\": {\"a\": {\"old\": 3, \"new\": 3}, \"b\": {\"old\": 2, \"new\": 2}}}\n"
+        ));
+        let table = comparison.to_table();
+        assert!(table.find("\na ").unwrap() < table.find("\nb ").unwrap());
+    }
+
+    #[test]
+    fn json_strings() {
+        assert_eq!(json_string("plain"), "\"plain\"");
+        assert_eq!(json_string("a\"b\\c\n"), "\"a\\\"b\\\\c\\u000a\"");
+    }
+}
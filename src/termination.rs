@@ -0,0 +1,505 @@
+// A best-effort look at whether loops terminate, to warn about ones that
+// might not before anyone waits on them to time out.
+
+use std::{collections::HashMap, fmt, ops::RangeInclusive};
+
+use crate::ir_definition::{Instruction, Intrinsic};
+
+/// A variable a loop can count with.
+#[derive(Debug, PartialEq, Eq, Clone, Hash)]
+pub enum Variable {
+    Global(String),
+    ArgLocal(u64),
+}
+
+impl Variable {
+    fn read_by(instruction: &Instruction) -> Option<Variable> {
+        match instruction {
+            Instruction::Read(name) => Some(Variable::Global(name.clone())),
+            Instruction::ArgLocalRead(index) => Some(Variable::ArgLocal(*index)),
+            _ => None,
+        }
+    }
+
+    fn written_by(instruction: &Instruction) -> Option<Variable> {
+        match instruction {
+            Instruction::Write(name) => Some(Variable::Global(name.clone())),
+            Instruction::ArgLocalWrite(index) => Some(Variable::ArgLocal(*index)),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Variable {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Variable::Global(name) => write!(f, "{name}"),
+            Variable::ArgLocal(index) => write!(f, "arglocal {index}"),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Verdict {
+    /// The loop counts a variable towards a bound it can't miss.
+    Terminates,
+    /// Nothing in the loop leaves it.
+    NeverExits,
+    /// The loop can only leave based on these variables (possibly none, if
+    /// it tests a constant), and never changes any of them.
+    ConditionNeverChanges(Vec<Variable>),
+    /// None of the above could be shown.
+    Unknown,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct LoopReport {
+    /// The name of the label the loop jumps back to.
+    pub head: String,
+    /// From the loop's label to the jump back to it, inclusive.
+    pub body: RangeInclusive<usize>,
+    pub verdict: Verdict,
+}
+
+impl LoopReport {
+    /// Whether this is worth warning about.
+    pub fn is_lint(&self) -> bool {
+        matches!(
+            self.verdict,
+            Verdict::NeverExits | Verdict::ConditionNeverChanges(_)
+        )
+    }
+}
+
+impl fmt::Display for LoopReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "the loop at {} (instructions {} to {}) ",
+            self.head,
+            self.body.start(),
+            self.body.end()
+        )?;
+        match &self.verdict {
+            Verdict::Terminates => write!(f, "terminates"),
+            Verdict::NeverExits => write!(f, "never exits"),
+            Verdict::ConditionNeverChanges(variables) if variables.is_empty() => {
+                write!(
+                    f,
+                    "may not terminate: it only exits on a constant condition"
+                )
+            }
+            Verdict::ConditionNeverChanges(variables) => {
+                let names: Vec<_> = variables.iter().map(Variable::to_string).collect();
+                write!(
+                    f,
+                    "may not terminate: it only exits based on {}, which it never changes",
+                    names.join(", ")
+                )
+            }
+            Verdict::Unknown => write!(f, "may or may not terminate"),
+        }
+    }
+}
+
+struct Loop<'a> {
+    program: &'a [Instruction],
+    labels: &'a HashMap<&'a str, usize>,
+    body: RangeInclusive<usize>,
+}
+
+/// Finds every loop (every jump or branch back to a label at or before it)
+/// and what can be said about whether it terminates.
+///
+/// This only looks at the instructions, not at what they compute, so it's
+/// easily fooled: calls are assumed to change any global, overflow is
+/// ignored, and a loop is only proven to terminate if it's a simple counting
+/// loop, like the ones front-ends emit for `while (i < n) { ...; i = i + 1; }`.
+pub fn analyze_loops(program: &[Instruction]) -> Vec<LoopReport> {
+    let labels: HashMap<&str, usize> = program
+        .iter()
+        .enumerate()
+        .filter_map(|(index, instruction)| match instruction {
+            Instruction::Label(label) => Some((label.name(), index)),
+            _ => None,
+        })
+        .collect();
+
+    program
+        .iter()
+        .enumerate()
+        .filter_map(|(index, instruction)| match instruction {
+            Instruction::Jump(label) | Instruction::BranchZero(label) => {
+                let &head = labels.get(label.name())?;
+                (head <= index).then(|| {
+                    let a_loop = Loop {
+                        program,
+                        labels: &labels,
+                        body: head..=index,
+                    };
+                    LoopReport {
+                        head: label.name().to_string(),
+                        body: head..=index,
+                        verdict: a_loop.verdict(),
+                    }
+                })
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+impl Loop<'_> {
+    fn start(&self) -> usize {
+        *self.body.start()
+    }
+
+    fn end(&self) -> usize {
+        *self.body.end()
+    }
+
+    fn target(&self, instruction: &Instruction) -> Option<usize> {
+        match instruction {
+            Instruction::Jump(label) | Instruction::BranchZero(label) => {
+                self.labels.get(label.name()).copied()
+            }
+            _ => None,
+        }
+    }
+
+    fn instructions(&self) -> impl Iterator<Item = (usize, &Instruction)> {
+        self.body.clone().map(|index| (index, &self.program[index]))
+    }
+
+    fn contains_call(&self) -> bool {
+        self.instructions()
+            .any(|(_, instruction)| matches!(instruction, Instruction::Call { .. }))
+    }
+
+    fn changes(&self, variable: &Variable) -> bool {
+        let written = self
+            .instructions()
+            .any(|(_, instruction)| Variable::written_by(instruction).as_ref() == Some(variable));
+        written || (matches!(variable, Variable::Global(_)) && self.contains_call())
+    }
+
+    // The branches that leave the loop. Leaving by returning or exiting the
+    // program counts too, but those always terminate the loop, so they're
+    // treated separately.
+    fn exit_branches(&self) -> Vec<usize> {
+        let mut exits: Vec<_> = self
+            .instructions()
+            .filter(|&(_, instruction)| {
+                self.target(instruction)
+                    .is_some_and(|target| !self.body.contains(&target))
+            })
+            .map(|(index, _)| index)
+            .collect();
+        // A conditional jump back falls out of the loop when it doesn't jump.
+        if matches!(self.program[self.end()], Instruction::BranchZero(_)) {
+            exits.push(self.end());
+        }
+        exits
+    }
+
+    // Whether the instruction at `index` runs on every trip around the loop.
+    // Going around the loop means getting from its top to its end (or back to
+    // its top), so the instruction is skipped only by jumping from before it to
+    // past it, or back to the top. Jumping back to a label in the middle of the
+    // loop, like inner loops do, still leads to `index`.
+    fn always_runs(&self, index: usize) -> bool {
+        self.instructions()
+            .filter(|&(branch, _)| branch < index)
+            .all(|(_, instruction)| match self.target(instruction) {
+                Some(target) if self.body.contains(&target) => {
+                    target <= index && target != self.start()
+                }
+                _ => true,
+            })
+    }
+
+    // The variables read by the straight-line code computing the condition
+    // tested at `branch`, or `None` if a call could be part of it.
+    fn condition_variables(&self, branch: usize) -> Option<Vec<Variable>> {
+        let mut variables = Vec::new();
+        for instruction in self.program[self.start()..branch].iter().rev() {
+            match instruction {
+                Instruction::Call { .. } => return None,
+                Instruction::Label(_)
+                | Instruction::Jump(_)
+                | Instruction::BranchZero(_)
+                | Instruction::Write(_)
+                | Instruction::ArgLocalWrite(_)
+                | Instruction::Intrinsic(_) => break,
+                _ => variables.extend(Variable::read_by(instruction)),
+            }
+        }
+        variables.reverse();
+        Some(variables)
+    }
+
+    fn verdict(&self) -> Verdict {
+        if self.instructions().any(|(_, instruction)| {
+            matches!(
+                instruction,
+                Instruction::Ret | Instruction::Intrinsic(Intrinsic::Exit)
+            )
+        }) {
+            // Leaving the loop that way may be what terminates it, but there's
+            // no telling when it happens.
+            return Verdict::Unknown;
+        }
+
+        let exits = self.exit_branches();
+        if exits.is_empty() {
+            // A call could exit the program.
+            return if self.contains_call() {
+                Verdict::Unknown
+            } else {
+                Verdict::NeverExits
+            };
+        }
+
+        if exits.iter().any(|&exit| self.counts_towards(exit)) {
+            return Verdict::Terminates;
+        }
+
+        let mut condition_variables = Vec::new();
+        for &exit in &exits {
+            if matches!(self.program[exit], Instruction::Jump(_)) {
+                // Unconditionally leaving is leaving, if it's reached.
+                return Verdict::Unknown;
+            }
+            let Some(variables) = self.condition_variables(exit) else {
+                return Verdict::Unknown;
+            };
+            for variable in variables {
+                if self.changes(&variable) {
+                    return Verdict::Unknown;
+                }
+                if !condition_variables.contains(&variable) {
+                    condition_variables.push(variable);
+                }
+            }
+        }
+        Verdict::ConditionNeverChanges(condition_variables)
+    }
+
+    // Whether the condition tested at `exit` is a counter that the loop moves
+    // towards its bound on every trip around it. There are two shapes:
+    //
+    //     ICONST n; WRITE v; top: ... READ v; BRANCHZERO out ... v = v - 1 ...
+    //     top: ... READ v; <bound>; LT; BRANCHZERO out ... v = v + k ...
+    //
+    // where n >= 0, k > 0, and the bound is a constant or a variable the loop
+    // doesn't change. `<bound>; READ v; GT` counts as the second shape too.
+    fn counts_towards(&self, exit: usize) -> bool {
+        if exit == self.end() || !self.always_runs(exit) {
+            return false;
+        }
+        match &self.program[self.start()..exit] {
+            [.., read] if Variable::read_by(read).is_some_and(|v| self.counts_down(&v)) => true,
+            [.., first, second, Instruction::Lt] => self.counts_up(first, second),
+            [.., first, second, Instruction::Gt] => self.counts_up(second, first),
+            _ => false,
+        }
+    }
+
+    fn counts_down(&self, counter: &Variable) -> bool {
+        let starts_nonnegative = match &self.program[..self.start()] {
+            [.., Instruction::Iconst(start), write] => {
+                *start >= 0 && Variable::written_by(write).as_ref() == Some(counter)
+            }
+            _ => false,
+        };
+        starts_nonnegative && self.step(counter) == Some(-1)
+    }
+
+    fn counts_up(&self, counter: &Instruction, bound: &Instruction) -> bool {
+        let Some(counter) = Variable::read_by(counter) else {
+            return false;
+        };
+        let bound_is_fixed = match Variable::read_by(bound) {
+            Some(bound) => bound != counter && !self.changes(&bound),
+            None => matches!(bound, Instruction::Iconst(_)),
+        };
+        bound_is_fixed && self.step(&counter).is_some_and(|step| step > 0)
+    }
+
+    // How much `counter` changes by on every trip around the loop, if the
+    // loop's only change to it is adding or subtracting a constant, and that
+    // always happens.
+    fn step(&self, counter: &Variable) -> Option<i64> {
+        if matches!(counter, Variable::Global(_)) && self.contains_call() {
+            return None;
+        }
+        let mut writes = self
+            .instructions()
+            .filter(|(_, instruction)| Variable::written_by(instruction).as_ref() == Some(counter))
+            .map(|(index, _)| index);
+        let write = writes.next()?;
+        if writes.next().is_some() || write < self.start() + 3 || !self.always_runs(write) {
+            return None;
+        }
+        let (read, step) = match &self.program[write - 3..write] {
+            [read, Instruction::Iconst(k), Instruction::Add] => (read, *k),
+            [read, Instruction::Iconst(k), Instruction::Sub] => (read, -*k),
+            _ => return None,
+        };
+        (Variable::read_by(read).as_ref() == Some(counter)).then_some(step)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assemble;
+
+    fn verdicts(text: &str) -> Vec<Verdict> {
+        analyze_loops(&assemble::program(text).unwrap())
+            .into_iter()
+            .map(|report| report.verdict)
+            .collect()
+    }
+
+    #[test]
+    fn counting_up() {
+        let verdicts = verdicts(
+            "RESERVE i 4 (null)
+             ICONST 0
+             WRITE i
+             top:
+             READ i
+             ICONST 10
+             LT
+             BRANCHZERO end
+             READ i
+             INTRINSIC PRINT_INT
+             READ i
+             ICONST 1
+             ADD
+             WRITE i
+             JUMP top
+             end:",
+        );
+        assert_eq!(verdicts, vec![Verdict::Terminates]);
+    }
+
+    #[test]
+    fn counting_down() {
+        let verdicts = verdicts(
+            "RESERVE c 4 (null)
+             ICONST 5
+             WRITE c
+             top:
+             READ c
+             BRANCHZERO end
+             READ c
+             ICONST 1
+             SUB
+             WRITE c
+             JUMP top
+             end:",
+        );
+        assert_eq!(verdicts, vec![Verdict::Terminates]);
+    }
+
+    #[test]
+    fn skippable_increment_proves_nothing() {
+        let verdicts = verdicts(
+            "FUNCTION f 1
+             top:
+             ARGLOCAL_READ 0
+             ICONST 10
+             LT
+             BRANCHZERO end
+             ARGLOCAL_READ 0
+             BRANCHZERO skip
+             ARGLOCAL_READ 0
+             ICONST 1
+             ADD
+             ARGLOCAL_WRITE 0
+             skip:
+             JUMP top
+             end:
+             ICONST 0
+             RET",
+        );
+        assert_eq!(verdicts, vec![Verdict::Unknown]);
+    }
+
+    #[test]
+    fn condition_never_changes() {
+        let verdicts = verdicts(
+            "RESERVE i 4 (null)
+             RESERVE n 4 (null)
+             top:
+             READ i
+             READ n
+             LT
+             BRANCHZERO end
+             READ n
+             INTRINSIC PRINT_INT
+             JUMP top
+             end:",
+        );
+        assert_eq!(
+            verdicts,
+            vec![Verdict::ConditionNeverChanges(vec![
+                Variable::Global("i".into()),
+                Variable::Global("n".into())
+            ])]
+        );
+    }
+
+    #[test]
+    fn calls_might_change_globals() {
+        let verdicts = verdicts(
+            "RESERVE i 4 (null)
+             top:
+             READ i
+             BRANCHZERO end
+             ICONST 42
+             CALL f 0
+             INTRINSIC PRINT_INT
+             JUMP top
+             end:",
+        );
+        assert_eq!(verdicts, vec![Verdict::Unknown]);
+    }
+
+    #[test]
+    fn never_exits() {
+        let program = assemble::program("top:\nICONST 1\nINTRINSIC PRINT_INT\nJUMP top").unwrap();
+        let reports = analyze_loops(&program);
+        assert_eq!(reports[0].verdict, Verdict::NeverExits);
+        assert!(reports[0].is_lint());
+        assert_eq!(
+            reports[0].to_string(),
+            "the loop at top (instructions 0 to 3) never exits"
+        );
+    }
+
+    #[test]
+    fn constant_conditions() {
+        assert_eq!(
+            verdicts("top:\nICONST 1\nBRANCHZERO end\nJUMP top\nend:"),
+            vec![Verdict::ConditionNeverChanges(vec![])]
+        );
+    }
+
+    #[test]
+    fn generated_loops_terminate() {
+        use crate::generate::{generate, GeneratorOptions};
+        for seed in 0..50 {
+            let program = generate(&GeneratorOptions {
+                seed,
+                calls: false,
+                ..Default::default()
+            });
+            for report in analyze_loops(&program) {
+                assert_eq!(report.verdict, Verdict::Terminates, "seed {seed}: {report}");
+            }
+        }
+    }
+}
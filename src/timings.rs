@@ -0,0 +1,91 @@
+// How long each phase of handling a program took, to tell whether a slow run
+// is the assembler's fault or the interpreter's.
+
+use std::{
+    fmt,
+    time::{Duration, Instant},
+};
+
+/// Durations of named phases, like "parse" or "execute", in the order they
+/// first ran.
+#[derive(Debug, Default, Clone)]
+pub struct Timings {
+    phases: Vec<(&'static str, Duration)>,
+}
+
+impl Timings {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Runs `f`, adding how long it took to `phase`.
+    pub fn time<T>(&mut self, phase: &'static str, f: impl FnOnce() -> T) -> T {
+        let started = Instant::now();
+        let value = f();
+        self.add(phase, started.elapsed());
+        value
+    }
+
+    pub fn add(&mut self, phase: &'static str, duration: Duration) {
+        match self.phases.iter_mut().find(|(name, _)| *name == phase) {
+            Some((_, total)) => *total += duration,
+            None => self.phases.push((phase, duration)),
+        }
+    }
+
+    pub fn phases(&self) -> &[(&'static str, Duration)] {
+        &self.phases
+    }
+
+    pub fn total(&self) -> Duration {
+        self.phases.iter().map(|(_, duration)| *duration).sum()
+    }
+
+    /// Each phase's duration in seconds, then the total, like
+    /// `{"parse": 0.000120, "execute": 1.500000, "total": 1.500120}`.
+    pub fn to_json(&self) -> String {
+        let members: Vec<_> = self
+            .phases
+            .iter()
+            .chain([&("total", self.total())])
+            .map(|(name, duration)| format!("\"{name}\": {:.6}", duration.as_secs_f64()))
+            .collect();
+        format!("{{{}}}\n", members.join(", "))
+    }
+}
+
+/// A table of the phases, in milliseconds, and the total.
+impl fmt::Display for Timings {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (name, duration) in self.phases.iter().chain([&("total", self.total())]) {
+            writeln!(f, "{name:<10}{:>12.3} ms", duration.as_secs_f64() * 1000.0)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn timings() {
+        let mut timings = Timings::new();
+        timings.add("parse", Duration::from_micros(1500));
+        assert_eq!(timings.time("execute", || 42), 42);
+        timings.add("execute", Duration::from_secs(2));
+        timings.add("parse", Duration::from_micros(500));
+
+        assert_eq!(timings.phases()[0], ("parse", Duration::from_millis(2)));
+        assert_eq!(timings.phases()[1].0, "execute");
+        assert!(timings.phases()[1].1 >= Duration::from_secs(2));
+        assert_eq!(timings.phases().len(), 2);
+        assert!(timings
+            .to_string()
+            .starts_with("parse            2.000 ms\nexecute       2000."));
+        assert!(timings
+            .to_json()
+            .starts_with("{\"parse\": 0.002000, \"execute\": 2.0"));
+        assert!(timings.to_json().contains(", \"total\": 2.0"));
+    }
+}
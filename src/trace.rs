@@ -0,0 +1,225 @@
+// Watching a program run in `interpret_rust`, one instruction at a time, to
+// see where a program from a student compiler goes wrong.
+
+use std::io;
+
+use crate::{
+    interpret_rust::Value,
+    ir_definition::{Instruction, Intrinsic},
+};
+
+/// Something an instruction did besides moving values on and off the stack.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Event<'a> {
+    /// A CALL entered `function`, with `args`.
+    Call {
+        function: &'a str,
+        args: &'a [Value],
+    },
+    /// A RET left `function`, giving back `result`.
+    Return {
+        function: &'a str,
+        result: &'a Value,
+    },
+    /// A WRITE set the global `name` to `value`.
+    GlobalWrite { name: &'a str, value: &'a Value },
+    /// An INTRINSIC ran with `argument`. For EXIT, it's the exit code.
+    Intrinsic {
+        intrinsic: Intrinsic,
+        argument: &'a Value,
+    },
+}
+
+/// Is told about each instruction just before it runs, and about the events
+/// it causes once it has run.
+pub trait Tracer {
+    /// `stack` is the operand stack as the instruction will find it, from the
+    /// bottom up, and `call_depth` is how many functions have been called and
+    /// haven't returned.
+    fn before(
+        &mut self,
+        index: usize,
+        instruction: &Instruction,
+        stack: &[Value],
+        call_depth: usize,
+    );
+
+    /// Called after the instruction at `index` did `event`. Instructions that
+    /// trap don't cause any.
+    fn event(&mut self, _index: usize, _event: Event) {}
+
+    /// Called once the program has stopped, however it stopped.
+    fn finish(&mut self) {}
+}
+
+/// Doesn't trace anything.
+impl Tracer for () {
+    fn before(&mut self, _: usize, _: &Instruction, _: &[Value], _: usize) {}
+}
+
+impl<T: Tracer + ?Sized> Tracer for &mut T {
+    fn before(
+        &mut self,
+        index: usize,
+        instruction: &Instruction,
+        stack: &[Value],
+        call_depth: usize,
+    ) {
+        (**self).before(index, instruction, stack, call_depth);
+    }
+
+    fn event(&mut self, index: usize, event: Event) {
+        (**self).event(index, event);
+    }
+
+    fn finish(&mut self) {
+        (**self).finish();
+    }
+}
+
+/// Writes a line for each instruction: its index, the instruction indented by
+/// the call depth, and the top of the stack, with the top at the right. Like
+/// `output_sink::Writer`, the first error writing is kept for `into_inner`,
+/// and nothing more is written after it.
+pub struct TraceWriter<W: io::Write> {
+    writer: W,
+    /// How many values from the top of the stack to show.
+    shown: usize,
+    error: Option<io::Error>,
+}
+
+impl<W: io::Write> TraceWriter<W> {
+    pub fn new(writer: W, shown: usize) -> Self {
+        TraceWriter {
+            writer,
+            shown,
+            error: None,
+        }
+    }
+
+    /// The writer back, or the first error writing to it.
+    pub fn into_inner(self) -> io::Result<W> {
+        match self.error {
+            Some(error) => Err(error),
+            None => Ok(self.writer),
+        }
+    }
+
+    fn attempt(&mut self, write: impl FnOnce(&mut W) -> io::Result<()>) {
+        if self.error.is_none() {
+            self.error = write(&mut self.writer).err();
+        }
+    }
+}
+
+impl<W: io::Write> Tracer for TraceWriter<W> {
+    fn before(
+        &mut self,
+        index: usize,
+        instruction: &Instruction,
+        stack: &[Value],
+        call_depth: usize,
+    ) {
+        let top = &stack[stack.len().saturating_sub(self.shown)..];
+        let mut values: Vec<_> = top.iter().map(Value::to_string).collect();
+        if top.len() < stack.len() {
+            values.insert(0, "...".to_string());
+        }
+        let instruction = format!("{}{instruction}", "  ".repeat(call_depth));
+        self.attempt(|writer| {
+            writeln!(
+                writer,
+                "{index:>6}  {instruction:<32}  [{}]",
+                values.join(", ")
+            )
+        });
+    }
+
+    fn finish(&mut self) {
+        self.attempt(|writer| writer.flush());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        assemble,
+        interpret_rust::{interpret_traced, RunLimits},
+    };
+
+    #[test]
+    fn writes_each_step() {
+        let program = assemble::program(
+            "JUMP main
+             FUNCTION f 0
+             SCONST \"x\"
+             RET
+             main:
+             ICONST 1
+             ICONST 2
+             ICONST 42
+             CALL f 0",
+        )
+        .unwrap();
+        let mut tracer = TraceWriter::new(Vec::new(), 2);
+        interpret_traced(&program, &RunLimits::default(), String::new(), &mut tracer).unwrap();
+        let trace = String::from_utf8(tracer.into_inner().unwrap()).unwrap();
+        assert_eq!(
+            trace,
+            "     0  JUMP main                         []
+     4  main:                             []
+     5  ICONST 1                          []
+     6  ICONST 2                          [1]
+     7  ICONST 42                         [1, 2]
+     8  CALL f 0                          [..., 2, 42]
+     2    SCONST \"x\"                      [..., 2, 42]
+     3    RET                             [..., 42, \"x\"]
+"
+        );
+    }
+
+    #[test]
+    fn events() {
+        // Keeps each event, as text.
+        struct Events(Vec<String>);
+
+        impl Tracer for Events {
+            fn before(&mut self, _: usize, _: &Instruction, _: &[Value], _: usize) {}
+
+            fn event(&mut self, index: usize, event: Event) {
+                self.0.push(format!("{index}: {event:?}"));
+            }
+        }
+
+        let program = assemble::program(
+            "RESERVE x 4 (null)
+             JUMP main
+             FUNCTION f 0
+             ARGLOCAL_READ 0
+             RET
+             main:
+             ICONST 0
+             ICONST 7
+             CALL f 1
+             WRITE x
+             READ x
+             INTRINSIC PRINT_INT
+             ICONST 3
+             INTRINSIC EXIT",
+        )
+        .unwrap();
+        let mut events = Events(Vec::new());
+        interpret_traced(&program, &RunLimits::default(), String::new(), &mut events).unwrap();
+        assert_eq!(
+            events.0,
+            [
+                "8: Call { function: \"f\", args: [Int(7)] }",
+                "4: Return { function: \"f\", result: Int(7) }",
+                "9: GlobalWrite { name: \"x\", value: Int(7) }",
+                "11: Intrinsic { intrinsic: PrintInt, argument: Int(7) }",
+                "13: Intrinsic { intrinsic: Exit, argument: Int(3) }",
+            ]
+        );
+    }
+}
@@ -0,0 +1,7 @@
+// Checks that a program is well-formed beyond what the parser can see. The C
+// interpreter trusts its input, so what these catch is undefined behavior
+// there.
+
+pub mod division;
+pub mod stack;
+pub mod types;
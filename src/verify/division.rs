@@ -0,0 +1,129 @@
+// Finds DIV and MOD instructions whose result is up to the platform in C, so
+// the C interpreter can do something different from the Rust one (see
+// `arithmetic`). Only constants are followed, and only within a basic block.
+
+use std::fmt;
+
+use crate::{
+    analysis::{cfg::Cfg, stack_effect},
+    arithmetic::{self, DivisionOverflow},
+    ir_definition::Instruction,
+};
+
+#[derive(Debug, PartialEq)]
+pub enum DivisionProblem {
+    /// A divisor that's always 0.
+    ByZero { index: usize },
+    /// The smallest integer divided by -1, where one of them is known and the
+    /// other could be it. Where neither is known, nothing is reported, or
+    /// every division of one variable by another would be.
+    Overflow { index: usize },
+}
+
+impl DivisionProblem {
+    pub fn index(&self) -> usize {
+        match self {
+            DivisionProblem::ByZero { index } | DivisionProblem::Overflow { index } => *index,
+        }
+    }
+}
+
+impl fmt::Display for DivisionProblem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DivisionProblem::ByZero { index } => {
+                write!(f, "instruction {index} always divides by zero")
+            }
+            DivisionProblem::Overflow { index } => write!(
+                f,
+                "instruction {index} can divide the smallest integer by -1, which wraps around \
+                 in the Rust interpreter but usually kills the C one"
+            ),
+        }
+    }
+}
+
+/// Finds the problems, in the order they appear in the program.
+pub fn check_division(program: &[Instruction]) -> Vec<DivisionProblem> {
+    let mut problems = Vec::new();
+    for block in Cfg::new(program).blocks {
+        // The constants on top of the stack, as far as they're known. Values
+        // from before the block aren't.
+        let mut stack: Vec<Option<i32>> = Vec::new();
+        for index in block.range {
+            let instruction = &program[index];
+            let (pops, pushes) = stack_effect(instruction);
+            // Anything popped from below what the block pushed is unknown.
+            // It isn't made up here, as a CALL can claim any number of
+            // arguments.
+            let popped = stack.split_off(stack.len().saturating_sub(pops));
+            let known = |depth: usize| {
+                (popped.len() + depth)
+                    .checked_sub(pops)
+                    .and_then(|i| popped.get(i).copied().flatten())
+            };
+            if let Instruction::Div | Instruction::Mod = instruction {
+                match (known(0), known(1)) {
+                    (_, Some(0)) => problems.push(DivisionProblem::ByZero { index }),
+                    (Some(i32::MIN), None | Some(-1)) | (None, Some(-1)) => {
+                        problems.push(DivisionProblem::Overflow { index })
+                    }
+                    _ => {}
+                }
+            }
+            let pushed = match (instruction, popped.as_slice()) {
+                (Instruction::Iconst(value), _) => i32::try_from(*value).ok(),
+                (_, &[Some(a), Some(b)]) if pops == 2 => {
+                    arithmetic::binary(instruction, a, b, DivisionOverflow::Fail)
+                        .and_then(Result::ok)
+                }
+                _ => None,
+            };
+            stack.extend((0..pushes).map(|_| pushed));
+        }
+    }
+    problems
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assemble;
+
+    #[test]
+    fn problems() {
+        let program = assemble::program(
+            "READ x ICONST 0 DIV
+             READ x ICONST -1 MOD
+             ICONST -2147483648 READ x DIV
+             ICONST -2147483647 ICONST 1 SUB ICONST 1 ICONST 2 SUB DIV
+             READ x ICONST -1 ICONST 2 ADD DIV
+             ICONST 5 ICONST -1 DIV
+             READ x READ y DIV
+             ICONST -1 l: DIV",
+        )
+        .unwrap();
+        assert_eq!(
+            check_division(&program),
+            [
+                DivisionProblem::ByZero { index: 2 },
+                DivisionProblem::Overflow { index: 5 },
+                DivisionProblem::Overflow { index: 8 },
+                DivisionProblem::Overflow { index: 15 },
+            ]
+        );
+    }
+
+    #[test]
+    fn huge_calls() {
+        let program = assemble::program(
+            "ICONST 1 ICONST 0 CALL f 8589934592 DIV
+             ICONST 0 CALL f 18446744073709551615 ICONST 0 DIV",
+        )
+        .unwrap();
+        assert_eq!(
+            check_division(&program),
+            [DivisionProblem::ByZero { index: 7 }]
+        );
+    }
+}
@@ -0,0 +1,271 @@
+// Tracks how deep the operand stack is through each path of the program, to
+// find instructions that pop values that aren't there, places that different
+// paths reach with different depths, and functions that return with values
+// left behind.
+
+use std::{collections::HashMap, fmt};
+
+use crate::{
+    analysis::{
+        cfg::{BlockId, Cfg},
+        stack_effect,
+    },
+    ir_definition::Instruction,
+};
+
+#[derive(Debug, PartialEq)]
+pub enum StackProblem {
+    /// An instruction that needs more values than there can be on the stack.
+    /// In a function, the depth only counts what the function pushed.
+    Underflow {
+        index: usize,
+        needs: usize,
+        depth: usize,
+    },
+    /// A place that one path reaches with one depth and another with another.
+    Mismatch {
+        index: usize,
+        first: usize,
+        second: usize,
+    },
+    /// A `RET` with more on the stack than the result. The Rust interpreter
+    /// throws the rest away, but the C one doesn't have to.
+    LeftoverAtRet { index: usize, extra: usize },
+    /// A `RET` that's reachable from the code outside of functions.
+    RetOutsideFunction { index: usize },
+}
+
+impl StackProblem {
+    pub fn index(&self) -> usize {
+        match self {
+            StackProblem::Underflow { index, .. }
+            | StackProblem::Mismatch { index, .. }
+            | StackProblem::LeftoverAtRet { index, .. }
+            | StackProblem::RetOutsideFunction { index } => *index,
+        }
+    }
+}
+
+impl fmt::Display for StackProblem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StackProblem::Underflow {
+                index,
+                needs,
+                depth,
+            } => write!(
+                f,
+                "instruction {index} needs {needs} values on the stack, \
+                 but there can be only {depth}"
+            ),
+            StackProblem::Mismatch {
+                index,
+                first,
+                second,
+            } => write!(
+                f,
+                "instruction {index} can be reached with {first} values on the stack, \
+                 or with {second}"
+            ),
+            StackProblem::LeftoverAtRet { index, extra } => write!(
+                f,
+                "the RET at instruction {index} leaves {extra} values on the stack \
+                 besides the result"
+            ),
+            StackProblem::RetOutsideFunction { index } => write!(
+                f,
+                "the RET at instruction {index} can be reached from outside of any function"
+            ),
+        }
+    }
+}
+
+/// Finds every stack problem on the paths from the start of the program and
+/// from the start of each function. Each function is checked on its own, from
+/// an empty stack, since its arguments aren't on the operand stack. Code that
+/// can't be reached isn't checked, and neither is a function marked
+/// `@no_verify_stack`, unless other code runs into it. Problems come in the
+/// order they appear in the program.
+pub fn check_stack(program: &[Instruction]) -> Vec<StackProblem> {
+    let cfg = Cfg::new(program);
+    let mut roots = Vec::new();
+    if !cfg.blocks.is_empty() {
+        roots.push((0, false));
+    }
+    roots.extend(
+        cfg.function_entries
+            .iter()
+            .filter(|&&(_, block)| !opts_out(&program[cfg.blocks[block].range.start]))
+            .map(|&(_, block)| (block, true)),
+    );
+
+    let mut problems = Vec::new();
+    for (root, in_function) in roots {
+        let mut depth_in: HashMap<BlockId, usize> = HashMap::from([(root, 0)]);
+        let mut to_visit = vec![root];
+        while let Some(block) = to_visit.pop() {
+            let mut depth = depth_in[&block];
+            for index in cfg.blocks[block].range.clone() {
+                let instruction = &program[index];
+                if *instruction == Instruction::Ret {
+                    if !in_function {
+                        problems.push(StackProblem::RetOutsideFunction { index });
+                    } else if depth > 1 {
+                        problems.push(StackProblem::LeftoverAtRet {
+                            index,
+                            extra: depth - 1,
+                        });
+                    }
+                }
+                let (pops, pushes) = stack_effect(instruction);
+                if depth < pops {
+                    problems.push(StackProblem::Underflow {
+                        index,
+                        needs: pops,
+                        depth,
+                    });
+                }
+                depth = depth.saturating_sub(pops) + pushes;
+            }
+            for &successor in &cfg.blocks[block].successors {
+                match depth_in.get(&successor) {
+                    None => {
+                        depth_in.insert(successor, depth);
+                        to_visit.push(successor);
+                    }
+                    Some(&first) if first != depth => problems.push(StackProblem::Mismatch {
+                        index: cfg.blocks[successor].range.start,
+                        first,
+                        second: depth,
+                    }),
+                    Some(_) => {}
+                }
+            }
+        }
+    }
+
+    // A block can be reached from more than one root, and a join more than
+    // once with the same wrong depth.
+    problems.sort_by_key(StackProblem::index);
+    problems.dedup();
+    problems
+}
+
+// Whether a function's stack shouldn't be checked, because it's marked
+// `@no_verify_stack`.
+fn opts_out(function: &Instruction) -> bool {
+    let Instruction::Function { attributes, .. } = function else {
+        return false;
+    };
+    attributes
+        .iter()
+        .any(|attribute| attribute.name == "no_verify_stack")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        assemble,
+        generate::{generate, GeneratorOptions},
+    };
+
+    #[test]
+    fn problems() {
+        let program = assemble::program(
+            "JUMP main
+             FUNCTION leaves_extra 0
+             ICONST 1
+             ICONST 2
+             RET
+             FUNCTION underflows 0
+             ADD
+             RET
+             main:
+             ICONST 0
+             BRANCHZERO skip
+             ICONST 1
+             skip:
+             ICONST 42
+             CALL leaves_extra 0
+             RET",
+        )
+        .unwrap();
+        let problems = check_stack(&program);
+        assert_eq!(
+            problems,
+            [
+                StackProblem::LeftoverAtRet { index: 4, extra: 1 },
+                StackProblem::Underflow {
+                    index: 6,
+                    needs: 2,
+                    depth: 0
+                },
+                StackProblem::Mismatch {
+                    index: 12,
+                    first: 0,
+                    second: 1
+                },
+                StackProblem::RetOutsideFunction { index: 15 },
+            ]
+        );
+        assert_eq!(
+            problems[2].to_string(),
+            "instruction 12 can be reached with 0 values on the stack, or with 1"
+        );
+    }
+
+    #[test]
+    fn unchecked_functions() {
+        let program = assemble::program(
+            "JUMP main
+             @no_verify_stack
+             FUNCTION unchecked 0
+             ADD
+             RET
+             FUNCTION checked 0
+             ADD
+             RET
+             main:
+             ICONST 0
+             CALL unchecked 0
+             RET",
+        )
+        .unwrap();
+        assert_eq!(
+            check_stack(&program),
+            [
+                StackProblem::Underflow {
+                    index: 5,
+                    needs: 2,
+                    depth: 0
+                },
+                StackProblem::RetOutsideFunction { index: 10 },
+            ]
+        );
+    }
+
+    #[test]
+    fn absurd_calls() {
+        let program = assemble::program("ICONST 42 CALL f 18446744073709551615").unwrap();
+        assert_eq!(
+            check_stack(&program),
+            [StackProblem::Underflow {
+                index: 1,
+                needs: usize::MAX,
+                depth: 1
+            }]
+        );
+    }
+
+    #[test]
+    fn generated_programs_are_fine() {
+        for seed in 0..20 {
+            let program = generate(&GeneratorOptions {
+                seed,
+                ..Default::default()
+            });
+            assert_eq!(check_stack(&program), [], "seed {seed}");
+        }
+    }
+}
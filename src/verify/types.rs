@@ -0,0 +1,423 @@
+// Infers whether each value is an integer or a string, to find instructions
+// that can be given the wrong one, like arithmetic on a string or a string
+// printed with PRINT_INT.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt,
+};
+
+use crate::{
+    analysis::cfg::{BlockId, Cfg},
+    ir_definition::{Instruction, Intrinsic},
+};
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Type {
+    Int,
+    Str,
+}
+
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Type::Int => write!(f, "an integer"),
+            Type::Str => write!(f, "a string"),
+        }
+    }
+}
+
+/// The types a value can have. Neither means nothing is known about it, like
+/// for a register that's never written.
+#[derive(Debug, Default, PartialEq, Clone, Copy)]
+struct Types {
+    int: bool,
+    string: bool,
+}
+
+impl Types {
+    const INT: Types = Types {
+        int: true,
+        string: false,
+    };
+    const STR: Types = Types {
+        int: false,
+        string: true,
+    };
+
+    fn join(&mut self, other: Types) {
+        self.int |= other.int;
+        self.string |= other.string;
+    }
+
+    /// A type the value can have other than `expected`.
+    fn other_than(self, expected: Type) -> Option<Type> {
+        match expected {
+            Type::Int if self.string => Some(Type::Str),
+            Type::Str if self.int => Some(Type::Int),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct TypeProblem {
+    pub index: usize,
+    pub expected: Type,
+    pub found: Type,
+}
+
+impl fmt::Display for TypeProblem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "instruction {} needs {}, but can be given {}",
+            self.index, self.expected, self.found
+        )
+    }
+}
+
+/// What's known at one point in the program.
+#[derive(Debug, Default, Clone, PartialEq)]
+struct State {
+    stack: Vec<Types>,
+    /// By index, as ARGLOCAL_READ and ARGLOCAL_WRITE give it. Only the ones
+    /// written are here, since an index can be anything up to `u64::MAX`.
+    slots: BTreeMap<u64, Types>,
+}
+
+impl State {
+    /// Adds what's known in `other`. Where the stacks are different heights
+    /// (which `stack::check_stack` reports), the first height is kept.
+    fn join(&mut self, other: &State) -> bool {
+        let before = self.clone();
+        for (types, other) in self.stack.iter_mut().zip(&other.stack) {
+            types.join(*other);
+        }
+        for (slot, other) in &other.slots {
+            self.slots.entry(*slot).or_default().join(*other);
+        }
+        *self != before
+    }
+
+    fn pop(&mut self) -> Types {
+        self.stack.pop().unwrap_or_default()
+    }
+}
+
+/// What's known about the whole program, however it got there. Front-ends
+/// reserve string variables without a value as well as integer ones, so a
+/// global holds whatever's written to it anywhere.
+#[derive(Debug, Default, Clone, PartialEq)]
+struct Summaries {
+    globals: HashMap<String, Types>,
+    registers: HashMap<i64, Types>,
+    /// The state each function starts in, from all of its calls.
+    entries: HashMap<String, State>,
+    /// The types each function can return.
+    results: HashMap<String, Types>,
+}
+
+struct Checker<'a> {
+    program: &'a [Instruction],
+    cfg: Cfg,
+    summaries: Summaries,
+    problems: Vec<TypeProblem>,
+}
+
+impl Checker<'_> {
+    fn expect(&mut self, index: usize, types: Types, expected: Type) {
+        if let Some(found) = types.other_than(expected) {
+            self.problems.push(TypeProblem {
+                index,
+                expected,
+                found,
+            });
+        }
+    }
+
+    fn step(&mut self, index: usize, state: &mut State, function: Option<&str>) {
+        match &self.program[index] {
+            Instruction::Iconst(_) => state.stack.push(Types::INT),
+            Instruction::Sconst(_) => state.stack.push(Types::STR),
+            Instruction::Read(name) => {
+                let types = self.summaries.globals.get(name).copied();
+                state.stack.push(types.unwrap_or_default());
+            }
+            Instruction::Write(name) => {
+                let value = state.pop();
+                let global = self.summaries.globals.entry(name.clone());
+                global.or_default().join(value);
+            }
+            Instruction::ReserveString { name, .. } => {
+                let global = self.summaries.globals.entry(name.clone());
+                global.or_default().join(Types::STR);
+            }
+            Instruction::ArgLocalRead(slot) => {
+                // Slots past the arguments are locals, which start at 0.
+                let types = state.slots.get(slot).copied().unwrap_or(Types::INT);
+                state.stack.push(types);
+            }
+            Instruction::ArgLocalWrite(slot) => {
+                let value = state.pop();
+                state.slots.insert(*slot, value);
+            }
+            Instruction::Push { reg } => {
+                let types = self.summaries.registers.get(reg).copied();
+                state.stack.push(types.unwrap_or_default());
+            }
+            Instruction::Pop { reg } => {
+                let value = state.pop();
+                // Register -1 is where unused values go to be thrown away.
+                if *reg != -1 {
+                    self.summaries
+                        .registers
+                        .entry(*reg)
+                        .or_default()
+                        .join(value);
+                }
+            }
+            Instruction::Not => {
+                let value = state.pop();
+                self.expect(index, value, Type::Int);
+                state.stack.push(Types::INT);
+            }
+            Instruction::BranchZero(_) => {
+                let value = state.pop();
+                self.expect(index, value, Type::Int);
+            }
+            Instruction::Call { label, num_args } => {
+                // Only what's on the stack can be an argument; `check_stack`
+                // reports the rest as an underflow. The arguments that
+                // aren't there are the first ones, and are left out.
+                let available = state.stack.len() as u64;
+                let first = num_args.saturating_sub(available);
+                let args = state
+                    .stack
+                    .split_off(state.stack.len() - (num_args - first) as usize);
+                state.pop(); // The placeholder, which is replaced by the result.
+                let entry = self
+                    .summaries
+                    .entries
+                    .entry(label.name().to_string())
+                    .or_default();
+                entry.join(&State {
+                    stack: Vec::new(),
+                    slots: (first..).zip(args).collect(),
+                });
+                let result = self.summaries.results.get(label.name()).copied();
+                state.stack.push(result.unwrap_or_default());
+            }
+            Instruction::Ret => {
+                let value = state.pop();
+                if let Some(function) = function {
+                    let result = self.summaries.results.entry(function.to_string());
+                    result.or_default().join(value);
+                }
+            }
+            Instruction::Intrinsic(Intrinsic::PrintString) => {
+                let value = state.pop();
+                self.expect(index, value, Type::Str);
+            }
+            Instruction::Intrinsic(Intrinsic::PrintInt | Intrinsic::Exit) => {
+                let value = state.pop();
+                self.expect(index, value, Type::Int);
+            }
+            Instruction::Nop
+            | Instruction::Label(_)
+            | Instruction::Jump(_)
+            | Instruction::Function { .. }
+            | Instruction::ReserveInt { .. } => {}
+            _ => {
+                // The binary operations.
+                let b = state.pop();
+                let a = state.pop();
+                self.expect(index, a, Type::Int);
+                self.expect(index, b, Type::Int);
+                state.stack.push(Types::INT);
+            }
+        }
+    }
+
+    /// Runs the analysis from the start of the program, and of each function,
+    /// once.
+    fn pass(&mut self) {
+        let mut roots = Vec::new();
+        if !self.cfg.blocks.is_empty() {
+            roots.push((0, None));
+        }
+        for (name, block) in &self.cfg.function_entries {
+            roots.push((*block, Some(name.clone())));
+        }
+
+        for (root, function) in roots {
+            let start = match &function {
+                Some(function) => self.summaries.entries.get(function).cloned(),
+                None => None,
+            };
+            let mut states: HashMap<BlockId, State> =
+                HashMap::from([(root, start.unwrap_or_default())]);
+            let mut to_visit = vec![root];
+            while let Some(block) = to_visit.pop() {
+                let mut state = states[&block].clone();
+                for index in self.cfg.blocks[block].range.clone() {
+                    self.step(index, &mut state, function.as_deref());
+                }
+                for &successor in &self.cfg.blocks[block].successors {
+                    let changed = match states.get_mut(&successor) {
+                        Some(successor_state) => successor_state.join(&state),
+                        None => {
+                            states.insert(successor, state.clone());
+                            true
+                        }
+                    };
+                    if changed {
+                        to_visit.push(successor);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Finds every instruction that can be given a value of the wrong type, on
+/// the paths from the start of the program and from the start of each
+/// function. What a function's arguments and result can be comes from all of
+/// its calls and returns, and what a global or register can hold from
+/// everywhere it's written, so a problem found can be on a path that's never taken. Code that
+/// can't be reached isn't checked. Problems come in the order they appear in
+/// the program.
+pub fn check_types(program: &[Instruction]) -> Vec<TypeProblem> {
+    let mut checker = Checker {
+        program,
+        cfg: Cfg::new(program),
+        summaries: Summaries::default(),
+        problems: Vec::new(),
+    };
+    // The summaries only ever grow, so this ends. Only the last pass's
+    // problems are kept, since that's the one that knew everything.
+    loop {
+        let before = checker.summaries.clone();
+        checker.problems.clear();
+        checker.pass();
+        if checker.summaries == before {
+            break;
+        }
+    }
+
+    let mut problems = checker.problems;
+    problems.sort_by_key(|problem| problem.index);
+    problems.dedup();
+    problems
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        assemble,
+        generate::{generate, GeneratorOptions},
+    };
+
+    #[test]
+    fn problems() {
+        let program = assemble::program(
+            "RESERVE count 4 (null)
+             RESERVE name 6 \"hello\"
+             JUMP main
+             FUNCTION greet 0
+             ARGLOCAL_READ 0
+             INTRINSIC PRINT_STRING
+             ARGLOCAL_READ 0
+             RET
+             main:
+             ICONST 42
+             SCONST \"world\"
+             CALL greet 1
+             ICONST 1
+             ADD
+             READ name
+             WRITE count
+             READ count
+             NOT
+             ICONST 42
+             ICONST 7
+             CALL greet 1
+             POP 3
+             PUSH 3
+             INTRINSIC PRINT_INT",
+        )
+        .unwrap();
+        let problems = check_types(&program);
+        assert_eq!(
+            problems,
+            [
+                TypeProblem {
+                    index: 5,
+                    expected: Type::Str,
+                    found: Type::Int
+                },
+                TypeProblem {
+                    index: 13,
+                    expected: Type::Int,
+                    found: Type::Str
+                },
+                TypeProblem {
+                    index: 17,
+                    expected: Type::Int,
+                    found: Type::Str
+                },
+                TypeProblem {
+                    index: 23,
+                    expected: Type::Int,
+                    found: Type::Str
+                },
+            ]
+        );
+        assert_eq!(
+            problems[1].to_string(),
+            "instruction 13 needs an integer, but can be given a string"
+        );
+    }
+
+    #[test]
+    fn huge_operands() {
+        // Slots are kept only where they're written, and a call only takes
+        // what's on the stack, however many arguments it says it has.
+        let program = assemble::program(
+            "SCONST \"a\"
+             ARGLOCAL_WRITE 18446744073709551615
+             ARGLOCAL_READ 18446744073709551615
+             INTRINSIC PRINT_INT
+             ICONST 0
+             SCONST \"b\"
+             CALL f 18446744073709551615
+             INTRINSIC PRINT_INT
+             FUNCTION f 0
+             ARGLOCAL_READ 18446744073709551614
+             INTRINSIC PRINT_INT
+             ARGLOCAL_READ 0
+             RET",
+        )
+        .unwrap();
+        assert_eq!(
+            check_types(&program),
+            [3, 10].map(|index| TypeProblem {
+                index,
+                expected: Type::Int,
+                found: Type::Str
+            })
+        );
+    }
+
+    #[test]
+    fn generated_programs_are_fine() {
+        for seed in 0..20 {
+            let program = generate(&GeneratorOptions {
+                seed,
+                ..Default::default()
+            });
+            assert_eq!(check_types(&program), [], "seed {seed}");
+        }
+    }
+}
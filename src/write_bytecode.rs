@@ -1,177 +1,381 @@
 use crate::bindings::*;
-use std::io;
+use std::{fmt, io};
 
 use crate::ir_definition::{Intrinsic, Instruction, Label};
 
-pub fn write_bytecode(ir_list: &[Instruction], out: &mut impl io::Write) -> io::Result<()> {
-    for node in ir_list {
-        node.write_bytecode(out)?;
+/// Which end of each integer in the bytecode comes first.
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+pub enum Endianness {
+    #[default]
+    Little,
+    Big,
+}
+
+/// How big each integer in the bytecode is.
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+pub enum IntWidth {
+    #[default]
+    W32,
+    /// Wide enough for every ICONST.
+    W64,
+}
+
+impl IntWidth {
+    pub fn bits(self) -> u32 {
+        match self {
+            IntWidth::W32 => 32,
+            IntWidth::W64 => 64,
+        }
+    }
+}
+
+/// How the integers in bytecode are laid out: every opcode, operand and
+/// string length. The default is what the C interpreter reads, which is what
+/// its structs hold on the little-endian machines it's built on. Bytecode
+/// written any other way is for carrying a program to another machine; only
+/// `disassemble_with` can read it.
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+pub struct BytecodeOptions {
+    pub endianness: Endianness,
+    pub int_width: IntWidth,
+}
+
+/// Why a program couldn't be written as bytecode. The instruction is given by
+/// its index in the program.
+#[derive(Debug)]
+pub enum BytecodeWriteError {
+    /// A number, or the length of a string counting its terminator, that
+    /// doesn't fit in the bytecode's integers, which have `bits` bits.
+    Overflow { index: usize, value: i128, bits: u32 },
+    /// A string with a NUL in it, which the C code would take to be the end.
+    InteriorNul { index: usize, text: String },
+    Io(io::Error),
+}
+
+impl fmt::Display for BytecodeWriteError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BytecodeWriteError::Overflow { index, value, bits } => write!(
+                f,
+                "instruction {index}: {value} doesn't fit in the bytecode's {bits}-bit integers"
+            ),
+            BytecodeWriteError::InteriorNul { index, text } => write!(
+                f,
+                "instruction {index}: {text:?} has a NUL in it, which bytecode strings can't"
+            ),
+            BytecodeWriteError::Io(error) => write!(f, "couldn't write the bytecode: {error}"),
+        }
+    }
+}
+
+impl std::error::Error for BytecodeWriteError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            BytecodeWriteError::Io(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for BytecodeWriteError {
+    fn from(error: io::Error) -> Self {
+        BytecodeWriteError::Io(error)
+    }
+}
+
+/// Writes `ir_list` to `out`, one instruction at a time, so if one of them
+/// can't be written, the ones before it already have been. It's written the
+/// way the C interpreter reads it.
+pub fn write_bytecode(ir_list: &[Instruction], out: &mut impl io::Write) -> Result<(), BytecodeWriteError> {
+    write_bytecode_with(ir_list, out, &BytecodeOptions::default())
+}
+
+/// Like `write_bytecode`, but with the integers laid out as `options` says.
+pub fn write_bytecode_with(
+    ir_list: &[Instruction],
+    out: &mut impl io::Write,
+    options: &BytecodeOptions,
+) -> Result<(), BytecodeWriteError> {
+    for (index, node) in ir_list.iter().enumerate() {
+        node.write_bytecode(out, options).map_err(|fault| match fault {
+            Fault::Overflow(value) => BytecodeWriteError::Overflow {
+                index,
+                value,
+                bits: options.int_width.bits(),
+            },
+            Fault::InteriorNul(text) => BytecodeWriteError::InteriorNul { index, text },
+            Fault::Io(error) => BytecodeWriteError::Io(error),
+        })?;
     }
     Ok(())
 }
 
+/// A `BytecodeWriteError` before it's known which instruction it's in.
+enum Fault {
+    Overflow(i128),
+    InteriorNul(String),
+    Io(io::Error),
+}
+
+impl From<io::Error> for Fault {
+    fn from(error: io::Error) -> Self {
+        Fault::Io(error)
+    }
+}
+
 trait WriteBytecode {
-    fn write_bytecode(&self, out: &mut impl io::Write) -> io::Result<()>;
+    fn write_bytecode(&self, out: &mut impl io::Write, options: &BytecodeOptions) -> Result<(), Fault>;
+}
+
+// Every integer in the bytecode is written through this.
+fn write_int(value: i128, out: &mut impl io::Write, options: &BytecodeOptions) -> Result<(), Fault> {
+    let bits = options.int_width.bits();
+    if value < -(1 << (bits - 1)) || value >= 1 << (bits - 1) {
+        return Err(Fault::Overflow(value));
+    }
+    // It fits, so the bytes left out are all sign.
+    let width = bits as usize / 8;
+    match options.endianness {
+        Endianness::Little => out.write_all(&value.to_le_bytes()[..width])?,
+        Endianness::Big => out.write_all(&value.to_be_bytes()[16 - width..])?,
+    }
+    Ok(())
 }
 
 impl WriteBytecode for i32 {
-    fn write_bytecode(&self, out: &mut impl io::Write) -> io::Result<()> {
-        out.write_all(&self.to_le_bytes())
+    fn write_bytecode(&self, out: &mut impl io::Write, options: &BytecodeOptions) -> Result<(), Fault> {
+        write_int((*self).into(), out, options)
     }
 }
 
+// Opcodes and intrinsics. They're written as signed integers, like everything else.
 impl WriteBytecode for u32 {
-    fn write_bytecode(&self, out: &mut impl io::Write) -> io::Result<()> {
-        out.write_all(&self.to_le_bytes())
+    fn write_bytecode(&self, out: &mut impl io::Write, options: &BytecodeOptions) -> Result<(), Fault> {
+        write_int((*self).into(), out, options)
     }
 }
 
 impl WriteBytecode for i64 {
-    fn write_bytecode(&self, out: &mut impl io::Write) -> io::Result<()> {
+    fn write_bytecode(&self, out: &mut impl io::Write, options: &BytecodeOptions) -> Result<(), Fault> {
         // Should we really be limiting ourselves to only 32 bits for integer constants in the IR?
         // I guess if we're mostly targeting MIPS-32, that makes sense.
-        i32::try_from(*self)
-            .expect("Integer too big for serialized bytecode format.")
-            .write_bytecode(out)
+        write_int((*self).into(), out, options)
     }
 }
 
 impl WriteBytecode for u64 {
-    fn write_bytecode(&self, out: &mut impl io::Write) -> io::Result<()> {
-        // This is an i32 on purpose, because the C code expects an int, not an unsigned int.
-        i32::try_from(*self)
-            .expect("Integer too big for serialized bytecode format.")
-            .write_bytecode(out)
+    fn write_bytecode(&self, out: &mut impl io::Write, options: &BytecodeOptions) -> Result<(), Fault> {
+        // This is signed on purpose, because the C code expects an int, not an unsigned int.
+        write_int((*self).into(), out, options)
     }
 }
 
 impl WriteBytecode for &str {
-    fn write_bytecode(&self, out: &mut impl io::Write) -> io::Result<()> {
+    fn write_bytecode(&self, out: &mut impl io::Write, options: &BytecodeOptions) -> Result<(), Fault> {
         let raw_bytes = self.as_bytes();
+        if raw_bytes.contains(&0) {
+            return Err(Fault::InteriorNul(self.to_string()));
+        }
 
         // TODO: But why is it signed? Is it safe to make it unsigned?
-        let length_including_null_terminator = i32::try_from(raw_bytes.len() + 1)
-            .expect("String too long for serialized bytecode format.");
-        length_including_null_terminator.write_bytecode(out)?;
+        let length_including_null_terminator = (raw_bytes.len() + 1) as i128;
+        write_int(length_including_null_terminator, out, options)?;
         out.write_all(raw_bytes)?;
-        out.write_all(&[0u8])
+        Ok(out.write_all(&[0u8])?)
     }
 }
 
 // TODO: `use`ing Label and Intrinsic is a little ugly because it's *so close*
 // to a name collision with the C stuff.
 impl WriteBytecode for Label {
-    fn write_bytecode(&self, out: &mut impl io::Write) -> io::Result<()> {
-        self.name().write_bytecode(out)
+    fn write_bytecode(&self, out: &mut impl io::Write, options: &BytecodeOptions) -> Result<(), Fault> {
+        self.name().write_bytecode(out, options)
     }
 }
 
 impl WriteBytecode for Intrinsic {
-    fn write_bytecode(&self, out: &mut impl io::Write) -> io::Result<()> {
+    fn write_bytecode(&self, out: &mut impl io::Write, options: &BytecodeOptions) -> Result<(), Fault> {
         let val_to_write = match self {
             Intrinsic::PrintInt => intrinsic_intrinsic_print_int,
             Intrinsic::PrintString => intrinsic_intrinsic_print_string,
             Intrinsic::Exit => intrinsic_intrinsic_exit,
         };
-        val_to_write.write_bytecode(out)
+        val_to_write.write_bytecode(out, options)
     }
 }
 // TODO: consider creating newtyping bindings for enums in ir.c instead, and then
 // importing all the variants, to cut down on noise.
 impl WriteBytecode for Instruction {
-    fn write_bytecode(&self, out: &mut impl io::Write) -> io::Result<()> {
+    fn write_bytecode(&self, out: &mut impl io::Write, options: &BytecodeOptions) -> Result<(), Fault> {
         match self {
-            Instruction::Nop => ir_op_ir_nop.write_bytecode(out),
+            Instruction::Nop => ir_op_ir_nop.write_bytecode(out, options),
             Instruction::Iconst(num) => {
-                ir_op_ir_iconst.write_bytecode(out)?;
-                num.write_bytecode(out)
+                ir_op_ir_iconst.write_bytecode(out, options)?;
+                num.write_bytecode(out, options)
             }
             Instruction::Sconst(text) => {
-                ir_op_ir_sconst.write_bytecode(out)?;
-                text.as_str().write_bytecode(out)
-            }
-            Instruction::Add => ir_op_ir_add.write_bytecode(out),
-            Instruction::Sub => ir_op_ir_sub.write_bytecode(out),
-            Instruction::Mul => ir_op_ir_mul.write_bytecode(out),
-            Instruction::Div => ir_op_ir_div.write_bytecode(out),
-            Instruction::Mod => ir_op_ir_mod.write_bytecode(out),
-            Instruction::Bor => ir_op_ir_bor.write_bytecode(out),
-            Instruction::Band => ir_op_ir_band.write_bytecode(out),
-            Instruction::Xor => ir_op_ir_xor.write_bytecode(out),
-            Instruction::Or => ir_op_ir_or.write_bytecode(out),
-            Instruction::And => ir_op_ir_and.write_bytecode(out),
-            Instruction::Eq => ir_op_ir_eq.write_bytecode(out),
-            Instruction::Lt => ir_op_ir_lt.write_bytecode(out),
-            Instruction::Gt => ir_op_ir_gt.write_bytecode(out),
-            Instruction::Not => ir_op_ir_not.write_bytecode(out),
+                ir_op_ir_sconst.write_bytecode(out, options)?;
+                text.as_str().write_bytecode(out, options)
+            }
+            Instruction::Add => ir_op_ir_add.write_bytecode(out, options),
+            Instruction::Sub => ir_op_ir_sub.write_bytecode(out, options),
+            Instruction::Mul => ir_op_ir_mul.write_bytecode(out, options),
+            Instruction::Div => ir_op_ir_div.write_bytecode(out, options),
+            Instruction::Mod => ir_op_ir_mod.write_bytecode(out, options),
+            Instruction::Bor => ir_op_ir_bor.write_bytecode(out, options),
+            Instruction::Band => ir_op_ir_band.write_bytecode(out, options),
+            Instruction::Xor => ir_op_ir_xor.write_bytecode(out, options),
+            Instruction::Or => ir_op_ir_or.write_bytecode(out, options),
+            Instruction::And => ir_op_ir_and.write_bytecode(out, options),
+            Instruction::Eq => ir_op_ir_eq.write_bytecode(out, options),
+            Instruction::Lt => ir_op_ir_lt.write_bytecode(out, options),
+            Instruction::Gt => ir_op_ir_gt.write_bytecode(out, options),
+            Instruction::Not => ir_op_ir_not.write_bytecode(out, options),
             Instruction::ReserveString {
                 size,
                 name,
                 initial_value,
             } => {
-                ir_op_ir_reserve.write_bytecode(out)?;
-                name.as_str().write_bytecode(out)?;
-                initial_value.as_str().write_bytecode(out)?;
-                size.write_bytecode(out)
+                ir_op_ir_reserve.write_bytecode(out, options)?;
+                name.as_str().write_bytecode(out, options)?;
+                initial_value.as_str().write_bytecode(out, options)?;
+                size.write_bytecode(out, options)
             }
             Instruction::ReserveInt { name } => {
-                ir_op_ir_reserve.write_bytecode(out)?;
-                name.as_str().write_bytecode(out)?;
+                ir_op_ir_reserve.write_bytecode(out, options)?;
+                name.as_str().write_bytecode(out, options)?;
                 // Write the size 0, and nothing else for the string, because the string is conceptually null.
-                0.write_bytecode(out)?;
-                4.write_bytecode(out)
+                0.write_bytecode(out, options)?;
+                4.write_bytecode(out, options)
             }
             Instruction::Read(name) => {
-                ir_op_ir_read.write_bytecode(out)?;
-                name.as_str().write_bytecode(out)
+                ir_op_ir_read.write_bytecode(out, options)?;
+                name.as_str().write_bytecode(out, options)
             }
             Instruction::Write(name) => {
-                ir_op_ir_write.write_bytecode(out)?;
-                name.as_str().write_bytecode(out)
+                ir_op_ir_write.write_bytecode(out, options)?;
+                name.as_str().write_bytecode(out, options)
             }
             Instruction::ArgLocalRead(index) => {
-                ir_op_ir_arglocal_read.write_bytecode(out)?;
-                index.write_bytecode(out)
+                ir_op_ir_arglocal_read.write_bytecode(out, options)?;
+                index.write_bytecode(out, options)
             }
             Instruction::ArgLocalWrite(index) => {
-                ir_op_ir_arglocal_write.write_bytecode(out)?;
-                index.write_bytecode(out)
+                ir_op_ir_arglocal_write.write_bytecode(out, options)?;
+                index.write_bytecode(out, options)
             }
             Instruction::Label(label) => {
-                ir_op_ir_lbl.write_bytecode(out)?;
-                label.write_bytecode(out)
+                ir_op_ir_lbl.write_bytecode(out, options)?;
+                label.write_bytecode(out, options)
             }
             Instruction::Jump(label) => {
-                ir_op_ir_jump.write_bytecode(out)?;
-                label.write_bytecode(out)
+                ir_op_ir_jump.write_bytecode(out, options)?;
+                label.write_bytecode(out, options)
             }
             Instruction::BranchZero(label) => {
-                ir_op_ir_branchzero.write_bytecode(out)?;
-                label.write_bytecode(out)
+                ir_op_ir_branchzero.write_bytecode(out, options)?;
+                label.write_bytecode(out, options)
             }
-            Instruction::Function { label, num_locs } => {
-                ir_op_ir_function.write_bytecode(out)?;
-                label.write_bytecode(out)?;
-                num_locs.write_bytecode(out)
+            Instruction::Function {
+                label, num_locs, ..
+            } => {
+                ir_op_ir_function.write_bytecode(out, options)?;
+                label.write_bytecode(out, options)?;
+                num_locs.write_bytecode(out, options)
             }
             Instruction::Call { label, num_args } => {
-                ir_op_ir_call.write_bytecode(out)?;
-                label.write_bytecode(out)?;
-                num_args.write_bytecode(out)
+                ir_op_ir_call.write_bytecode(out, options)?;
+                label.write_bytecode(out, options)?;
+                num_args.write_bytecode(out, options)
             }
-            Instruction::Ret => ir_op_ir_ret.write_bytecode(out),
+            Instruction::Ret => ir_op_ir_ret.write_bytecode(out, options),
             Instruction::Intrinsic(intrinsic) => {
-                ir_op_ir_intrinsic.write_bytecode(out)?;
-                intrinsic.write_bytecode(out)
+                ir_op_ir_intrinsic.write_bytecode(out, options)?;
+                intrinsic.write_bytecode(out, options)
             }
             Instruction::Push { reg } => {
-                ir_op_ir_push.write_bytecode(out)?;
-                reg.write_bytecode(out)
+                ir_op_ir_push.write_bytecode(out, options)?;
+                reg.write_bytecode(out, options)
             }
             Instruction::Pop { reg } => {
-                ir_op_ir_pop.write_bytecode(out)?;
-                reg.write_bytecode(out)
+                ir_op_ir_pop.write_bytecode(out, options)?;
+                reg.write_bytecode(out, options)
             }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn errors() {
+        let write = |program: &[Instruction]| write_bytecode(program, &mut Vec::new());
+        assert!(write(&[Instruction::Iconst(i32::MIN.into())]).is_ok());
+        match write(&[Instruction::Nop, Instruction::Iconst(1 << 31)]) {
+            Err(BytecodeWriteError::Overflow {
+                index: 1,
+                value,
+                bits: 32,
+            }) => assert_eq!(value, 1 << 31),
+            other => panic!("{other:?}"),
+        }
+        match write(&[Instruction::ArgLocalRead(u64::MAX)]) {
+            Err(BytecodeWriteError::Overflow { index: 0, value, .. }) => {
+                assert_eq!(value, u64::MAX.into())
+            }
+            other => panic!("{other:?}"),
+        }
+        let error = write(&[Instruction::Sconst("a\0b".into())]).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "instruction 0: \"a\\0b\" has a NUL in it, which bytecode strings can't"
+        );
+
+        let mut full = [0u8; 6];
+        let error = write_bytecode(&[Instruction::Iconst(1)], &mut &mut full[..]).unwrap_err();
+        assert!(matches!(error, BytecodeWriteError::Io(_)));
+    }
+
+    #[test]
+    fn options() {
+        let write = |program: &[Instruction], endianness, int_width| {
+            let mut bytecode = Vec::new();
+            let options = BytecodeOptions {
+                endianness,
+                int_width,
+            };
+            write_bytecode_with(program, &mut bytecode, &options).map(|()| bytecode)
+        };
+        let iconst = [Instruction::Iconst(-2)];
+        let opcode = ir_op_ir_iconst as u8;
+        assert_eq!(
+            write(&iconst, Endianness::Little, IntWidth::W32).unwrap(),
+            [opcode, 0, 0, 0, 0xfe, 0xff, 0xff, 0xff]
+        );
+        assert_eq!(
+            write(&iconst, Endianness::Big, IntWidth::W32).unwrap(),
+            [0, 0, 0, opcode, 0xff, 0xff, 0xff, 0xfe]
+        );
+        assert_eq!(
+            write(&[Instruction::Sconst("a".into())], Endianness::Big, IntWidth::W64).unwrap(),
+            [0, 0, 0, 0, 0, 0, 0, ir_op_ir_sconst as u8, 0, 0, 0, 0, 0, 0, 0, 2, b'a', 0]
+        );
+
+        let big = [Instruction::Iconst(1 << 40)];
+        assert!(write(&big, Endianness::Little, IntWidth::W64).is_ok());
+        assert_eq!(
+            write(&big, Endianness::Little, IntWidth::W32)
+                .unwrap_err()
+                .to_string(),
+            "instruction 0: 1099511627776 doesn't fit in the bytecode's 32-bit integers"
+        );
+        assert_eq!(
+            write(&[Instruction::ArgLocalRead(u64::MAX)], Endianness::Little, IntWidth::W64)
+                .unwrap_err()
+                .to_string(),
+            "instruction 0: 18446744073709551615 doesn't fit in the bytecode's 64-bit integers"
+        );
+    }
+}